@@ -0,0 +1,280 @@
+use crate::fs::FileSystem;
+use crate::ignore::Ignore;
+use crate::index::{Entry, Index};
+use crate::object::blob;
+use crate::object::tree::FileMode;
+use crate::object::GitObject;
+use crate::pathspec::{Item, Pathspec};
+use crate::Git;
+use std::collections::HashSet;
+use std::io;
+
+/// What `add` stages besides the files a pathspec matches.
+#[derive(Debug, Clone, Default)]
+pub struct AddOptions {
+    /// Only stage modified and deleted tracked files (`-u`).
+    pub update: bool,
+    /// Work out what would be staged without writing anything (`-n`).
+    pub dry_run: bool,
+    /// Record new files without their content (`-N`). Tracked files are
+    /// left alone.
+    pub intent_to_add: bool,
+    /// Add ignored files as well (`-f`).
+    pub force: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Staged {
+    Add(String),
+    Remove(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AddResult {
+    /// Tracked files first, then new ones, as `add -n` lists them.
+    pub staged: Vec<Staged>,
+    /// Paths named on the command line that are ignored and weren't added.
+    pub ignored: Vec<String>,
+}
+
+impl<F: FileSystem> Git<F> {
+    /// Stages the files `pathspec` matches: new and modified files are
+    /// added and tracked files gone from the worktree are removed. Nothing
+    /// is written when one of the items matches nothing.
+    pub fn add(&mut self, pathspec: &Pathspec, options: &AddOptions) -> io::Result<AddResult> {
+        let mut index = match self.read_index() {
+            Ok(bytes) => self.ls_files_stage(&bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Index::new(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let items = pathspec.includes().collect::<Vec<_>>();
+        let mut seen = vec![false; items.len()];
+        let mut result = AddResult::default();
+
+        for entry in &index.entries {
+            if !pathspec.matches(&entry.name) {
+                continue;
+            }
+            mark_seen(&items, &mut seen, &entry.name);
+            if options.intent_to_add {
+                continue;
+            }
+
+            let name = entry.name.clone();
+            match self.file_system.stat(name.clone()) {
+                Ok(metadata) if metadata.is_dir() && entry.mode != FileMode::Gitlink => {
+                    result.staged.push(Staged::Remove(name))
+                }
+                Ok(_) if self.worktree_changed(entry)? => result.staged.push(Staged::Add(name)),
+                Ok(_) => {}
+                Err(e) if is_missing(&e) => result.staged.push(Staged::Remove(name)),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut new_files = HashSet::new();
+        if !options.update {
+            let tracked = index.entries.iter().map(|x| x.name.clone()).collect();
+            let mut ignore = self.ignore()?;
+            let walk_ignore = if options.force {
+                None
+            } else {
+                Some(&mut ignore)
+            };
+            // Other repositories inside the worktree ("dir/") aren't added.
+            for path in self.untracked_files(&tracked, walk_ignore)? {
+                if path.ends_with('/') || !pathspec.matches(&path) {
+                    continue;
+                }
+                mark_seen(&items, &mut seen, &path);
+                new_files.insert(path.clone());
+                result.staged.push(Staged::Add(path));
+            }
+
+            if !options.force {
+                for (item, seen) in items.iter().zip(seen.iter_mut()) {
+                    if *seen || item.has_wildcards() || item.pattern.is_empty() {
+                        continue;
+                    }
+                    if let Some(path) = self.ignored_prefix(&mut ignore, &item.pattern)? {
+                        *seen = true;
+                        if !result.ignored.contains(&path) {
+                            result.ignored.push(path);
+                        }
+                    }
+                }
+            }
+        }
+
+        for (item, &seen) in items.iter().zip(seen.iter()) {
+            if !seen
+                && !item.pattern.is_empty()
+                && self.file_system.stat(item.pattern.clone()).is_err()
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("pathspec '{}' did not match any files", item.original),
+                ));
+            }
+        }
+        if options.dry_run {
+            return Ok(result);
+        }
+
+        for staged in &result.staged {
+            match staged {
+                Staged::Add(name) if options.intent_to_add && new_files.contains(name) => {
+                    // Nothing is known about the file until it is really added.
+                    let mode = FileMode::from_stat(self.file_system.stat(name.clone())?.mode);
+                    let hash = blob::hash_bytes(b"");
+                    let mut entry = Entry::cacheinfo(mode, hash, name.clone());
+                    entry.intent_to_add = true;
+                    index.add(entry);
+                }
+                Staged::Add(name) => {
+                    let entry = self.stage_file(name)?;
+                    index.add(entry);
+                }
+                Staged::Remove(name) => {
+                    index.remove(name);
+                }
+            }
+        }
+        self.write_index(&index)?;
+
+        Ok(result)
+    }
+
+    /// Writes the worktree file at `path` as a blob and returns its entry.
+    fn stage_file(&mut self, path: &str) -> io::Result<Entry> {
+        let bytes = self.read_worktree(path)?;
+        let blob = self.hash_object(&bytes).map(GitObject::Blob)?;
+        self.write_object(&blob)?;
+        self.index_entry(path.to_string(), &blob.calc_hash())
+    }
+
+    /// The first existing path on the way to `path` that is ignored.
+    fn ignored_prefix(&self, ignore: &mut Ignore, path: &str) -> io::Result<Option<String>> {
+        let components = path.split('/').collect::<Vec<_>>();
+        for i in 1..=components.len() {
+            let prefix = components[..i].join("/");
+            let is_dir = match self.file_system.stat(prefix.clone()) {
+                Ok(metadata) => metadata.is_dir(),
+                Err(_) => return Ok(None),
+            };
+            if self.is_ignored(ignore, &prefix, is_dir)? {
+                return Ok(Some(prefix));
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn mark_seen(items: &[&Item], seen: &mut [bool], path: &str) {
+    for (item, seen) in items.iter().zip(seen.iter_mut()) {
+        *seen |= item.matches(path);
+    }
+}
+
+/// A path below a file fails with "not a directory" rather than "not found".
+fn is_missing(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::NotADirectory
+    )
+}
+
+#[test]
+fn test_add() {
+    use crate::fs::inmem::InMemFileSystem;
+
+    let mut git = Git::new(InMemFileSystem::init());
+    let mut write = |path: &str, data: &[u8]| {
+        let dir = path.rsplit_once('/').map_or("", |x| x.0);
+        git.file_system.create_dir(dir.to_string()).unwrap();
+        git.file_system.write(path.to_string(), data).unwrap();
+    };
+    write(".gitignore", b"*.log\n");
+    write("a.txt", b"same\n");
+    write("b.txt", b"same\n");
+    write("src/main.rs", b"fn main() {}\n");
+    write("src/lib.rs", b"\n");
+    write("debug.log", b"");
+    let spec = |args: &[&str]| Pathspec::parse(args).unwrap();
+    let names = |git: &Git<InMemFileSystem>| {
+        let index = git.ls_files_stage(&git.read_index().unwrap()).unwrap();
+        index
+            .entries
+            .into_iter()
+            .map(|x| x.name)
+            .collect::<Vec<_>>()
+    };
+
+    let options = AddOptions {
+        dry_run: true,
+        ..AddOptions::default()
+    };
+    let result = git.add(&spec(&["."]), &options).unwrap();
+    assert_eq!(result.staged.len(), 5);
+    assert!(git.read_index().is_err());
+
+    // Files with the same content are all kept.
+    git.add(&spec(&["*.txt"]), &AddOptions::default()).unwrap();
+    git.add(&spec(&["src", ":!src/lib.rs"]), &AddOptions::default())
+        .unwrap();
+    assert_eq!(names(&git), vec!["a.txt", "b.txt", "src/main.rs"]);
+
+    let err = git
+        .add(&spec(&["src", "missing"]), &AddOptions::default())
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "pathspec 'missing' did not match any files"
+    );
+    assert_eq!(names(&git).len(), 3);
+
+    let result = git
+        .add(&spec(&["debug.log", "src"]), &AddOptions::default())
+        .unwrap();
+    assert_eq!(result.ignored, vec!["debug.log"]);
+    assert_eq!(result.staged, vec![Staged::Add("src/lib.rs".to_string())]);
+    let options = AddOptions {
+        force: true,
+        ..AddOptions::default()
+    };
+    git.add(&spec(&["debug.log"]), &options).unwrap();
+
+    // `-u` stages deletions and modifications but no new files.
+    git.file_system.remove("a.txt".to_string()).unwrap();
+    git.file_system
+        .write("b.txt".to_string(), b"changed\n")
+        .unwrap();
+    git.file_system.write("new".to_string(), b"").unwrap();
+    let options = AddOptions {
+        update: true,
+        ..AddOptions::default()
+    };
+    let result = git.add(&Pathspec::default(), &options).unwrap();
+    assert_eq!(
+        result.staged,
+        vec![
+            Staged::Remove("a.txt".to_string()),
+            Staged::Add("b.txt".to_string())
+        ]
+    );
+    assert!(!names(&git).contains(&"new".to_string()));
+
+    let options = AddOptions {
+        intent_to_add: true,
+        ..AddOptions::default()
+    };
+    git.add(&spec(&["new"]), &options).unwrap();
+    let tree = git.write_tree().unwrap();
+    let names = tree
+        .contents
+        .iter()
+        .map(|x| x.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["b.txt", "debug.log", "src"]);
+}
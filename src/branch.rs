@@ -0,0 +1,409 @@
+use crate::config::Level;
+use crate::fs::FileSystem;
+use crate::revision::is_valid_ref_name;
+use crate::Git;
+use std::io;
+
+impl<F: FileSystem> Git<F> {
+    /// Creates the branch `name` at `commit`, logging that it came from
+    /// `start` (the name the commit was given as). With `force` an existing
+    /// branch other than the current one is reset.
+    pub fn create_branch(
+        &mut self,
+        name: &str,
+        commit: &str,
+        start: &str,
+        force: bool,
+    ) -> io::Result<()> {
+        let refname = branch_ref(name)?;
+        let old = self.read_ref(refname.clone()).ok();
+        let message = match &old {
+            Some(_) if !force => {
+                return Err(invalid(format!("a branch named '{}' already exists", name)))
+            }
+            Some(_) if self.is_current(name) => {
+                return Err(invalid(format!(
+                    "cannot force update the branch '{}' checked out at '{}'",
+                    name,
+                    self.worktree_path()
+                )))
+            }
+            Some(_) => format!("branch: Reset to {}", start),
+            None => format!("branch: Created from {}", start),
+        };
+
+        self.write_branch(&refname, commit)?;
+        self.append_reflog(&refname, old.as_deref(), commit, &message)
+    }
+
+    /// Deletes a branch with its reflog and config, returning the commit it
+    /// pointed at. Without `force` it must be merged into its upstream, or
+    /// into `HEAD` when it has none.
+    pub fn delete_branch(&mut self, name: &str, force: bool) -> io::Result<String> {
+        let refname = format!("refs/heads/{}", name);
+        let hash = self
+            .read_ref(refname.clone())
+            .map_err(|_| not_found(format!("branch '{}' not found.", name)))?;
+        if self.is_current(name) {
+            return Err(invalid(format!(
+                "Cannot delete branch '{}' checked out at '{}'",
+                name,
+                self.worktree_path()
+            )));
+        }
+
+        if !force {
+            let into = match self.upstream(name)? {
+                Some(upstream) => self.resolve_ref(upstream).ok(),
+                None => None,
+            };
+            let into = match into {
+                Some(into) => Some(into),
+                None => self.resolve_ref("HEAD".to_string()).ok(),
+            };
+            let merged = match into {
+                Some(into) => self.ahead_behind(&hash, &into)?.0 == 0,
+                None => false,
+            };
+            if !merged {
+                return Err(invalid(format!(
+                    "The branch '{0}' is not fully merged.\n\
+                     If you are sure you want to delete it, run 'clumsy branch -D {0}'.",
+                    name
+                )));
+            }
+        }
+
+        self.delete_ref(&refname)?;
+        let mut config = self.config_file(Level::Local)?;
+        if config.remove_section("branch", Some(name))? {
+            self.write_config_file(&config)?;
+        }
+
+        Ok(hash)
+    }
+
+    /// Renames (or with `copy`, copies) a branch along with its reflog and
+    /// config. `HEAD` follows a renamed current branch, even an unborn one.
+    pub fn rename_branch(
+        &mut self,
+        old: &str,
+        new: &str,
+        force: bool,
+        copy: bool,
+    ) -> io::Result<()> {
+        let (old_ref, new_ref) = (format!("refs/heads/{}", old), branch_ref(new)?);
+        let hash = self.read_ref(old_ref.clone()).ok();
+        let current = self.is_current(old);
+        if hash.is_none() && !current {
+            return Err(invalid(format!("No branch named '{}'.", old)));
+        }
+        if old != new && self.read_ref(new_ref.clone()).is_ok() {
+            if !force {
+                return Err(invalid(format!("a branch named '{}' already exists", new)));
+            }
+            if self.is_current(new) {
+                return Err(invalid(format!(
+                    "cannot force update the branch '{}' checked out at '{}'",
+                    new,
+                    self.worktree_path()
+                )));
+            }
+        }
+
+        if let Some(hash) = &hash {
+            let (old_log, new_log) = (format!("logs/{}", old_ref), format!("logs/{}", new_ref));
+            let log = match self.file_system.read(self.git_path(&old_log)) {
+                Ok(bytes) => Some(bytes),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            };
+            if !copy {
+                self.delete_ref(&old_ref)?;
+            }
+            self.write_branch(&new_ref, hash)?;
+            if let Some(log) = log {
+                let path = self.git_path(&new_log);
+                if let Some((dir, _)) = path.rsplit_once('/') {
+                    self.file_system.create_dir(dir.to_string())?;
+                }
+                self.file_system.write(path, &log)?;
+            }
+            let verb = if copy { "copied" } else { "renamed" };
+            let message = format!("Branch: {} {} to {}", verb, old_ref, new_ref);
+            self.append_reflog(&new_ref, Some(hash), hash, &message)?;
+        }
+        if current && !copy {
+            self.write_symref("HEAD".to_string(), &new_ref)?;
+        }
+
+        let mut config = self.config_file(Level::Local)?;
+        if copy {
+            let entries = self
+                .config_level(Level::Local)?
+                .entries
+                .into_iter()
+                .filter(|x| x.section == "branch" && x.subsection.as_deref() == Some(old))
+                .collect::<Vec<_>>();
+            for entry in entries {
+                let key = format!("branch.{}.{}", new, entry.key);
+                config.add(&key, entry.value.as_deref().unwrap_or(""))?;
+            }
+        } else {
+            config.rename_section("branch", Some(old), "branch", Some(new))?;
+        }
+        self.write_config_file(&config)
+    }
+
+    /// Makes `name` track `upstream`, a local or remote-tracking branch.
+    /// Returns the upstream's full ref name.
+    pub fn set_upstream(&mut self, name: &str, upstream: &str) -> io::Result<String> {
+        if self.read_ref(format!("refs/heads/{}", name)).is_err() {
+            return Err(invalid(format!("branch '{}' does not exist", name)));
+        }
+        let refname = self
+            .ref_candidates(upstream)
+            .into_iter()
+            .find(|x| self.resolve_ref(x.clone()).is_ok())
+            .ok_or_else(|| {
+                invalid(format!(
+                    "the requested upstream branch '{}' does not exist",
+                    upstream
+                ))
+            })?;
+
+        let (remote, merge) = if refname.starts_with("refs/heads/") {
+            (".".to_string(), refname.clone())
+        } else {
+            self.remote_branch(&refname)?.ok_or_else(|| {
+                invalid(format!(
+                    "cannot set up tracking information; starting point '{}' is not a branch",
+                    upstream
+                ))
+            })?
+        };
+
+        let mut config = self.config_file(Level::Local)?;
+        config.set(&format!("branch.{}.remote", name), &remote)?;
+        config.set(&format!("branch.{}.merge", name), &merge)?;
+        self.write_config_file(&config)?;
+
+        Ok(refname)
+    }
+
+    pub fn unset_upstream(&mut self, name: &str) -> io::Result<()> {
+        let mut config = self.config_file(Level::Local)?;
+        let removed = config.unset(&format!("branch.{}.remote", name), true)?
+            + config.unset(&format!("branch.{}.merge", name), true)?;
+        if removed == 0 {
+            return Err(invalid(format!(
+                "Branch '{}' has no upstream information",
+                name
+            )));
+        }
+
+        // A section left empty goes too.
+        let file = self.config_level(Level::Local)?;
+        let left = file.entries.iter().any(|x| {
+            x.section == "branch"
+                && x.subsection.as_deref() == Some(name)
+                && x.key != "remote"
+                && x.key != "merge"
+        });
+        if !left {
+            config.remove_section("branch", Some(name))?;
+        }
+        self.write_config_file(&config)
+    }
+
+    /// The ref `name` tracks, from `branch.<name>.remote` and `.merge`.
+    /// The ref needn't exist.
+    pub fn upstream(&self, name: &str) -> io::Result<Option<String>> {
+        let config = self.config()?;
+        let remote = config.get(&format!("branch.{}.remote", name));
+        let merge = config.get(&format!("branch.{}.merge", name));
+        let (remote, merge) = match (remote, merge) {
+            (Some(remote), Some(merge)) => (remote, merge),
+            _ => return Ok(None),
+        };
+        if remote == "." {
+            return Ok(Some(merge));
+        }
+
+        Ok(config
+            .get_all(&format!("remote.{}.fetch", remote))
+            .iter()
+            .find_map(|spec| {
+                let (src, dst) = spec.trim_start_matches('+').split_once(':')?;
+                map_glob(src, dst, &merge)
+            }))
+    }
+
+    /// How many commits `a` has that `b` doesn't, and the other way around.
+    pub fn ahead_behind(&self, a: &str, b: &str) -> io::Result<(usize, usize)> {
+        let count = |from: &str, hide: &str| -> io::Result<usize> {
+            let mut walk = self.rev_walk();
+            walk.push(from)?;
+            walk.hide(hide)?;
+            walk.try_fold(0, |n, x| x.map(|_| n + 1))
+        };
+        Ok((count(a, b)?, count(b, a)?))
+    }
+
+    /// The remote and its branch that the remote-tracking ref `refname` is
+    /// fetched from.
+    fn remote_branch(&self, refname: &str) -> io::Result<Option<(String, String)>> {
+        let config = self.config()?;
+        for remote in config.subsections("remote") {
+            for spec in config.get_all(&format!("remote.{}.fetch", remote)) {
+                let found = spec
+                    .trim_start_matches('+')
+                    .split_once(':')
+                    .and_then(|(src, dst)| map_glob(dst, src, refname));
+                if let Some(merge) = found {
+                    return Ok(Some((remote, merge)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn is_current(&self, name: &str) -> bool {
+        self.head_ref().ok().as_deref() == Some(&format!("refs/heads/{}", name))
+    }
+
+    pub(crate) fn worktree_path(&self) -> String {
+        self.file_system
+            .canonicalize(".".to_string())
+            .unwrap_or_else(|_| ".".to_string())
+    }
+
+    pub(crate) fn write_branch(&mut self, refname: &str, hash: &str) -> io::Result<()> {
+        let path = self.git_path(refname);
+        if let Some((dir, _)) = path.rsplit_once('/') {
+            self.file_system.create_dir(dir.to_string())?;
+        }
+        let bytes = hex::decode(hash).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        self.write_ref(refname.to_string(), &bytes)
+    }
+
+    /// Removes a file below `.git` and the directories it leaves empty,
+    /// keeping the namespace itself (`refs/heads`, `logs/refs/heads`).
+    pub(crate) fn remove_ref_file(&mut self, path: &str) -> io::Result<()> {
+        match self.file_system.remove(self.git_path(path)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            x => x?,
+        }
+        let keep = if path.starts_with("logs/") { 3 } else { 2 };
+        let mut dir = path;
+        while let Some(pos) = dir.rfind('/') {
+            dir = &dir[..pos];
+            if dir.split('/').count() <= keep {
+                break;
+            }
+            match self.file_system.read_dir(self.git_path(dir)) {
+                Ok(names) if names.is_empty() => self.file_system.remove_dir(self.git_path(dir))?,
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `name` can be a branch: a valid ref name that isn't `HEAD`
+/// and doesn't look like an option.
+pub fn is_valid_branch_name(name: &str) -> bool {
+    name != "HEAD" && !name.starts_with('-') && is_valid_ref_name(name)
+}
+
+fn branch_ref(name: &str) -> io::Result<String> {
+    if is_valid_branch_name(name) {
+        Ok(format!("refs/heads/{}", name))
+    } else {
+        Err(invalid(format!("'{}' is not a valid branch name", name)))
+    }
+}
+
+/// Maps `name` through a refspec side `from` to the side `to`, where a `*`
+/// in each stands for the same text.
+pub(crate) fn map_glob(from: &str, to: &str, name: &str) -> Option<String> {
+    match (from.split_once('*'), to.split_once('*')) {
+        (Some((prefix, suffix)), Some((to_prefix, to_suffix))) => {
+            let middle = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+            Some(format!("{}{}{}", to_prefix, middle, to_suffix))
+        }
+        (None, None) if name == from => Some(to.to_string()),
+        _ => None,
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+fn not_found(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, msg)
+}
+
+#[test]
+fn test_branch() {
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::test_util::{commit, tree};
+
+    let mut git = Git::new(InMemFileSystem::init());
+    git.file_system
+        .create_dir(".git/refs/heads".to_string())
+        .unwrap();
+    git.write_symref("HEAD".to_string(), "refs/heads/master")
+        .unwrap();
+
+    // Every commit has the same date, so only the graph orders them.
+    let tree = tree(&mut git, &[]);
+    let mut commit = |parents: &[&str], message: &str| commit(&mut git, &tree, parents, message);
+    let one = commit(&[], "one");
+    let two = commit(&[&one], "two");
+    let three = commit(&[&one], "three");
+
+    git.create_branch("master", &two, "two", false).unwrap();
+    git.create_branch("old", &one, "one", false).unwrap();
+    git.create_branch("topic", &three, "master", false).unwrap();
+    let err = git.create_branch("topic", &one, "one", false).unwrap_err();
+    assert_eq!(err.to_string(), "a branch named 'topic' already exists");
+    assert!(git.create_branch("a..b", &one, "one", false).is_err());
+
+    assert_eq!(git.ahead_behind(&one, &three).unwrap(), (0, 1));
+    assert_eq!(git.ahead_behind(&three, &two).unwrap(), (1, 1));
+
+    // Merged into HEAD, or into the upstream once there is one.
+    assert!(git.delete_branch("topic", false).is_err());
+    git.set_upstream("old", "topic").unwrap();
+    assert_eq!(
+        git.upstream("old").unwrap().as_deref(),
+        Some("refs/heads/topic")
+    );
+    git.rename_branch("old", "feature/old", false, false)
+        .unwrap();
+    assert!(git.read_ref("refs/heads/old".to_string()).is_err());
+    let log = git.read_reflog("refs/heads/feature/old").unwrap();
+    assert_eq!(log.len(), 2);
+    assert_eq!(
+        git.upstream("feature/old").unwrap().as_deref(),
+        Some("refs/heads/topic")
+    );
+
+    assert_eq!(git.delete_branch("feature/old", false).unwrap(), one);
+    assert!(git.upstream("feature/old").unwrap().is_none());
+    assert!(git
+        .file_system
+        .read_dir(".git/refs/heads/feature".to_string())
+        .is_err());
+    assert!(git
+        .file_system
+        .read_dir(".git/refs/heads".to_string())
+        .is_ok());
+
+    let err = git.delete_branch("master", true).unwrap_err();
+    assert!(err.to_string().starts_with("Cannot delete branch 'master'"));
+    git.rename_branch("master", "main", false, false).unwrap();
+    assert_eq!(git.head_ref().unwrap(), "refs/heads/main");
+}
@@ -0,0 +1,372 @@
+use crate::clone::{CloneOptions, ClonedHead};
+use crate::fetch::Transport;
+use crate::fs::FileSystem;
+use crate::protocol::RemoteRef;
+use crate::Git;
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use std::io::prelude::*;
+
+const V2_SIGNATURE: &str = "# v2 git bundle\n";
+const V3_SIGNATURE: &str = "# v3 git bundle\n";
+
+/// History as a file: refs, and a pack with everything they need but
+/// the prerequisites, commits a repository must have to take it in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bundle {
+    /// 2, or 3 with capabilities in the header.
+    pub version: u32,
+    /// Each commit left out, with the subject of its message.
+    pub prerequisites: Vec<(String, String)>,
+    /// Each ref with what it points to, in the order they were given.
+    pub refs: Vec<(String, String)>,
+    pub pack: Vec<u8>,
+}
+
+impl Bundle {
+    /// Reads a bundle: the signature of its version, capabilities in
+    /// version 3, prerequisites after a `-`, refs, and after an empty
+    /// line the pack. Errors read as following the bundle's name.
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let not_bundle = || invalid("does not look like a v2 or v3 bundle file");
+        let version = if bytes.starts_with(V2_SIGNATURE.as_bytes()) {
+            2
+        } else if bytes.starts_with(V3_SIGNATURE.as_bytes()) {
+            3
+        } else {
+            return Err(not_bundle());
+        };
+
+        let mut bundle = Bundle {
+            version,
+            prerequisites: Vec::new(),
+            refs: Vec::new(),
+            pack: Vec::new(),
+        };
+        let mut rest = &bytes[V2_SIGNATURE.len()..];
+        loop {
+            let end = rest
+                .iter()
+                .position(|&x| x == b'\n')
+                .ok_or_else(not_bundle)?;
+            let line = std::str::from_utf8(&rest[..end]).map_err(|_| not_bundle())?;
+            rest = &rest[end + 1..];
+            if line.is_empty() {
+                break;
+            }
+
+            if let Some(capability) = line.strip_prefix('@').filter(|_| version == 3) {
+                if capability != "object-format=sha1" {
+                    return Err(invalid(&format!(
+                        "uses unsupported capability '{}'",
+                        capability
+                    )));
+                }
+                continue;
+            }
+            let (line, prerequisite) = match line.strip_prefix('-') {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+            let (hash, name) = line.split_once(' ').unwrap_or((line, ""));
+            if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(invalid(&format!("has an unrecognized header: {}", line)));
+            }
+            match prerequisite {
+                true => &mut bundle.prerequisites,
+                false => &mut bundle.refs,
+            }
+            .push((hash.to_string(), name.to_string()));
+        }
+
+        if !rest.starts_with(b"PACK") {
+            return Err(invalid("has no pack"));
+        }
+        bundle.pack = rest.to_vec();
+        Ok(bundle)
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut header = match self.version {
+            3 => format!("{}@object-format=sha1\n", V3_SIGNATURE),
+            _ => V2_SIGNATURE.to_string(),
+        };
+        for (hash, subject) in &self.prerequisites {
+            header.push_str(&format!("-{} {}\n", hash, subject));
+        }
+        for (hash, name) in &self.refs {
+            header.push_str(&format!("{} {}\n", hash, name));
+        }
+        header.push('\n');
+        writer.write_all(header.as_bytes())?;
+        writer.write_all(&self.pack)
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+impl Transport for Bundle {
+    fn list_refs(&mut self) -> io::Result<Vec<RemoteRef>> {
+        Ok(self
+            .refs
+            .iter()
+            .map(|(hash, name)| RemoteRef {
+                name: name.clone(),
+                hash: hash.clone(),
+                symref_target: None,
+                peeled: None,
+            })
+            .collect())
+    }
+
+    /// Takes in the whole pack, unless every one of `wants` is there
+    /// already.
+    fn fetch_objects<F: FileSystem>(
+        &mut self,
+        git: &mut Git<F>,
+        wants: &[String],
+        _: Option<&mut dyn Write>,
+    ) -> io::Result<()> {
+        match wants.iter().all(|x| git.has_object(x)) {
+            true => Ok(()),
+            false => git.unbundle(self),
+        }
+    }
+}
+
+impl<F: FileSystem> Git<F> {
+    /// Bundles what `revs` reach, taking them as `rev-list` does:
+    /// revisions, `^<rev>` and `<rev>..<rev>` to leave history out, and
+    /// `--all`, `--branches` or `--tags` for refs. The revisions that
+    /// name refs, and `HEAD`, become the bundle's refs.
+    pub fn create_bundle(&self, revs: &[String], version: u32) -> io::Result<Bundle> {
+        let mut wants = Vec::new();
+        let mut haves = Vec::new();
+        let mut refs = Vec::new();
+        let mut include = |hash: String, refname: Option<String>| {
+            if let Some(refname) = refname {
+                if !refs.iter().any(|(_, x)| *x == refname) {
+                    refs.push((hash.clone(), refname));
+                }
+            }
+            wants.push(hash);
+        };
+
+        for rev in revs {
+            let prefix = match rev.as_str() {
+                "--all" => Some("refs/"),
+                "--branches" => Some("refs/heads/"),
+                "--tags" => Some("refs/tags/"),
+                _ => None,
+            };
+            if let Some(prefix) = prefix {
+                for (name, hash) in self.all_refs()? {
+                    if name.starts_with(prefix) {
+                        include(hash, Some(name));
+                    }
+                }
+                if let (true, Ok(hash)) = (rev == "--all", self.resolve_ref("HEAD".to_string())) {
+                    include(hash, Some("HEAD".to_string()));
+                }
+                continue;
+            }
+            if rev.starts_with("--") {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unrecognized argument: {}", rev),
+                ));
+            }
+
+            let positive = match (rev.strip_prefix('^'), rev.split_once("..")) {
+                (Some(negative), _) => {
+                    haves.push(self.rev_parse(negative)?);
+                    continue;
+                }
+                (None, Some((from, to))) => {
+                    haves.push(self.rev_parse(if from.is_empty() { "HEAD" } else { from })?);
+                    if to.is_empty() {
+                        "HEAD"
+                    } else {
+                        to
+                    }
+                }
+                (None, None) => rev.as_str(),
+            };
+            let hash = self.rev_parse(positive)?;
+            let refname = match positive {
+                "HEAD" => Some("HEAD".to_string()),
+                name => self
+                    .ref_candidates(name)
+                    .into_iter()
+                    .find(|x| self.resolve_ref(x.clone()).is_ok()),
+            };
+            include(hash, refname);
+        }
+
+        let objects = self.objects_between(&wants, &haves)?;
+        let sent: HashSet<_> = objects.iter().collect();
+        refs.retain(|(hash, _)| sent.contains(hash));
+        if refs.is_empty() {
+            return Err(io::Error::other("Refusing to create empty bundle."));
+        }
+
+        // The commits left out that those bundled build on.
+        let mut walk = self.rev_walk();
+        for want in &wants {
+            walk.push(want)?;
+        }
+        for have in &haves {
+            walk.hide(have)?;
+        }
+        let commits = walk.collect::<io::Result<Vec<_>>>()?;
+        let bundled: HashSet<_> = commits.iter().collect();
+        let mut prerequisites = Vec::new();
+        for commit in &commits {
+            for parent in self.read_commit(commit.clone())?.parents {
+                if !bundled.contains(&parent) && !prerequisites.iter().any(|(x, _)| *x == parent) {
+                    let message = self.read_commit(parent.clone())?.message;
+                    let subject = message.lines().next().unwrap_or("").to_string();
+                    prerequisites.push((parent, subject));
+                }
+            }
+        }
+
+        Ok(Bundle {
+            version,
+            prerequisites,
+            refs,
+            pack: self.write_pack(&objects)?,
+        })
+    }
+
+    /// The prerequisites of `bundle` the repository lacks.
+    pub fn missing_prerequisites(&self, bundle: &Bundle) -> Vec<String> {
+        bundle
+            .prerequisites
+            .iter()
+            .filter(|(hash, _)| !self.has_object(hash))
+            .map(|(hash, _)| hash.clone())
+            .collect()
+    }
+
+    /// Stores the pack of `bundle`, provided the repository has its
+    /// prerequisites. Leaves the refs alone.
+    pub fn unbundle(&mut self, bundle: &Bundle) -> io::Result<()> {
+        let missing = self.missing_prerequisites(bundle);
+        if !missing.is_empty() {
+            return Err(io::Error::other(format!(
+                "Repository lacks these prerequisite commits:\n{}",
+                missing.join("\n")
+            )));
+        }
+        self.index_pack(&bundle.pack).map(|_| ())
+    }
+
+    /// Fills a repository fresh from `init` from `bundle`, which `url`
+    /// names, as `clone_from` would from a repository. A bundle's `HEAD`
+    /// isn't symbolic, so its branch is guessed: `master` if it is there,
+    /// or else the first branch at the same commit.
+    pub fn clone_bundle(
+        &mut self,
+        bundle: &Bundle,
+        url: &str,
+        options: &CloneOptions,
+    ) -> io::Result<ClonedHead> {
+        self.unbundle(bundle)?;
+        let refs: BTreeMap<_, _> = bundle
+            .refs
+            .iter()
+            .filter(|(_, name)| name != "HEAD")
+            .map(|(hash, name)| (name.clone(), hash.clone()))
+            .collect();
+        let head_hash = bundle
+            .refs
+            .iter()
+            .find(|(_, name)| name == "HEAD")
+            .map(|(hash, _)| hash.clone());
+        let head = head_hash.as_ref().and_then(|hash| {
+            let at_head = |name: &String| refs.get(name) == Some(hash);
+            Some("refs/heads/master".to_string())
+                .filter(at_head)
+                .or_else(|| {
+                    bundle
+                        .refs
+                        .iter()
+                        .map(|(_, name)| name)
+                        .find(|x| x.starts_with("refs/heads/") && at_head(x))
+                        .cloned()
+                })
+        });
+        self.clone_refs(&refs, head, head_hash, url, options)
+    }
+}
+
+#[test]
+fn test_bundle() {
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::test_util::commit_file;
+
+    let mut source = Git::new(InMemFileSystem::init());
+    source.init(false, "main").unwrap();
+    let first = commit_file(&mut source, b"one\n", Vec::new());
+    let second = commit_file(&mut source, b"two\n", vec![first.clone()]);
+    source.write_branch("refs/heads/old", &first).unwrap();
+
+    let bundle = source.create_bundle(&["--all".to_string()], 2).unwrap();
+    assert_eq!(
+        bundle.refs,
+        [
+            (second.clone(), "refs/heads/main".to_string()),
+            (first.clone(), "refs/heads/old".to_string()),
+            (second.clone(), "HEAD".to_string()),
+        ]
+    );
+    assert!(bundle.prerequisites.is_empty());
+    let mut bytes = Vec::new();
+    bundle.write_to(&mut bytes).unwrap();
+    assert!(bytes.starts_with(format!("# v2 git bundle\n{} refs/heads/main\n", second).as_bytes()));
+    assert_eq!(Bundle::parse(&bytes).unwrap(), bundle);
+    assert!(Bundle::parse(b"# v4 git bundle\n\nPACK").is_err());
+
+    let mut git = Git::new(InMemFileSystem::init());
+    git.init(false, "master").unwrap();
+    let head = git
+        .clone_bundle(&bundle, "/all.bundle", &CloneOptions::default())
+        .unwrap();
+    assert_eq!(head, ClonedHead::Branch("main".to_string()));
+    assert_eq!(
+        git.read_ref("refs/remotes/origin/old".to_string()).unwrap(),
+        first
+    );
+    assert_eq!(git.file_system.read("file".to_string()).unwrap(), b"two\n");
+
+    // Only what is new since `first`, which it needs.
+    let third = commit_file(&mut source, b"three\n", vec![second.clone()]);
+    let revs = ["old..main".to_string()];
+    let bundle = source.create_bundle(&revs, 3).unwrap();
+    assert_eq!(
+        bundle.refs,
+        [(third.clone(), "refs/heads/main".to_string())]
+    );
+    assert_eq!(
+        bundle.prerequisites,
+        [(first.clone(), "message".to_string())]
+    );
+    let mut bytes = Vec::new();
+    bundle.write_to(&mut bytes).unwrap();
+    assert!(bytes.starts_with(b"# v3 git bundle\n@object-format=sha1\n-"));
+    assert_eq!(Bundle::parse(&bytes).unwrap(), bundle);
+
+    let mut empty = Git::new(InMemFileSystem::init());
+    empty.init(false, "main").unwrap();
+    assert_eq!(empty.missing_prerequisites(&bundle), vec![first.clone()]);
+    assert!(empty.unbundle(&bundle).is_err());
+    git.unbundle(&bundle).unwrap();
+    assert_eq!(git.read_commit(third).unwrap().parents, [second]);
+
+    let revs = ["main..main".to_string()];
+    let err = source.create_bundle(&revs, 2).unwrap_err();
+    assert_eq!(err.to_string(), "Refusing to create empty bundle.");
+}
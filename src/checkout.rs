@@ -0,0 +1,416 @@
+use crate::fs::FileSystem;
+use crate::index::diff::Diff;
+use crate::index::{Entry, Index};
+use crate::object::blob;
+use crate::object::tree::FileMode;
+use crate::Git;
+use chrono::{TimeZone, Utc};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+impl<F: FileSystem> Git<F> {
+    /// Moves the index and worktree from `HEAD` to `commit` without touching
+    /// `HEAD` itself.
+    ///
+    /// Like git's two-way merge, paths that are the same in both commits
+    /// keep whatever local changes they have. Paths that differ are only
+    /// updated when they have no staged or unstaged changes, otherwise the
+    /// checkout aborts before anything is written. `force` throws local
+    /// changes away instead.
+    pub fn checkout(&mut self, commit: String, force: bool) -> io::Result<()> {
+        let head = match self.resolve_ref("HEAD".to_string()) {
+            Ok(hash) => self.tree_map(self.read_commit(hash)?.tree)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        let target = self.tree_map(self.read_commit(commit)?.tree)?;
+
+        let mut index = match self.read_index() {
+            Ok(bytes) => self.ls_files_stage(&bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Index::new(Vec::new()),
+            Err(e) => return Err(e),
+        }
+        .entries
+        .into_iter()
+        .map(|x| (x.name.clone(), x))
+        .collect::<BTreeMap<_, _>>();
+
+        let names = head
+            .keys()
+            .chain(target.keys())
+            .chain(index.keys())
+            .cloned()
+            .collect::<BTreeSet<_>>();
+
+        let mut diff = Vec::new();
+        let mut dirty = Vec::new();
+        let mut untracked = Vec::new();
+
+        for name in names {
+            let h = head.get(&name);
+            let m = target.get(&name);
+            let entry = index.get(&name);
+            let i = entry.map(|x| (x.mode, x.hash.clone()));
+            let i = i.as_ref();
+
+            // Hashing the worktree is left for the paths where it decides
+            // something.
+            let changed = || match entry {
+                Some(entry) => self.worktree_changed(entry),
+                None => Ok(false),
+            };
+            let keep = if force {
+                i == m && !changed()?
+            } else {
+                h == m || i == m
+            };
+            if keep {
+                continue;
+            }
+
+            if !force {
+                if i != h || changed()? {
+                    dirty.push(name);
+                    continue;
+                }
+                if let (None, Some(m)) = (entry, m) {
+                    let lost = self.untracked_in_the_way(&name, m, &index)?;
+                    if !lost.is_empty() {
+                        untracked.extend(lost);
+                        continue;
+                    }
+                }
+            }
+
+            match (m, entry) {
+                (Some(m), Some(entry)) => {
+                    diff.push(Diff::Modify(placeholder(&name, m), entry.clone()))
+                }
+                (Some(m), None) => diff.push(Diff::Add(placeholder(&name, m))),
+                (None, Some(entry)) => diff.push(Diff::Remove(entry.clone())),
+                (None, None) => {}
+            }
+        }
+
+        if !dirty.is_empty() {
+            return Err(would_overwrite(
+                "Your local changes to the following files",
+                &dirty,
+                "Please commit your changes or stash them before you switch branches.",
+            ));
+        }
+        if !untracked.is_empty() {
+            return Err(would_overwrite(
+                "The following untracked working tree files",
+                &untracked,
+                "Please move or remove them before you switch branches.",
+            ));
+        }
+
+        let updated = diff
+            .iter()
+            .filter_map(|x| match x {
+                Diff::Add(e) | Diff::Modify(e, _) => Some(e.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let removed = diff
+            .iter()
+            .filter_map(|x| match x {
+                Diff::Remove(e) => Some(e.name.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        self.diff_apply(diff)?;
+
+        for entry in updated {
+            // The mode comes from the tree, as a symlink may have been
+            // written as a plain file.
+            let mode = entry.mode;
+            let mut entry = self.index_entry(entry.name.clone(), &entry.hash)?;
+            entry.mode = mode;
+            index.insert(entry.name.clone(), entry);
+        }
+        for name in removed {
+            index.remove(&name);
+        }
+
+        self.write_index(&Index::new(index.into_values().collect()))
+    }
+
+    /// Whether the worktree file differs from its index entry, in content
+    /// or in mode. A missing file doesn't count, as there's nothing to lose.
+    pub fn worktree_changed(&self, entry: &Entry) -> io::Result<bool> {
+        // Submodules have their own worktree, which isn't looked into.
+        if entry.mode == FileMode::Gitlink {
+            return Ok(false);
+        }
+        let metadata = match self.file_system.stat(entry.name.clone()) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        // Nothing of an intent-to-add file is staged yet.
+        if entry.intent_to_add {
+            return Ok(true);
+        }
+        // A symlink checked out with `core.symlinks=false` is a plain file.
+        let mode = match FileMode::from_stat(metadata.mode) {
+            FileMode::Blob if entry.mode == FileMode::Symlink => entry.mode,
+            mode => mode,
+        };
+        if mode != entry.mode {
+            return Ok(true);
+        }
+
+        // Without a timestamp (e.g. in memory) the stat data proves nothing.
+        if metadata.mtime != 0
+            && metadata.size == entry.size
+            && metadata.mtime as i64 == entry.m_time.timestamp()
+            && metadata.mtime_nsec == entry.m_time.timestamp_subsec_nanos()
+            && metadata.ino == entry.inode
+        {
+            return Ok(false);
+        }
+
+        let bytes = self.read_worktree(&entry.name)?;
+        Ok(blob::hash_bytes(&bytes) != entry.hash)
+    }
+
+    /// Untracked worktree files that writing `file` to `name` would destroy:
+    /// a file where a parent directory has to go, files inside a directory
+    /// that has to become a file, or a different file at `name` itself.
+    pub fn untracked_in_the_way(
+        &self,
+        name: &str,
+        (mode, hash): &(FileMode, Vec<u8>),
+        index: &BTreeMap<String, Entry>,
+    ) -> io::Result<Vec<String>> {
+        let mut lost = Vec::new();
+
+        let components = name.split('/').collect::<Vec<_>>();
+        for i in 1..components.len() {
+            let dir = components[..i].join("/");
+            match self.file_system.stat(dir.clone()) {
+                Ok(metadata) if !metadata.is_dir() && !index.contains_key(&dir) => lost.push(dir),
+                _ => {}
+            }
+        }
+
+        match self.file_system.stat(name.to_string()) {
+            Ok(metadata) if metadata.is_dir() => lost.extend(
+                self.worktree_files(name)?
+                    .into_iter()
+                    .filter(|x| !index.contains_key(x)),
+            ),
+            Ok(metadata) => {
+                if FileMode::from_stat(metadata.mode) != *mode
+                    || blob::hash_bytes(&self.read_worktree(name)?) != *hash
+                {
+                    lost.push(name.to_string());
+                }
+            }
+            Err(_) => {}
+        }
+
+        Ok(lost)
+    }
+
+    /// Every file in `tree` with its mode and hash.
+    pub fn tree_map(&self, tree: String) -> io::Result<BTreeMap<String, (FileMode, Vec<u8>)>> {
+        Ok(self
+            .read_tree_recursive(tree)?
+            .into_iter()
+            .map(|(name, file)| (name, (file.mode, file.hash)))
+            .collect())
+    }
+}
+
+/// An entry that only carries a name, mode and hash; its stat data is
+/// filled in once the file has been written.
+fn placeholder(name: &str, (mode, hash): &(FileMode, Vec<u8>)) -> Entry {
+    let epoch = Utc.timestamp_opt(0, 0).unwrap();
+    Entry::new(
+        epoch,
+        epoch,
+        0,
+        0,
+        *mode,
+        0,
+        0,
+        0,
+        hash.to_vec(),
+        name.to_string(),
+    )
+}
+
+fn would_overwrite(what: &str, paths: &[String], hint: &str) -> io::Error {
+    let list = paths
+        .iter()
+        .map(|x| format!("\t{}\n", x))
+        .collect::<String>();
+
+    io::Error::other(format!(
+        "{} would be overwritten by checkout:\n{}{}\nAborting",
+        what, list, hint
+    ))
+}
+
+#[test]
+fn test_checkout_keeps_and_protects_local_changes() {
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::test_util::{blob, commit, tree};
+
+    let mut git = Git::new(InMemFileSystem::init());
+    let (a1, a2, shared) = (
+        blob(&mut git, b"a1"),
+        blob(&mut git, b"a2"),
+        blob(&mut git, b"s"),
+    );
+    let mut commit = |a: &str| {
+        let tree = tree(
+            &mut git,
+            &[
+                (FileMode::Blob, "a", a),
+                (FileMode::Blob, "shared", &shared),
+            ],
+        );
+        commit(&mut git, &tree, &[], "message")
+    };
+    let (c1, c2) = (commit(&a1), commit(&a2));
+
+    let read = |git: &Git<InMemFileSystem>, x: &str| git.file_system.read(x.to_string()).unwrap();
+
+    // From an unborn branch everything is written out.
+    git.checkout(c1.clone(), false).unwrap();
+    git.write_ref("refs/heads/master".to_string(), &hex::decode(&c1).unwrap())
+        .unwrap();
+    assert_eq!(read(&git, "a"), b"a1");
+
+    // Local changes to paths that don't differ are carried over.
+    git.file_system
+        .write("shared".to_string(), b"local")
+        .unwrap();
+    git.checkout(c2.clone(), false).unwrap();
+    git.write_ref("refs/heads/master".to_string(), &hex::decode(&c2).unwrap())
+        .unwrap();
+    assert_eq!(read(&git, "a"), b"a2");
+    assert_eq!(read(&git, "shared"), b"local");
+
+    // ... but never clobbered.
+    git.file_system.write("a".to_string(), b"dirty").unwrap();
+    let err = git.checkout(c1.clone(), false).unwrap_err();
+    assert!(err.to_string().contains("\ta\n"));
+    assert_eq!(read(&git, "a"), b"dirty");
+
+    git.checkout(c1, true).unwrap();
+    assert_eq!(read(&git, "a"), b"a1");
+    assert_eq!(read(&git, "shared"), b"s");
+}
+
+#[test]
+fn test_checkout_directories() {
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::test_util::{blob, commit, tree};
+
+    let mut git = Git::new(InMemFileSystem::init());
+    let blob = blob(&mut git, b"content");
+
+    // src/deep/f and x  ->  src and x/y
+    let deep = tree(&mut git, &[(FileMode::Blob, "f", &blob)]);
+    let src = tree(&mut git, &[(FileMode::Tree, "deep", &deep)]);
+    let one = tree(
+        &mut git,
+        &[(FileMode::Tree, "src", &src), (FileMode::Blob, "x", &blob)],
+    );
+    let x = tree(&mut git, &[(FileMode::Blob, "y", &blob)]);
+    let two = tree(
+        &mut git,
+        &[(FileMode::Blob, "src", &blob), (FileMode::Tree, "x", &x)],
+    );
+    let one = commit(&mut git, &one, &[], "message");
+    let two = commit(&mut git, &two, &[], "message");
+
+    git.checkout(one.clone(), false).unwrap();
+    git.write_ref("refs/heads/master".to_string(), &hex::decode(&one).unwrap())
+        .unwrap();
+    assert_eq!(git.worktree_files("").unwrap(), vec!["src/deep/f", "x"]);
+
+    git.checkout(two.clone(), false).unwrap();
+    git.write_ref("refs/heads/master".to_string(), &hex::decode(&two).unwrap())
+        .unwrap();
+    assert_eq!(git.worktree_files("").unwrap(), vec!["src", "x/y"]);
+
+    // An untracked file inside the directory that has to go stops it.
+    git.file_system
+        .write("x/untracked".to_string(), b"mine")
+        .unwrap();
+    assert!(git.checkout(one.clone(), false).is_err());
+    git.file_system.remove("x/untracked".to_string()).unwrap();
+
+    git.checkout(one, false).unwrap();
+    assert_eq!(git.worktree_files("").unwrap(), vec!["src/deep/f", "x"]);
+}
+
+#[test]
+fn test_checkout_modes() {
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::test_util::{blob, commit, tree};
+
+    let mut git = Git::new(InMemFileSystem::init());
+    let script = blob(&mut git, b"#!/bin/sh\n");
+    let target = blob(&mut git, b"run.sh");
+    let tree = tree(
+        &mut git,
+        &[
+            (FileMode::Symlink, "link", &target),
+            (FileMode::BlobExecutable, "run.sh", &script),
+        ],
+    );
+    let commit = commit(&mut git, &tree, &[], "message");
+
+    let mut plain = Git::new(git.file_system.clone());
+
+    git.checkout(commit.clone(), false).unwrap();
+    let stat = |git: &Git<InMemFileSystem>, x: &str| git.file_system.stat(x.to_string()).unwrap();
+    assert!(stat(&git, "run.sh").is_executable());
+    assert!(stat(&git, "link").is_symlink());
+    assert_eq!(
+        git.file_system.read_link("link".to_string()).unwrap(),
+        "run.sh"
+    );
+
+    let index = git.ls_files_stage(&git.read_index().unwrap()).unwrap();
+    let modes = index.entries.iter().map(|x| x.mode).collect::<Vec<_>>();
+    assert_eq!(modes, vec![FileMode::Symlink, FileMode::BlobExecutable]);
+    assert!(!index
+        .entries
+        .iter()
+        .any(|x| git.worktree_changed(x).unwrap()));
+    let tree = git.write_tree().unwrap();
+    assert_eq!(tree.contents[0].mode, FileMode::Symlink);
+    assert_eq!(tree.contents[1].mode, FileMode::BlobExecutable);
+
+    // Losing the executable bit is a local change.
+    git.file_system
+        .set_permissions("run.sh".to_string(), 0o644)
+        .unwrap();
+    assert!(git.worktree_changed(&index.entries[1]).unwrap());
+
+    // Without symlinks the link text ends up in a plain file.
+    plain
+        .file_system
+        .write(".git/config".to_string(), b"[core]\n\tsymlinks = false\n")
+        .unwrap();
+    plain.checkout(commit, false).unwrap();
+    assert!(!stat(&plain, "link").is_symlink());
+    assert_eq!(
+        plain.file_system.read("link".to_string()).unwrap(),
+        b"run.sh"
+    );
+
+    let index = plain.ls_files_stage(&plain.read_index().unwrap()).unwrap();
+    assert_eq!(index.entries[0].mode, FileMode::Symlink);
+    assert!(!plain.worktree_changed(&index.entries[0]).unwrap());
+}
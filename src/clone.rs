@@ -0,0 +1,268 @@
+use crate::config::Level;
+use crate::fs::FileSystem;
+use crate::Git;
+use std::collections::BTreeMap;
+use std::io;
+
+#[derive(Debug, Default)]
+pub struct CloneOptions {
+    /// Only the repository, with the source's branches as its own.
+    pub bare: bool,
+    /// The branch, or tag to detach at, to check out instead of the
+    /// source's `HEAD`.
+    pub branch: Option<String>,
+    /// Copies objects even where they could be hard-linked.
+    pub no_hardlinks: bool,
+}
+
+/// Where a clone left `HEAD`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClonedHead {
+    /// On a branch yet to be born, the source having no commits.
+    Empty,
+    Branch(String),
+    Detached(String),
+    /// Nowhere, the source's `HEAD` pointing at nothing it had.
+    Missing,
+}
+
+impl<F: FileSystem> Git<F> {
+    /// Creates an empty repository in the git directory with `HEAD` on
+    /// the unborn `branch`.
+    pub fn init(&mut self, bare: bool, branch: &str) -> io::Result<()> {
+        for dir in &["objects/info", "objects/pack", "refs/heads", "refs/tags"] {
+            self.file_system.create_dir(self.git_path(dir))?;
+        }
+        self.write_symref("HEAD".to_string(), &format!("refs/heads/{}", branch))?;
+
+        let mut config = self.config_file(Level::Local)?;
+        config.set("core.repositoryformatversion", "0")?;
+        config.set("core.filemode", "true")?;
+        config.set("core.bare", if bare { "true" } else { "false" })?;
+        if !bare {
+            config.set("core.logallrefupdates", "true")?;
+        }
+        self.write_config_file(&config)
+    }
+
+    /// Fills a repository fresh from `init` with everything in `source`,
+    /// which `url` names: its objects, loose or packed, hard-linked where
+    /// possible, its branches as `refs/remotes/origin/*` (or as branches
+    /// of a bare clone) and its tags. Then checks out the source's `HEAD`
+    /// or `options.branch`.
+    ///
+    /// Hard links take `source`'s paths as they are, so they only happen
+    /// when both file systems agree on them, as with an absolute path.
+    pub fn clone_from<G: FileSystem>(
+        &mut self,
+        source: &Git<G>,
+        url: &str,
+        options: &CloneOptions,
+    ) -> io::Result<ClonedHead> {
+        self.copy_objects(source, !options.no_hardlinks)?;
+        let refs = source.all_refs()?;
+        let head = source.head_ref().ok();
+        let head_hash = source.resolve_ref("HEAD".to_string()).ok();
+        self.clone_refs(&refs, head, head_hash, url, options)
+    }
+
+    /// Takes `refs` from the source of a clone whose objects are already
+    /// here, and checks out the branch its `HEAD` is on, or where it
+    /// points if detached, or `options.branch`.
+    pub(crate) fn clone_refs(
+        &mut self,
+        refs: &BTreeMap<String, String>,
+        source_head: Option<String>,
+        source_head_hash: Option<String>,
+        url: &str,
+        options: &CloneOptions,
+    ) -> io::Result<ClonedHead> {
+        for (refname, hash) in refs {
+            let target = match refname.strip_prefix("refs/heads/") {
+                Some(name) if !options.bare => format!("refs/remotes/origin/{}", name),
+                _ if refname.starts_with("refs/heads/") || refname.starts_with("refs/tags/") => {
+                    refname.clone()
+                }
+                _ => continue,
+            };
+            self.write_branch(&target, hash)?;
+        }
+
+        let mut config = self.config_file(Level::Local)?;
+        config.set("remote.origin.url", url)?;
+        if !options.bare {
+            config.set("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*")?;
+        }
+        self.write_config_file(&config)?;
+
+        // The branch the source is on, even when it has no commits yet.
+        let source_branch = source_head
+            .as_deref()
+            .and_then(|x| x.strip_prefix("refs/heads/"))
+            .filter(|x| refs.contains_key(&format!("refs/heads/{}", x)));
+        if let (Some(branch), false) = (source_branch, options.bare) {
+            self.write_symref(
+                "refs/remotes/origin/HEAD".to_string(),
+                &format!("refs/remotes/origin/{}", branch),
+            )?;
+        }
+
+        let head = match &options.branch {
+            Some(name) => {
+                if refs.contains_key(&format!("refs/heads/{}", name)) {
+                    ClonedHead::Branch(name.clone())
+                } else if let Some(hash) = refs.get(&format!("refs/tags/{}", name)) {
+                    ClonedHead::Detached(self.peel(hash.clone(), "commit")?)
+                } else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("Remote branch {} not found in upstream origin", name),
+                    ));
+                }
+            }
+            None => match (source_branch, &source_head) {
+                (Some(branch), _) => ClonedHead::Branch(branch.to_string()),
+                (None, Some(head)) => {
+                    let branch = head.strip_prefix("refs/heads/").unwrap_or(head);
+                    self.write_symref("HEAD".to_string(), &format!("refs/heads/{}", branch))?;
+                    return Ok(ClonedHead::Empty);
+                }
+                (None, None) => match source_head_hash {
+                    Some(hash) => ClonedHead::Detached(hash),
+                    None => return Ok(ClonedHead::Missing),
+                },
+            },
+        };
+
+        let message = format!("clone: from {}", url);
+        match &head {
+            ClonedHead::Branch(branch) => {
+                let refname = format!("refs/heads/{}", branch);
+                let hash = refs[&refname].clone();
+                self.write_symref("HEAD".to_string(), &refname)?;
+                if options.bare {
+                    return Ok(head);
+                }
+                self.checkout(hash.clone(), true)?;
+                self.write_branch(&refname, &hash)?;
+                self.append_reflog(&refname, None, &hash, &message)?;
+                self.append_reflog("HEAD", None, &hash, &message)?;
+
+                let mut config = self.config_file(Level::Local)?;
+                config.set(&format!("branch.{}.remote", branch), "origin")?;
+                config.set(&format!("branch.{}.merge", branch), &refname)?;
+                self.write_config_file(&config)?;
+            }
+            ClonedHead::Detached(hash) => {
+                if !options.bare {
+                    self.checkout(hash.clone(), true)?;
+                }
+                self.write_branch("HEAD", hash)?;
+                if !options.bare {
+                    self.append_reflog("HEAD", None, hash, &message)?;
+                }
+            }
+            ClonedHead::Empty | ClonedHead::Missing => {}
+        }
+        Ok(head)
+    }
+
+    /// Brings over the loose objects and packs of `source`.
+    fn copy_objects<G: FileSystem>(&mut self, source: &Git<G>, hardlinks: bool) -> io::Result<()> {
+        let mut paths = Vec::new();
+        for dir in source.file_system.read_dir(source.git_path("objects"))? {
+            let is_loose = dir.len() == 2 && dir.chars().all(|c| c.is_ascii_hexdigit());
+            if !is_loose && dir != "pack" {
+                continue;
+            }
+            for name in source
+                .file_system
+                .read_dir(source.git_path(&format!("objects/{}", dir)))?
+            {
+                if is_loose || name.ends_with(".pack") || name.ends_with(".idx") {
+                    paths.push((dir.clone(), format!("objects/{}/{}", dir, name)));
+                }
+            }
+        }
+
+        for (dir, path) in paths {
+            self.file_system
+                .create_dir(self.git_path(&format!("objects/{}", dir)))?;
+            let (from, to) = (source.git_path(&path), self.git_path(&path));
+            if hardlinks && self.file_system.hard_link(from.clone(), to.clone()).is_ok() {
+                continue;
+            }
+            let bytes = source.file_system.read(from)?;
+            self.file_system.write(to, &bytes)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_clone() {
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::test_util::commit_file;
+
+    let mut source = Git::new(InMemFileSystem::init());
+    source.init(false, "main").unwrap();
+    let commit = commit_file(&mut source, b"hello\n", Vec::new());
+    source.write_branch("refs/tags/v1", &commit).unwrap();
+    // A branch only in packed-refs.
+    source
+        .file_system
+        .write(
+            ".git/packed-refs".to_string(),
+            format!("# pack-refs with: peeled\n{} refs/heads/old\n", commit).as_bytes(),
+        )
+        .unwrap();
+
+    let mut git = Git::new(InMemFileSystem::init());
+    git.init(false, "master").unwrap();
+    let head = git
+        .clone_from(&source, "/src", &CloneOptions::default())
+        .unwrap();
+    assert_eq!(head, ClonedHead::Branch("main".to_string()));
+    assert_eq!(git.head_ref().unwrap(), "refs/heads/main");
+    assert_eq!(git.read_ref("refs/heads/main".to_string()).unwrap(), commit);
+    let remote = |name: &str| git.resolve_ref(format!("refs/remotes/origin/{}", name));
+    assert_eq!(remote("old").unwrap(), commit);
+    assert_eq!(remote("HEAD").unwrap(), commit);
+    assert_eq!(git.read_ref("refs/tags/v1".to_string()).unwrap(), commit);
+    assert_eq!(
+        git.file_system.read("file".to_string()).unwrap(),
+        b"hello\n"
+    );
+    let config = git.config_level(Level::Local).unwrap();
+    assert_eq!(config.get("remote.origin.url").as_deref(), Some("/src"));
+    assert_eq!(
+        config.get("branch.main.merge").as_deref(),
+        Some("refs/heads/main")
+    );
+
+    let mut bare = Git::with_git_dir(InMemFileSystem::init(), "repo.git".to_string());
+    bare.init(true, "master").unwrap();
+    let options = CloneOptions {
+        bare: true,
+        branch: Some("v1".to_string()),
+        ..CloneOptions::default()
+    };
+    assert_eq!(
+        bare.clone_from(&source, "/src", &options).unwrap(),
+        ClonedHead::Detached(commit.clone())
+    );
+    assert_eq!(bare.read_ref("refs/heads/old".to_string()).unwrap(), commit);
+    assert!(bare.file_system.stat("file".to_string()).is_err());
+
+    let mut git = Git::new(InMemFileSystem::init());
+    git.init(false, "master").unwrap();
+    let options = CloneOptions {
+        branch: Some("nope".to_string()),
+        ..CloneOptions::default()
+    };
+    let err = git.clone_from(&source, "/src", &options).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Remote branch nope not found in upstream origin"
+    );
+}
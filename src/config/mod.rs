@@ -0,0 +1,766 @@
+pub mod parse;
+
+use crate::fs::FileSystem;
+use crate::wildmatch::{wildmatch, WM_CASEFOLD, WM_PATHNAME};
+use parse::{Event, Parser};
+use std::env;
+use std::fmt;
+use std::io;
+
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Level {
+    System,
+    Global,
+    Local,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::System => write!(f, "system"),
+            Level::Global => write!(f, "global"),
+            Level::Local => write!(f, "local"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub section: String,
+    pub subsection: Option<String>,
+    pub key: String,
+    pub value: Option<String>,
+    pub level: Level,
+    pub origin: String,
+}
+
+impl Entry {
+    pub fn name(&self) -> String {
+        match &self.subsection {
+            Some(sub) => format!("{}.{}.{}", self.section, sub, self.key),
+            None => format!("{}.{}", self.section, self.key),
+        }
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}={}", self.name(), value),
+            None => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+/// Information used to evaluate `includeIf` conditions.
+#[derive(Debug, Clone, Default)]
+pub struct IncludeContext {
+    pub git_dir: Option<String>,
+    pub branch: Option<String>,
+}
+
+/// All configuration entries visible to a repository, lowest precedence first.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub entries: Vec<Entry>,
+}
+
+/// A fully qualified variable name split into its parts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Key {
+    pub section: String,
+    pub subsection: Option<String>,
+    pub name: String,
+}
+
+impl Key {
+    /// Splits `section[.subsection].name`. Section and name are
+    /// case-insensitive and get lowercased; the subsection keeps its case.
+    pub fn parse(key: &str) -> Option<Self> {
+        let first = key.find('.')?;
+        let last = key.rfind('.')?;
+
+        let section = key[..first].to_lowercase();
+        let name = key[last + 1..].to_lowercase();
+        let subsection = if first == last {
+            None
+        } else {
+            Some(key[first + 1..last].to_string())
+        };
+
+        let valid_section = !section.is_empty()
+            && section
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+        let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+
+        if valid_section && valid_name {
+            Some(Self {
+                section,
+                subsection,
+                name,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn matches(&self, section: &str, subsection: Option<&str>, name: &str) -> bool {
+        self.section == section && self.subsection.as_deref() == subsection && self.name == name
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn from(bytes: &[u8], level: Level) -> Option<Self> {
+        let entries = Parser::new(bytes)
+            .events()
+            .ok()?
+            .into_iter()
+            .filter_map(|x| match x {
+                Event::Entry(entry) => Some(Entry {
+                    section: entry.section,
+                    subsection: entry.subsection,
+                    key: entry.key,
+                    value: entry.value,
+                    level,
+                    origin: String::new(),
+                }),
+                _ => None,
+            })
+            .collect();
+
+        Some(Self { entries })
+    }
+
+    /// Reads `path` and everything it includes, appending the entries in the
+    /// order git would see them. A missing file is not an error.
+    pub fn load<F: FileSystem>(
+        &mut self,
+        fs: &F,
+        path: &str,
+        level: Level,
+        ctx: &IncludeContext,
+    ) -> io::Result<()> {
+        self.load_with_depth(fs, path, level, ctx, 0)
+    }
+
+    fn load_with_depth<F: FileSystem>(
+        &mut self,
+        fs: &F,
+        path: &str,
+        level: Level,
+        ctx: &IncludeContext,
+        depth: usize,
+    ) -> io::Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("exceeded maximum include depth while including {}", path),
+            ));
+        }
+
+        let bytes = match fs.read(path.to_string()) {
+            Ok(bytes) => bytes,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let events = Parser::new(&bytes).events().map_err(|line| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("bad config line {} in file {}", line, path),
+            )
+        })?;
+
+        for event in events {
+            let entry = match event {
+                Event::Entry(entry) => entry,
+                _ => continue,
+            };
+
+            let include = match (&entry.section[..], &entry.subsection, &entry.key[..]) {
+                ("include", None, "path") => entry.value.clone(),
+                ("includeif", Some(cond), "path") if self.condition(fs, cond, path, ctx) => {
+                    entry.value.clone()
+                }
+                _ => None,
+            };
+
+            self.entries.push(Entry {
+                section: entry.section,
+                subsection: entry.subsection,
+                key: entry.key,
+                value: entry.value,
+                level,
+                origin: path.to_string(),
+            });
+
+            if let Some(include) = include {
+                let target = resolve_include(&expand_path(&include), path);
+                self.load_with_depth(fs, &target, level, ctx, depth + 1)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn condition<F: FileSystem>(
+        &self,
+        fs: &F,
+        cond: &str,
+        path: &str,
+        ctx: &IncludeContext,
+    ) -> bool {
+        let (pattern, flags) = if let Some(x) = cond.strip_prefix("gitdir:") {
+            (x, WM_PATHNAME)
+        } else if let Some(x) = cond.strip_prefix("gitdir/i:") {
+            (x, WM_PATHNAME | WM_CASEFOLD)
+        } else if let Some(x) = cond.strip_prefix("onbranch:") {
+            let pattern = if x.ends_with('/') {
+                format!("{}**", x)
+            } else {
+                x.to_string()
+            };
+            return ctx
+                .branch
+                .as_ref()
+                .map(|branch| wildmatch(&pattern, branch, WM_PATHNAME))
+                .unwrap_or(false);
+        } else {
+            return false;
+        };
+
+        let git_dir = match &ctx.git_dir {
+            Some(dir) => fs.canonicalize(dir.clone()).unwrap_or_else(|_| dir.clone()),
+            None => return false,
+        };
+
+        let mut pattern = expand_path(pattern);
+        if let Some(rest) = pattern.strip_prefix("./") {
+            pattern = resolve_include(rest, path);
+        }
+        if !pattern.starts_with('/') && !pattern.starts_with("**/") {
+            pattern = format!("**/{}", pattern);
+        }
+        if pattern.ends_with('/') {
+            pattern.push_str("**");
+        }
+
+        wildmatch(&pattern, &git_dir, flags)
+    }
+
+    fn find(&self, key: &str) -> impl Iterator<Item = &Entry> {
+        let key = Key::parse(key);
+        self.entries.iter().filter(move |x| {
+            key.as_ref()
+                .map(|k| k.matches(&x.section, x.subsection.as_deref(), &x.key))
+                .unwrap_or(false)
+        })
+    }
+
+    /// The entry that wins for `key`: the last one read.
+    pub fn get_entry(&self, key: &str) -> Option<&Entry> {
+        self.find(key).last()
+    }
+
+    /// The raw value for `key`. A key without `=` yields an empty string.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.get_entry(key)
+            .map(|x| x.value.clone().unwrap_or_default())
+    }
+
+    pub fn get_all(&self, key: &str) -> Vec<String> {
+        self.find(key)
+            .map(|x| x.value.clone().unwrap_or_default())
+            .collect()
+    }
+
+    pub fn get_bool(&self, key: &str) -> io::Result<Option<bool>> {
+        match self.get_entry(key) {
+            Some(entry) => match &entry.value {
+                None => Ok(Some(true)),
+                Some(value) => parse_bool(value).map(Some).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("bad boolean config value '{}' for '{}'", value, key),
+                    )
+                }),
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_int(&self, key: &str) -> io::Result<Option<i64>> {
+        match self.get(key) {
+            Some(value) => parse_int(&value).map(Some).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("bad numeric config value '{}' for '{}'", value, key),
+                )
+            }),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_path(&self, key: &str) -> Option<String> {
+        self.get(key).map(|x| expand_path(&x))
+    }
+
+    /// Every section/subsection pair matching `section`, in first-seen order.
+    pub fn subsections(&self, section: &str) -> Vec<String> {
+        let section = section.to_lowercase();
+        self.entries
+            .iter()
+            .filter(|x| x.section == section)
+            .filter_map(|x| x.subsection.clone())
+            .fold(Vec::new(), |mut acc, x| {
+                if !acc.contains(&x) {
+                    acc.push(x);
+                }
+                acc
+            })
+    }
+}
+
+/// A single config file kept verbatim so edits preserve comments and layout.
+#[derive(Debug, Clone)]
+pub struct ConfigFile {
+    pub path: String,
+    bytes: Vec<u8>,
+}
+
+impl ConfigFile {
+    pub fn new(path: String, bytes: Vec<u8>) -> Self {
+        Self { path, bytes }
+    }
+
+    pub fn open<F: FileSystem>(fs: &F, path: String) -> io::Result<Self> {
+        match fs.read(path.clone()) {
+            Ok(bytes) => Ok(Self::new(path, bytes)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::new(path, Vec::new())),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn events(&self) -> io::Result<Vec<Event>> {
+        Parser::new(&self.bytes).events().map_err(|line| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("bad config line {} in file {}", line, self.path),
+            )
+        })
+    }
+
+    /// Sets `key` to `value`, replacing its assignment or adding a new one
+    /// to the section. A key with several values is refused, as in git.
+    pub fn set(&mut self, key: &str, value: &str) -> io::Result<()> {
+        let key = parse_key(key)?;
+        let events = self.events()?;
+
+        let existing = events
+            .iter()
+            .filter_map(|x| match x {
+                Event::Entry(e) if key.matches(&e.section, e.subsection.as_deref(), &e.key) => {
+                    Some(e)
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        match existing[..] {
+            [] => self.add_parsed(&key, value, &events),
+            [entry] => {
+                let line = format_entry(&key.name, value, entry.inline);
+                self.bytes.splice(entry.start..entry.end, line.into_bytes());
+                Ok(())
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot overwrite multiple values with a single value",
+            )),
+        }
+    }
+
+    /// Adds another value for a multi-valued `key`.
+    pub fn add(&mut self, key: &str, value: &str) -> io::Result<()> {
+        let key = parse_key(key)?;
+        let events = self.events()?;
+        self.add_parsed(&key, value, &events)
+    }
+
+    fn add_parsed(&mut self, key: &Key, value: &str, events: &[Event]) -> io::Result<()> {
+        let in_section = |section: &str, subsection: Option<&str>| {
+            section == key.section && subsection == key.subsection.as_deref()
+        };
+
+        let position = events
+            .iter()
+            .filter_map(|x| match x {
+                Event::Section(s) if in_section(&s.section, s.subsection.as_deref()) => Some(s.end),
+                Event::Entry(e) if in_section(&e.section, e.subsection.as_deref()) => Some(e.end),
+                _ => None,
+            })
+            .next_back();
+
+        let line = format_entry(&key.name, value, false);
+        match position {
+            Some(pos) => {
+                let line = if pos > 0 && self.bytes[pos - 1] != b'\n' {
+                    format!("\n{}", line)
+                } else {
+                    line
+                };
+                self.bytes.splice(pos..pos, line.into_bytes());
+            }
+            None => {
+                if !self.bytes.is_empty() && !self.bytes.ends_with(b"\n") {
+                    self.bytes.push(b'\n');
+                }
+                let header = match &key.subsection {
+                    Some(sub) => format!("[{} \"{}\"]\n", key.section, escape_subsection(sub)),
+                    None => format!("[{}]\n", key.section),
+                };
+                self.bytes.extend_from_slice(header.as_bytes());
+                self.bytes.extend_from_slice(line.as_bytes());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the assignments of `key`. Without `all` there must not be
+    /// more than one. Returns how many were removed.
+    pub fn unset(&mut self, key: &str, all: bool) -> io::Result<usize> {
+        let parsed = parse_key(key)?;
+        let events = self.events()?;
+
+        let spans = events
+            .iter()
+            .filter_map(|x| match x {
+                Event::Entry(e) if parsed.matches(&e.section, e.subsection.as_deref(), &e.key) => {
+                    Some((e.start, e.end))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        if !all && spans.len() > 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} has multiple values", key),
+            ));
+        }
+
+        spans.iter().rev().for_each(|&(start, end)| {
+            self.bytes.splice(start..end, Vec::new());
+        });
+
+        Ok(spans.len())
+    }
+
+    /// Removes a whole section (and every variable in it).
+    pub fn remove_section(&mut self, section: &str, subsection: Option<&str>) -> io::Result<bool> {
+        let section = section.to_lowercase();
+        let events = self.events()?;
+
+        let mut spans = Vec::new();
+        let mut current: Option<(usize, usize)> = None;
+        for event in events.iter() {
+            if let Event::Section(s) = event {
+                if let Some(span) = current.take() {
+                    spans.push(span);
+                }
+                if s.section == section && s.subsection.as_deref() == subsection {
+                    current = Some((s.start, s.end));
+                }
+            } else if let (Some(span), Event::Entry(e)) = (current.as_mut(), event) {
+                span.1 = e.end;
+            }
+        }
+        if let Some(span) = current.take() {
+            spans.push(span);
+        }
+
+        spans.iter().rev().for_each(|&(start, end)| {
+            self.bytes.splice(start..end, Vec::new());
+        });
+
+        Ok(!spans.is_empty())
+    }
+
+    /// Renames a section, keeping its variables.
+    pub fn rename_section(
+        &mut self,
+        section: &str,
+        subsection: Option<&str>,
+        new_section: &str,
+        new_subsection: Option<&str>,
+    ) -> io::Result<bool> {
+        let section_lower = section.to_lowercase();
+        let events = self.events()?;
+
+        let headers = events
+            .iter()
+            .filter_map(|x| match x {
+                Event::Section(s)
+                    if s.section == section_lower && s.subsection.as_deref() == subsection =>
+                {
+                    Some((s.start, s.header_end))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let new_section = new_section.to_lowercase();
+        let header = match new_subsection {
+            Some(sub) => format!("[{} \"{}\"]", new_section, escape_subsection(sub)),
+            None => format!("[{}]", new_section),
+        };
+        headers.iter().rev().for_each(|&(start, end)| {
+            self.bytes.splice(start..end, header.clone().into_bytes());
+        });
+
+        Ok(!headers.is_empty())
+    }
+}
+
+fn parse_key(key: &str) -> io::Result<Key> {
+    Key::parse(key)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid key: {}", key)))
+}
+
+fn format_entry(name: &str, value: &str, inline: bool) -> String {
+    let line = format!("{} = {}\n", name, escape_value(value));
+    if inline {
+        line
+    } else {
+        format!("\t{}", line)
+    }
+}
+
+fn escape_subsection(sub: &str) -> String {
+    sub.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+pub fn escape_value(value: &str) -> String {
+    let needs_quote = value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains('#')
+        || value.contains(';');
+
+    let escaped = value.chars().fold(String::new(), |mut acc, c| {
+        match c {
+            '"' => acc.push_str("\\\""),
+            '\\' => acc.push_str("\\\\"),
+            '\n' => acc.push_str("\\n"),
+            '\t' => acc.push_str("\\t"),
+            '\u{8}' => acc.push_str("\\b"),
+            c => acc.push(c),
+        }
+        acc
+    });
+
+    if needs_quote {
+        format!("\"{}\"", escaped)
+    } else {
+        escaped
+    }
+}
+
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" => Some(true),
+        "false" | "no" | "off" | "" => Some(false),
+        x => parse_int(x).map(|x| x != 0),
+    }
+}
+
+pub fn parse_int(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (digits, factor) = match value.chars().last()?.to_ascii_lowercase() {
+        'k' => (&value[..value.len() - 1], 1024),
+        'm' => (&value[..value.len() - 1], 1024 * 1024),
+        'g' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+
+    digits.parse::<i64>().ok()?.checked_mul(factor)
+}
+
+/// Expands a leading `~/` to the home directory.
+pub fn expand_path(path: &str) -> String {
+    match (path.strip_prefix("~/"), env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{}", home.trim_end_matches('/'), rest),
+        _ if path == "~" => env::var("HOME").unwrap_or_else(|_| path.to_string()),
+        _ => path.to_string(),
+    }
+}
+
+/// Include paths are relative to the directory of the including file.
+fn resolve_include(include: &str, from: &str) -> String {
+    if include.starts_with('/') {
+        return include.to_string();
+    }
+
+    match from.rfind('/') {
+        Some(pos) => format!("{}/{}", &from[..pos], include),
+        None => include.to_string(),
+    }
+}
+
+/// The file backing `level`, honouring git's environment overrides.
+pub fn level_path(level: Level, git_dir: &str) -> Option<String> {
+    match level {
+        Level::System => {
+            if env::var_os("GIT_CONFIG_NOSYSTEM").is_some() {
+                return None;
+            }
+            Some(env::var("GIT_CONFIG_SYSTEM").unwrap_or_else(|_| "/etc/gitconfig".to_string()))
+        }
+        Level::Global => env::var("GIT_CONFIG_GLOBAL")
+            .ok()
+            .or_else(|| env::var("HOME").ok().map(|x| format!("{}/.gitconfig", x))),
+        Level::Local => Some(format!("{}/config", git_dir)),
+    }
+}
+
+/// Global configuration may also live under `$XDG_CONFIG_HOME/git/config`,
+/// which is read before `~/.gitconfig`.
+pub fn xdg_path() -> Option<String> {
+    if env::var_os("GIT_CONFIG_GLOBAL").is_some() {
+        return None;
+    }
+
+    env::var("XDG_CONFIG_HOME")
+        .ok()
+        .filter(|x| !x.is_empty())
+        .map(|x| format!("{}/git/config", x))
+        .or_else(|| {
+            env::var("HOME")
+                .ok()
+                .map(|x| format!("{}/.config/git/config", x))
+        })
+}
+
+#[test]
+fn test_config_from() {
+    let bytes = b"[core]\n\tbare = false\n\tfilemode\n[remote \"origin\"]\n\turl = ../repo.git ; comment\n\tfetch = +refs/heads/*:refs/remotes/origin/*\n[Section.Sub]\n\tKey = \"  quoted # value\"\n";
+    let config = Config::from(bytes, Level::Local).unwrap();
+
+    assert_eq!(config.get("core.bare"), Some("false".to_string()));
+    assert_eq!(config.get_bool("core.filemode").unwrap(), Some(true));
+    assert_eq!(
+        config.get("remote.origin.url"),
+        Some("../repo.git".to_string())
+    );
+    assert_eq!(
+        config.get("section.sub.key"),
+        Some("  quoted # value".to_string())
+    );
+    assert_eq!(config.get("remote.Origin.url"), None);
+}
+
+#[test]
+fn test_config_multi_value() {
+    let bytes =
+        b"[remote \"origin\"]\n\tfetch = a\n\tfetch = b\n[remote \"origin\"]\n\tfetch = c\n";
+    let config = Config::from(bytes, Level::Local).unwrap();
+
+    assert_eq!(config.get_all("remote.origin.fetch"), vec!["a", "b", "c"]);
+    assert_eq!(config.get("remote.origin.fetch"), Some("c".to_string()));
+    assert_eq!(config.subsections("remote"), vec!["origin"]);
+
+    // Setting one of several values would be a guess.
+    let mut file = ConfigFile::new(".git/config".to_string(), bytes.to_vec());
+    let err = file.set("remote.origin.fetch", "d").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "cannot overwrite multiple values with a single value"
+    );
+    assert_eq!(file.as_bytes(), &bytes[..]);
+
+    // So would removing one.
+    let err = file.unset("remote.origin.fetch", false).unwrap_err();
+    assert_eq!(err.to_string(), "remote.origin.fetch has multiple values");
+    assert_eq!(file.as_bytes(), &bytes[..]);
+    assert_eq!(file.unset("remote.origin.fetch", true).unwrap(), 3);
+}
+
+#[test]
+fn test_config_typed() {
+    let bytes = b"[pack]\n\twindow = 10k\n\tbig = 1g\n\tbad = 1x\n[core]\n\ta = yes\n\tb = off\n\tc = 0\n\td = maybe\n";
+    let config = Config::from(bytes, Level::Local).unwrap();
+
+    assert_eq!(config.get_int("pack.window").unwrap(), Some(10 * 1024));
+    assert_eq!(
+        config.get_int("pack.big").unwrap(),
+        Some(1024 * 1024 * 1024)
+    );
+    assert!(config.get_int("pack.bad").is_err());
+    assert_eq!(config.get_bool("core.a").unwrap(), Some(true));
+    assert_eq!(config.get_bool("core.b").unwrap(), Some(false));
+    assert_eq!(config.get_bool("core.c").unwrap(), Some(false));
+    assert!(config.get_bool("core.d").is_err());
+    assert_eq!(config.get_bool("core.e").unwrap(), None);
+}
+
+#[test]
+fn test_config_file_edit() {
+    let mut file = ConfigFile::new(
+        ".git/config".to_string(),
+        b"# keep me\n[core]\n\tbare = false\n[user]\n\tname = a\n".to_vec(),
+    );
+
+    file.set("core.bare", "true").unwrap();
+    file.set("user.email", "a@example.com").unwrap();
+    file.set("remote.origin.url", "/tmp/x").unwrap();
+    file.add("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*")
+        .unwrap();
+    assert_eq!(file.unset("user.name", false).unwrap(), 1);
+
+    assert_eq!(
+        String::from_utf8(file.as_bytes().to_vec()).unwrap(),
+        "# keep me\n[core]\n\tbare = true\n[user]\n\temail = a@example.com\n[remote \"origin\"]\n\turl = /tmp/x\n\tfetch = +refs/heads/*:refs/remotes/origin/*\n"
+    );
+}
+
+#[test]
+fn test_config_include() {
+    use crate::fs::inmem::InMemFileSystem;
+
+    let mut fs = InMemFileSystem::init();
+    fs.write(
+        ".git/config".to_string(),
+        b"[user]\n\tname = before\n[include]\n\tpath = extra\n[includeIf \"onbranch:feature/\"]\n\tpath = feature\n",
+    )
+    .unwrap();
+    fs.write(".git/extra".to_string(), b"[user]\n\tname = included\n")
+        .unwrap();
+    fs.write(
+        ".git/feature".to_string(),
+        b"[user]\n\temail = f@example.com\n",
+    )
+    .unwrap();
+
+    let ctx = IncludeContext {
+        git_dir: Some(".git".to_string()),
+        branch: Some("feature/x".to_string()),
+    };
+    let mut config = Config::new();
+    config.load(&fs, ".git/config", Level::Local, &ctx).unwrap();
+
+    assert_eq!(config.get("user.name"), Some("included".to_string()));
+    assert_eq!(config.get("user.email"), Some("f@example.com".to_string()));
+}
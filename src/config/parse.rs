@@ -0,0 +1,299 @@
+/// A section header, with byte offsets into the parsed file.
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub section: String,
+    pub subsection: Option<String>,
+    pub start: usize,
+    pub header_end: usize,
+    pub end: usize,
+}
+
+/// A variable assignment, with byte offsets covering its whole line(s).
+#[derive(Debug, Clone)]
+pub struct Variable {
+    pub section: String,
+    pub subsection: Option<String>,
+    pub key: String,
+    pub value: Option<String>,
+    pub start: usize,
+    pub end: usize,
+    pub inline: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    Section(Section),
+    Entry(Variable),
+}
+
+pub struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            pos: 0,
+            line: 1,
+        }
+    }
+
+    /// Parses the whole file. On failure returns the offending line number.
+    pub fn events(mut self) -> Result<Vec<Event>, usize> {
+        let mut events = Vec::new();
+        let mut current: Option<(String, Option<String>)> = None;
+        let mut inline = false;
+
+        // UTF-8 BOM
+        if self.bytes.starts_with(b"\xef\xbb\xbf") {
+            self.pos = 3;
+        }
+
+        loop {
+            let mut line_start = self.pos;
+            while let Some(c) = self.peek() {
+                if c == b'\n' {
+                    self.bump();
+                    line_start = self.pos;
+                    inline = false;
+                } else if c == b' ' || c == b'\t' || c == b'\r' {
+                    self.bump();
+                } else {
+                    break;
+                }
+            }
+
+            let c = match self.peek() {
+                Some(c) => c,
+                None => break,
+            };
+
+            match c {
+                b'#' | b';' => self.skip_line(),
+                b'[' => {
+                    let start = self.pos;
+                    let (section, subsection) = self.header().ok_or(self.line)?;
+                    let header_end = self.pos;
+                    while let Some(b' ') | Some(b'\t') | Some(b'\r') = self.peek() {
+                        self.bump();
+                    }
+                    inline = !matches!(self.peek(), None | Some(b'\n') | Some(b'#') | Some(b';'));
+                    if !inline {
+                        self.skip_line();
+                    }
+                    events.push(Event::Section(Section {
+                        section: section.clone(),
+                        subsection: subsection.clone(),
+                        start,
+                        header_end,
+                        end: self.pos,
+                    }));
+                    current = Some((section, subsection));
+                }
+                c if c.is_ascii_alphabetic() => {
+                    let start = if inline { self.pos } else { line_start };
+                    let (section, subsection) = current.clone().ok_or(self.line)?;
+                    let key = self.key();
+
+                    while let Some(b' ') | Some(b'\t') | Some(b'\r') = self.peek() {
+                        self.bump();
+                    }
+                    let value = match self.peek() {
+                        Some(b'=') => {
+                            self.bump();
+                            Some(self.value().ok_or(self.line)?)
+                        }
+                        None | Some(b'\n') | Some(b'#') | Some(b';') => {
+                            self.skip_line();
+                            None
+                        }
+                        _ => return Err(self.line),
+                    };
+
+                    events.push(Event::Entry(Variable {
+                        section,
+                        subsection,
+                        key,
+                        value,
+                        start,
+                        end: self.pos,
+                        inline,
+                    }));
+                    inline = false;
+                }
+                _ => return Err(self.line),
+            }
+        }
+
+        Ok(events)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == b'\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.bump() {
+            if c == b'\n' {
+                break;
+            }
+        }
+    }
+
+    fn header(&mut self) -> Option<(String, Option<String>)> {
+        self.bump();
+        let mut name = Vec::new();
+
+        loop {
+            match self.bump()? {
+                b']' => break,
+                b' ' | b'\t' => {
+                    while let Some(b' ') | Some(b'\t') = self.peek() {
+                        self.bump();
+                    }
+                    if self.bump()? != b'"' {
+                        return None;
+                    }
+                    let mut sub = Vec::new();
+                    loop {
+                        match self.bump()? {
+                            b'\n' => return None,
+                            b'"' => break,
+                            b'\\' => sub.push(self.bump().filter(|&x| x != b'\n')?),
+                            c => sub.push(c),
+                        }
+                    }
+                    if self.bump()? != b']' {
+                        return None;
+                    }
+                    let name = String::from_utf8(name).ok()?.to_lowercase();
+                    let sub = String::from_utf8_lossy(&sub).into_owned();
+                    return Some((name, Some(sub)));
+                }
+                c if c.is_ascii_alphanumeric() || c == b'-' || c == b'.' => name.push(c),
+                _ => return None,
+            }
+        }
+
+        let name = String::from_utf8(name).ok()?.to_lowercase();
+        if name.is_empty() {
+            return None;
+        }
+
+        // Deprecated `[section.subsection]` syntax.
+        match name.find('.') {
+            Some(pos) => Some((name[..pos].to_string(), Some(name[pos + 1..].to_string()))),
+            None => Some((name, None)),
+        }
+    }
+
+    fn key(&mut self) -> String {
+        let mut key = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || c == b'-' {
+                key.push(c.to_ascii_lowercase() as char);
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        key
+    }
+
+    fn value(&mut self) -> Option<String> {
+        let mut value = Vec::new();
+        let mut quote = false;
+        let mut spaces = 0;
+
+        loop {
+            let c = match self.bump() {
+                Some(c) => c,
+                None if quote => return None,
+                None => break,
+            };
+
+            match c {
+                b'\n' if quote => return None,
+                b'\n' => break,
+                b'#' | b';' if !quote => {
+                    self.skip_line();
+                    break;
+                }
+                b' ' | b'\t' | b'\r' if !quote => {
+                    if !value.is_empty() {
+                        spaces += 1;
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            value.extend((0..spaces).map(|_| b' '));
+            spaces = 0;
+
+            match c {
+                b'\\' => match self.bump()? {
+                    b'\n' => {}
+                    b't' => value.push(b'\t'),
+                    b'b' => value.push(b'\x08'),
+                    b'n' => value.push(b'\n'),
+                    b'\\' => value.push(b'\\'),
+                    b'"' => value.push(b'"'),
+                    _ => return None,
+                },
+                b'"' => quote = !quote,
+                c => value.push(c),
+            }
+        }
+
+        Some(String::from_utf8_lossy(&value).into_owned())
+    }
+}
+
+#[test]
+fn test_parse_continuation_and_escapes() {
+    let bytes = b"[alias]\n\tlg = log \\\n\t  --oneline\n\ttab = \"a\\tb\"\n[core] bare = true\n";
+    let events = Parser::new(bytes).events().unwrap();
+
+    let values = events
+        .iter()
+        .filter_map(|x| match x {
+            Event::Entry(e) => Some((e.key.clone(), e.value.clone(), e.inline)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        values,
+        vec![
+            (
+                "lg".to_string(),
+                Some("log    --oneline".to_string()),
+                false
+            ),
+            ("tab".to_string(), Some("a\tb".to_string()), false),
+            ("bare".to_string(), Some("true".to_string()), true),
+        ]
+    );
+}
+
+#[test]
+fn test_parse_error_line() {
+    assert_eq!(
+        Parser::new(b"[core]\n\tok = 1\n\t= nope\n").events().err(),
+        Some(3)
+    );
+    assert_eq!(Parser::new(b"key = outside\n").events().err(), Some(1));
+}
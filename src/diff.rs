@@ -0,0 +1,1702 @@
+use crate::fs::FileSystem;
+use crate::object::tree::FileMode;
+use crate::pretty::abbrev;
+use crate::quote::quote_path;
+use crate::Git;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{self, Write};
+
+/// Lines of context around each hunk, and twice this is the most that
+/// can separate two changes shown in the same hunk.
+const CONTEXT: usize = 3;
+
+/// How far the indent heuristic slides a group of changed lines.
+const MAX_SLIDING: isize = 100;
+
+/// A run of lines removed from the old file and the lines that replace
+/// it in the new one; either side may be empty.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub old: usize,
+    pub old_len: usize,
+    pub new: usize,
+    pub new_len: usize,
+}
+
+/// One side of a file that differs between two trees.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffFile {
+    pub path: String,
+    pub mode: FileMode,
+    pub hash: String,
+}
+
+/// A file that differs between two trees: added when there is no `old`
+/// side, deleted when there is no `new` one, and renamed when the paths
+/// of both sides differ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilePair {
+    pub old: Option<DiffFile>,
+    pub new: Option<DiffFile>,
+}
+
+impl FilePair {
+    /// The path the file ends up at, or had before it was deleted.
+    pub fn path(&self) -> &str {
+        match (&self.old, &self.new) {
+            (_, Some(file)) | (Some(file), None) => &file.path,
+            (None, None) => "",
+        }
+    }
+}
+
+impl<F: FileSystem> Git<F> {
+    /// The files that differ between two trees, in path order; without
+    /// `old` every file of `new` is added. A file deleted in one place and
+    /// added unchanged in another is a rename, as git finds exact ones.
+    pub fn diff_trees(&self, old: Option<String>, new: String) -> io::Result<Vec<FilePair>> {
+        let old = match old {
+            Some(tree) => self.tree_map(tree)?,
+            None => BTreeMap::new(),
+        };
+        let new = self.tree_map(new)?;
+        let side = |path: &str, (mode, hash): &(FileMode, Vec<u8>)| DiffFile {
+            path: path.to_string(),
+            mode: *mode,
+            hash: hex::encode(hash),
+        };
+
+        let mut pairs = Vec::new();
+        for path in old.keys().chain(new.keys()).collect::<BTreeSet<_>>() {
+            match (old.get(path), new.get(path)) {
+                (Some(a), Some(b)) if a == b => {}
+                // A file that became a symlink is one deleted and another added.
+                (Some(a), Some(b)) if kind(a.0) != kind(b.0) => {
+                    pairs.push(FilePair {
+                        old: Some(side(path, a)),
+                        new: None,
+                    });
+                    pairs.push(FilePair {
+                        old: None,
+                        new: Some(side(path, b)),
+                    });
+                }
+                (a, b) => pairs.push(FilePair {
+                    old: a.map(|x| side(path, x)),
+                    new: b.map(|x| side(path, x)),
+                }),
+            }
+        }
+
+        // Renames show up where the file was added.
+        let mut deleted = pairs
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.new.is_none())
+            .map(|(i, x)| (i, x.old.clone().unwrap()))
+            .collect::<Vec<_>>();
+        let mut renamed = BTreeSet::new();
+        for pair in pairs.iter_mut() {
+            let file = match (&pair.old, &pair.new) {
+                (None, Some(file)) => file,
+                _ => continue,
+            };
+            let found = deleted
+                .iter()
+                .position(|(_, x)| x.hash == file.hash && kind(x.mode) == kind(file.mode));
+            if let Some(found) = found {
+                let (i, source) = deleted.remove(found);
+                renamed.insert(i);
+                pair.old = Some(source);
+            }
+        }
+        Ok(pairs
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !renamed.contains(i))
+            .map(|(_, x)| x)
+            .collect())
+    }
+
+    /// Writes `pairs` as a patch, the way `diff --git` shows it.
+    pub fn write_patch(&self, out: &mut impl Write, pairs: &[FilePair]) -> io::Result<()> {
+        for pair in pairs {
+            let a = pair.old.as_ref().map_or(pair.path(), |x| x.path.as_str());
+            let b = pair.path();
+            writeln!(
+                out,
+                "diff --git {} {}",
+                quote_path(&format!("a/{}", a)),
+                quote_path(&format!("b/{}", b))
+            )?;
+
+            let hashes = (
+                pair.old.as_ref().map_or("0000000", |x| abbrev(&x.hash)),
+                pair.new.as_ref().map_or("0000000", |x| abbrev(&x.hash)),
+            );
+            match (&pair.old, &pair.new) {
+                (None, Some(new)) => {
+                    writeln!(out, "new file mode {}", new.mode)?;
+                    writeln!(out, "index {}..{}", hashes.0, hashes.1)?;
+                }
+                (Some(old), None) => {
+                    writeln!(out, "deleted file mode {}", old.mode)?;
+                    writeln!(out, "index {}..{}", hashes.0, hashes.1)?;
+                }
+                (Some(old), Some(new)) => {
+                    if old.mode != new.mode {
+                        writeln!(out, "old mode {}\nnew mode {}", old.mode, new.mode)?;
+                    }
+                    if old.path != new.path {
+                        writeln!(out, "similarity index 100%")?;
+                        writeln!(out, "rename from {}", quote_path(&old.path))?;
+                        writeln!(out, "rename to {}", quote_path(&new.path))?;
+                    }
+                    if old.hash == new.hash {
+                        continue;
+                    }
+                    match old.mode == new.mode {
+                        true => writeln!(out, "index {}..{} {}", hashes.0, hashes.1, new.mode)?,
+                        false => writeln!(out, "index {}..{}", hashes.0, hashes.1)?,
+                    }
+                }
+                (None, None) => continue,
+            }
+
+            let old = self.diff_blob(pair.old.as_ref())?;
+            let new = self.diff_blob(pair.new.as_ref())?;
+            let name = |file: &Option<DiffFile>, prefix: &str| match file {
+                Some(_) => quote_path(&format!("{}{}", prefix, if prefix == "a/" { a } else { b })),
+                None => "/dev/null".to_string(),
+            };
+            let (a_name, b_name) = (name(&pair.old, "a/"), name(&pair.new, "b/"));
+            if is_binary(&old) || is_binary(&new) {
+                writeln!(out, "Binary files {} and {} differ", a_name, b_name)?;
+                continue;
+            }
+            writeln!(out, "--- {}\n+++ {}", a_name, b_name)?;
+            let (old, new) = (lines(&old), lines(&new));
+            write_hunks(out, &old, &new, &diff_lines(&old, &new))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the `--stat` summary of `pairs`: a line with a histogram of
+    /// added and removed lines for each file, then the totals.
+    pub fn write_stat(&self, out: &mut impl Write, pairs: &[FilePair]) -> io::Result<()> {
+        let mut stats = Vec::new();
+        for pair in pairs {
+            let name = match (&pair.old, &pair.new) {
+                (Some(old), Some(new)) if old.path != new.path => rename_name(&old.path, &new.path),
+                _ => quote_path(pair.path()),
+            };
+            let old = self.diff_blob(pair.old.as_ref())?;
+            let new = self.diff_blob(pair.new.as_ref())?;
+            let stat = if is_binary(&old) || is_binary(&new) {
+                (name, true, new.len(), old.len())
+            } else {
+                let (old, new) = (lines(&old), lines(&new));
+                let changes = diff_lines(&old, &new);
+                let added = changes.iter().map(|x| x.new_len).sum();
+                let deleted = changes.iter().map(|x| x.old_len).sum();
+                (name, false, added, deleted)
+            };
+            stats.push(stat);
+        }
+        write_stat(out, &stats)
+    }
+
+    /// The paths of `tree` that differ from each of the `parents` trees,
+    /// which are the only ones a combined diff of a merge looks at.
+    pub fn combined_paths(&self, parents: &[String], tree: &str) -> io::Result<Vec<String>> {
+        let result = self.tree_map(tree.to_string())?;
+        let mut paths: Option<BTreeSet<String>> = None;
+        for parent in parents {
+            let parent = self.tree_map(parent.clone())?;
+            let changed = parent
+                .keys()
+                .chain(result.keys())
+                .filter(|x| parent.get(*x) != result.get(*x))
+                .cloned()
+                .collect::<BTreeSet<_>>();
+            paths = Some(match paths {
+                Some(paths) => paths.intersection(&changed).cloned().collect(),
+                None => changed,
+            });
+        }
+        Ok(paths.unwrap_or_default().into_iter().collect())
+    }
+
+    /// Writes the combined diff of a merge, whose result is `tree`,
+    /// against all of its `parents` at once. A `dense` one (`--cc`) also
+    /// leaves out hunks where the result takes one parent's side.
+    pub fn write_combined(
+        &self,
+        out: &mut impl Write,
+        parents: &[String],
+        tree: &str,
+        dense: bool,
+    ) -> io::Result<()> {
+        let file = |map: &BTreeMap<String, (FileMode, Vec<u8>)>, path: &str| {
+            map.get(path).map(|(mode, hash)| DiffFile {
+                path: path.to_string(),
+                mode: *mode,
+                hash: hex::encode(hash),
+            })
+        };
+        let result = self.tree_map(tree.to_string())?;
+        let maps = parents
+            .iter()
+            .map(|x| self.tree_map(x.clone()))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        for path in self.combined_paths(parents, tree)? {
+            let sides = maps.iter().map(|x| file(x, &path)).collect::<Vec<_>>();
+            self.write_combined_file(out, &path, &sides, file(&result, &path), dense)?;
+        }
+        Ok(())
+    }
+
+    fn write_combined_file(
+        &self,
+        out: &mut impl Write,
+        path: &str,
+        parents: &[Option<DiffFile>],
+        result: Option<DiffFile>,
+        dense: bool,
+    ) -> io::Result<()> {
+        let blobs = parents
+            .iter()
+            .map(|x| self.diff_blob(x.as_ref()))
+            .collect::<io::Result<Vec<_>>>()?;
+        let blob = self.diff_blob(result.as_ref())?;
+        let binary = is_binary(&blob) || blobs.iter().any(|x| is_binary(x));
+
+        let result_lines = lines(&blob);
+        let cnt = result_lines.len();
+        let all_mask = (1u64 << parents.len()) - 1;
+        let mut slines = (0..cnt + 2)
+            .map(|i| SLine {
+                line: result_lines.get(i).copied(),
+                flag: 0,
+                lost: Vec::new(),
+                p_lno: Vec::new(),
+                mark: false,
+                no_pre_delete: false,
+            })
+            .collect::<Vec<_>>();
+        for (n, parent) in blobs.iter().enumerate() {
+            let bit = 1u64 << n;
+            let parent_lines = lines(parent);
+            // Lost lines hang on the line after them.
+            let mut lost = vec![Vec::new(); cnt + 1];
+            for change in diff_lines(&parent_lines, &result_lines) {
+                for sline in &mut slines[change.new..change.new + change.new_len] {
+                    sline.flag |= bit;
+                }
+                lost[change.new].extend(&parent_lines[change.old..change.old + change.old_len]);
+            }
+            let mut p_lno = 1;
+            for (lno, lost) in lost.iter().enumerate() {
+                let sline = &mut slines[lno];
+                sline.p_lno.push(p_lno);
+                coalesce(&mut sline.lost, lost, bit);
+                p_lno += sline.lost.iter().filter(|x| x.1 & bit != 0).count();
+                if lno < cnt && sline.flag & bit == 0 {
+                    p_lno += 1;
+                }
+            }
+            slines[cnt + 1].p_lno.push(p_lno);
+        }
+
+        let show_hunks = !binary && make_hunks(&mut slines, cnt, all_mask, dense);
+        let mode_differs = parents
+            .iter()
+            .any(|x| x.as_ref().map(|x| x.mode) != result.as_ref().map(|x| x.mode));
+        if !binary && !show_hunks && !mode_differs {
+            return Ok(());
+        }
+
+        let added = parents.iter().all(|x| x.is_none());
+        let deleted = result.is_none();
+        let mode = |file: &Option<DiffFile>| file.as_ref().map_or(0, |x| x.mode.bits());
+        let hash = |file: &Option<DiffFile>| {
+            file.as_ref()
+                .map_or("0000000".to_string(), |x| abbrev(&x.hash).to_string())
+        };
+        let join = |f: &dyn Fn(&Option<DiffFile>) -> String| {
+            parents.iter().map(f).collect::<Vec<_>>().join(",")
+        };
+
+        let kind = if dense { "cc" } else { "combined" };
+        writeln!(out, "diff --{} {}", kind, quote_path(path))?;
+        if mode_differs {
+            if added {
+                writeln!(out, "new file mode {:06o}", mode(&result))?;
+            } else {
+                let modes = join(&|x| format!("{:06o}", mode(x)));
+                match deleted {
+                    true => writeln!(out, "deleted file mode {}", modes)?,
+                    false => writeln!(out, "mode {}..{:06o}", modes, mode(&result))?,
+                }
+            }
+        }
+        writeln!(out, "index {}..{}", join(&hash), hash(&result))?;
+        if binary {
+            return writeln!(out, "Binary files differ");
+        }
+        match added {
+            true => writeln!(out, "--- /dev/null")?,
+            false => writeln!(out, "--- {}", quote_path(&format!("a/{}", path)))?,
+        }
+        match deleted {
+            true => writeln!(out, "+++ /dev/null")?,
+            false => writeln!(out, "+++ {}", quote_path(&format!("b/{}", path)))?,
+        }
+        if !deleted {
+            dump_slines(out, &slines, cnt, parents.len())?;
+        }
+        Ok(())
+    }
+
+    /// The content a side of a diff compares: nothing for a missing one,
+    /// and the commit for a submodule.
+    fn diff_blob(&self, file: Option<&DiffFile>) -> io::Result<Vec<u8>> {
+        match file {
+            None => Ok(Vec::new()),
+            Some(file) if file.mode == FileMode::Gitlink => {
+                Ok(format!("Subproject commit {}\n", file.hash).into_bytes())
+            }
+            Some(file) => self.read_blob(file.hash.clone()),
+        }
+    }
+}
+
+/// Regular files and executables can change into each other; anything
+/// else becomes a different file.
+fn kind(mode: FileMode) -> FileMode {
+    match mode {
+        FileMode::BlobExecutable => FileMode::Blob,
+        x => x,
+    }
+}
+
+/// Whether git would treat `data` as binary: it has a NUL byte early on.
+pub fn is_binary(data: &[u8]) -> bool {
+    data[..data.len().min(8000)].contains(&0)
+}
+
+/// Splits `data` into lines, each keeping its newline.
+pub fn lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// The changes that turn `old` into `new`: a shortest edit script from
+/// Myers' algorithm, with each group of changed lines slid to where git
+/// would show it.
+pub fn diff_lines<'a>(old: &[&'a [u8]], new: &[&'a [u8]]) -> Vec<Change> {
+    let mut ids = HashMap::new();
+    let mut id = |line: &'a [u8]| {
+        let next = ids.len();
+        *ids.entry(line).or_insert(next)
+    };
+    let a = old.iter().map(|x| id(x)).collect::<Vec<_>>();
+    let b = new.iter().map(|x| id(x)).collect::<Vec<_>>();
+
+    let mut old_side = Side::new(old, &a);
+    let mut new_side = Side::new(new, &b);
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let count = |ids: &[usize]| {
+        let mut counts = HashMap::new();
+        for id in ids {
+            *counts.entry(*id).or_insert(0) += 1;
+        }
+        counts
+    };
+    let (counts_a, counts_b) = (count(&a), count(&b));
+    let records_a = Records::new(&a, prefix, a.len() - suffix, &counts_b, &mut old_side);
+    let records_b = Records::new(&b, prefix, b.len() - suffix, &counts_a, &mut new_side);
+
+    let mut myers = Myers::new(&records_a.ids, &records_b.ids);
+    myers.compare(
+        0,
+        records_a.ids.len() as isize,
+        0,
+        records_b.ids.len() as isize,
+        false,
+    );
+    for (i, _) in myers.changed_a.iter().enumerate().filter(|x| *x.1) {
+        old_side.set(records_a.index[i] as isize, true);
+    }
+    for (i, _) in myers.changed_b.iter().enumerate().filter(|x| *x.1) {
+        new_side.set(records_b.index[i] as isize, true);
+    }
+    old_side.compact(&new_side);
+    new_side.compact(&old_side);
+
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if !old_side.is_changed(i as isize) && !new_side.is_changed(j as isize) {
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let (old, new) = (i, j);
+        while old_side.is_changed(i as isize) {
+            i += 1;
+        }
+        while new_side.is_changed(j as isize) {
+            j += 1;
+        }
+        changes.push(Change {
+            old,
+            old_len: i - old,
+            new,
+            new_len: j - new,
+        });
+    }
+    changes
+}
+
+/// Lines kept for Myers' algorithm, by their index in the file, after
+/// the common ends are trimmed and lines that can't match are set aside
+/// as changed.
+struct Records {
+    ids: Vec<usize>,
+    index: Vec<usize>,
+}
+
+impl Records {
+    /// Keeps the lines of `ids[start..end]` that occur in the other file
+    /// (`others` counts them there), except frequent ones amid lines
+    /// without a match, like xdiff's `xdl_cleanup_records`.
+    fn new(
+        ids: &[usize],
+        start: usize,
+        end: usize,
+        others: &HashMap<usize, usize>,
+        changed: &mut Side,
+    ) -> Self {
+        let limit = bogosqrt(ids.len()).min(1024);
+        // 0 for no match, 1 for a few and 2 for many.
+        let matches = (start..end)
+            .map(|i| match others.get(&ids[i]).copied().unwrap_or(0) {
+                0 => 0,
+                n if n >= limit => 2,
+                _ => 1,
+            })
+            .collect::<Vec<_>>();
+
+        let mut records = Records {
+            ids: Vec::new(),
+            index: Vec::new(),
+        };
+        for i in 0..matches.len() {
+            if matches[i] == 1 || (matches[i] == 2 && !discard_multimatch(&matches, i)) {
+                records.ids.push(ids[start + i]);
+                records.index.push(start + i);
+            } else {
+                changed.set((start + i) as isize, true);
+            }
+        }
+        records
+    }
+}
+
+fn bogosqrt(mut n: usize) -> usize {
+    let mut i = 1;
+    while n > 0 {
+        i <<= 1;
+        n >>= 2;
+    }
+    i
+}
+
+/// Whether a line with many matches sits among enough lines without any
+/// that it is better treated as changed too.
+fn discard_multimatch(matches: &[u8], i: usize) -> bool {
+    let start = i.saturating_sub(100);
+    let end = (i + 100).min(matches.len() - 1);
+
+    let (mut none, mut many) = (0, 1);
+    for &x in matches[start..i].iter().rev() {
+        match x {
+            0 => none += 1,
+            2 => many += 1,
+            _ => break,
+        }
+    }
+    if none == 0 {
+        return false;
+    }
+    let (mut none_after, mut many_after) = (0, 0);
+    for &x in &matches[i + 1..=end] {
+        match x {
+            0 => none_after += 1,
+            2 => many_after += 1,
+            _ => break,
+        }
+    }
+    if none_after == 0 {
+        return false;
+    }
+    many += many_after;
+    many * 4 < many + none + none_after
+}
+
+/// Where xdiff splits a box of the edit graph, and whether each half
+/// still needs a minimal diff.
+struct Midpoint {
+    i1: isize,
+    i2: isize,
+    min_lo: bool,
+    min_hi: bool,
+}
+
+/// xdiff's variant of Myers' algorithm, which gives up on a minimal
+/// diff when it gets expensive.
+struct Myers<'a> {
+    a: &'a [usize],
+    b: &'a [usize],
+    changed_a: Vec<bool>,
+    changed_b: Vec<bool>,
+    forward: Vec<isize>,
+    backward: Vec<isize>,
+    offset: isize,
+    max_cost: isize,
+}
+
+impl<'a> Myers<'a> {
+    fn new(a: &'a [usize], b: &'a [usize]) -> Self {
+        let diagonals = a.len() + b.len() + 3;
+        Myers {
+            a,
+            b,
+            changed_a: vec![false; a.len()],
+            changed_b: vec![false; b.len()],
+            forward: vec![0; diagonals],
+            backward: vec![0; diagonals],
+            offset: b.len() as isize + 1,
+            max_cost: (bogosqrt(diagonals) as isize).max(256),
+        }
+    }
+
+    fn compare(
+        &mut self,
+        mut off1: isize,
+        mut lim1: isize,
+        mut off2: isize,
+        mut lim2: isize,
+        need_min: bool,
+    ) {
+        let (a, b) = (self.a, self.b);
+        while off1 < lim1 && off2 < lim2 && a[off1 as usize] == b[off2 as usize] {
+            off1 += 1;
+            off2 += 1;
+        }
+        while off1 < lim1 && off2 < lim2 && a[lim1 as usize - 1] == b[lim2 as usize - 1] {
+            lim1 -= 1;
+            lim2 -= 1;
+        }
+
+        if off1 == lim1 {
+            for i in off2..lim2 {
+                self.changed_b[i as usize] = true;
+            }
+        } else if off2 == lim2 {
+            for i in off1..lim1 {
+                self.changed_a[i as usize] = true;
+            }
+        } else {
+            let split = self.split(off1, lim1, off2, lim2, need_min);
+            self.compare(off1, split.i1, off2, split.i2, split.min_lo);
+            self.compare(split.i1, lim1, split.i2, lim2, split.min_hi);
+        }
+    }
+
+    fn split(
+        &mut self,
+        off1: isize,
+        lim1: isize,
+        off2: isize,
+        lim2: isize,
+        need_min: bool,
+    ) -> Midpoint {
+        const SNAKE_CNT: isize = 20;
+        const HEUR_MIN_COST: isize = 256;
+        let (a, b) = (self.a, self.b);
+        let ha1 = |i: isize| a[i as usize];
+        let ha2 = |i: isize| b[i as usize];
+        let o = self.offset;
+        let (kf, kb) = (&mut self.forward, &mut self.backward);
+        let at = |d: isize| (d + o) as usize;
+
+        let (dmin, dmax) = (off1 - lim2, lim1 - off2);
+        let (fmid, bmid) = (off1 - off2, lim1 - lim2);
+        let odd = (fmid - bmid) & 1 != 0;
+        let (mut fmin, mut fmax) = (fmid, fmid);
+        let (mut bmin, mut bmax) = (bmid, bmid);
+        kf[at(fmid)] = off1;
+        kb[at(bmid)] = lim1;
+
+        let mut ec = 1;
+        loop {
+            let mut got_snake = false;
+
+            // Widen the range of diagonals by one, the other way at the
+            // edge of the box, with sentinels just outside it.
+            if fmin > dmin {
+                fmin -= 1;
+                kf[at(fmin - 1)] = -1;
+            } else {
+                fmin += 1;
+            }
+            if fmax < dmax {
+                fmax += 1;
+                kf[at(fmax + 1)] = -1;
+            } else {
+                fmax -= 1;
+            }
+            for d in (fmin..=fmax).rev().step_by(2) {
+                let mut i1 = if kf[at(d - 1)] >= kf[at(d + 1)] {
+                    kf[at(d - 1)] + 1
+                } else {
+                    kf[at(d + 1)]
+                };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+                while i1 < lim1 && i2 < lim2 && ha1(i1) == ha2(i2) {
+                    i1 += 1;
+                    i2 += 1;
+                }
+                if i1 - prev1 > SNAKE_CNT {
+                    got_snake = true;
+                }
+                kf[at(d)] = i1;
+                if odd && bmin <= d && d <= bmax && kb[at(d)] <= i1 {
+                    return Midpoint {
+                        i1,
+                        i2,
+                        min_lo: true,
+                        min_hi: true,
+                    };
+                }
+            }
+
+            if bmin > dmin {
+                bmin -= 1;
+                kb[at(bmin - 1)] = isize::MAX;
+            } else {
+                bmin += 1;
+            }
+            if bmax < dmax {
+                bmax += 1;
+                kb[at(bmax + 1)] = isize::MAX;
+            } else {
+                bmax -= 1;
+            }
+            for d in (bmin..=bmax).rev().step_by(2) {
+                let mut i1 = if kb[at(d - 1)] < kb[at(d + 1)] {
+                    kb[at(d - 1)]
+                } else {
+                    kb[at(d + 1)] - 1
+                };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+                while i1 > off1 && i2 > off2 && ha1(i1 - 1) == ha2(i2 - 1) {
+                    i1 -= 1;
+                    i2 -= 1;
+                }
+                if prev1 - i1 > SNAKE_CNT {
+                    got_snake = true;
+                }
+                kb[at(d)] = i1;
+                if !odd && fmin <= d && d <= fmax && i1 <= kf[at(d)] {
+                    return Midpoint {
+                        i1,
+                        i2,
+                        min_lo: true,
+                        min_hi: true,
+                    };
+                }
+            }
+
+            if need_min {
+                ec += 1;
+                continue;
+            }
+
+            // Past a point, take a diagonal that got well ahead with a
+            // long enough snake behind it.
+            if got_snake && ec > HEUR_MIN_COST {
+                let mut best = None;
+                let mut best_v = 0;
+                for d in (fmin..=fmax).rev().step_by(2) {
+                    let dd = (d - fmid).abs();
+                    let i1 = kf[at(d)];
+                    let i2 = i1 - d;
+                    let v = (i1 - off1) + (i2 - off2) - dd;
+                    if v > 4 * ec
+                        && v > best_v
+                        && off1 + SNAKE_CNT <= i1
+                        && i1 < lim1
+                        && off2 + SNAKE_CNT <= i2
+                        && i2 < lim2
+                        && (1..=SNAKE_CNT).all(|k| ha1(i1 - k) == ha2(i2 - k))
+                    {
+                        best_v = v;
+                        best = Some((i1, i2));
+                    }
+                }
+                if let Some((i1, i2)) = best {
+                    return Midpoint {
+                        i1,
+                        i2,
+                        min_lo: true,
+                        min_hi: false,
+                    };
+                }
+
+                let mut best_v = 0;
+                for d in (bmin..=bmax).rev().step_by(2) {
+                    let dd = (d - bmid).abs();
+                    let i1 = kb[at(d)];
+                    let i2 = i1 - d;
+                    let v = (lim1 - i1) + (lim2 - i2) - dd;
+                    if v > 4 * ec
+                        && v > best_v
+                        && off1 < i1
+                        && i1 <= lim1 - SNAKE_CNT
+                        && off2 < i2
+                        && i2 <= lim2 - SNAKE_CNT
+                        && (0..SNAKE_CNT).all(|k| ha1(i1 + k) == ha2(i2 + k))
+                    {
+                        best_v = v;
+                        best = Some((i1, i2));
+                    }
+                }
+                if let Some((i1, i2)) = best {
+                    return Midpoint {
+                        i1,
+                        i2,
+                        min_lo: false,
+                        min_hi: true,
+                    };
+                }
+            }
+
+            // Enough is enough: take whichever search got furthest.
+            if ec >= self.max_cost {
+                let (mut fbest, mut fbest1) = (-1, -1);
+                for d in (fmin..=fmax).rev().step_by(2) {
+                    let mut i1 = kf[at(d)].min(lim1);
+                    let mut i2 = i1 - d;
+                    if lim2 < i2 {
+                        i1 = lim2 + d;
+                        i2 = lim2;
+                    }
+                    if fbest < i1 + i2 {
+                        fbest = i1 + i2;
+                        fbest1 = i1;
+                    }
+                }
+                let (mut bbest, mut bbest1) = (isize::MAX, isize::MAX);
+                for d in (bmin..=bmax).rev().step_by(2) {
+                    let mut i1 = kb[at(d)].max(off1);
+                    let mut i2 = i1 - d;
+                    if i2 < off2 {
+                        i1 = off2 + d;
+                        i2 = off2;
+                    }
+                    if i1 + i2 < bbest {
+                        bbest = i1 + i2;
+                        bbest1 = i1;
+                    }
+                }
+                return if (lim1 + lim2) - bbest < fbest - (off1 + off2) {
+                    Midpoint {
+                        i1: fbest1,
+                        i2: fbest - fbest1,
+                        min_lo: true,
+                        min_hi: false,
+                    }
+                } else {
+                    Midpoint {
+                        i1: bbest1,
+                        i2: bbest - bbest1,
+                        min_lo: false,
+                        min_hi: true,
+                    }
+                };
+            }
+            ec += 1;
+        }
+    }
+}
+
+/// The lines of one file of a diff and which of them changed, with a
+/// guard at either end so groups never run off the file.
+struct Side<'a> {
+    lines: &'a [&'a [u8]],
+    ids: &'a [usize],
+    changed: Vec<bool>,
+}
+
+/// The changed lines `start..end`, or the spot between two lines when
+/// it is empty.
+#[derive(Debug, Copy, Clone)]
+struct Group {
+    start: isize,
+    end: isize,
+}
+
+impl<'a> Side<'a> {
+    fn new(lines: &'a [&'a [u8]], ids: &'a [usize]) -> Self {
+        Self {
+            lines,
+            ids,
+            changed: vec![false; ids.len() + 2],
+        }
+    }
+
+    fn len(&self) -> isize {
+        self.ids.len() as isize
+    }
+
+    fn is_changed(&self, i: isize) -> bool {
+        self.changed[(i + 1) as usize]
+    }
+
+    fn set(&mut self, i: isize, changed: bool) {
+        self.changed[(i + 1) as usize] = changed;
+    }
+
+    fn first_group(&self) -> Group {
+        let mut end = 0;
+        while self.is_changed(end) {
+            end += 1;
+        }
+        Group { start: 0, end }
+    }
+
+    fn next_group(&self, g: &mut Group) -> bool {
+        if g.end == self.len() {
+            return false;
+        }
+        g.start = g.end + 1;
+        g.end = g.start;
+        while self.is_changed(g.end) {
+            g.end += 1;
+        }
+        true
+    }
+
+    fn previous_group(&self, g: &mut Group) -> bool {
+        if g.start == 0 {
+            return false;
+        }
+        g.end = g.start - 1;
+        g.start = g.end;
+        while self.is_changed(g.start - 1) {
+            g.start -= 1;
+        }
+        true
+    }
+
+    /// Moves the group one line down when the line after it matches its
+    /// first one, merging with any group it runs into.
+    fn slide_down(&mut self, g: &mut Group) -> bool {
+        if g.end >= self.len() || self.ids[g.start as usize] != self.ids[g.end as usize] {
+            return false;
+        }
+        self.set(g.start, false);
+        self.set(g.end, true);
+        g.start += 1;
+        g.end += 1;
+        while self.is_changed(g.end) {
+            g.end += 1;
+        }
+        true
+    }
+
+    fn slide_up(&mut self, g: &mut Group) -> bool {
+        if g.start == 0 || self.ids[(g.start - 1) as usize] != self.ids[(g.end - 1) as usize] {
+            return false;
+        }
+        g.start -= 1;
+        g.end -= 1;
+        self.set(g.start, true);
+        self.set(g.end, false);
+        while self.is_changed(g.start - 1) {
+            g.start -= 1;
+        }
+        true
+    }
+
+    /// Slides each group of changes that can move to where it reads
+    /// best: lined up with a change in the `other` file if it can be,
+    /// otherwise where the indent heuristic scores it best.
+    fn compact(&mut self, other: &Side) {
+        let mut g = self.first_group();
+        let mut go = other.first_group();
+        loop {
+            if g.end != g.start {
+                let mut size;
+                let mut earliest_end;
+                let mut end_matching_other;
+                loop {
+                    size = g.end - g.start;
+                    end_matching_other = None;
+                    while self.slide_up(&mut g) {
+                        other.previous_group(&mut go);
+                    }
+                    earliest_end = g.end;
+                    if go.end > go.start {
+                        end_matching_other = Some(g.end);
+                    }
+                    while self.slide_down(&mut g) {
+                        other.next_group(&mut go);
+                        if go.end > go.start {
+                            end_matching_other = Some(g.end);
+                        }
+                    }
+                    if size == g.end - g.start {
+                        break;
+                    }
+                }
+
+                if g.end == earliest_end {
+                    // It can't move.
+                } else if end_matching_other.is_some() {
+                    while go.end == go.start {
+                        self.slide_up(&mut g);
+                        other.previous_group(&mut go);
+                    }
+                } else {
+                    let first = earliest_end.max(g.end - size - 1).max(g.end - MAX_SLIDING);
+                    let mut best: Option<(isize, Score)> = None;
+                    for shift in first..=g.end {
+                        let mut score = Score::default();
+                        score.add(&self.measure_split(shift));
+                        score.add(&self.measure_split(shift - size));
+                        if best.is_none_or(|(_, best)| score.cmp(&best) <= 0) {
+                            best = Some((shift, score));
+                        }
+                    }
+                    let best = best.map_or(g.end, |x| x.0);
+                    while g.end > best {
+                        self.slide_up(&mut g);
+                        other.previous_group(&mut go);
+                    }
+                }
+            }
+
+            if !self.next_group(&mut g) {
+                break;
+            }
+            other.next_group(&mut go);
+        }
+    }
+
+    fn measure_split(&self, split: isize) -> Split {
+        let indent_at = |i: isize| indent(self.lines[i as usize]);
+        let mut m = Split {
+            end_of_file: split >= self.len(),
+            indent: if split >= self.len() {
+                -1
+            } else {
+                indent_at(split)
+            },
+            pre_blank: 0,
+            pre_indent: -1,
+            post_blank: 0,
+            post_indent: -1,
+        };
+
+        for i in (0..split).rev() {
+            m.pre_indent = indent_at(i);
+            if m.pre_indent != -1 {
+                break;
+            }
+            m.pre_blank += 1;
+            if m.pre_blank == MAX_BLANKS {
+                m.pre_indent = 0;
+                break;
+            }
+        }
+        for i in split + 1..self.len() {
+            m.post_indent = indent_at(i);
+            if m.post_indent != -1 {
+                break;
+            }
+            m.post_blank += 1;
+            if m.post_blank == MAX_BLANKS {
+                m.post_indent = 0;
+                break;
+            }
+        }
+        m
+    }
+}
+
+const MAX_INDENT: i32 = 200;
+const MAX_BLANKS: i32 = 20;
+
+/// The width of a line's leading whitespace, or -1 for a blank line.
+fn indent(line: &[u8]) -> i32 {
+    let mut width = 0;
+    for &c in line {
+        if !c.is_ascii_whitespace() && c != b'\x0b' {
+            return width;
+        }
+        match c {
+            b' ' => width += 1,
+            b'\t' => width += 8 - width % 8,
+            _ => {}
+        }
+        if width >= MAX_INDENT {
+            return MAX_INDENT;
+        }
+    }
+    -1
+}
+
+/// What surrounds the boundary before a line, which the indent
+/// heuristic judges it by. Indents are -1 where there is no line.
+struct Split {
+    end_of_file: bool,
+    indent: i32,
+    pre_blank: i32,
+    pre_indent: i32,
+    post_blank: i32,
+    post_indent: i32,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct Score {
+    effective_indent: i32,
+    penalty: i32,
+}
+
+impl Score {
+    /// Adds the badness of a boundary, with xdiff's weights.
+    fn add(&mut self, m: &Split) {
+        if m.pre_indent == -1 && m.pre_blank == 0 {
+            self.penalty += 1;
+        }
+        if m.end_of_file {
+            self.penalty += 21;
+        }
+
+        let post_blank = if m.indent == -1 { 1 + m.post_blank } else { 0 };
+        let total_blank = m.pre_blank + post_blank;
+        self.penalty += -30 * total_blank + 6 * post_blank;
+
+        let indent = if m.indent != -1 {
+            m.indent
+        } else {
+            m.post_indent
+        };
+        let any_blanks = total_blank != 0;
+        self.effective_indent += indent;
+
+        if indent == -1 || m.pre_indent == -1 || indent == m.pre_indent {
+        } else if indent > m.pre_indent {
+            self.penalty += if any_blanks { 10 } else { -4 };
+        } else if m.post_indent != -1 && m.post_indent > indent {
+            self.penalty += if any_blanks { 17 } else { 24 };
+        } else {
+            self.penalty += if any_blanks { 17 } else { 23 };
+        }
+    }
+
+    fn cmp(&self, other: &Score) -> i32 {
+        let indents = (self.effective_indent - other.effective_indent).signum();
+        60 * indents + (self.penalty - other.penalty)
+    }
+}
+
+/// Writes the hunks of a unified diff with their `@@` headers.
+pub fn write_hunks(
+    out: &mut impl Write,
+    old: &[&[u8]],
+    new: &[&[u8]],
+    changes: &[Change],
+) -> io::Result<()> {
+    let mut i = 0;
+    while i < changes.len() {
+        let mut j = i;
+        while j + 1 < changes.len()
+            && changes[j + 1].old - (changes[j].old + changes[j].old_len) <= 2 * CONTEXT
+        {
+            j += 1;
+        }
+        let (first, last) = (changes[i], changes[j]);
+        let lead = first.old.min(CONTEXT);
+        let trail = (old.len() - last.old - last.old_len).min(CONTEXT);
+        let (old_start, old_end) = (first.old - lead, last.old + last.old_len + trail);
+        let (new_start, new_end) = (first.new - lead, last.new + last.new_len + trail);
+
+        let range = |start: usize, len: usize| match len {
+            0 => format!("{},0", start),
+            1 => format!("{}", start + 1),
+            _ => format!("{},{}", start + 1, len),
+        };
+        write!(
+            out,
+            "@@ -{} +{} @@",
+            range(old_start, old_end - old_start),
+            range(new_start, new_end - new_start)
+        )?;
+        if let Some(func) = old[..old_start].iter().rev().find_map(|x| func_name(x)) {
+            out.write_all(b" ")?;
+            out.write_all(func)?;
+        }
+        out.write_all(b"\n")?;
+
+        let mut at = old_start;
+        for change in &changes[i..=j] {
+            for line in &old[at..change.old] {
+                write_line(out, b' ', line)?;
+            }
+            for line in &old[change.old..change.old + change.old_len] {
+                write_line(out, b'-', line)?;
+            }
+            for line in &new[change.new..change.new + change.new_len] {
+                write_line(out, b'+', line)?;
+            }
+            at = change.old + change.old_len;
+        }
+        for line in &old[at..old_end] {
+            write_line(out, b' ', line)?;
+        }
+        i = j + 1;
+    }
+    Ok(())
+}
+
+fn write_line(out: &mut impl Write, prefix: u8, line: &[u8]) -> io::Result<()> {
+    out.write_all(&[prefix])?;
+    out.write_all(line)?;
+    if !line.ends_with(b"\n") {
+        out.write_all(b"\n\\ No newline at end of file\n")?;
+    }
+    Ok(())
+}
+
+/// The text shown after a hunk header for a line that starts with a
+/// letter, `_` or `$`, like git's default function names.
+fn func_name(line: &[u8]) -> Option<&[u8]> {
+    match line.first() {
+        Some(&c) if c.is_ascii_alphabetic() || c == b'_' || c == b'$' => {
+            let line = &line[..line.len().min(80)];
+            let end = line
+                .iter()
+                .rposition(|x| !x.is_ascii_whitespace())
+                .map_or(0, |x| x + 1);
+            Some(&line[..end])
+        }
+        _ => None,
+    }
+}
+
+/// Writes a diffstat from the name, binary flag, and added and deleted
+/// line counts (bytes for binary files) of each file, 80 columns wide.
+fn write_stat(out: &mut impl Write, stats: &[(String, bool, usize, usize)]) -> io::Result<()> {
+    let width = 80;
+    let digits = |x: usize| x.to_string().len();
+    let max_len = stats.iter().map(|x| x.0.len()).max().unwrap_or(0);
+    let max_change = stats
+        .iter()
+        .filter(|x| !x.1)
+        .map(|x| x.2 + x.3)
+        .max()
+        .unwrap_or(0);
+    let bin_width = stats
+        .iter()
+        .filter(|x| x.1)
+        .map(|x| 14 + digits(x.2) + digits(x.3))
+        .max()
+        .unwrap_or(0);
+    // Change counts line up with "Bin".
+    let number_width = match stats.iter().any(|x| x.1) {
+        true => digits(max_change).max(3),
+        false => digits(max_change),
+    };
+
+    let mut graph_width = if max_change + 4 > bin_width {
+        max_change
+    } else {
+        bin_width - 4
+    };
+    let mut name_width = max_len;
+    if name_width + number_width + 6 + graph_width > width {
+        let limit = (width * 3 / 8).saturating_sub(number_width + 6);
+        if graph_width > limit {
+            graph_width = limit.max(6);
+        }
+        if name_width > width - number_width - 6 - graph_width {
+            name_width = width - number_width - 6 - graph_width;
+        } else {
+            graph_width = width - number_width - 6 - name_width;
+        }
+    }
+
+    let scale = |x: usize| match x {
+        0 => 0,
+        x => 1 + x * (graph_width - 1) / max_change,
+    };
+    let (mut insertions, mut deletions) = (0, 0);
+    for (name, binary, added, deleted) in stats {
+        let (mut prefix, mut name, mut len) = ("", name.as_str(), name_width);
+        if name.len() > name_width {
+            prefix = "...";
+            len = name_width.saturating_sub(3);
+            name = &name[name.len() - len..];
+            if let Some(slash) = name.find('/') {
+                name = &name[slash..];
+            }
+        }
+        write!(out, " {}{:<len$} |", prefix, name, len = len)?;
+
+        if *binary {
+            write!(out, " {:>width$}", "Bin", width = number_width)?;
+            if *added == 0 && *deleted == 0 {
+                writeln!(out)?;
+            } else {
+                writeln!(out, " {} -> {} bytes", deleted, added)?;
+            }
+            continue;
+        }
+        insertions += added;
+        deletions += deleted;
+        let total = added + deleted;
+        write!(out, " {:>width$}", total, width = number_width)?;
+        let (mut add, mut del) = (*added, *deleted);
+        if graph_width <= max_change {
+            let mut total = scale(total);
+            if total < 2 && add > 0 && del > 0 {
+                total = 2;
+            }
+            if add < del {
+                add = scale(add);
+                del = total - add;
+            } else {
+                del = scale(del);
+                add = total - del;
+            }
+        }
+        match total {
+            0 => writeln!(out)?,
+            _ => writeln!(out, " {}{}", "+".repeat(add), "-".repeat(del))?,
+        }
+    }
+
+    let plural =
+        |n: usize, one: &str, many: &str| format!(" {} {}", n, if n == 1 { one } else { many });
+    let mut summary = plural(stats.len(), "file changed", "files changed");
+    if insertions > 0 || deletions == 0 {
+        summary += &format!(",{}", plural(insertions, "insertion(+)", "insertions(+)"));
+    }
+    if deletions > 0 || insertions == 0 {
+        summary += &format!(",{}", plural(deletions, "deletion(-)", "deletions(-)"));
+    }
+    writeln!(out, "{}", summary)
+}
+
+/// How `--stat` names a renamed file, with the part both paths share
+/// outside braces: `dir/{a => b}/file`.
+fn rename_name(a: &str, b: &str) -> String {
+    if quote_path(a) != a || quote_path(b) != b {
+        return format!("{} => {}", quote_path(a), quote_path(b));
+    }
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let at = |s: &[u8], i: isize| s.get(i as usize).copied().unwrap_or(0);
+
+    let mut prefix = 0;
+    for (i, (x, y)) in a.iter().zip(b).enumerate() {
+        if x != y {
+            break;
+        }
+        if *x == b'/' {
+            prefix = i + 1;
+        }
+    }
+
+    // With a common prefix, this may look at its slash too.
+    let stop = prefix as isize - if prefix > 0 { 1 } else { 0 };
+    let (mut i, mut j) = (a.len() as isize, b.len() as isize);
+    let mut suffix = 0;
+    while stop <= i && stop <= j && at(a, i) == at(b, j) {
+        if at(a, i) == b'/' {
+            suffix = a.len() - i as usize;
+        }
+        i -= 1;
+        j -= 1;
+    }
+
+    let a_mid = a.len().saturating_sub(prefix + suffix);
+    let b_mid = b.len().saturating_sub(prefix + suffix);
+    let text = |s: &[u8]| String::from_utf8_lossy(s).into_owned();
+    let middle = format!(
+        "{} => {}",
+        text(&a[prefix..prefix + a_mid]),
+        text(&b[prefix..prefix + b_mid])
+    );
+    if prefix + suffix == 0 {
+        middle
+    } else {
+        format!(
+            "{}{{{}}}{}",
+            text(&a[..prefix]),
+            middle,
+            text(&a[a.len() - suffix..])
+        )
+    }
+}
+
+/// A line of a merge result in a combined diff, with the lines of the
+/// parents lost just before it. The slot after the last line only holds
+/// lost lines.
+struct SLine<'a> {
+    line: Option<&'a [u8]>,
+    /// The parents, as bits, that the line is added against.
+    flag: u64,
+    lost: Vec<(&'a [u8], u64)>,
+    /// For each parent, the number of its first line in a hunk that
+    /// starts here.
+    p_lno: Vec<usize>,
+    mark: bool,
+    /// Set on context before a hunk, which doesn't show lines lost
+    /// before it though they still count in the hunk header.
+    no_pre_delete: bool,
+}
+
+impl SLine<'_> {
+    fn interesting(&self, all_mask: u64) -> bool {
+        self.flag & all_mask != 0 || !self.lost.is_empty()
+    }
+}
+
+/// Merges the lines parent `bit` lost here into those other parents
+/// lost, sharing the ones they have in common.
+fn coalesce<'a>(base: &mut Vec<(&'a [u8], u64)>, new: &[&'a [u8]], bit: u64) {
+    if new.is_empty() {
+        return;
+    }
+    if base.is_empty() {
+        base.extend(new.iter().map(|x| (*x, bit)));
+        return;
+    }
+
+    let same = |x: &[u8], y: &[u8]| {
+        x.strip_suffix(b"\n").unwrap_or(x) == y.strip_suffix(b"\n").unwrap_or(y)
+    };
+    let (n, m) = (base.len(), new.len());
+    let mut lcs = vec![vec![0; m + 1]; n + 1];
+    for i in 1..=n {
+        for j in 1..=m {
+            lcs[i][j] = if same(base[i - 1].0, new[j - 1]) {
+                lcs[i - 1][j - 1] + 1
+            } else {
+                lcs[i][j - 1].max(lcs[i - 1][j])
+            };
+        }
+    }
+
+    let mut merged = Vec::new();
+    let (mut i, mut j) = (n, m);
+    while i > 0 || j > 0 {
+        if i > 0 && j > 0 && same(base[i - 1].0, new[j - 1]) {
+            merged.push((base[i - 1].0, base[i - 1].1 | bit));
+            i -= 1;
+            j -= 1;
+        } else if j > 0 && (i == 0 || lcs[i][j - 1] >= lcs[i - 1][j]) {
+            merged.push((new[j - 1], bit));
+            j -= 1;
+        } else {
+            merged.push(base[i - 1]);
+            i -= 1;
+        }
+    }
+    merged.reverse();
+    *base = merged;
+}
+
+/// Marks the lines to show, with their context. A dense diff drops the
+/// hunks where the result only differs from the parents one way, which
+/// means it took one side. Returns whether anything is left.
+fn make_hunks(slines: &mut [SLine], cnt: usize, all_mask: u64, dense: bool) -> bool {
+    for sline in slines[..=cnt].iter_mut() {
+        sline.mark = sline.interesting(all_mask);
+    }
+    if !dense {
+        return give_context(slines, cnt, all_mask);
+    }
+
+    let mut i = 0;
+    while i <= cnt {
+        while i <= cnt && !slines[i].mark {
+            i += 1;
+        }
+        if i > cnt {
+            break;
+        }
+        let hunk_begin = i;
+        let mut j = i + 1;
+        while j <= cnt {
+            if !slines[j].mark {
+                // Carry on when something interesting follows within
+                // the context.
+                let mut la = adjust_hunk_tail(slines, all_mask, hunk_begin, j);
+                la = (la + CONTEXT).min(cnt + 1);
+                let mut contin = false;
+                while la > 0 && j < la {
+                    la -= 1;
+                    if slines[la].mark {
+                        contin = true;
+                        break;
+                    }
+                }
+                if !contin {
+                    break;
+                }
+                j = la;
+            }
+            j += 1;
+        }
+        let hunk_end = j;
+
+        let mut same_diff = 0;
+        let mut has_interesting = false;
+        'lines: for sline in &slines[i..hunk_end] {
+            let diffs = std::iter::once(sline.flag & all_mask)
+                .filter(|x| *x != 0)
+                .chain(sline.lost.iter().map(|x| x.1));
+            for diff in diffs {
+                if same_diff == 0 {
+                    same_diff = diff;
+                } else if same_diff != diff {
+                    has_interesting = true;
+                    break 'lines;
+                }
+            }
+        }
+        if !has_interesting && same_diff != all_mask {
+            for sline in &mut slines[hunk_begin..hunk_end] {
+                sline.mark = false;
+            }
+        }
+        i = hunk_end;
+    }
+    give_context(slines, cnt, all_mask)
+}
+
+/// The end of a hunk for the purpose of trailing context: a last line
+/// only there for the lines lost before it already gives one.
+fn adjust_hunk_tail(slines: &[SLine], all_mask: u64, hunk_begin: usize, i: usize) -> usize {
+    if hunk_begin < i && slines[i - 1].flag & all_mask == 0 {
+        i - 1
+    } else {
+        i
+    }
+}
+
+fn give_context(slines: &mut [SLine], cnt: usize, all_mask: u64) -> bool {
+    let find_next = |slines: &[SLine], mut i: usize, uninteresting: bool| {
+        while i <= cnt && slines[i].mark == uninteresting {
+            i += 1;
+        }
+        i
+    };
+
+    let mut i = find_next(slines, 0, false);
+    if i > cnt {
+        return false;
+    }
+    while i <= cnt {
+        for sline in &mut slines[i.saturating_sub(CONTEXT)..i] {
+            sline.no_pre_delete |= !sline.mark;
+            sline.mark = true;
+        }
+        loop {
+            let j = find_next(slines, i, true);
+            if j > cnt {
+                // The rest are all interesting.
+                return true;
+            }
+            let k = find_next(slines, j, false);
+            let j = adjust_hunk_tail(slines, all_mask, i, j);
+            if k < j + CONTEXT {
+                // Bridge a small gap to the next interesting line.
+                for sline in &mut slines[j..k] {
+                    sline.mark = true;
+                }
+                i = k;
+                continue;
+            }
+            i = k;
+            for sline in &mut slines[j..(j + CONTEXT).min(cnt + 1)] {
+                sline.mark = true;
+            }
+            break;
+        }
+    }
+    true
+}
+
+/// Writes the marked lines of a combined diff: a column per parent with
+/// `-` for lines it lost and `+` for lines added against it.
+fn dump_slines(
+    out: &mut impl Write,
+    slines: &[SLine],
+    cnt: usize,
+    parents: usize,
+) -> io::Result<()> {
+    let text = |line: &[u8]| line.strip_suffix(b"\n").unwrap_or(line).to_vec();
+    let at = "@".repeat(parents + 1);
+    let mut lno = 0;
+    loop {
+        let mut comment = None;
+        while lno <= cnt && !slines[lno].mark {
+            if let Some(line) = slines[lno].line {
+                if func_name(line).is_some() {
+                    comment = Some(line);
+                }
+            }
+            lno += 1;
+        }
+        if lno > cnt {
+            return Ok(());
+        }
+        let mut hunk_end = lno + 1;
+        while hunk_end <= cnt && slines[hunk_end].mark {
+            hunk_end += 1;
+        }
+        let mut rlines = hunk_end - lno;
+        if hunk_end > cnt {
+            rlines -= 1;
+        }
+
+        write!(out, "{}", at)?;
+        for n in 0..parents {
+            let start = slines[lno].p_lno[n];
+            write!(out, " -{},{}", start, slines[hunk_end].p_lno[n] - start)?;
+        }
+        write!(out, " +{},{} {}", lno + 1, rlines, at)?;
+        if let Some(comment) = comment {
+            // Like git, up to the last non-space of the first 40 bytes,
+            // but not including it.
+            let comment = &comment[..comment.len().min(40)];
+            let comment = &comment[..comment
+                .iter()
+                .position(|&x| x == b'\n')
+                .unwrap_or(comment.len())];
+            let end = comment
+                .iter()
+                .rposition(|x| !x.is_ascii_whitespace())
+                .unwrap_or(0);
+            if end > 0 {
+                out.write_all(b" ")?;
+                out.write_all(&comment[..end])?;
+            }
+        }
+        writeln!(out)?;
+
+        while lno < hunk_end {
+            let sline = &slines[lno];
+            let lost = if sline.no_pre_delete {
+                &[][..]
+            } else {
+                &sline.lost[..]
+            };
+            for (line, parents_lost) in lost {
+                let columns = (0..parents)
+                    .map(|n| if parents_lost & 1 << n != 0 { '-' } else { ' ' })
+                    .collect::<String>();
+                out.write_all(columns.as_bytes())?;
+                out.write_all(&text(line))?;
+                out.write_all(b"\n")?;
+            }
+            if let Some(line) = sline.line {
+                let columns = (0..parents)
+                    .map(|n| if sline.flag & 1 << n != 0 { '+' } else { ' ' })
+                    .collect::<String>();
+                out.write_all(columns.as_bytes())?;
+                out.write_all(&text(line))?;
+                out.write_all(b"\n")?;
+            }
+            lno += 1;
+        }
+    }
+}
+
+#[test]
+fn test_diff_lines() {
+    let old = lines(b"a\nb\nc\nd\n");
+    let new = lines(b"a\nc\nd\ne\n");
+    assert_eq!(
+        diff_lines(&old, &new),
+        vec![
+            Change {
+                old: 1,
+                old_len: 1,
+                new: 1,
+                new_len: 0
+            },
+            Change {
+                old: 4,
+                old_len: 0,
+                new: 3,
+                new_len: 1
+            },
+        ]
+    );
+
+    // A function added between two others starts at its own line rather
+    // than the blank line before it.
+    let old = lines(b"fn a() {\n}\n\nfn c() {\n}\n");
+    let new = lines(b"fn a() {\n}\n\nfn b() {\n}\n\nfn c() {\n}\n");
+    let mut out = Vec::new();
+    write_hunks(&mut out, &old, &new, &diff_lines(&old, &new)).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "@@ -1,5 +1,8 @@\n fn a() {\n }\n \n+fn b() {\n+}\n+\n fn c() {\n }\n"
+    );
+}
+
+#[test]
+fn test_write_hunks() {
+    let old = lines(b"a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n");
+    let new = lines(b"a\nB\nc\nd\ne\nf\ng\nh\ni\nJ\nk");
+    let mut out = Vec::new();
+    write_hunks(&mut out, &old, &new, &diff_lines(&old, &new)).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "@@ -1,5 +1,5 @@\n a\n-b\n+B\n c\n d\n e\n@@ -7,4 +7,5 @@ f\n g\n h\n i\n-j\n+J\n+k\n\\ No newline at end of file\n"
+    );
+}
+
+#[test]
+fn test_write_stat() {
+    let mut out = Vec::new();
+    let stats = [
+        ("bin".to_string(), true, 4, 4),
+        ("d/x".to_string(), false, 0, 1),
+        ("f".to_string(), false, 3, 2),
+    ];
+    write_stat(&mut out, &stats).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        " bin | Bin 4 -> 4 bytes\n d/x |   1 -\n f   |   5 +++--\n 3 files changed, 3 insertions(+), 3 deletions(-)\n"
+    );
+    assert_eq!(rename_name("a/b/c", "a/d/c"), "a/{b => d}/c");
+    assert_eq!(rename_name("f", "g"), "f => g");
+}
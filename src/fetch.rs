@@ -0,0 +1,391 @@
+use crate::fs::FileSystem;
+use crate::protocol::{Connection, RemoteRef};
+use crate::remote::{RefUpdate, Refspec, Remote, UpdateStatus};
+use crate::Git;
+use std::io;
+use std::io::prelude::*;
+
+#[derive(Debug, Default)]
+pub struct FetchOptions {
+    /// Refspecs to fetch instead of the remote's own. Refs they name
+    /// outright are for merging.
+    pub refspecs: Option<Vec<Refspec>>,
+    /// Allows updates other than fast-forwards, as if every refspec had
+    /// a `+`.
+    pub force: bool,
+    /// Allows updating the branch `HEAD` is on.
+    pub update_head_ok: bool,
+    /// Fetches tags only where the refspecs ask for them, rather than
+    /// every one pointing into what is fetched.
+    pub no_tags: bool,
+    /// What the reflog entries of updated refs start with, `fetch` if
+    /// empty.
+    pub reflog_action: String,
+}
+
+/// Where a fetch gets its refs and objects: a server at the other end of
+/// a connection, or a bundle.
+pub trait Transport {
+    /// The refs there are to fetch.
+    fn list_refs(&mut self) -> io::Result<Vec<RemoteRef>>;
+
+    /// Brings the objects of `wants` that `git` lacks into it, along with
+    /// everything they need.
+    fn fetch_objects<F: FileSystem>(
+        &mut self,
+        git: &mut Git<F>,
+        wants: &[String],
+        progress: Option<&mut dyn Write>,
+    ) -> io::Result<()>;
+}
+
+impl<R: Read, W: Write> Transport for Connection<R, W> {
+    fn list_refs(&mut self) -> io::Result<Vec<RemoteRef>> {
+        self.ls_refs(&[])
+    }
+
+    fn fetch_objects<F: FileSystem>(
+        &mut self,
+        git: &mut Git<F>,
+        wants: &[String],
+        progress: Option<&mut dyn Write>,
+    ) -> io::Result<()> {
+        git.fetch_pack(self, wants, progress).map(|_| ())
+    }
+}
+
+/// A remote ref about to be fetched, and where it goes.
+struct Fetched<'a> {
+    remote_ref: &'a RemoteRef,
+    dst: Option<String>,
+    force: bool,
+    merge: bool,
+}
+
+impl<F: FileSystem> Git<F> {
+    /// Fetches the refs of `remote` its refspecs, or those of `options`,
+    /// ask for from `transport`, or its `HEAD` if there are none, along with the tags pointing into them,
+    /// and updates the refs they map to: new ones are created, others
+    /// only fast-forwarded unless forced, and tags never moved unless
+    /// forced. Writes everything fetched to `FETCH_HEAD`, the refs to
+    /// merge first. Returns what happened to each ref.
+    pub fn fetch(
+        &mut self,
+        transport: &mut impl Transport,
+        remote: &Remote,
+        options: &FetchOptions,
+        mut progress: Option<&mut dyn Write>,
+    ) -> io::Result<Vec<RefUpdate>> {
+        let remote_refs = transport.list_refs()?;
+        let fetched = self.fetched_refs(&remote_refs, remote, options)?;
+
+        let config = self.config()?;
+        let bare = config.get_bool("core.bare")?.unwrap_or(false);
+        let head = self.head_ref().ok();
+        if let (Some(head), false, false) = (&head, bare, options.update_head_ok) {
+            if fetched.iter().any(|x| x.dst.as_ref() == Some(head)) {
+                return Err(io::Error::other(format!(
+                    "refusing to fetch into branch '{}' checked out at '{}'",
+                    head,
+                    self.worktree_path()
+                )));
+            }
+        }
+
+        let wants: Vec<_> = fetched.iter().map(|x| x.remote_ref.hash.clone()).collect();
+        let reborrowed = progress.as_mut().map(|x| &mut **x as &mut dyn Write);
+        transport.fetch_objects(self, &wants, reborrowed)?;
+
+        // Tags follow what they point into, whether it came now or was
+        // already here, so long as something is stored locally.
+        let mut tags = Vec::new();
+        if !options.no_tags && fetched.iter().any(|x| x.dst.is_some()) {
+            tags.extend(remote_refs.iter().filter(|x| {
+                x.name.starts_with("refs/tags/")
+                    && !x.name.ends_with("^{}")
+                    && !fetched.iter().any(|y| y.dst.as_ref() == Some(&x.name))
+                    && self.read_ref(x.name.clone()).is_err()
+                    && self.has_object(x.peeled.as_ref().unwrap_or(&x.hash))
+            }));
+            // Annotated tags to what was here already didn't come with
+            // the pack.
+            let wants: Vec<_> = tags.iter().map(|x| x.hash.clone()).collect();
+            transport.fetch_objects(self, &wants, progress)?;
+        }
+
+        let action = match options.reflog_action.as_str() {
+            "" => "fetch",
+            action => action,
+        };
+        let mut updates = Vec::new();
+        for fetched in &fetched {
+            let src = &fetched.remote_ref.name;
+            let new = &fetched.remote_ref.hash;
+            let dst = match &fetched.dst {
+                Some(dst) => dst,
+                None => {
+                    updates.push(RefUpdate {
+                        src: src.clone(),
+                        dst: None,
+                        old: None,
+                        new: Some(new.clone()),
+                        status: UpdateStatus::Fetched,
+                    });
+                    continue;
+                }
+            };
+            let old = self.resolve_ref(dst.clone()).ok();
+            let force = fetched.force || options.force;
+            let (status, message) = match &old {
+                None if src.starts_with("refs/tags/") => (UpdateStatus::Created, "storing tag"),
+                None if src.starts_with("refs/heads/") => (UpdateStatus::Created, "storing head"),
+                None => (UpdateStatus::Created, "storing ref"),
+                Some(old) if old == new => (UpdateStatus::UpToDate, ""),
+                Some(_) if dst.starts_with("refs/tags/") && !force => (
+                    UpdateStatus::Rejected("would clobber existing tag".to_string()),
+                    "",
+                ),
+                Some(old) if self.is_fast_forward(old, new) => {
+                    (UpdateStatus::FastForward, "fast-forward")
+                }
+                Some(_) if force => (UpdateStatus::Forced, "forced-update"),
+                Some(_) => (UpdateStatus::Rejected("non-fast-forward".to_string()), ""),
+            };
+            if !message.is_empty() {
+                let message = format!("{}: {}", action, message);
+                self.update_ref_logged(dst, old.as_deref(), new, &message)?;
+            }
+            updates.push(RefUpdate {
+                src: src.clone(),
+                dst: Some(dst.clone()),
+                old,
+                new: Some(new.clone()),
+                status,
+            });
+        }
+        for tag in &tags {
+            let message = format!("{}: storing tag", action);
+            self.update_ref_logged(&tag.name, None, &tag.hash, &message)?;
+            updates.push(RefUpdate {
+                src: tag.name.clone(),
+                dst: Some(tag.name.clone()),
+                old: None,
+                new: Some(tag.hash.clone()),
+                status: UpdateStatus::Created,
+            });
+        }
+
+        let mut fetch_head = Vec::new();
+        let followed = tags.iter().map(|&x| (x, false));
+        let all = fetched
+            .iter()
+            .map(|x| (x.remote_ref, x.merge))
+            .chain(followed);
+        let (merge, rest): (Vec<_>, Vec<_>) = all.partition(|x| x.1);
+        for (remote_ref, merge) in merge.into_iter().chain(rest) {
+            let name = &remote_ref.name;
+            let description = if let Some(x) = name.strip_prefix("refs/heads/") {
+                format!("branch '{}' of ", x)
+            } else if let Some(x) = name.strip_prefix("refs/tags/") {
+                format!("tag '{}' of ", x)
+            } else if let Some(x) = name.strip_prefix("refs/remotes/") {
+                format!("remote-tracking branch '{}' of ", x)
+            } else if name == "HEAD" {
+                String::new()
+            } else {
+                format!("'{}' of ", name)
+            };
+            fetch_head.extend(
+                format!(
+                    "{}\t{}\t{}{}\n",
+                    remote_ref.hash,
+                    if merge { "" } else { "not-for-merge" },
+                    description,
+                    remote.url
+                )
+                .as_bytes(),
+            );
+        }
+        self.file_system
+            .write(self.git_path("FETCH_HEAD"), &fetch_head)?;
+        Ok(updates)
+    }
+
+    /// The remote refs the refspecs ask for, and where each goes.
+    fn fetched_refs<'a>(
+        &self,
+        remote_refs: &'a [RemoteRef],
+        remote: &Remote,
+        options: &FetchOptions,
+    ) -> io::Result<Vec<Fetched<'a>>> {
+        // Without refspecs of its own, the upstream of the current
+        // branch is to be merged, if it comes from this remote.
+        let config = self.config()?;
+        let upstream = self
+            .head_ref()
+            .ok()
+            .and_then(|x| x.strip_prefix("refs/heads/").map(str::to_string))
+            .filter(|x| config.get(&format!("branch.{}.remote", x)).as_ref() == Some(&remote.name))
+            .and_then(|x| config.get(&format!("branch.{}.merge", x)));
+
+        // Without refspecs, only `HEAD` is fetched, to be merged.
+        let head = [Refspec::parse("HEAD")?];
+        let specs = match options.refspecs.as_ref().unwrap_or(&remote.fetch) {
+            specs if specs.is_empty() => &head[..],
+            specs => specs,
+        };
+        let explicit = options.refspecs.is_some() || remote.fetch.is_empty();
+
+        let mut fetched = Vec::new();
+        for spec in specs {
+            if spec.is_glob() {
+                for remote_ref in remote_refs {
+                    if let Some(dst) = spec.map(&remote_ref.name) {
+                        fetched.push(Fetched {
+                            remote_ref,
+                            dst: Some(dst),
+                            force: spec.force,
+                            merge: options.refspecs.is_none()
+                                && upstream.as_ref() == Some(&remote_ref.name),
+                        });
+                    }
+                }
+                continue;
+            }
+
+            let remote_ref = self
+                .ref_candidates(&spec.src)
+                .iter()
+                .find_map(|name| remote_refs.iter().find(|x| &x.name == name))
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("couldn't find remote ref {}", spec.src),
+                    )
+                })?;
+            let dst = spec.dst.as_ref().map(|dst| {
+                if dst.starts_with("refs/") {
+                    dst.clone()
+                } else if remote_ref.name.starts_with("refs/tags/") {
+                    format!("refs/tags/{}", dst)
+                } else {
+                    format!("refs/heads/{}", dst)
+                }
+            });
+            fetched.push(Fetched {
+                remote_ref,
+                dst,
+                force: spec.force,
+                merge: explicit || upstream.as_ref() == Some(&remote_ref.name),
+            });
+        }
+        Ok(fetched)
+    }
+}
+
+#[test]
+fn test_fetch_refspecs() {
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::test_util::commit_file;
+
+    let mut server = Git::with_git_dir(InMemFileSystem::init(), "repo.git".to_string());
+    server.init(true, "main").unwrap();
+    let first = commit_file(&mut server, b"one\n", Vec::new());
+    server.write_branch("refs/tags/v1", &first).unwrap();
+
+    let mut git = Git::new(InMemFileSystem::init());
+    git.init(false, "main").unwrap();
+    let mut config = git.config_file(crate::config::Level::Local).unwrap();
+    config.set("remote.origin.url", "/repo.git").unwrap();
+    config
+        .set("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*")
+        .unwrap();
+    config.set("branch.main.remote", "origin").unwrap();
+    config.set("branch.main.merge", "refs/heads/main").unwrap();
+    git.write_config_file(&config).unwrap();
+    let remote = git.remote("origin").unwrap();
+
+    let fetch = |git: &mut Git<InMemFileSystem>, server: &Git<_>, options: &FetchOptions| {
+        std::thread::scope(|scope| {
+            let (mut requests, client_output) = io::pipe().unwrap();
+            let (client_input, mut responses) = io::pipe().unwrap();
+            let served = scope.spawn(move || server.upload_pack(&mut requests, &mut responses, 2));
+            let mut connection = Connection::new(client_input, client_output).unwrap();
+            let updates = git.fetch(&mut connection, &remote, options, None);
+            drop(connection);
+            served.join().unwrap().unwrap();
+            updates
+        })
+    };
+
+    let updates = fetch(&mut git, &server, &FetchOptions::default()).unwrap();
+    let summary: Vec<_> = updates
+        .iter()
+        .map(|x| (x.dst.as_deref().unwrap(), x.status.clone()))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("refs/remotes/origin/main", UpdateStatus::Created),
+            ("refs/tags/v1", UpdateStatus::Created),
+        ]
+    );
+    assert_eq!(
+        git.read_commit(first.clone()).unwrap().parents,
+        Vec::<String>::new()
+    );
+    let fetch_head = git.file_system.read(".git/FETCH_HEAD".to_string()).unwrap();
+    assert_eq!(
+        String::from_utf8(fetch_head).unwrap(),
+        format!(
+            "{0}\t\tbranch 'main' of /repo.git\n{0}\tnot-for-merge\ttag 'v1' of /repo.git\n",
+            first
+        )
+    );
+
+    // Rewritten history only comes in with a `+`.
+    let second = commit_file(&mut server, b"two\n", Vec::new());
+    let options = FetchOptions {
+        refspecs: Some(vec![
+            Refspec::parse("main:refs/remotes/origin/main").unwrap()
+        ]),
+        ..FetchOptions::default()
+    };
+    let updates = fetch(&mut git, &server, &options).unwrap();
+    assert_eq!(
+        updates[0].status,
+        UpdateStatus::Rejected("non-fast-forward".to_string())
+    );
+    let tracking = || {
+        git.read_ref("refs/remotes/origin/main".to_string())
+            .unwrap()
+    };
+    assert_eq!(tracking(), first);
+
+    let updates = fetch(&mut git, &server, &FetchOptions::default()).unwrap();
+    assert_eq!(updates[0].status, UpdateStatus::Forced);
+    assert_eq!(
+        git.read_ref("refs/remotes/origin/main".to_string())
+            .unwrap(),
+        second
+    );
+    let log = git.read_reflog("refs/remotes/origin/main").unwrap();
+    assert_eq!(log.last().unwrap().message, "fetch: forced-update");
+
+    let options = FetchOptions {
+        refspecs: Some(vec![Refspec::parse("nope").unwrap()]),
+        ..FetchOptions::default()
+    };
+    let err = fetch(&mut git, &server, &options).unwrap_err();
+    assert_eq!(err.to_string(), "couldn't find remote ref nope");
+
+    // Into the current branch only when asked to.
+    let options = FetchOptions {
+        refspecs: Some(vec![Refspec::parse("main:main").unwrap()]),
+        ..FetchOptions::default()
+    };
+    let err = fetch(&mut git, &server, &options).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("refusing to fetch into branch 'refs/heads/main'"));
+}
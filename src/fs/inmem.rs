@@ -9,28 +9,30 @@ use std::io;
 #[derive(Debug, Clone)]
 enum Entity {
     Dir(HashMap<String, Entity>),
-    File(Vec<u8>),
+    /// File contents along with their permission bits.
+    File(Vec<u8>, u32),
+    Symlink(String),
 }
 
 impl Entity {
     pub fn change_dir(&self, path: String) -> io::Result<&Entity> {
         path.split("/").try_fold(self, |st, x| match st {
-            Self::File(_) => Err(io::Error::from(io::ErrorKind::NotFound)),
             Self::Dir(dir) => dir.get(x).ok_or(io::Error::from(io::ErrorKind::NotFound)),
+            _ => Err(io::Error::from(io::ErrorKind::NotFound)),
         })
     }
 
     pub fn change_dir_mut(&mut self, path: String) -> io::Result<&mut Entity> {
         path.split("/").try_fold(self, |st, x| match st {
-            Self::File(_) => Err(io::Error::from(io::ErrorKind::NotFound)),
             Self::Dir(dir) => dir
                 .get_mut(x)
                 .ok_or(io::Error::from(io::ErrorKind::NotFound)),
+            _ => Err(io::Error::from(io::ErrorKind::NotFound)),
         })
     }
 
     pub fn read(&self) -> io::Result<Vec<u8>> {
-        if let Self::File(data) = self {
+        if let Self::File(data, _) = self {
             Ok(data.clone())
         } else {
             Err(io::Error::from(io::ErrorKind::NotFound))
@@ -39,31 +41,31 @@ impl Entity {
 
     pub fn write(&mut self, name: String, data: &[u8]) -> io::Result<()> {
         if let Self::Dir(dir) = self {
-            dir.insert(name, Self::File(data.to_vec()));
+            // Like a real file system, overwriting keeps the permissions.
+            let perm = match dir.get(&name) {
+                Some(Self::File(_, perm)) => *perm,
+                _ => 0o644,
+            };
+            dir.insert(name, Self::File(data.to_vec(), perm));
             Ok(())
         } else {
             Err(io::Error::from(io::ErrorKind::NotFound))
         }
     }
 
-    pub fn make_dir(&mut self, name: String) -> io::Result<()> {
-        if let Self::Dir(dir) = self {
-            dir.insert(name, Self::Dir(HashMap::new()));
-            Ok(())
-        } else {
-            Err(io::Error::from(io::ErrorKind::NotFound))
-        }
+    pub fn remove(&mut self, name: String) -> io::Result<()> {
+        self.take(name).map(|_| ())
     }
 
-    pub fn remove(&mut self, name: String) -> io::Result<()> {
+    pub fn take(&mut self, name: String) -> io::Result<Entity> {
         let (path, name) = path_split(name);
         match path.len() {
             0 => if let Self::Dir(dir) = self { 
-                dir.remove(&name).ok_or(io::Error::from(io::ErrorKind::InvalidInput)).map(|_| ())
+                dir.remove(&name).ok_or(io::Error::from(io::ErrorKind::NotFound))
             } else {
                 Err(io::Error::from(io::ErrorKind::InvalidInput))
             },
-            _ => self.change_dir_mut(path.join("/")).and_then(|x| x.remove(name))
+            _ => self.change_dir_mut(path.join("/")).and_then(|x| x.take(name))
         }
     }
 }
@@ -82,7 +84,8 @@ impl Serialize for Entity {
                 }
                 s.end()
             }
-            Self::File(file) => serializer.serialize_bytes(file),
+            Self::File(file, _) => serializer.serialize_bytes(file),
+            Self::Symlink(target) => serializer.serialize_str(target),
         }
     }
 }
@@ -111,7 +114,7 @@ impl InMemFileSystem {
                         ),
                         (
                             "HEAD".to_owned(),
-                            Entity::File(b"ref: refs/heads/master".to_vec()),
+                            Entity::File(b"ref: refs/heads/master".to_vec(), 0o644),
                         ),
                     ]
                     .into_iter()
@@ -145,40 +148,155 @@ impl FileSystem for InMemFileSystem {
     fn stat(&self, path: String) -> io::Result<Metadata> {
         let entity = self.root.change_dir(path)?;
 
-        if let Entity::File(_) = entity {
-            Ok(Metadata {
-                dev: 0,
-                ino: 0,
-                mode: 33188,
-                uid: 0,
-                gid: 0,
-                size: 0,
-                mtime: 0,
-                mtime_nsec: 0,
-                ctime: 0,
-                ctime_nsec: 0,
-            })
-        } else {
-            Err(io::Error::from(io::ErrorKind::InvalidData))
-        }
+        let (mode, size) = match entity {
+            Entity::File(data, perm) => (0o100000 | perm, data.len() as u32),
+            Entity::Symlink(target) => (0o120777, target.len() as u32),
+            Entity::Dir(_) => (16877, 0),
+        };
+
+        Ok(Metadata {
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size,
+            mtime: 0,
+            mtime_nsec: 0,
+            ctime: 0,
+            ctime_nsec: 0,
+        })
     }
 
     fn create_dir(&mut self, path: String) -> io::Result<()> {
-        let (dir_name, dir) = path_split(path);
-        self.root
-            .change_dir_mut(dir_name.join("/"))
-            .and_then(|x| x.make_dir(dir))
+        path.split('/')
+            .filter(|x| !x.is_empty())
+            .try_fold(&mut self.root, |st, x| match st {
+                Entity::Dir(dir) => Ok(dir
+                    .entry(x.to_string())
+                    .or_insert_with(|| Entity::Dir(HashMap::new()))),
+                _ => Err(io::Error::from(io::ErrorKind::AlreadyExists)),
+            })
+            .and_then(|x| match x {
+                Entity::Dir(_) => Ok(()),
+                _ => Err(io::Error::from(io::ErrorKind::AlreadyExists)),
+            })
     }
 
     fn rename(&mut self, from: String, to: String) -> io::Result<()> {
-        let file = self.read(from.clone())?;
-        self.remove(from.clone())?;
-        self.write(to, &file)
+        let (dir_name, name) = path_split(to);
+        if !dir_name.is_empty()
+            && !matches!(self.root.change_dir(dir_name.join("/"))?, Entity::Dir(_))
+        {
+            return Err(io::Error::from(io::ErrorKind::NotADirectory));
+        }
+
+        let entity = self.root.take(from)?;
+        match if !dir_name.is_empty() {
+            self.root.change_dir_mut(dir_name.join("/"))?
+        } else {
+            &mut self.root
+        } {
+            Entity::Dir(dir) => {
+                dir.insert(name, entity);
+                Ok(())
+            }
+            _ => Err(io::Error::from(io::ErrorKind::NotFound)),
+        }
     }
 
     fn remove(&mut self, path: String) -> io::Result<()> {
         self.root.remove(path)
     }
+
+    fn remove_dir(&mut self, path: String) -> io::Result<()> {
+        match self.root.change_dir(path.clone())? {
+            Entity::Dir(dir) if dir.is_empty() => self.root.remove(path),
+            Entity::Dir(_) => Err(io::Error::from(io::ErrorKind::DirectoryNotEmpty)),
+            _ => Err(io::Error::from(io::ErrorKind::NotADirectory)),
+        }
+    }
+
+    fn canonicalize(&self, path: String) -> io::Result<String> {
+        self.root.change_dir(path.clone())?;
+        Ok(format!("/{}", path))
+    }
+
+    fn read_dir(&self, path: String) -> io::Result<Vec<String>> {
+        let entity = if path.is_empty() {
+            &self.root
+        } else {
+            self.root.change_dir(path)?
+        };
+
+        if let Entity::Dir(dir) = entity {
+            let mut names = dir.keys().cloned().collect::<Vec<_>>();
+            names.sort();
+            Ok(names)
+        } else {
+            Err(io::Error::from(io::ErrorKind::InvalidInput))
+        }
+    }
+
+    fn symlink(&mut self, target: String, path: String) -> io::Result<()> {
+        let (dir_name, name) = path_split(path);
+
+        match if !dir_name.is_empty() {
+            self.root.change_dir_mut(dir_name.join("/"))?
+        } else {
+            &mut self.root
+        } {
+            Entity::Dir(dir) if dir.contains_key(&name) => {
+                Err(io::Error::from(io::ErrorKind::AlreadyExists))
+            }
+            Entity::Dir(dir) => {
+                dir.insert(name, Entity::Symlink(target));
+                Ok(())
+            }
+            _ => Err(io::Error::from(io::ErrorKind::NotFound)),
+        }
+    }
+
+    fn read_link(&self, path: String) -> io::Result<String> {
+        match self.root.change_dir(path)? {
+            Entity::Symlink(target) => Ok(target.clone()),
+            _ => Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        }
+    }
+
+    /// Files are values here, so the link is a copy that won't see later
+    /// writes to the original.
+    fn hard_link(&mut self, target: String, path: String) -> io::Result<()> {
+        let file = match self.root.change_dir(target)? {
+            file @ Entity::File(..) => file.clone(),
+            _ => return Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        };
+        if self.root.change_dir(path.clone()).is_ok() {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+        }
+        let (dir_name, name) = path_split(path);
+        match if !dir_name.is_empty() {
+            self.root.change_dir_mut(dir_name.join("/"))?
+        } else {
+            &mut self.root
+        } {
+            Entity::Dir(dir) => {
+                dir.insert(name, file);
+                Ok(())
+            }
+            _ => Err(io::Error::from(io::ErrorKind::NotFound)),
+        }
+    }
+
+    fn set_permissions(&mut self, path: String, mode: u32) -> io::Result<()> {
+        match self.root.change_dir_mut(path)? {
+            Entity::File(_, perm) => {
+                *perm = mode & 0o777;
+                Ok(())
+            }
+            _ => Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        }
+    }
 }
 
 fn path_split(path: String) -> (Vec<String>, String) {
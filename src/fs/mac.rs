@@ -4,16 +4,20 @@ use std::fs;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+#[cfg(target_os = "linux")]
+use std::os::linux::fs::MetadataExt;
 #[cfg(target_os = "macos")]
 use std::os::macos::fs::MetadataExt;
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+use std::os::unix::fs::PermissionsExt;
 use std::path::PathBuf;
 
-#[cfg(target_os = "macos")]
+#[cfg(any(target_os = "macos", target_os = "linux"))]
 pub struct MacOSFileSystem {
     root: PathBuf,
 }
 
-#[cfg(target_os = "macos")]
+#[cfg(any(target_os = "macos", target_os = "linux"))]
 impl MacOSFileSystem {
     pub fn init() -> io::Result<Self> {
         Ok(MacOSFileSystem {
@@ -22,7 +26,7 @@ impl MacOSFileSystem {
     }
 }
 
-#[cfg(target_os = "macos")]
+#[cfg(any(target_os = "macos", target_os = "linux"))]
 impl FileSystem for MacOSFileSystem {
     fn read(&self, path: String) -> io::Result<Vec<u8>> {
         let mut file = File::open(self.root.join(path))?;
@@ -40,7 +44,7 @@ impl FileSystem for MacOSFileSystem {
 
     fn stat(&self, path: String) -> io::Result<Metadata> {
         let path = self.root.join(path);
-        let metadata = path.metadata()?;
+        let metadata = path.symlink_metadata()?;
 
         Ok(Metadata {
             dev: metadata.st_dev() as u32,
@@ -71,4 +75,48 @@ impl FileSystem for MacOSFileSystem {
         let path = self.root.join(path);
         fs::remove_file(path)
     }
+
+    fn remove_dir(&mut self, path: String) -> io::Result<()> {
+        let path = self.root.join(path);
+        fs::remove_dir(path)
+    }
+
+    fn canonicalize(&self, path: String) -> io::Result<String> {
+        let path = fs::canonicalize(self.root.join(path))?;
+        path.into_os_string()
+            .into_string()
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))
+    }
+
+    fn read_dir(&self, path: String) -> io::Result<Vec<String>> {
+        let mut names = fs::read_dir(self.root.join(path))?
+            .map(|entry| {
+                entry?
+                    .file_name()
+                    .into_string()
+                    .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    }
+
+    fn symlink(&mut self, target: String, path: String) -> io::Result<()> {
+        std::os::unix::fs::symlink(target, self.root.join(path))
+    }
+
+    fn read_link(&self, path: String) -> io::Result<String> {
+        fs::read_link(self.root.join(path))?
+            .into_os_string()
+            .into_string()
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))
+    }
+
+    fn hard_link(&mut self, target: String, path: String) -> io::Result<()> {
+        fs::hard_link(self.root.join(target), self.root.join(path))
+    }
+
+    fn set_permissions(&mut self, path: String, mode: u32) -> io::Result<()> {
+        fs::set_permissions(self.root.join(path), fs::Permissions::from_mode(mode))
+    }
 }
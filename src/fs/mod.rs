@@ -7,9 +7,21 @@ pub trait FileSystem {
     fn read(&self, path: String) -> io::Result<Vec<u8>>;
     fn write(&mut self, path: String, data: &[u8]) -> io::Result<()>;
     fn stat(&self, path: String) -> io::Result<Metadata>;
+    /// Creates a directory along with any missing parents.
     fn create_dir(&mut self, path: String) -> io::Result<()>;
     fn rename(&mut self, from: String, to: String) -> io::Result<()>;
     fn remove(&mut self, path: String) -> io::Result<()>;
+    /// Removes an empty directory.
+    fn remove_dir(&mut self, path: String) -> io::Result<()>;
+    fn canonicalize(&self, path: String) -> io::Result<String>;
+    fn read_dir(&self, path: String) -> io::Result<Vec<String>>;
+    /// Creates a symbolic link at `path` pointing to `target`.
+    fn symlink(&mut self, target: String, path: String) -> io::Result<()>;
+    fn read_link(&self, path: String) -> io::Result<String>;
+    /// Creates `path` as another name for the file at `target`.
+    fn hard_link(&mut self, target: String, path: String) -> io::Result<()>;
+    /// Sets the permission bits (e.g. `0o755`) of a file.
+    fn set_permissions(&mut self, path: String, mode: u32) -> io::Result<()>;
 }
 
 #[derive(Debug)]
@@ -25,3 +37,17 @@ pub struct Metadata {
     pub ctime: u32,
     pub ctime_nsec: u32,
 }
+
+impl Metadata {
+    pub fn is_dir(&self) -> bool {
+        self.mode & 0o170000 == 0o040000
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.mode & 0o170000 == 0o120000
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.mode & 0o100 != 0
+    }
+}
@@ -0,0 +1,137 @@
+/// Draws the ASCII history graph of `log --graph`, one commit at a time.
+///
+/// Each column holds the commit it is waiting for; a commit takes over the
+/// column that expects it and hands it on to its parents.
+#[derive(Debug, Default)]
+pub struct Graph {
+    columns: Vec<String>,
+}
+
+/// The rows drawn for one commit.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Rows {
+    /// Prefix of the line that carries the commit itself.
+    pub commit: String,
+    /// Lines that fork into or merge back the parents' columns.
+    pub connectors: Vec<String>,
+    /// Prefix of the lines after the connectors.
+    pub padding: String,
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Places `hash` in the graph; `parents` should already be rewritten to
+    /// the commits that are actually shown.
+    ///
+    /// The lines of the commit's text are prefixed with, in turn, `commit`,
+    /// the connectors and then `padding`, all padded to the same width.
+    pub fn next(&mut self, hash: &str, parents: &[String]) -> Rows {
+        let idx = match self.columns.iter().position(|x| x == hash) {
+            Some(idx) => idx,
+            None => {
+                self.columns.push(hash.to_string());
+                self.columns.len() - 1
+            }
+        };
+        let width = self.columns.len();
+
+        let row = |mark: &str| {
+            (0..width)
+                .map(|i| if i == idx { mark } else { "|" })
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let commit = row("*");
+
+        let mut next = self.columns.clone();
+        next.remove(idx);
+
+        let mut existing = Vec::new();
+        let mut added = Vec::new();
+        for parent in parents {
+            match next.iter().position(|x| x == parent) {
+                Some(pos) => existing.push(pos),
+                None if !added.contains(parent) => added.push(parent.clone()),
+                None => {}
+            }
+        }
+        let shift = added.len() as isize - 1;
+
+        let mut line = vec![b' '; 2 * (width + added.len()) + 2];
+        let mut drawn = false;
+        for i in 0..idx {
+            line[2 * i] = b'|';
+        }
+        if !added.is_empty() {
+            line[2 * idx] = b'|';
+        }
+        for k in 1..added.len() {
+            line[2 * idx + 2 * k - 1] = b'\\';
+            drawn = true;
+        }
+        for pos in existing {
+            if pos < idx {
+                line[2 * idx - 1] = b'/';
+            } else if added.is_empty() {
+                // The parent's column slides over into this one.
+                line[2 * idx] = b'|';
+            } else {
+                line[2 * idx + 1] = b'\\';
+            }
+            drawn = true;
+        }
+        for j in idx + 1..width {
+            match shift {
+                0 => line[2 * j] = b'|',
+                s if s > 0 => line[2 * j + 1] = b'\\',
+                _ => line[2 * j - 1] = b'/',
+            }
+            drawn |= shift != 0;
+        }
+
+        let tail = next.split_off(idx);
+        next.extend(added);
+        next.extend(tail);
+        self.columns = next;
+        let padding = vec!["|"; self.columns.len()].join(" ");
+
+        let connectors = if drawn {
+            vec![String::from_utf8_lossy(&line).trim_end().to_string()]
+        } else {
+            Vec::new()
+        };
+
+        Rows {
+            commit,
+            connectors,
+            padding,
+        }
+    }
+}
+
+#[test]
+fn test_graph_merge() {
+    let s = |x: &str| x.to_string();
+    let mut graph = Graph::new();
+
+    let merge = graph.next("m", &[s("a"), s("b")]);
+    assert_eq!(merge.commit, "*");
+    assert_eq!(merge.connectors, vec![s("|\\")]);
+
+    let a = graph.next("a", &[s("root")]);
+    assert_eq!(a.commit, "* |");
+    assert_eq!(a.padding, "| |");
+    assert!(a.connectors.is_empty());
+
+    let b = graph.next("b", &[s("root")]);
+    assert_eq!(b.commit, "| *");
+    assert_eq!(b.connectors, vec![s("|/")]);
+
+    let root = graph.next("root", &[]);
+    assert_eq!(root.commit, "*");
+    assert_eq!(root.padding, "");
+    assert!(root.connectors.is_empty());
+}
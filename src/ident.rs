@@ -0,0 +1,313 @@
+use crate::config::Config;
+use crate::object::commit::{parse_offset, User};
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone, Utc};
+use std::convert::TryFrom;
+use std::io;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Role {
+    Author,
+    Committer,
+}
+
+impl Role {
+    fn env_prefix(self) -> &'static str {
+        match self {
+            Role::Author => "GIT_AUTHOR",
+            Role::Committer => "GIT_COMMITTER",
+        }
+    }
+
+    fn config_section(self) -> &'static str {
+        match self {
+            Role::Author => "author",
+            Role::Committer => "committer",
+        }
+    }
+}
+
+/// Works out the identity for `role` the way git does: the `GIT_<ROLE>_*`
+/// variables win, then `<role>.name`/`<role>.email`, then `user.*`.
+pub fn resolve<E>(role: Role, config: &Config, env: E) -> io::Result<User>
+where
+    E: Fn(&str) -> Option<String>,
+{
+    let prefix = role.env_prefix();
+    let section = role.config_section();
+
+    let lookup = |field: &str| {
+        env(&format!("{}_{}", prefix, field.to_uppercase()))
+            .or_else(|| config.get(&format!("{}.{}", section, field)))
+            .or_else(|| config.get(&format!("user.{}", field)))
+            .or_else(|| if field == "email" { env("EMAIL") } else { None })
+    };
+
+    let name = lookup("name");
+    let email = lookup("email");
+
+    let (name, email) = match (name, email) {
+        (Some(name), Some(email)) if !name.trim().is_empty() => (name, email),
+        (Some(_), Some(_)) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "empty ident name not allowed",
+            ))
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "{} identity unknown\n\n*** Please tell me who you are.\n\nRun\n\n  clumsy config --global user.email \"you@example.com\"\n  clumsy config --global user.name \"Your Name\"\n",
+                    match role {
+                        Role::Author => "Author",
+                        Role::Committer => "Committer",
+                    }
+                ),
+            ))
+        }
+    };
+
+    let ts = resolve_date(role, env)?;
+
+    Ok(User::new(sanitize(&name), sanitize(&email), ts))
+}
+
+/// The timestamp for `role`: `GIT_<ROLE>_DATE` if set, otherwise now.
+pub fn resolve_date<E>(role: Role, env: E) -> io::Result<DateTime<FixedOffset>>
+where
+    E: Fn(&str) -> Option<String>,
+{
+    match env(&format!("{}_DATE", role.env_prefix())) {
+        Some(date) => parse_date(&date).ok_or_else(|| invalid_date(&date)),
+        None => Ok(now()),
+    }
+}
+
+pub fn invalid_date(date: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid date format: {}", date),
+    )
+}
+
+/// The current time in the local timezone.
+pub fn now() -> DateTime<FixedOffset> {
+    let local = Local::now();
+    local.with_timezone(local.offset())
+}
+
+/// Splits `Name <email>` as accepted by `--author`.
+pub fn parse_ident(ident: &str) -> Option<(String, String)> {
+    let start = ident.find('<')?;
+    let end = start + ident[start..].find('>')?;
+
+    let name = ident[..start].trim();
+    let email = ident[start + 1..end].trim();
+
+    Some((sanitize(name), sanitize(email)))
+}
+
+/// git strips "crud" from both ends and drops characters that would
+/// corrupt the ident line.
+fn sanitize(value: &str) -> String {
+    let is_crud = |c: char| c <= ' ' || ".,:;<>\"\\'".contains(c);
+
+    value
+        .trim_matches(is_crud)
+        .chars()
+        .filter(|&c| c != '<' && c != '>' && c != '\n')
+        .collect()
+}
+
+/// Parses the date formats git accepts for `--date` and `GIT_*_DATE`:
+/// the internal `<unix-seconds> <tz>` form (optionally prefixed with `@`),
+/// RFC 2822, ISO 8601 and `now`. Dates without a zone use local time.
+pub fn parse_date(date: &str) -> Option<DateTime<FixedOffset>> {
+    let date = date.trim();
+
+    if date == "now" {
+        return Some(now());
+    }
+
+    if let Some(ts) = parse_raw_date(date) {
+        return Some(ts);
+    }
+
+    if let Ok(ts) = DateTime::parse_from_rfc2822(date) {
+        return Some(ts);
+    }
+
+    let zoned = [
+        "%Y-%m-%dT%H:%M:%S%.f%:z",
+        "%Y-%m-%dT%H:%M:%S%.f%z",
+        "%Y-%m-%d %H:%M:%S%.f %z",
+        "%Y-%m-%d %H:%M:%S%.f%:z",
+        "%Y-%m-%d %H:%M:%S%.f%z",
+        "%a %b %e %H:%M:%S %Y %z",
+        "%a, %d %b %Y %H:%M:%S %z",
+    ];
+    if let Some(ts) = zoned
+        .iter()
+        .find_map(|fmt| DateTime::parse_from_str(date, fmt).ok())
+    {
+        return Some(ts);
+    }
+
+    if let Some(utc) = date.strip_suffix('Z') {
+        return ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+            .iter()
+            .find_map(|fmt| NaiveDateTime::parse_from_str(utc, fmt).ok())
+            .map(|x| Utc.from_utc_datetime(&x).fixed_offset());
+    }
+
+    let naive = [
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%a %b %e %H:%M:%S %Y",
+    ]
+    .iter()
+    .find_map(|fmt| NaiveDateTime::parse_from_str(date, fmt).ok())
+    .or_else(|| {
+        chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .ok()
+            .and_then(|x| x.and_hms_opt(0, 0, 0))
+    })?;
+
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|x| x.fixed_offset())
+}
+
+/// Like `parse_date`, but also accepts the relative forms used by
+/// `--since`/`--until` such as `2 weeks ago`, `yesterday` and `today`.
+pub fn parse_approxidate(date: &str) -> Option<DateTime<FixedOffset>> {
+    let date = date.trim();
+    let day = chrono::Duration::days(1);
+
+    match date {
+        "today" => return Some(now()),
+        "yesterday" => return Some(now() - day),
+        _ => {}
+    }
+
+    let words = date.replace('.', " ");
+    let words = words.split_whitespace().collect::<Vec<_>>();
+    if let [n, unit, "ago"] = words.as_slice() {
+        // Counts too large for `TimeDelta` arithmetic are invalid, not clamped.
+        let n = i32::try_from(n.parse::<i64>().ok()?).ok()?;
+        let unit = match unit.trim_end_matches('s') {
+            "second" => chrono::Duration::seconds(1),
+            "minute" => chrono::Duration::minutes(1),
+            "hour" => chrono::Duration::hours(1),
+            "day" => day,
+            "week" => day * 7,
+            "month" => day * 30,
+            "year" => day * 365,
+            _ => return None,
+        };
+        return now().checked_sub_signed(unit.checked_mul(n)?);
+    }
+
+    parse_date(date)
+}
+
+fn parse_raw_date(date: &str) -> Option<DateTime<FixedOffset>> {
+    let mut iter = date.split_whitespace();
+    let secs = iter.next()?;
+    let explicit = secs.starts_with('@');
+    let secs = secs.trim_start_matches('@');
+
+    // Plain numbers shorter than 9 digits are ambiguous (e.g. 20050407).
+    if !secs.chars().all(|c| c.is_ascii_digit()) || (!explicit && secs.len() < 9) {
+        return None;
+    }
+    let secs = secs.parse::<i64>().ok()?;
+
+    let offset = match iter.next() {
+        Some(tz) => parse_offset(tz)?,
+        None => FixedOffset::east_opt(0)?,
+    };
+    if iter.next().is_some() {
+        return None;
+    }
+
+    Utc.timestamp_opt(secs, 0)
+        .single()
+        .map(|x| x.with_timezone(&offset))
+}
+
+#[test]
+fn test_parse_date() {
+    let expected = FixedOffset::east_opt(2 * 3600)
+        .unwrap()
+        .with_ymd_and_hms(2005, 4, 7, 22, 13, 13)
+        .unwrap();
+
+    assert_eq!(parse_date("1112904793 +0200"), Some(expected));
+    assert_eq!(parse_date("@1112904793 +0200"), Some(expected));
+    assert_eq!(
+        parse_date("Thu, 07 Apr 2005 22:13:13 +0200"),
+        Some(expected)
+    );
+    assert_eq!(parse_date("2005-04-07T22:13:13+02:00"), Some(expected));
+    assert_eq!(parse_date("2005-04-07 22:13:13 +0200"), Some(expected));
+    assert_eq!(parse_date("Thu Apr 7 22:13:13 2005 +0200"), Some(expected));
+    assert_eq!(
+        parse_date("2005-04-07T20:13:13Z").map(|x| x.timestamp()),
+        Some(expected.timestamp())
+    );
+    assert_eq!(parse_date("yesterday-ish"), None);
+}
+
+#[test]
+fn test_parse_approxidate() {
+    let ago = |x: &str| now().timestamp() - parse_approxidate(x).unwrap().timestamp();
+
+    assert!((ago("2 weeks ago") - 14 * 86400).abs() < 5);
+    assert!((ago("3.days.ago") - 3 * 86400).abs() < 5);
+    assert!((ago("yesterday") - 86400).abs() < 5);
+    assert_eq!(
+        parse_approxidate("1112904793 +0200").map(|x| x.timestamp()),
+        Some(1112904793)
+    );
+    assert_eq!(parse_approxidate("2 fortnights ago"), None);
+    assert_eq!(parse_approxidate("9999999999 years ago"), None);
+    assert_eq!(parse_approxidate("2000000000 years ago"), None);
+}
+
+#[test]
+fn test_resolve_ident() {
+    let config = Config::from(
+        b"[user]\n\tname = Config User\n\temail = config@example.com\n[committer]\n\tname = Committer\n",
+        crate::config::Level::Local,
+    )
+    .unwrap();
+
+    let env = |key: &str| match key {
+        "GIT_AUTHOR_NAME" => Some("Env Author".to_string()),
+        "GIT_AUTHOR_DATE" => Some("1112904793 +0200".to_string()),
+        _ => None,
+    };
+
+    let author = resolve(Role::Author, &config, env).unwrap();
+    assert_eq!(author.name, "Env Author");
+    assert_eq!(author.email, "config@example.com");
+    assert_eq!(author.ts.timestamp(), 1112904793);
+
+    let committer = resolve(Role::Committer, &config, env).unwrap();
+    assert_eq!(committer.name, "Committer");
+    assert_eq!(committer.email, "config@example.com");
+
+    let empty = Config::new();
+    assert!(resolve(Role::Author, &empty, |_| None).is_err());
+}
+
+#[test]
+fn test_parse_ident() {
+    assert_eq!(
+        parse_ident("A U Thor <author@example.com>"),
+        Some(("A U Thor".to_string(), "author@example.com".to_string()))
+    );
+    assert_eq!(parse_ident("no email"), None);
+}
@@ -0,0 +1,255 @@
+use crate::fs::FileSystem;
+use crate::wildmatch::{wildmatch, WM_PATHNAME};
+use crate::Git;
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+/// One line of an ignore file.
+#[derive(Debug, Clone)]
+struct Pattern {
+    pattern: String,
+    negated: bool,
+    dir_only: bool,
+    /// Patterns with a slash match the path relative to the ignore file;
+    /// the others only match the last component.
+    anchored: bool,
+}
+
+impl Pattern {
+    fn parse(line: &str) -> Option<Self> {
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        // Trailing spaces are dropped unless escaped with a backslash.
+        let mut line = line.to_string();
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line.pop();
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest.to_string()),
+            None => (false, line.strip_prefix('\\').unwrap_or(&line).to_string()),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line.as_str()),
+        };
+        if line.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            pattern: line.strip_prefix('/').unwrap_or(line).to_string(),
+            negated,
+            dir_only,
+            anchored: line.contains('/'),
+        })
+    }
+
+    /// `path` is relative to the directory the pattern was read from.
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            wildmatch(&self.pattern, path, WM_PATHNAME)
+        } else {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            wildmatch(&self.pattern, name, WM_PATHNAME)
+        }
+    }
+}
+
+fn parse_patterns(bytes: &[u8]) -> Vec<Pattern> {
+    String::from_utf8_lossy(bytes)
+        .lines()
+        .filter_map(Pattern::parse)
+        .collect()
+}
+
+/// The ignore rules of a worktree: `.gitignore` files, which are read as
+/// directories are visited, followed by `.git/info/exclude` and
+/// `core.excludesFile`.
+#[derive(Debug, Clone, Default)]
+pub struct Ignore {
+    global: Vec<Pattern>,
+    dirs: HashMap<String, Vec<Pattern>>,
+}
+
+impl Ignore {
+    /// The last pattern that matches decides, with the `.gitignore` closest
+    /// to `path` taking precedence.
+    fn decide(&self, path: &str, is_dir: bool) -> Option<bool> {
+        let mut dirs = path
+            .match_indices('/')
+            .map(|(i, _)| &path[..i])
+            .collect::<Vec<_>>();
+        dirs.insert(0, "");
+
+        dirs.iter()
+            .rev()
+            .filter_map(|&dir| {
+                let rel = if dir.is_empty() {
+                    path
+                } else {
+                    &path[dir.len() + 1..]
+                };
+                self.dirs
+                    .get(dir)?
+                    .iter()
+                    .rev()
+                    .find(|x| x.matches(rel, is_dir))
+            })
+            .chain(self.global.iter().rev().filter(|x| x.matches(path, is_dir)))
+            .next()
+            .map(|x| !x.negated)
+    }
+}
+
+impl<F: FileSystem> Git<F> {
+    /// Loads `.git/info/exclude` and `core.excludesFile`; per-directory
+    /// `.gitignore` files are picked up by `is_ignored` as needed.
+    pub fn ignore(&self) -> io::Result<Ignore> {
+        let mut ignore = Ignore::default();
+
+        let excludes = match self.config()?.get_path("core.excludesFile") {
+            Some(path) => Some(path),
+            None => std::env::var("XDG_CONFIG_HOME")
+                .ok()
+                .filter(|x| !x.is_empty())
+                .map(|x| format!("{}/git/ignore", x))
+                .or_else(|| {
+                    std::env::var("HOME")
+                        .ok()
+                        .map(|x| format!("{}/.config/git/ignore", x))
+                }),
+        };
+        // The excludes file lives outside the worktree.
+        if let Some(bytes) = excludes.and_then(|x| std::fs::read(x).ok()) {
+            ignore.global.extend(parse_patterns(&bytes));
+        }
+        if let Ok(bytes) = self.file_system.read(self.git_path("info/exclude")) {
+            ignore.global.extend(parse_patterns(&bytes));
+        }
+
+        Ok(ignore)
+    }
+
+    /// Whether `path` (or one of its parent directories) is ignored.
+    pub fn is_ignored(&self, ignore: &mut Ignore, path: &str, is_dir: bool) -> io::Result<bool> {
+        let components = path.split('/').collect::<Vec<_>>();
+        for i in 0..components.len() {
+            let dir = components[..i].join("/");
+            if !ignore.dirs.contains_key(&dir) {
+                let file = if dir.is_empty() {
+                    ".gitignore".to_string()
+                } else {
+                    format!("{}/.gitignore", dir)
+                };
+                let patterns = match self.file_system.read(file) {
+                    Ok(bytes) => parse_patterns(&bytes),
+                    Err(_) => Vec::new(),
+                };
+                ignore.dirs.insert(dir.clone(), patterns);
+            }
+
+            if i > 0 && ignore.decide(&dir, true) == Some(true) {
+                return Ok(true);
+            }
+        }
+
+        Ok(ignore.decide(path, is_dir) == Some(true))
+    }
+
+    /// Worktree files that aren't in `tracked`, sorted. Ignored files and
+    /// directories are left out when `ignore` is given.
+    pub fn untracked_files(
+        &self,
+        tracked: &HashSet<String>,
+        mut ignore: Option<&mut Ignore>,
+    ) -> io::Result<Vec<String>> {
+        let mut files = Vec::new();
+        let mut dirs = vec![String::new()];
+
+        while let Some(dir) = dirs.pop() {
+            for name in self.file_system.read_dir(dir.clone())? {
+                let path = if dir.is_empty() {
+                    name
+                } else {
+                    format!("{}/{}", dir, name)
+                };
+                if path == ".git" {
+                    continue;
+                }
+
+                let is_dir = self.file_system.stat(path.clone())?.is_dir();
+                if let Some(ignore) = ignore.as_deref_mut() {
+                    if !tracked.contains(&path) && self.is_ignored(ignore, &path, is_dir)? {
+                        continue;
+                    }
+                }
+
+                if tracked.contains(&path) {
+                    // A directory can only be tracked as a submodule.
+                    continue;
+                } else if !is_dir {
+                    files.push(path);
+                } else if self.file_system.stat(format!("{}/.git", path)).is_ok() {
+                    // Another repository is listed as a whole, like git does.
+                    files.push(format!("{}/", path));
+                } else {
+                    dirs.push(path);
+                }
+            }
+        }
+
+        files.sort();
+        Ok(files)
+    }
+}
+
+#[test]
+fn test_ignore() {
+    use crate::fs::inmem::InMemFileSystem;
+
+    let mut git = Git::new(InMemFileSystem::init());
+    let mut write = |path: &str, data: &[u8]| {
+        let dir = path.rsplit_once('/').map_or("", |x| x.0);
+        git.file_system.create_dir(dir.to_string()).unwrap();
+        git.file_system.write(path.to_string(), data).unwrap();
+    };
+    write(".gitignore", b"*.log\n/build/\n# comment\ndoc/*.html\n");
+    write("src/.gitignore", b"!keep.log\n");
+    write(".git/info/exclude", b"secret\n");
+    for path in [
+        "a.log",
+        "build/out",
+        "src/build/x",
+        "src/keep.log",
+        "src/other.log",
+        "doc/index.html",
+        "doc/sub/index.html",
+        "secret",
+        "tracked.log",
+    ] {
+        write(path, b"");
+    }
+
+    let mut ignore = git.ignore().unwrap();
+    assert!(git.is_ignored(&mut ignore, "build/out", false).unwrap());
+    assert!(!git.is_ignored(&mut ignore, "src/keep.log", false).unwrap());
+
+    let tracked = vec!["tracked.log".to_string()].into_iter().collect();
+    assert_eq!(
+        git.untracked_files(&tracked, Some(&mut ignore)).unwrap(),
+        vec![
+            ".gitignore",
+            "doc/sub/index.html",
+            "src/.gitignore",
+            "src/build/x",
+            "src/keep.log",
+        ]
+    );
+    assert_eq!(git.untracked_files(&tracked, None).unwrap().len(), 10);
+}
@@ -1,3 +1,4 @@
+use crate::object::tree::FileMode;
 use chrono::{DateTime, TimeZone, Utc};
 use sha1::{Digest, Sha1};
 use std;
@@ -11,26 +12,35 @@ pub struct Entry {
     pub m_time: DateTime<Utc>,
     pub dev: u32,
     pub inode: u32,
-    pub mode: u32,
+    pub mode: FileMode,
     pub uid: u32,
     pub gid: u32,
     pub size: u32,
     pub hash: Vec<u8>,
     pub name: String,
+    /// Recorded by `add --intent-to-add`: the path is known but its
+    /// content isn't staged, so it is left out of trees.
+    pub intent_to_add: bool,
 }
 
+/// Set in the flags of entries followed by a second flags word, which only
+/// index version 3 has.
+const EXTENDED: u16 = 0x4000;
+const INTENT_TO_ADD: u16 = 0x2000;
+
 #[derive(Debug, Clone)]
 pub struct Index {
     pub entries: Vec<Entry>,
 }
 
 impl Entry {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         c_time: DateTime<Utc>,
         m_time: DateTime<Utc>,
         dev: u32,
         inode: u32,
-        mode: u32,
+        mode: FileMode,
         uid: u32,
         gid: u32,
         size: u32,
@@ -48,9 +58,17 @@ impl Entry {
             size,
             hash,
             name,
+            intent_to_add: false,
         }
     }
 
+    /// An entry without stat data, as `update-index --cacheinfo` records
+    /// it; the next refresh fills the stat data in.
+    pub fn cacheinfo(mode: FileMode, hash: Vec<u8>, name: String) -> Self {
+        let epoch = Utc.timestamp_opt(0, 0).unwrap();
+        Self::new(epoch, epoch, 0, 0, mode, 0, 0, 0, hash, name)
+    }
+
     pub fn from(bytes: &[u8]) -> Option<Self> {
         let c_time = hex_to_num(&bytes[0..4]);
         let c_time_nano = hex_to_num(&bytes[4..8]);
@@ -58,17 +76,26 @@ impl Entry {
         let m_time_nano = hex_to_num(&bytes[12..16]);
         let dev = hex_to_num(&bytes[16..20]);
         let inode = hex_to_num(&bytes[20..24]);
-        let mode = hex_to_num(&bytes[24..28]);
+        let mode = FileMode::from_bits(hex_to_num(&bytes[24..28]))?;
         let uid = hex_to_num(&bytes[28..32]);
         let gid = hex_to_num(&bytes[32..36]);
         let size = hex_to_num(&bytes[36..40]);
         let hash = Vec::from(&bytes[40..60]);
-        let name_size = hex_to_num(&bytes[60..62]);
-        let name = String::from_utf8(Vec::from(&bytes[62..(62 + name_size as usize)])).ok()?;
+        let flags = hex_to_num(&bytes[60..62]) as u16;
+        let (extended, start) = if flags & EXTENDED != 0 {
+            (hex_to_num(&bytes[62..64]) as u16, 64)
+        } else {
+            (0, 62)
+        };
+        let name_size = match (flags & 0xfff) as usize {
+            0xfff => bytes[start..].iter().position(|&b| b == 0)?,
+            size => size,
+        };
+        let name = String::from_utf8(Vec::from(&bytes[start..(start + name_size)])).ok()?;
 
         let entry = Self {
-            c_time: Utc.timestamp(c_time.into(), c_time_nano),
-            m_time: Utc.timestamp(m_time.into(), m_time_nano),
+            c_time: Utc.timestamp_opt(c_time.into(), c_time_nano).single()?,
+            m_time: Utc.timestamp_opt(m_time.into(), m_time_nano).single()?,
             dev,
             inode,
             mode,
@@ -77,6 +104,7 @@ impl Entry {
             size,
             hash,
             name,
+            intent_to_add: extended & INTENT_TO_ADD != 0,
         };
 
         Some(entry)
@@ -89,32 +117,36 @@ impl Entry {
         let mtime_nano = self.m_time.timestamp_subsec_nanos();
 
         let meta = [
-            ctime, ctime_nano, mtime, mtime_nano, self.dev, self.inode, self.mode, self.uid,
+            ctime, ctime_nano, mtime, mtime_nano, self.dev, self.inode, self.mode.bits(), self.uid,
             self.gid, self.size,
         ]
         .iter()
         .flat_map(|&x| Vec::from(x.to_be_bytes()))
         .collect::<Vec<_>>();
 
-        let name_size = self.name.len() as u16;
+        // Longer names are stored in full but their length is capped.
+        let name_size = self.name.len().min(0xfff) as u16;
         let name = self.name.as_bytes();
+        let flags = if self.intent_to_add {
+            [
+                (name_size | EXTENDED).to_be_bytes(),
+                INTENT_TO_ADD.to_be_bytes(),
+            ]
+            .concat()
+        } else {
+            name_size.to_be_bytes().to_vec()
+        };
 
-        let len = 62 + name_size as usize;
+        let len = 60 + flags.len() + name.len();
 
         let padding = (0..(8 - len % 8)).map(|_| b'\0').collect::<Vec<u8>>();
 
-        [
-            meta,
-            self.hash.clone(),
-            Vec::from(name_size.to_be_bytes()),
-            name.to_vec(),
-            padding,
-        ]
-        .concat()
+        [meta, self.hash.clone(), flags, name.to_vec(), padding].concat()
     }
 
     pub fn size(&self) -> usize {
-        let size = 62 + self.name.len();
+        let flags = if self.intent_to_add { 4 } else { 2 };
+        let size = 60 + flags + self.name.len();
         size + (8 - size % 8)
     }
 }
@@ -124,7 +156,7 @@ impl fmt::Display for Entry {
         write!(
             f,
             "{} {} 0\t{}",
-            num_to_mode(self.mode as u16),
+            self.mode,
             hex::encode(&self.hash),
             self.name
         )
@@ -136,14 +168,46 @@ impl Index {
         Self { entries }
     }
 
+    pub fn get(&self, name: &str) -> Option<&Entry> {
+        self.position(name).ok().map(|i| &self.entries[i])
+    }
+
+    /// Adds `entry`, replacing the entry of the same path. Entries that
+    /// would turn a path into both a file and a directory, i.e. the
+    /// entry's parent directories or files below it, are dropped.
+    pub fn add(&mut self, entry: Entry) {
+        self.entries.retain(|x| !file_dir_conflict(&x.name, &entry.name));
+
+        match self.position(&entry.name) {
+            Ok(i) => self.entries[i] = entry,
+            Err(i) => self.entries.insert(i, entry),
+        }
+    }
+
+    /// Whether adding `name` would replace entries that `add` drops.
+    pub fn has_conflict(&self, name: &str) -> bool {
+        self.entries.iter().any(|x| file_dir_conflict(&x.name, name))
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Entry> {
+        let i = self.position(name).ok()?;
+        Some(self.entries.remove(i))
+    }
+
+    /// Entries are kept sorted by name, byte by byte.
+    fn position(&self, name: &str) -> Result<usize, usize> {
+        self.entries
+            .binary_search_by(|x| x.name.as_bytes().cmp(name.as_bytes()))
+    }
+
     pub fn from(bytes: &[u8]) -> Option<Self> {
         // インデックスファイルじゃない
         if &bytes[0..4] != b"DIRC" {
             return None;
         }
 
-        // バージョン２だけに対応
-        if hex_to_num(&bytes[4..8]) != 2 {
+        // バージョン２と、拡張フラグのある３に対応
+        if !matches!(hex_to_num(&bytes[4..8]), 2 | 3) {
             return None;
         }
 
@@ -161,9 +225,14 @@ impl Index {
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
+        let version: u32 = if self.entries.iter().any(|x| x.intent_to_add) {
+            3
+        } else {
+            2
+        };
         let header = [
             *b"DIRC",
-            [0x00, 0x00, 0x00, 0x02],
+            version.to_be_bytes(),
             (self.entries.len() as u32).to_be_bytes(),
         ]
         .concat();
@@ -175,7 +244,7 @@ impl Index {
             .collect::<Vec<_>>();
 
         let content = [header, entries].concat();
-        let hash = Vec::from(Sha1::digest(&content).as_slice());
+        let hash = Sha1::digest(&content).to_vec();
 
         [content, hash].concat()
     }
@@ -187,6 +256,12 @@ impl fmt::Display for Index {
     }
 }
 
+/// Whether one path is a directory above the other.
+fn file_dir_conflict(a: &str, b: &str) -> bool {
+    let (short, long) = if a.len() < b.len() { (a, b) } else { (b, a) };
+    long.len() > short.len() && long.starts_with(short) && long.as_bytes()[short.len()] == b'/'
+}
+
 fn hex_to_num(hex: &[u8]) -> u32 {
     hex.iter()
         .rev()
@@ -196,20 +271,6 @@ fn hex_to_num(hex: &[u8]) -> u32 {
         .0
 }
 
-fn num_to_mode(val: u16) -> String {
-    let file_type = val >> 13;
-    let (user, group, other) = {
-        let permission = val & 0x01ff;
-        let user = (permission & 0x01c0) >> 6;
-        let group = (permission & 0x0038) >> 3;
-        let other = permission & 0x0007;
-
-        (user, group, other)
-    };
-
-    format!("{:03b}{}{}{}", file_type, user, group, other)
-}
-
 #[test]
 fn test_hex_to_num() {
     let expected = hex_to_num(&[0x00, 0x00, 0x02, 0x62]);
@@ -310,9 +371,10 @@ fn test_index_from() {
 }
 
 #[test]
-fn test_num_to_mode() {
-    let val = num_to_mode(33188);
+fn test_mode_from_bits() {
+    let val = FileMode::from_bits(33188).unwrap().to_string();
     assert_eq!(val, String::from("100644"));
+    assert_eq!(FileMode::from_bits(0o120000), Some(FileMode::Symlink));
 }
 
 #[test]
@@ -334,3 +396,68 @@ fn test_entry_as_bytes() {
         assert!(false);
     }
 }
+
+#[test]
+fn test_intent_to_add() {
+    let mut entry = Entry::new(
+        Utc.timestamp_opt(0, 0).unwrap(),
+        Utc.timestamp_opt(0, 0).unwrap(),
+        0,
+        0,
+        FileMode::Blob,
+        0,
+        0,
+        0,
+        vec![0; 20],
+        "new.txt".to_string(),
+    );
+    entry.intent_to_add = true;
+    assert_eq!(entry.as_bytes().len(), entry.size());
+
+    let index = Index::new(vec![entry]);
+    let bytes = index.as_bytes();
+    assert_eq!(&bytes[4..8], &[0, 0, 0, 3]);
+    let parsed = Index::from(&bytes).expect("index should parse");
+    assert!(parsed.entries[0].intent_to_add);
+    assert_eq!(parsed.entries[0].name, "new.txt");
+}
+
+#[test]
+fn test_index_edit() {
+    let entry =
+        |name: &str, hash: u8| Entry::cacheinfo(FileMode::Blob, vec![hash; 20], name.to_string());
+    let names = |index: &Index| {
+        index
+            .entries
+            .iter()
+            .map(|x| x.name.clone())
+            .collect::<Vec<_>>()
+    };
+
+    let mut index = Index::new(Vec::new());
+    index.add(entry("b", 1));
+    index.add(entry("a", 2));
+    // The same content under another name is another entry.
+    index.add(entry("c", 2));
+    assert_eq!(names(&index), vec!["a", "b", "c"]);
+
+    index.add(entry("b", 3));
+    assert_eq!(names(&index), vec!["a", "b", "c"]);
+    assert_eq!(index.get("b").unwrap().hash, vec![3; 20]);
+
+    // A file replaces a directory of the same name, and the other way round.
+    index.add(entry("d/x", 4));
+    index.add(entry("d/y", 4));
+    index.add(entry("d.txt", 4));
+    assert_eq!(names(&index), vec!["a", "b", "c", "d.txt", "d/x", "d/y"]);
+    assert!(index.has_conflict("d") && !index.has_conflict("d.txt"));
+    index.add(entry("d", 5));
+    assert_eq!(names(&index), vec!["a", "b", "c", "d", "d.txt"]);
+    index.add(entry("d/z", 6));
+    assert_eq!(names(&index), vec!["a", "b", "c", "d.txt", "d/z"]);
+
+    assert_eq!(index.remove("b").unwrap().hash, vec![3; 20]);
+    assert!(index.remove("b").is_none());
+    assert!(index.get("b").is_none());
+    assert_eq!(names(&index), vec!["a", "c", "d.txt", "d/z"]);
+}
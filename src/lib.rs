@@ -1,9 +1,42 @@
+pub mod add;
+pub mod branch;
+pub mod bundle;
+pub mod checkout;
+pub mod clone;
+pub mod config;
+pub mod diff;
+pub mod fetch;
 pub mod fs;
+pub mod graph;
+pub mod ident;
+pub mod ignore;
 pub mod index;
+pub mod mv;
 pub mod object;
-
-use chrono::{Local, TimeZone, Utc};
+pub mod pack;
+pub mod pathspec;
+pub mod pretty;
+pub mod protocol;
+pub mod push;
+pub mod quote;
+pub mod receive_pack;
+pub mod reflog;
+pub mod remote;
+pub mod reset;
+pub mod restore;
+pub mod revision;
+pub mod revwalk;
+pub mod rm;
+pub mod tag;
+#[cfg(test)]
+mod test_util;
+pub mod upload_pack;
+pub mod wildmatch;
+
+use chrono::{TimeZone, Utc};
+use config::{Config, ConfigFile, IncludeContext, Level};
 use fs::FileSystem;
+use ident::Role;
 use index::{Entry, Index};
 use index::diff::{diff_index, Diff};
 use libflate::zlib::{Decoder, Encoder};
@@ -11,43 +44,91 @@ use object::blob::Blob;
 use object::commit;
 use object::commit::Commit;
 use object::tree;
-use object::tree::Tree;
-use object::GitObject;
+use object::tree::{FileMode, Tree};
+use object::{GitObject, ObjectType};
+use std::collections::BTreeMap;
 use std::io;
 use std::io::prelude::*;
 
 #[derive(Debug)]
 pub struct Git<F: FileSystem> {
     pub file_system: F,
+    /// Where the repository lives, relative to the worktree or absolute.
+    pub git_dir: String,
+    packs: pack::PackCache,
 }
 
 impl<F: FileSystem> Git<F> {
     pub fn new(file_system: F) -> Self {
-        Self { file_system }
+        Self::with_git_dir(file_system, ".git".to_string())
+    }
+
+    pub fn with_git_dir(file_system: F, git_dir: String) -> Self {
+        Self {
+            file_system,
+            git_dir,
+            packs: pack::PackCache::default(),
+        }
+    }
+
+    /// The path of `path` inside the git directory.
+    pub fn git_path(&self, path: &str) -> String {
+        format!("{}/{}", self.git_dir, path)
     }
 
     pub fn read_index(&self) -> io::Result<Vec<u8>> {
-        self.file_system.read(".git/index".to_string())
+        self.file_system.read(self.git_path("index"))
     }
 
     pub fn write_index(&mut self, index: &Index) -> io::Result<()> {
         self.file_system
-            .write(".git/index".to_string(), &index.as_bytes())
+            .write(self.git_path("index"), &index.as_bytes())
     }
 
+    /// The compressed object, as stored loose. Packed objects are
+    /// compressed the same way, so callers needn't care where they live;
+    /// those after the content want `read_raw_object` instead.
     pub fn read_object(&self, hash: String) -> io::Result<Vec<u8>> {
+        match self.read_loose_object(&hash) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => match self.read_packed_object(&hash) {
+                Ok(Some((object_type, data))) => {
+                    let mut encoder = Encoder::new(Vec::new())?;
+                    write!(encoder, "{} {}\0", object_type, data.len())?;
+                    encoder.write_all(&data)?;
+                    encoder.finish().into_result()
+                }
+                _ => Err(e),
+            },
+            x => x,
+        }
+    }
+
+    fn read_loose_object(&self, hash: &str) -> io::Result<Vec<u8>> {
+        if hash.len() < 2 {
+            return Err(io::Error::from(io::ErrorKind::NotFound));
+        }
         let (sub_dir, file) = hash.split_at(2);
         self.file_system
-            .read(format!(".git/objects/{}/{}", sub_dir, file))
+            .read(self.git_path(&format!("objects/{}/{}", sub_dir, file)))
+    }
+
+    /// Whether the repository has the object `hash`, loose or packed.
+    pub fn has_object(&self, hash: &str) -> bool {
+        let loose = match hash.get(..2) {
+            Some(sub_dir) => format!("objects/{}/{}", sub_dir, &hash[2..]),
+            None => return false,
+        };
+        self.file_system.stat(self.git_path(&loose)).is_ok()
+            || self.has_packed_object(hash).unwrap_or(false)
     }
 
     pub fn write_object(&mut self, object: &GitObject) -> io::Result<()> {
         let hash = hex::encode(object.calc_hash());
         let (sub_dir, file) = hash.split_at(2);
 
-        let path = format!(".git/objects/{}", sub_dir);
+        let path = self.git_path(&format!("objects/{}", sub_dir));
         // ディレクトリがなかったら
-        if let Err(_) = self.file_system.stat(path.clone()) {
+        if self.file_system.stat(path.clone()).is_err() {
             self.file_system.create_dir(path.clone())?;
         }
 
@@ -61,7 +142,7 @@ impl<F: FileSystem> Git<F> {
     }
 
     pub fn head_ref(&self) -> io::Result<String> {
-        let path = ".git/HEAD".to_string();
+        let path = self.git_path("HEAD");
         let file = self.file_system.read(path)?;
         let refs =
             String::from_utf8(file).map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
@@ -75,92 +156,419 @@ impl<F: FileSystem> Git<F> {
         Ok(path.trim().to_string())
     }
 
+    /// Refs that aren't stored loose are looked up in `packed-refs`.
     pub fn read_ref(&self, path: String) -> io::Result<String> {
-        let path = format!(".git/{}", path);
-        let file = self.file_system.read(path)?;
+        let file = match self.file_system.read(self.git_path(&path)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return self.packed_refs()?.remove(&path).ok_or(e);
+            }
+            x => x?,
+        };
         let hash =
             String::from_utf8(file).map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
 
         Ok(hash.trim().to_string())
     }
 
+    /// The refs in `packed-refs`, with the hashes they point to.
+    pub fn packed_refs(&self) -> io::Result<BTreeMap<String, String>> {
+        let mut refs = BTreeMap::new();
+        let bytes = match self.file_system.read(self.git_path("packed-refs")) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(refs),
+            Err(e) => return Err(e),
+        };
+        for line in String::from_utf8_lossy(&bytes).lines() {
+            // Comments, and the peeled values of tags.
+            if line.starts_with('#') || line.starts_with('^') {
+                continue;
+            }
+            if let Some((hash, refname)) = line.split_once(' ') {
+                refs.insert(refname.to_string(), hash.to_string());
+            }
+        }
+        Ok(refs)
+    }
+
+    /// Removes `refname`, loose or packed, and its reflog.
+    pub(crate) fn delete_ref(&mut self, refname: &str) -> io::Result<()> {
+        self.remove_ref_file(refname)?;
+        self.remove_ref_file(&format!("logs/{}", refname))?;
+        self.remove_packed_ref(refname)
+    }
+
+    /// Drops `refname` and its peeled value from `packed-refs`.
+    fn remove_packed_ref(&mut self, refname: &str) -> io::Result<()> {
+        let path = self.git_path("packed-refs");
+        let bytes = match self.file_system.read(path.clone()) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let text = String::from_utf8_lossy(&bytes);
+        let mut kept = String::new();
+        let mut dropping = false;
+        for line in text.lines() {
+            if line.starts_with('^') && dropping {
+                continue;
+            }
+            dropping = line.split_once(' ').map(|x| x.1) == Some(refname);
+            if !dropping {
+                kept.push_str(line);
+                kept.push('\n');
+            }
+        }
+        if kept != text {
+            self.file_system.write(path, kept.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Follows symbolic refs (`ref: ...`) until a hash is found.
+    pub fn resolve_ref(&self, path: String) -> io::Result<String> {
+        let mut path = path;
+        for _ in 0..5 {
+            let value = self.read_ref(path)?;
+            match value.strip_prefix("ref: ") {
+                Some(target) => path = target.trim().to_string(),
+                None => return Ok(value),
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("symbolic ref loop at {}", path),
+        ))
+    }
+
+    /// Lists the loose refs under `prefix` (e.g. `refs/heads`) with the
+    /// hashes they resolve to, sorted by name.
+    pub fn list_refs(&self, prefix: &str) -> io::Result<Vec<(String, String)>> {
+        let names = match self.file_system.read_dir(self.git_path(prefix)) {
+            Ok(names) => names,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut refs = Vec::new();
+        for name in names {
+            let path = format!("{}/{}", prefix, name);
+            match self.file_system.read_dir(self.git_path(&path)) {
+                Ok(_) => refs.extend(self.list_refs(&path)?),
+                Err(_) => refs.push((path.clone(), self.resolve_ref(path)?)),
+            }
+        }
+
+        Ok(refs)
+    }
+
+    /// Every ref of the repository, loose or in `packed-refs`, with the
+    /// loose one winning where there are both.
+    pub fn all_refs(&self) -> io::Result<BTreeMap<String, String>> {
+        let mut refs = self.packed_refs()?;
+        refs.extend(self.list_refs("refs")?);
+        Ok(refs)
+    }
+
+    pub fn write_symref(&mut self, path: String, target: &str) -> io::Result<()> {
+        let path = self.git_path(&path);
+        self.file_system
+            .write(path, format!("ref: {}\n", target).as_bytes())
+    }
+
     pub fn write_ref(&mut self, path: String, hash: &[u8]) -> io::Result<()> {
-        let path = format!(".git/{}", path);
+        let path = self.git_path(&path);
         self.file_system.write(path, hex::encode(hash).as_bytes())
     }
 
+    pub fn config(&self) -> io::Result<Config> {
+        [Level::System, Level::Global, Level::Local]
+            .iter()
+            .try_fold(Config::new(), |mut acc, &level| {
+                acc.entries.extend(self.config_level(level)?.entries);
+                Ok(acc)
+            })
+    }
+
+    pub fn config_level(&self, level: Level) -> io::Result<Config> {
+        let ctx = IncludeContext {
+            git_dir: Some(self.git_dir.clone()),
+            branch: self
+                .head_ref()
+                .ok()
+                .and_then(|x| x.strip_prefix("refs/heads/").map(String::from)),
+        };
+
+        let mut config = Config::new();
+        if level == Level::Global {
+            if let Some(path) = config::xdg_path() {
+                config.load(&self.file_system, &path, level, &ctx)?;
+            }
+        }
+        if let Some(path) = config::level_path(level, &self.git_dir) {
+            config.load(&self.file_system, &path, level, &ctx)?;
+        }
+
+        Ok(config)
+    }
+
+    pub fn config_file(&self, level: Level) -> io::Result<ConfigFile> {
+        let path = config::level_path(level, &self.git_dir).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no {} config file", level),
+            )
+        })?;
+        ConfigFile::open(&self.file_system, path)
+    }
+
+    pub fn write_config_file(&mut self, file: &ConfigFile) -> io::Result<()> {
+        self.file_system.write(file.path.clone(), file.as_bytes())
+    }
+
     pub fn cat_file_p(&self, bytes: &[u8]) -> io::Result<GitObject> {
+        let mut d = Decoder::new(bytes)?;
+        let mut buf = Vec::new();
+        d.read_to_end(&mut buf)?;
+
+        GitObject::parse(&buf)
+    }
+
+    pub fn read_commit(&self, hash: String) -> io::Result<Commit> {
+        self.read_git_object(hash.clone())
+            .and_then(|x| match x {
+                GitObject::Commit(commit) => Ok(commit),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is not a commit", hash),
+                )),
+            })
+    }
+
+    /// The object `hash`, parsed.
+    pub fn read_git_object(&self, hash: String) -> io::Result<GitObject> {
+        let (object_type, data) = self.read_raw_object(hash)?;
+        GitObject::from_raw(object_type, &data)
+    }
+
+    /// The type and content of an object, without parsing the content.
+    pub fn read_raw_object(&self, hash: String) -> io::Result<(ObjectType, Vec<u8>)> {
+        let bytes = match self.read_loose_object(&hash) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return match self.read_packed_object(&hash) {
+                    Ok(Some(object)) => Ok(object),
+                    _ => Err(e),
+                };
+            }
+            x => x?,
+        };
         let mut d = Decoder::new(&bytes[..])?;
         let mut buf = Vec::new();
         d.read_to_end(&mut buf)?;
 
-        GitObject::new(&buf).ok_or(io::Error::from(io::ErrorKind::InvalidData))
+        let pos = buf.iter().position(|&x| x == b'\0');
+        let header = pos.and_then(|pos| std::str::from_utf8(&buf[..pos]).ok());
+        match (pos, header.and_then(ObjectType::from)) {
+            (Some(pos), Some(object_type)) => Ok((object_type, buf.split_off(pos + 1))),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} has a bad object header", hash),
+            )),
+        }
+    }
+
+    /// The content of a blob as raw bytes.
+    pub fn read_blob(&self, hash: String) -> io::Result<Vec<u8>> {
+        match self.read_raw_object(hash.clone())? {
+            (ObjectType::Blob, bytes) => Ok(bytes),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a blob", hash),
+            )),
+        }
+    }
+
+    pub fn read_tree(&self, hash: String) -> io::Result<Tree> {
+        self.read_git_object(hash.clone())
+            .and_then(|x| match x {
+                GitObject::Tree(tree) => Ok(tree),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is not a tree", hash),
+                )),
+            })
+    }
+
+    /// All blobs reachable from a tree, keyed by their full path.
+    pub fn read_tree_recursive(&self, hash: String) -> io::Result<Vec<(String, tree::File)>> {
+        let mut files = Vec::new();
+        self.helper_read_tree_recursive(hash, String::new(), &mut files)?;
+        Ok(files)
+    }
+
+    fn helper_read_tree_recursive(
+        &self,
+        hash: String,
+        prefix: String,
+        files: &mut Vec<(String, tree::File)>,
+    ) -> io::Result<()> {
+        for file in self.read_tree(hash)?.contents {
+            let path = format!("{}{}", prefix, file.name);
+            if file.mode == FileMode::Tree {
+                self.helper_read_tree_recursive(hex::encode(&file.hash), format!("{}/", path), files)?;
+            } else {
+                files.push((path, file));
+            }
+        }
+        Ok(())
     }
 
     pub fn ls_files_stage(&self, bytes: &[u8]) -> io::Result<Index> {
-        Index::from(&bytes).ok_or(io::Error::from(io::ErrorKind::InvalidData))
+        Index::from(bytes).ok_or(io::Error::from(io::ErrorKind::InvalidData))
     }
 
     pub fn hash_object(&self, bytes: &[u8]) -> io::Result<Blob> {
-        let blob = Blob::from(&bytes).ok_or(io::Error::from(io::ErrorKind::InvalidInput))?;
+        let blob = Blob::from(bytes).ok_or(io::Error::from(io::ErrorKind::InvalidInput))?;
         Ok(blob)
     }
 
-    pub fn update_index(&self, idx: Index, hash: &[u8], file_name: String) -> io::Result<Index> {
+    /// An index entry for `file_name` with the worktree file's stat data.
+    pub fn index_entry(&self, file_name: String, hash: &[u8]) -> io::Result<Entry> {
         let metadata = self.file_system.stat(file_name.clone())?;
-        let entry = Entry::new(
-            Utc.timestamp(metadata.ctime as i64, metadata.ctime_nsec),
-            Utc.timestamp(metadata.mtime as i64, metadata.mtime_nsec),
+        let time = |secs: i64, nsecs: u32| {
+            Utc.timestamp_opt(secs, nsecs).single().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: file time out of range", file_name),
+                )
+            })
+        };
+        Ok(Entry::new(
+            time(metadata.ctime as i64, metadata.ctime_nsec)?,
+            time(metadata.mtime as i64, metadata.mtime_nsec)?,
             metadata.dev,
             metadata.ino,
-            metadata.mode,
+            FileMode::from_stat(metadata.mode),
             metadata.uid,
             metadata.gid,
             metadata.size,
             Vec::from(hash),
-            file_name.clone(),
-        );
+            file_name,
+        ))
+    }
 
-        let mut entries: Vec<Entry> = idx 
-            .entries
-            .into_iter()
-            .filter(|x| x.name != entry.name && x.hash != entry.hash)
-            .collect();
-        entries.push(entry);
-        entries.sort_by(|a, b| a.name.cmp(&b.name));
+    /// Adds or replaces the entry for `file_name`, taking the stat data
+    /// from the worktree file.
+    pub fn update_index(
+        &self,
+        mut idx: Index,
+        hash: &[u8],
+        file_name: String,
+    ) -> io::Result<Index> {
+        let entry = self.index_entry(file_name, hash)?;
+        idx.add(entry);
+
+        Ok(idx)
+    }
 
-        Ok(Index::new(entries))
+    /// Updates the stat data of entries whose files are unchanged, so
+    /// later checks don't have to hash them again, and returns the paths
+    /// that do differ from the index.
+    pub fn refresh_index(&self, idx: &mut Index) -> io::Result<Vec<String>> {
+        let mut changed = Vec::new();
+        for entry in idx.entries.iter_mut() {
+            if entry.mode == FileMode::Gitlink || entry.intent_to_add {
+                continue;
+            }
+            match self.file_system.stat(entry.name.clone()) {
+                Ok(_) if self.worktree_changed(entry)? => changed.push(entry.name.clone()),
+                Ok(_) => {
+                    // Keeps a symlink's mode when it is checked out as a file.
+                    let mode = entry.mode;
+                    *entry = self.index_entry(entry.name.clone(), &entry.hash)?;
+                    entry.mode = mode;
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => changed.push(entry.name.clone()),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(changed)
     }
 
-    pub fn write_tree(&self) -> io::Result<Tree> {
+    /// Builds a tree from the index. Subtrees are written as they are
+    /// made; the top tree is returned for the caller to write.
+    /// Intent-to-add entries have no content yet and are left out.
+    pub fn write_tree(&mut self) -> io::Result<Tree> {
         let bytes = self.read_index()?;
         let index = self.ls_files_stage(&bytes)?;
 
-        let contents = index
+        let entries = index
             .entries
             .iter()
-            // TODO: 一旦modeは `100644` で固定
-            .map(|x| tree::File::new(100644, x.name.clone(), &x.hash))
+            .filter(|x| !x.intent_to_add)
+            .map(|x| (x.name.as_str(), x))
             .collect::<Vec<_>>();
 
+        self.helper_write_tree(&entries)
+    }
+
+    /// `entries` are sorted and named relative to the tree being built, so
+    /// the files of each subdirectory come one after another.
+    fn helper_write_tree(&mut self, entries: &[(&str, &Entry)]) -> io::Result<Tree> {
+        let mut contents = Vec::new();
+        let mut rest = entries;
+
+        while let Some(&(name, entry)) = rest.first() {
+            let dir = match name.split_once('/') {
+                Some((dir, _)) => dir,
+                None => {
+                    contents.push(tree::File::new(entry.mode, name.to_string(), &entry.hash));
+                    rest = &rest[1..];
+                    continue;
+                }
+            };
+
+            let prefix = format!("{}/", dir);
+            let sub = rest
+                .iter()
+                .take_while(|(x, _)| x.starts_with(&prefix))
+                .map(|&(x, e)| (&x[prefix.len()..], e))
+                .collect::<Vec<_>>();
+            rest = &rest[sub.len()..];
+
+            let tree = self.helper_write_tree(&sub).map(GitObject::Tree)?;
+            self.write_object(&tree)?;
+            contents.push(tree::File::new(FileMode::Tree, dir.to_string(), &tree.calc_hash()));
+        }
+
         Ok(Tree::new(contents))
     }
 
+    pub fn ident(&self, role: Role) -> io::Result<commit::User> {
+        let config = self.config()?;
+        ident::resolve(role, &config, |key| std::env::var(key).ok())
+    }
+
     pub fn commit_tree(
         &self,
-        name: String,
-        email: String,
+        author: commit::User,
+        committer: commit::User,
         tree_hash: String,
         message: String,
     ) -> io::Result<Commit> {
-        let parent = self.head_ref().and_then(|x| self.read_ref(x)).ok();
-        let offs = {
-            let local = Local::now();
-            *local.offset()
+        // On an unborn branch the commit is a root.
+        let parents = match self.resolve_ref("HEAD".to_string()) {
+            Ok(head) => vec![head],
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
         };
-        let ts = offs.from_utc_datetime(&Utc::now().naive_utc());
-        let author = commit::User::new(name.clone(), email.clone(), ts);
-        let commit = Commit::new(tree_hash, parent, author.clone(), author.clone(), message);
+        let message = if message.ends_with('\n') {
+            message
+        } else {
+            format!("{}\n", message)
+        };
+        let commit = Commit::new(tree_hash, parents, author, committer, message);
 
         Ok(commit)
     }
@@ -170,13 +578,7 @@ impl<F: FileSystem> Git<F> {
     }
 
     pub fn reset_index(&mut self, hash: String) -> io::Result<Vec<Diff>> {
-        let commit = self
-            .read_object(hash)
-            .and_then(|x| self.cat_file_p(&x))
-            .and_then(|x| match x {
-                GitObject::Commit(commit) => Ok(commit),
-                _ => Err(io::Error::from(io::ErrorKind::InvalidData)),
-            })?;
+        let commit = self.read_commit(hash)?;
 
         let prev_index = self.read_index().and_then(|x| self.ls_files_stage(&x))?;
         let next_index = self.tree2index(commit.tree.clone())?;
@@ -184,84 +586,227 @@ impl<F: FileSystem> Git<F> {
         Ok(diff_index(prev_index, next_index))
     }
 
+    /// Applies `diff` to the worktree. Removals go first so that a file can
+    /// replace a directory (and vice versa); missing directories are created
+    /// and directories left empty are removed.
     pub fn diff_apply(&mut self, diff: Vec<Diff>) -> io::Result<()> {
-        diff.iter().try_for_each(|d| match d {
-            Diff::Add(e) => 
-                self.read_object(hex::encode(e.hash.clone()))
-                    .and_then(|x| self.cat_file_p(&x))
-                    .and_then(|x| match x {
-                        GitObject::Blob(blob) => Ok(blob),
-                        _ => Err(io::Error::from(io::ErrorKind::InvalidData)),
-                    })
-                    .and_then(|blob| self.file_system.write(e.name.clone(), blob.content.as_bytes())),
-            Diff::Modify(e, _) =>
-                self.read_object(hex::encode(e.hash.clone()))
-                    .and_then(|x| self.cat_file_p(&x))
-                    .and_then(|x| match x {
-                        GitObject::Blob(blob) => Ok(blob),
-                        _ => Err(io::Error::from(io::ErrorKind::InvalidData)),
-                    })
-                    .and_then(|blob| self.file_system.write(e.name.clone(), blob.content.as_bytes())),
-            Diff::Rename(n, p) => self.file_system.rename(p.name.clone(), n.name.clone()),
-            Diff::Remove(e) => self.file_system.remove(e.name.clone()),
+        let (removes, writes): (Vec<_>, Vec<_>) =
+            diff.into_iter().partition(|x| matches!(x, Diff::Remove(_)));
+        let symlinks = self.config()?.get_bool("core.symlinks")?.unwrap_or(true);
+
+        removes.iter().chain(writes.iter()).try_for_each(|d| match d {
+            Diff::Add(e) | Diff::Modify(e, _) => {
+                let bytes = self.read_blob(hex::encode(&e.hash))?;
+                self.prepare_path(&e.name)?;
+                self.write_worktree(&e.name, e.mode, &bytes, symlinks)
+            }
+            Diff::Rename(n, p) => {
+                self.prepare_path(&n.name)?;
+                self.file_system.rename(p.name.clone(), n.name.clone())?;
+                self.prune_empty_dirs(&p.name)
+            }
+            Diff::Remove(e) => {
+                match self.file_system.remove(e.name.clone()) {
+                    Err(x) if x.kind() == io::ErrorKind::NotFound => {}
+                    x => x?,
+                }
+                self.prune_empty_dirs(&e.name)
+            }
             Diff::None => Ok(()),
         })
     }
 
+    /// Writes a blob out as a file of the given mode. Symlinks are
+    /// created from the blob's text, or written as a plain file holding it
+    /// without `symlinks` (`core.symlinks=false`).
+    fn write_worktree(
+        &mut self,
+        path: &str,
+        mode: FileMode,
+        bytes: &[u8],
+        symlinks: bool,
+    ) -> io::Result<()> {
+        if mode == FileMode::Symlink && symlinks {
+            let target = String::from_utf8(bytes.to_vec())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            return self.file_system.symlink(target, path.to_string());
+        }
+
+        self.file_system.write(path.to_string(), bytes)?;
+        let perm = if mode == FileMode::BlobExecutable { 0o755 } else { 0o644 };
+        self.file_system.set_permissions(path.to_string(), perm)
+    }
+
+    /// The content git hashes for a worktree path: the file's bytes, or the
+    /// target of a symlink.
+    pub fn read_worktree(&self, path: &str) -> io::Result<Vec<u8>> {
+        if self.file_system.stat(path.to_string())?.is_symlink() {
+            Ok(self.file_system.read_link(path.to_string())?.into_bytes())
+        } else {
+            self.file_system.read(path.to_string())
+        }
+    }
+
+    /// Makes room for a file at `path`: creates its parent directories,
+    /// replacing files that are in the way, and removes whatever is at
+    /// `path` itself so a symlink is never written through.
+    fn prepare_path(&mut self, path: &str) -> io::Result<()> {
+        let components = path.split('/').collect::<Vec<_>>();
+        for i in 1..components.len() {
+            let dir = components[..i].join("/");
+            match self.file_system.stat(dir.clone()) {
+                Ok(metadata) if metadata.is_dir() => continue,
+                Ok(_) => self.file_system.remove(dir.clone())?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            self.file_system.create_dir(dir)?;
+        }
+
+        match self.file_system.stat(path.to_string()) {
+            Ok(metadata) if metadata.is_dir() => self.file_system.remove_dir(path.to_string()),
+            Ok(_) => self.file_system.remove(path.to_string()),
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Removes the directories above `path` that have become empty.
+    fn prune_empty_dirs(&mut self, path: &str) -> io::Result<()> {
+        let mut dir = path;
+        while let Some(pos) = dir.rfind('/') {
+            dir = &dir[..pos];
+            match self.file_system.read_dir(dir.to_string()) {
+                Ok(names) if names.is_empty() => self.file_system.remove_dir(dir.to_string())?,
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// All files below `dir` in the worktree (`""` for the top), skipping
+    /// `.git`.
+    pub fn worktree_files(&self, dir: &str) -> io::Result<Vec<String>> {
+        let mut files = Vec::new();
+        for name in self.file_system.read_dir(dir.to_string())? {
+            let path = if dir.is_empty() {
+                name
+            } else {
+                format!("{}/{}", dir, name)
+            };
+            if path == ".git" {
+                continue;
+            }
+
+            if self.file_system.stat(path.clone())?.is_dir() {
+                files.extend(self.worktree_files(&path)?);
+            } else {
+                files.push(path);
+            }
+        }
+        Ok(files)
+    }
+
     pub fn tree2index(&mut self, hash: String) -> io::Result<Index>
     {
         let idx = Index::new(Vec::new());
         self.helper_tree2index(idx, hash, String::new())
     }
 
-    fn helper_tree2index(&mut self, idx: Index, hash: String, name: String) -> io::Result<Index> {
-        let obj = self
-            .read_object(hash.clone())
-            .and_then(|x| self.cat_file_p(&x))?;
-
-        match obj {
-            GitObject::Blob(blob) => {
-                let meta = self.file_system.stat(name.clone()).unwrap_or(fs::Metadata {
-                    dev: 0,
-                    ino: 0,
-                    mode: 33188,
-                    uid: 0,
-                    gid: 0,
-                    size: 0,
-                    mtime: 0,
-                    mtime_nsec: 0,
-                    ctime: 0,
-                    ctime_nsec: 0,
-                });
-
-                let entry = Entry::new(
-                    Utc.timestamp(meta.ctime as i64, meta.ctime_nsec),
-                    Utc.timestamp(meta.mtime as i64, meta.mtime_nsec),
-                    meta.dev,
-                    meta.ino,
-                    meta.mode,
-                    meta.uid,
-                    meta.gid,
-                    meta.size,
-                    blob.calc_hash(),
-                    name
-                );
-
-                let mut entries: Vec<Entry> = idx 
-                    .entries
-                    .into_iter()
-                    .filter(|x| x.name != entry.name || x.hash != entry.hash)
-                    .collect();
-                entries.push(entry);
-                entries.sort_by(|a, b| a.name.cmp(&b.name));
-
-                Ok(Index::new(entries))
-            },
-            GitObject::Tree(tree) => tree.contents.iter().try_fold(idx, |acc, x| {
-                self.helper_tree2index(acc, hex::encode(&x.hash), format!("{}{}{}", name, if name.is_empty() { "" } else { "/" }, x.name.clone()))
-            }),
-            _ => Err(io::Error::from(io::ErrorKind::InvalidData)),
+    /// The entries carry the tree's modes but no stat data, as the
+    /// worktree needn't hold their content.
+    fn helper_tree2index(&mut self, mut idx: Index, hash: String, name: String) -> io::Result<Index> {
+        for file in self.read_tree(hash)?.contents {
+            let path = format!("{}{}{}", name, if name.is_empty() { "" } else { "/" }, file.name);
+            if file.mode == FileMode::Tree {
+                idx = self.helper_tree2index(idx, hex::encode(&file.hash), path)?;
+            } else {
+                idx.add(Entry::cacheinfo(file.mode, file.hash, path));
+            }
         }
+
+        Ok(idx)
+    }
+}
+
+
+#[test]
+fn test_update_index() {
+    use fs::inmem::InMemFileSystem;
+
+    let mut git = Git::new(InMemFileSystem::init());
+    for (name, data) in [("a", "same\n"), ("b", "same\n"), ("c", "other\n")] {
+        git.file_system
+            .write(name.to_string(), data.as_bytes())
+            .unwrap();
     }
+    let hash = |data: &str| object::blob::hash_bytes(data.as_bytes());
+
+    // Staging one file after another keeps the earlier ones, whatever
+    // their content.
+    let mut index = Index::new(Vec::new());
+    for (name, data) in [
+        ("b", "same\n"),
+        ("c", "other\n"),
+        ("a", "same\n"),
+        ("b", "same\n"),
+    ] {
+        index = git
+            .update_index(index, &hash(data), name.to_string())
+            .unwrap();
+    }
+    let names = |index: &Index| {
+        index
+            .entries
+            .iter()
+            .map(|x| x.name.clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names(&index), vec!["a", "b", "c"]);
+    assert_eq!(index.get("a").unwrap().hash, index.get("b").unwrap().hash);
+
+    git.file_system
+        .write("c".to_string(), b"changed\n")
+        .unwrap();
+    index.add(Entry::cacheinfo(
+        FileMode::Blob,
+        hash("same\n"),
+        "a".to_string(),
+    ));
+    index.add(Entry::cacheinfo(
+        FileMode::Blob,
+        hash("gone\n"),
+        "d".to_string(),
+    ));
+    assert_eq!(index.get("a").unwrap().size, 0);
+
+    let changed = git.refresh_index(&mut index).unwrap();
+    assert_eq!(changed, vec!["c", "d"]);
+    assert_eq!(index.get("a").unwrap().size, 5);
+    assert_eq!(index.get("c").unwrap().hash, hash("other\n"));
+
+    index.remove("d");
+    assert_eq!(names(&index), vec!["a", "b", "c"]);
 }
 
+#[test]
+fn test_commit_tree() {
+    use fs::inmem::InMemFileSystem;
+
+    let mut git = Git::new(InMemFileSystem::init());
+    let user = test_util::user_at(13);
+    let commit = |git: &Git<_>| {
+        git.commit_tree(user.clone(), user.clone(), "t".repeat(40), "m".to_string())
+    };
+
+    // An unborn branch gives a root commit.
+    assert!(commit(&git).unwrap().parents.is_empty());
+
+    git.write_branch("refs/heads/master", &"1".repeat(40)).unwrap();
+    assert_eq!(commit(&git).unwrap().parents, vec!["1".repeat(40)]);
+
+    // A HEAD that can't be read isn't taken for an unborn branch.
+    git.file_system
+        .write(".git/HEAD".to_string(), b"ref: HEAD")
+        .unwrap();
+    assert_eq!(commit(&git).unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
@@ -1,84 +1,2641 @@
-use clumsy::fs::inmem::InMemFileSystem;
+use clumsy::add::{AddOptions, Staged};
+use clumsy::branch::is_valid_branch_name;
+use clumsy::bundle::Bundle;
+use clumsy::clone::{CloneOptions, ClonedHead};
+use clumsy::config::{self, Config, ConfigFile, Level};
+use clumsy::fetch::FetchOptions;
 use clumsy::fs::mac::MacOSFileSystem;
 use clumsy::fs::FileSystem;
-use clumsy::object::GitObject;
+use clumsy::graph::Graph;
+use clumsy::index::{self, Index};
+use clumsy::mv::MvOptions;
+use clumsy::object::tree::{self, FileMode, Tree};
+use clumsy::object::{GitObject, ObjectType};
+use clumsy::pathspec::Pathspec;
+use clumsy::pretty::{DateFormat, Format, Pretty};
+use clumsy::protocol::{Connection, PushConnection};
+use clumsy::push::{Lease, PushOptions};
+use clumsy::quote::quote_path;
+use clumsy::remote::{RefUpdate, Refspec, Remote, UpdateStatus};
+use clumsy::reset::ResetMode;
+use clumsy::restore::RestoreOptions;
+use clumsy::revwalk::{RevWalk, Sort};
+use clumsy::rm::{RmOptions, Unsafe};
+use clumsy::tag::version_cmp;
 use clumsy::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::io;
+use std::io::IsTerminal;
 
-use libflate::zlib::{Decoder, Encoder};
 use std::fs::File;
 use std::io::prelude::*;
 
-fn main() -> io::Result<()> {
-    let args: Vec<String> = std::env::args().collect();
-    let fs = MacOSFileSystem::init()?;
-    let mut git = Git::new(fs);
+/// Shows the command's usage along with the message, and exits with 129.
+#[derive(Debug)]
+struct Usage(String);
 
-    let sub_cmd = args.get(1).unwrap().clone();
-    match sub_cmd.as_str() {
-        "cat-file" => {
-            let obj = cat_file_p(args.get(2).unwrap().clone())?;
-            println!("{}", obj);
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Usage {}
+
+/// Ends the command quietly with the given exit code, e.g. 1 when
+/// `cat-file -e` finds no object.
+#[derive(Debug)]
+struct Exit(i32);
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "exit status {}", self.0)
+    }
+}
+
+impl std::error::Error for Exit {}
+
+fn usage_error(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, Usage(msg.into()))
+}
+
+fn exit(code: i32) -> io::Error {
+    io::Error::other(Exit(code))
+}
+
+/// A command's arguments, taken one at a time as git's option parser
+/// does: `--name=value` is the option `--name` with its value inline,
+/// and an option without one takes the argument after it. Short flags
+/// may be bundled, `-qm msg` as `-q -m msg`, and one that takes a value
+/// takes the rest of the bundle if there is any, `-mmsg` as `-m msg`.
+struct Args<'a> {
+    iter: std::slice::Iter<'a, String>,
+    flag: String,
+    inline: Option<&'a str>,
+    /// What is left of a bundle of short flags, `m` after `-q` in `-qm`.
+    bundle: &'a str,
+}
+
+impl<'a> Args<'a> {
+    fn new(args: &'a [String]) -> Self {
+        Args {
+            iter: args.iter(),
+            flag: String::new(),
+            inline: None,
+            bundle: "",
+        }
+    }
+
+    /// The value of the option last taken.
+    fn value(&mut self) -> io::Result<String> {
+        match self.inline() {
+            Some(value) => Ok(value.to_string()),
+            None => self
+                .iter
+                .next()
+                .cloned()
+                .ok_or_else(|| usage_error(format!("option '{}' requires a value", self.flag))),
+        }
+    }
+
+    /// The value of the option last taken if it was given inline, for
+    /// options whose value is optional.
+    fn inline(&mut self) -> Option<&'a str> {
+        match std::mem::take(&mut self.bundle) {
+            "" => self.inline.take(),
+            bundle => Some(bundle),
+        }
+    }
+
+    /// The arguments not taken yet, e.g. those after `--`.
+    fn rest(&mut self) -> std::iter::Cloned<&mut std::slice::Iter<'a, String>> {
+        self.iter.by_ref().cloned()
+    }
+}
+
+impl Iterator for Args<'_> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        self.inline = None;
+        if let Some(c) = self.bundle.chars().next() {
+            self.bundle = &self.bundle[c.len_utf8()..];
+            self.flag = format!("-{}", c);
+            return Some(self.flag.clone());
+        }
+
+        let arg = self.iter.next()?;
+        self.flag = match arg.find('=') {
+            Some(pos) if arg.starts_with("--") => {
+                self.inline = Some(&arg[pos + 1..]);
+                arg[..pos].to_string()
+            }
+            // `-3` is a number, as in `log -3`, not three flags.
+            _ if arg.starts_with('-')
+                && !arg.starts_with("--")
+                && !arg[1..].bytes().all(|x| x.is_ascii_digit()) =>
+            {
+                let c = arg[1..].chars().next().unwrap();
+                self.bundle = &arg[1 + c.len_utf8()..];
+                format!("-{}", c)
+            }
+            _ => arg.clone(),
+        };
+        Some(self.flag.clone())
+    }
+}
+
+type Run = fn(&mut Git<MacOSFileSystem>, &[String]) -> io::Result<()>;
+
+struct Command {
+    name: &'static str,
+    usage: &'static str,
+    /// Whether the command fails outside of a repository.
+    needs_repo: bool,
+    run: Run,
+}
+
+const COMMANDS: &[Command] = &[
+    Command {
+        name: "add",
+        usage: "clumsy add [-n] [-v] [-f] [-N] [-A | -u] [--] [<pathspec>...]",
+        needs_repo: true,
+        run: |git, args| add(git, args),
+    },
+    Command {
+        name: "branch",
+        usage: "clumsy branch [-v [-v]] [-a | -r] [--list] [--show-current]\n   \
+                or: clumsy branch [-f] <branch-name> [<start-point>]\n   \
+                or: clumsy branch (-d | -D) <branch-name>...\n   \
+                or: clumsy branch (-m | -M | -c | -C) [<old-branch>] <new-branch>\n   \
+                or: clumsy branch (-u <upstream> | --set-upstream-to=<upstream>) [<branch-name>]\n   \
+                or: clumsy branch --unset-upstream [<branch-name>]",
+        needs_repo: true,
+        run: |git, args| branch(git, args),
+    },
+    Command {
+        name: "bundle",
+        usage: "clumsy bundle create [-q] [--version=<version>] <file> <rev-list-args>...\n   \
+                or: clumsy bundle verify [-q] <file>\n   \
+                or: clumsy bundle list-heads <file> [<refname>...]\n   \
+                or: clumsy bundle unbundle <file> [<refname>...]",
+        needs_repo: true,
+        run: |git, args| bundle(git, args),
+    },
+    Command {
+        name: "cat-file",
+        usage: "clumsy cat-file (-t | -s | -e | -p | <type>) <object>\n   \
+                or: clumsy cat-file (--batch | --batch-check)",
+        needs_repo: true,
+        run: |git, args| cat_file(git, args),
+    },
+    Command {
+        name: "checkout",
+        usage: "clumsy checkout [-f] [-b <branch> | -B <branch> | --detach] <branch | commit>\n   \
+                or: clumsy checkout [<tree-ish>] [--] <pathspec>...",
+        needs_repo: true,
+        run: |git, args| switch(git, args, true),
+    },
+    Command {
+        name: "clone",
+        usage: "clumsy clone [--bare] [-b <branch>] [--no-hardlinks] [-q] [--] <repo> [<dir>]",
+        needs_repo: false,
+        run: |_, args| clone(args),
+    },
+    Command {
+        name: "commit",
+        usage: "clumsy commit [-q] [-a] (-m <msg> | -F <file>) [--author <author>] [--date <date>]",
+        needs_repo: true,
+        run: |git, args| commit(git, args),
+    },
+    Command {
+        name: "config",
+        usage: "clumsy config [<file-option>] [--type <type>] <name> [<value>]\n   \
+                or: clumsy config [<file-option>] (--get | --get-all | --unset | --unset-all) <name>\n   \
+                or: clumsy config [<file-option>] (--add | --replace-all) <name> <value>\n   \
+                or: clumsy config [<file-option>] --remove-section <name>\n   \
+                or: clumsy config [<file-option>] --rename-section <old-name> <new-name>\n   \
+                or: clumsy config [<file-option>] (-l | --list)",
+        needs_repo: false,
+        run: |git, args| config(git, args),
+    },
+    Command {
+        name: "fetch",
+        usage: "clumsy fetch [-f] [-u] [-n] [-q] [--upload-pack=<exec>] [<repository> [<refspec>...]]",
+        needs_repo: true,
+        run: |git, args| fetch(git, args),
+    },
+    Command {
+        name: "fetch-pack",
+        usage: "clumsy fetch-pack [--all] [-q] [--upload-pack=<exec>] <directory> [<refs>...]",
+        needs_repo: true,
+        run: |git, args| fetch_pack(git, args),
+    },
+    Command {
+        name: "hash-object",
+        usage: "clumsy hash-object <file>",
+        needs_repo: false,
+        run: |_, args| match args {
+            [path] => {
+                let blob = hash_object(path.clone())?;
+                println!("{}", hex::encode(blob.calc_hash()));
+                Ok(())
+            }
+            _ => Err(usage_error("exactly one file expected")),
+        },
+    },
+    Command {
+        name: "log",
+        usage: "clumsy log [<options>] [<revision-range>] [[--] <path>...]",
+        needs_repo: true,
+        run: |git, args| log(git, args),
+    },
+    Command {
+        name: "ls-files",
+        usage: "clumsy ls-files [-c] [-s] [-m] [-o] [-d] [--exclude-standard]",
+        needs_repo: true,
+        run: |git, args| ls_files(git, args),
+    },
+    Command {
+        name: "ls-tree",
+        usage: "clumsy ls-tree [-r] [-t] [--name-only] <tree-ish> [<path>...]",
+        needs_repo: true,
+        run: |git, args| ls_tree(git, args),
+    },
+    Command {
+        name: "mv",
+        usage: "clumsy mv [-v] [-n] [-f] [-k] <source>... <destination>",
+        needs_repo: true,
+        run: |git, args| mv(git, args),
+    },
+    Command {
+        name: "push",
+        usage: "clumsy push [-f] [--force-with-lease[=<refname>[:<expect>]]] [--atomic] [-u] [-d] [-q]\n                   \
+                [--receive-pack=<exec>] [<repository> [<refspec>...]]",
+        needs_repo: true,
+        run: |git, args| push(git, args),
+    },
+    Command {
+        name: "receive-pack",
+        usage: "clumsy receive-pack <directory>",
+        needs_repo: false,
+        run: |_, args| receive_pack(args),
+    },
+    Command {
+        name: "reset",
+        usage: "clumsy reset [--soft | --mixed | --hard | --keep | --merge] [-q] [<commit>]\n   \
+                or: clumsy reset [-q] [<tree-ish>] [--] <pathspec>...",
+        needs_repo: true,
+        run: |git, args| reset(git, args),
+    },
+    Command {
+        name: "restore",
+        usage: "clumsy restore [--staged] [--worktree] [--source=<tree-ish>] [--overlay] [--] <pathspec>...",
+        needs_repo: true,
+        run: |git, args| restore(git, args),
+    },
+    Command {
+        name: "rm",
+        usage: "clumsy rm [-f] [-n] [-q] [-r] [--cached] [--ignore-unmatch] [--] <pathspec>...",
+        needs_repo: true,
+        run: |git, args| rm(git, args),
+    },
+    Command {
+        name: "show",
+        usage: "clumsy show [--stat] [--name-only] [-s] [-c | --cc] [--format=<format>] [<object>...]",
+        needs_repo: true,
+        run: |git, args| show(git, args),
+    },
+    Command {
+        name: "switch",
+        usage: "clumsy switch [-f] [-c <branch> | -C <branch> | --detach] <branch | commit>",
+        needs_repo: true,
+        run: |git, args| switch(git, args, false),
+    },
+    Command {
+        name: "tag",
+        usage: "clumsy tag [-a] [-f] [-m <msg>] <tagname> [<commit> | <object>]\n   \
+                or: clumsy tag -d <tagname>...\n   \
+                or: clumsy tag [-n[<num>]] -l [--sort=<key>] [<pattern>...]\n   \
+                or: clumsy tag -v <tagname>...",
+        needs_repo: true,
+        run: |git, args| tag(git, args),
+    },
+    Command {
+        name: "update-index",
+        usage: "clumsy update-index [--add] [--remove] [--force-remove] [--replace] [--refresh]\n       \
+                [--cacheinfo <mode>,<object>,<path>] [--] [<file>...]",
+        needs_repo: true,
+        run: |git, args| update_index(git, args),
+    },
+    Command {
+        name: "upload-pack",
+        usage: "clumsy upload-pack [--strict] [--timeout=<n>] <directory>",
+        needs_repo: false,
+        run: |_, args| upload_pack(args),
+    },
+];
+
+const USAGE: &str =
+    "usage: clumsy [-C <path>] [--git-dir=<path>] [--version] [--help] <command> [<args>]";
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let code = match run(&args) {
+        Ok(code) => code,
+        Err(e) => report(&e),
+    };
+    std::process::exit(code)
+}
+
+/// Prints `e` the way git would and picks the matching exit code.
+fn report(e: &io::Error) -> i32 {
+    if let Some(Exit(code)) = e.get_ref().and_then(|x| x.downcast_ref::<Exit>()) {
+        return *code;
+    }
+    if e.kind() == io::ErrorKind::BrokenPipe {
+        return 0;
+    }
+    eprintln!("fatal: {}", e);
+    128
+}
+
+fn run(args: &[String]) -> io::Result<i32> {
+    let mut git_dir = std::env::var("GIT_DIR").unwrap_or_else(|_| ".git".to_string());
+
+    let mut iter = args.iter();
+    let name = loop {
+        let arg = match iter.next() {
+            Some(arg) => arg,
+            None => {
+                print_help(&mut io::stderr())?;
+                return Ok(1);
+            }
+        };
+        let value = |iter: &mut std::slice::Iter<String>| {
+            iter.next()
+                .cloned()
+                .ok_or_else(|| io::Error::other(format!("no directory given for '{}' option", arg)))
+        };
+
+        match arg.as_str() {
+            "-C" => {
+                let dir = value(&mut iter)?;
+                if !dir.is_empty() {
+                    std::env::set_current_dir(&dir).map_err(|e| {
+                        io::Error::new(e.kind(), format!("cannot change to '{}': {}", dir, e))
+                    })?;
+                }
+            }
+            "--git-dir" => git_dir = value(&mut iter)?,
+            x if x.starts_with("--git-dir=") => git_dir = x["--git-dir=".len()..].to_string(),
+            "-h" | "--help" => {
+                print_help(&mut io::stdout())?;
+                return Ok(0);
+            }
+            "--version" => {
+                println!("clumsy version {}", env!("CARGO_PKG_VERSION"));
+                return Ok(0);
+            }
+            "help" => {
+                return match iter.next() {
+                    Some(name) => {
+                        let command = find_command(name)?;
+                        println!("usage: {}", command.usage);
+                        Ok(0)
+                    }
+                    None => print_help(&mut io::stdout()).map(|_| 0),
+                };
+            }
+            x if x.starts_with('-') => {
+                eprintln!("unknown option: {}\n{}", x, USAGE);
+                return Ok(129);
+            }
+            _ => break arg,
+        }
+    };
+
+    let command = match find_command(name) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("clumsy: {}", e);
+            return Ok(1);
+        }
+    };
+    let args = iter.cloned().collect::<Vec<_>>();
+    match args.first().map(|x| x.as_str()) {
+        Some("-h") => {
+            println!("usage: {}", command.usage);
+            return Ok(129);
+        }
+        Some("--help") => {
+            println!("usage: {}", command.usage);
+            return Ok(0);
+        }
+        _ => {}
+    }
+
+    // The repository is looked for here only, not in parent directories.
+    let mut git = Git::with_git_dir(MacOSFileSystem::init()?, git_dir);
+    if command.needs_repo && git.file_system.stat(git.git_path("HEAD")).is_err() {
+        return Err(io::Error::other(format!(
+            "not a git repository: {}",
+            git.git_dir
+        )));
+    }
+
+    match (command.run)(&mut git, &args) {
+        Err(e) if e.get_ref().is_some_and(|x| x.is::<Usage>()) => {
+            eprintln!("error: {}\n\nusage: {}", e, command.usage);
+            Ok(129)
+        }
+        x => x.map(|_| 0),
+    }
+}
+
+fn find_command(name: &str) -> io::Result<&'static Command> {
+    COMMANDS.iter().find(|x| x.name == name).ok_or_else(|| {
+        io::Error::other(format!(
+            "'{}' is not a clumsy command. See 'clumsy --help'.",
+            name
+        ))
+    })
+}
+
+fn print_help(out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "{}\n\nThese are the available commands:", USAGE)?;
+    for command in COMMANDS {
+        writeln!(out, "   {}", command.name)?;
+    }
+    writeln!(out, "\nSee 'clumsy help <command>' for its usage.")
+}
+
+#[derive(PartialEq, Eq)]
+enum BranchAction {
+    List,
+    Delete,
+    Move,
+    Copy,
+    SetUpstream(String),
+    UnsetUpstream,
+    ShowCurrent,
+}
+
+fn branch<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<()> {
+    let mut action = None;
+    let mut verbose = 0;
+    let mut all = false;
+    let mut remotes = false;
+    let mut force = false;
+    let mut quiet = false;
+    let mut rest = Vec::new();
+
+    let mut args = Args::new(args);
+    while let Some(flag) = args.next() {
+        let next = match flag.as_str() {
+            "-v" | "--verbose" => {
+                verbose += 1;
+                continue;
+            }
+            "-a" | "--all" => {
+                all = true;
+                continue;
+            }
+            "-r" | "--remotes" => {
+                remotes = true;
+                continue;
+            }
+            "-f" | "--force" => {
+                force = true;
+                continue;
+            }
+            "-q" | "--quiet" => {
+                quiet = true;
+                continue;
+            }
+            "-l" | "--list" => BranchAction::List,
+            "-d" | "--delete" => BranchAction::Delete,
+            "-m" | "--move" => BranchAction::Move,
+            "-c" | "--copy" => BranchAction::Copy,
+            "-D" | "-M" | "-C" => {
+                force = true;
+                match flag.as_str() {
+                    "-D" => BranchAction::Delete,
+                    "-M" => BranchAction::Move,
+                    _ => BranchAction::Copy,
+                }
+            }
+            "-u" | "--set-upstream-to" => BranchAction::SetUpstream(args.value()?),
+            "--unset-upstream" => BranchAction::UnsetUpstream,
+            "--show-current" => BranchAction::ShowCurrent,
+            "--" => {
+                rest.extend(args.rest());
+                break;
+            }
+            x if x.starts_with('-') => return Err(usage_error(format!("unknown option '{}'", x))),
+            _ => {
+                rest.push(flag);
+                continue;
+            }
+        };
+        if action.as_ref().is_some_and(|x| *x != next) {
+            return Err(usage_error("options are incompatible"));
+        }
+        action = Some(next);
+    }
+
+    let current = git
+        .head_ref()
+        .ok()
+        .and_then(|x| x.strip_prefix("refs/heads/").map(String::from));
+    let current_or = |name: Option<&String>, what: &str| -> io::Result<String> {
+        match (name, &current) {
+            (Some(name), _) => Ok(name.clone()),
+            (None, Some(current)) => Ok(current.clone()),
+            (None, None) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("could not {}: HEAD is detached", what),
+            )),
+        }
+    };
+
+    match action {
+        None if !rest.is_empty() => {
+            if rest.len() > 2 {
+                return Err(usage_error("too many arguments"));
+            }
+            let start = rest.get(1).map_or("HEAD", |x| x.as_str());
+            let commit = git
+                .rev_parse(start)
+                .and_then(|x| git.peel(x, "commit"))
+                .map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("not a valid object name: '{}'", start),
+                    )
+                })?;
+            // The log names the branch a new one starts from, if any.
+            let start = match (start, &current) {
+                ("HEAD", Some(current)) => current.as_str(),
+                _ => start,
+            };
+            git.create_branch(&rest[0], &commit, start, force)
+        }
+        None | Some(BranchAction::List) => list_branches(git, verbose, all, remotes),
+        Some(BranchAction::ShowCurrent) => {
+            if let Some(current) = &current {
+                println!("{}", current);
+            }
+            Ok(())
+        }
+        Some(BranchAction::Delete) => {
+            if rest.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "branch name required",
+                ));
+            }
+            let mut failed = false;
+            for name in &rest {
+                match git.delete_branch(name, force) {
+                    Ok(hash) if !quiet => {
+                        println!("Deleted branch {} (was {}).", name, pretty::abbrev(&hash))
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        eprintln!("error: {}", e);
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                return Err(exit(1));
+            }
+            Ok(())
+        }
+        Some(action @ (BranchAction::Move | BranchAction::Copy)) => {
+            let (old, new) = match rest.as_slice() {
+                [new] => (current_or(None, "rename the current branch")?, new),
+                [old, new] => (old.clone(), new),
+                _ => return Err(usage_error("branch name required")),
+            };
+            if !is_valid_branch_name(new) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("'{}' is not a valid branch name", new),
+                ));
+            }
+            git.rename_branch(&old, new, force, action == BranchAction::Copy)
+        }
+        Some(BranchAction::SetUpstream(upstream)) => {
+            if rest.len() > 1 {
+                return Err(usage_error("too many arguments to set new upstream"));
+            }
+            let name = current_or(rest.first(), "set upstream")?;
+            let refname = git.set_upstream(&name, &upstream)?;
+            if !quiet {
+                println!(
+                    "branch '{}' set up to track '{}'.",
+                    name,
+                    short_ref_name(&refname)
+                );
+            }
+            Ok(())
+        }
+        Some(BranchAction::UnsetUpstream) => {
+            if rest.len() > 1 {
+                return Err(usage_error("too many arguments to unset upstream"));
+            }
+            let name = current_or(rest.first(), "unset upstream")?;
+            git.unset_upstream(&name)
+        }
+    }
+}
+
+/// `refs/heads/x` as `x` and `refs/remotes/origin/x` as `origin/x`.
+fn short_ref_name(refname: &str) -> &str {
+    ["refs/heads/", "refs/remotes/", "refs/tags/", "refs/"]
+        .iter()
+        .find_map(|x| refname.strip_prefix(x))
+        .unwrap_or(refname)
+}
+
+fn list_branches<F: FileSystem>(
+    git: &Git<F>,
+    verbose: usize,
+    all: bool,
+    remotes: bool,
+) -> io::Result<()> {
+    let head = git.head_ref().ok();
+
+    // Label, ref name and hash of each line, plus the target of a symref.
+    let mut items = Vec::new();
+    if head.is_none() {
+        if let Ok(hash) = git.resolve_ref("HEAD".to_string()) {
+            let label = format!("(HEAD detached at {})", pretty::abbrev(&hash));
+            items.push((label, "HEAD".to_string(), hash, None));
+        }
+    }
+    let refs = git.all_refs()?.into_iter().filter(|(refname, _)| {
+        (!remotes && refname.starts_with("refs/heads/"))
+            || ((all || remotes) && refname.starts_with("refs/remotes/"))
+    });
+    for (refname, hash) in refs {
+        let short = short_ref_name(&refname);
+        let label = if all && refname.starts_with("refs/remotes/") {
+            format!("remotes/{}", short)
+        } else {
+            short.to_string()
+        };
+        let target = git
+            .read_ref(refname.clone())?
+            .strip_prefix("ref: ")
+            .map(|x| short_ref_name(x.trim()).to_string());
+        items.push((label, refname, hash, target));
+    }
+
+    let width = items.iter().map(|x| x.0.len()).max().unwrap_or(0);
+    let mut out = io::stdout().lock();
+    for (label, refname, hash, target) in &items {
+        let current = head.as_deref() == Some(refname.as_str()) || refname == "HEAD";
+        let marker = if current { '*' } else { ' ' };
+        match (target, verbose) {
+            (Some(target), 0) => writeln!(out, "{} {} -> {}", marker, label, target)?,
+            (Some(target), _) => writeln!(
+                out,
+                "{} {:<width$} -> {}",
+                marker,
+                label,
+                target,
+                width = width
+            )?,
+            (None, 0) => writeln!(out, "{} {}", marker, label)?,
+            (None, _) => {
+                let tracking = match refname.strip_prefix("refs/heads/") {
+                    Some(name) => tracking_info(git, name, hash, verbose > 1)?,
+                    None => String::new(),
+                };
+                let subject = pretty::subject(&git.read_commit(hash.clone())?.message);
+                writeln!(
+                    out,
+                    "{} {:<width$} {} {}{}",
+                    marker,
+                    label,
+                    pretty::abbrev(hash),
+                    tracking,
+                    subject,
+                    width = width
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The `[ahead 1, behind 2] ` part of `branch -v`; with `name` the
+/// upstream is named too, as `-vv` does.
+fn tracking_info<F: FileSystem>(
+    git: &Git<F>,
+    branch: &str,
+    hash: &str,
+    name: bool,
+) -> io::Result<String> {
+    let upstream = match git.upstream(branch)? {
+        Some(upstream) => upstream,
+        None => return Ok(String::new()),
+    };
+    let counts = match git.resolve_ref(upstream.clone()) {
+        Ok(theirs) => match git.ahead_behind(hash, &theirs)? {
+            (0, 0) => None,
+            (ahead, 0) => Some(format!("ahead {}", ahead)),
+            (0, behind) => Some(format!("behind {}", behind)),
+            (ahead, behind) => Some(format!("ahead {}, behind {}", ahead, behind)),
+        },
+        Err(_) => Some("gone".to_string()),
+    };
+
+    Ok(match (counts, name) {
+        (None, false) => String::new(),
+        (None, true) => format!("[{}] ", short_ref_name(&upstream)),
+        (Some(counts), false) => format!("[{}] ", counts),
+        (Some(counts), true) => format!("[{}: {}] ", short_ref_name(&upstream), counts),
+    })
+}
+
+#[derive(PartialEq, Eq)]
+enum TagAction {
+    List,
+    Delete,
+    Verify,
+}
+
+fn tag<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<()> {
+    let mut action = None;
+    let mut annotate = false;
+    let mut messages = Vec::new();
+    let mut force = false;
+    let mut lines = None;
+    let mut sort = None;
+    let mut rest = Vec::new();
+
+    let mut args = Args::new(args);
+    while let Some(flag) = args.next() {
+        let next = match flag.as_str() {
+            "-a" | "--annotate" => {
+                annotate = true;
+                continue;
+            }
+            "-m" | "--message" => {
+                messages.push(args.value()?);
+                continue;
+            }
+            "-f" | "--force" => {
+                force = true;
+                continue;
+            }
+            "--sort" => {
+                sort = Some(args.value()?);
+                continue;
+            }
+            // The number of lines is optional, and only ever glued on.
+            "-n" => {
+                let n = match args.inline() {
+                    Some(x) => x.parse().map_err(|_| {
+                        usage_error(format!("option 'n' expects a numerical value, not '{}'", x))
+                    })?,
+                    None => 1,
+                };
+                lines = Some(n);
+                TagAction::List
+            }
+            "-l" | "--list" => TagAction::List,
+            "-d" | "--delete" => TagAction::Delete,
+            "-v" | "--verify" => TagAction::Verify,
+            "--" => {
+                rest.extend(args.rest());
+                break;
+            }
+            x if x.starts_with('-') => return Err(usage_error(format!("unknown option '{}'", x))),
+            _ => {
+                rest.push(flag);
+                continue;
+            }
+        };
+        if action.as_ref().is_some_and(|x| *x != next) {
+            return Err(usage_error("options are incompatible"));
+        }
+        action = Some(next);
+    }
+
+    match action {
+        None if !rest.is_empty() => {
+            if rest.len() > 2 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "too many arguments",
+                ));
+            }
+            let name = &rest[0];
+            let target = rest.get(1).map_or("HEAD", |x| x.as_str());
+            let object = git.rev_parse(target).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Failed to resolve '{}' as a valid ref.", target),
+                )
+            })?;
+            // `-m` alone makes an annotated tag too.
+            let message = match (annotate, messages.is_empty()) {
+                (_, false) => Some(messages.join("\n\n")),
+                (true, true) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "no tag message?",
+                    ))
+                }
+                (false, true) => None,
+            };
+            let old = git.create_tag(name, &object, message.as_deref(), force)?;
+            if let Some(old) = old {
+                if git.read_ref(format!("refs/tags/{}", name))? != old {
+                    println!("Updated tag '{}' (was {})", name, pretty::abbrev(&old));
+                }
+            }
+            Ok(())
+        }
+        None if annotate || !messages.is_empty() => Err(usage_error("tag name required")),
+        None | Some(TagAction::List) => list_tags(git, &rest, sort.as_deref(), lines),
+        Some(TagAction::Delete) => {
+            let mut failed = false;
+            for name in &rest {
+                match git.delete_tag(name) {
+                    Ok(hash) => println!("Deleted tag '{}' (was {})", name, pretty::abbrev(&hash)),
+                    Err(e) => {
+                        eprintln!("error: {}", e);
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                return Err(exit(1));
+            }
+            Ok(())
+        }
+        Some(TagAction::Verify) => {
+            // Without signatures to check, this shows what was signed.
+            for name in &rest {
+                let hash = git
+                    .resolve_ref(format!("refs/tags/{}", name))
+                    .map_err(|_| {
+                        eprintln!("error: tag '{}' not found.", name);
+                        exit(1)
+                    })?;
+                let (object_type, _) = git.read_raw_object(hash.clone())?;
+                if object_type != ObjectType::Tag {
+                    eprintln!(
+                        "error: {}: cannot verify a non-tag object of type {}.",
+                        name, object_type
+                    );
+                    return Err(exit(1));
+                }
+                print!("{}", git.read_tag(hash)?);
+                eprintln!("error: no signature found");
+            }
+            Err(exit(1))
+        }
+    }
+}
+
+fn list_tags<F: FileSystem>(
+    git: &Git<F>,
+    patterns: &[String],
+    sort: Option<&str>,
+    lines: Option<usize>,
+) -> io::Result<()> {
+    let mut tags = git.list_tags(patterns)?;
+    let (reverse, key) = match sort {
+        Some(x) => x.strip_prefix('-').map_or((false, x), |x| (true, x)),
+        None => (false, "refname"),
+    };
+    match key {
+        "refname" => {}
+        "version:refname" | "v:refname" => tags.sort_by(|a, b| version_cmp(&a.0, &b.0)),
+        x => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown field name: {}", x),
+            ))
+        }
+    }
+    if reverse {
+        tags.reverse();
+    }
+
+    let mut out = io::stdout().lock();
+    for (name, hash) in tags {
+        let n = match lines {
+            Some(n) => n,
+            None => {
+                writeln!(out, "{}", name)?;
+                continue;
+            }
+        };
+        // Tags show their own message, commits theirs.
+        let message = match git.read_git_object(hash)? {
+            GitObject::Tag(tag) => tag.message,
+            GitObject::Commit(commit) => commit.message,
+            _ => String::new(),
+        };
+        let message = message.lines().take(n).collect::<Vec<_>>().join("\n    ");
+        writeln!(out, "{:<15} {}", name, message)?;
+    }
+    Ok(())
+}
+
+/// `clone` of a repository on this machine. Works on its own directory
+/// rather than the repository `clumsy` started in.
+fn clone(args: &[String]) -> io::Result<()> {
+    let mut options = CloneOptions::default();
+    let mut quiet = false;
+    let mut rest = Vec::new();
+
+    let mut args = Args::new(args);
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--bare" => options.bare = true,
+            "-b" | "--branch" => options.branch = Some(args.value()?),
+            "--no-hardlinks" => options.no_hardlinks = true,
+            "-l" | "--local" => {}
+            "-q" | "--quiet" => quiet = true,
+            "--depth" => {
+                args.value()?;
+                eprintln!("warning: --depth is ignored in local clones; use file:// instead.");
+            }
+            "--" => {
+                rest.extend(args.rest());
+                break;
+            }
+            x if x.starts_with('-') => return Err(usage_error(format!("unknown option '{}'", x))),
+            x => rest.push(x.to_string()),
+        }
+    }
+
+    let (repo, dir) = match rest.as_slice() {
+        [] => return Err(usage_error("You must specify a repository to clone.")),
+        [repo] => (repo, None),
+        [repo, dir] => (repo, Some(dir.clone())),
+        _ => return Err(usage_error("Too many arguments.")),
+    };
+
+    let missing = || io::Error::other(format!("repository '{}' does not exist", repo));
+    let url = std::fs::canonicalize(repo)
+        .map_err(|_| missing())?
+        .to_string_lossy()
+        .into_owned();
+    let source_dir = git_dir_at(&url);
+    let bundle = match source_dir {
+        Some(_) => None,
+        None if std::path::Path::new(&url).is_file() => {
+            Some(read_bundle(repo).map_err(|_| exit(128))?)
+        }
+        None => return Err(missing()),
+    };
+
+    // `path/to/repo.git/` is cloned into `repo`, or `repo.git` when bare.
+    let dir = dir.unwrap_or_else(|| {
+        let name = repo.trim_end_matches('/');
+        let name = name.strip_suffix("/.git").unwrap_or(name);
+        let name = name.rsplit('/').next().unwrap_or(name);
+        let name = name.strip_suffix(".git").unwrap_or(name);
+        if options.bare {
+            format!("{}.git", name)
+        } else {
+            name.to_string()
+        }
+    });
+    let created = match std::fs::read_dir(&dir) {
+        Ok(entries) => {
+            if entries.count() > 0 {
+                return Err(io::Error::other(format!(
+                    "destination path '{}' already exists and is not an empty directory.",
+                    dir
+                )));
+            }
+            false
+        }
+        Err(_) => true,
+    };
+
+    if !quiet {
+        match options.bare {
+            true => eprintln!("Cloning into bare repository '{}'...", dir),
+            false => eprintln!("Cloning into '{}'...", dir),
+        }
+    }
+    std::fs::create_dir_all(&dir)?;
+    let dir = std::fs::canonicalize(&dir)?;
+
+    let result = std::env::set_current_dir(&dir).and_then(|_| {
+        let git_dir = if options.bare { "." } else { ".git" };
+        let mut git = Git::with_git_dir(MacOSFileSystem::init()?, git_dir.to_string());
+        git.init(options.bare, "master")?;
+        match (&bundle, &source_dir) {
+            (Some(bundle), _) => {
+                check_prerequisites(&git, bundle)
+                    .map_err(|_| io::Error::other("remote transport reported error"))?;
+                git.clone_bundle(bundle, &url, &options)
+            }
+            (None, Some(source_dir)) => {
+                let source = Git::with_git_dir(MacOSFileSystem::init()?, source_dir.clone());
+                git.clone_from(&source, &url, &options)
+            }
+            (None, None) => unreachable!(),
+        }
+    });
+    // Don't leave half a clone behind.
+    let head = result.inspect_err(|_| {
+        let _ = std::env::set_current_dir("/");
+        let _ = std::fs::remove_dir_all(&dir);
+        if !created {
+            let _ = std::fs::create_dir(&dir);
+        }
+    })?;
+
+    match head {
+        ClonedHead::Empty => eprintln!("warning: You appear to have cloned an empty repository."),
+        ClonedHead::Missing => {
+            eprintln!("warning: remote HEAD refers to nonexistent ref, unable to checkout")
+        }
+        _ if quiet => {}
+        // Bundles are fetched from, not copied, and have no "done." to show.
+        ClonedHead::Detached(hash) if bundle.is_some() => {
+            eprintln!("Note: switching to '{}'.", hash)
+        }
+        ClonedHead::Branch(_) if bundle.is_some() => {}
+        ClonedHead::Detached(hash) => eprintln!("done.\nNote: switching to '{}'.", hash),
+        ClonedHead::Branch(_) => eprintln!("done."),
+    }
+    Ok(())
+}
+
+fn fetch_pack<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<()> {
+    let mut all = false;
+    let mut quiet = false;
+    let mut upload_pack = "git-upload-pack".to_string();
+    let mut rest = Vec::new();
+
+    let mut args = Args::new(args);
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--all" => all = true,
+            "-q" | "--quiet" => quiet = true,
+            "--upload-pack" | "--exec" => upload_pack = args.value()?,
+            x if x.starts_with('-') => return Err(usage_error(format!("unknown option '{}'", x))),
+            x => rest.push(x.to_string()),
+        }
+    }
+    let (dir, names) = match rest.split_first() {
+        Some((dir, names)) if all || !names.is_empty() => (dir, names),
+        _ => return Err(usage_error("no refs to fetch")),
+    };
+
+    let mut child = spawn_service(&upload_pack, dir, 2)?;
+    let (stdin, stdout) = (child.stdin.take().unwrap(), child.stdout.take().unwrap());
+    let mut connection = Connection::new(io::BufReader::new(stdout), stdin)?;
+
+    let remote_refs = connection.ls_refs(&[])?;
+    let mut fetched = Vec::new();
+    let mut unmatched = Vec::new();
+    if all {
+        fetched.extend(remote_refs.iter().map(|x| (x.hash.clone(), x.name.clone())));
+    }
+    for name in names {
+        match remote_refs.iter().find(|x| &x.name == name) {
+            Some(x) => fetched.push((x.hash.clone(), x.name.clone())),
+            None if name.len() == 40 && name.chars().all(|c| c.is_ascii_hexdigit()) => {
+                fetched.push((name.clone(), name.clone()))
+            }
+            None => unmatched.push(name),
+        }
+    }
+
+    let wants: Vec<_> = fetched.iter().map(|x| x.0.clone()).collect();
+    let mut stderr = RemoteProgress(io::stderr(), Vec::new());
+    let progress = if quiet {
+        None
+    } else {
+        Some(&mut stderr as &mut dyn Write)
+    };
+    git.fetch_pack(&mut connection, &wants, progress)?;
+    drop(connection);
+    child.wait()?;
+
+    for name in &unmatched {
+        eprintln!("error: no such remote ref {}", name);
+    }
+    for (hash, name) in fetched {
+        println!("{} {}", hash, name);
+    }
+    match unmatched.is_empty() {
+        true => Ok(()),
+        false => Err(exit(1)),
+    }
+}
+
+/// Shows the progress messages of a remote, each line marked as theirs.
+/// Lines may come split across messages and are held back until whole.
+struct RemoteProgress<W: Write>(W, Vec<u8>);
+
+impl<W: Write> Write for RemoteProgress<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.1.extend(buf);
+        // Lines end in `\r` when the next one is to overwrite them.
+        while let Some(end) = self.1.iter().position(|&x| x == b'\n' || x == b'\r') {
+            let line: Vec<u8> = self.1.drain(..=end).collect();
+            self.0.write_all(b"remote: ")?;
+            self.0.write_all(&line[..end])?;
+            self.0.write_all(b"        ")?;
+            self.0.write_all(&line[end..])?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// The git directory of the repository at `path`: the `.git` inside a
+/// worktree, or `path` itself for a bare repository.
+fn git_dir_at(path: &str) -> Option<String> {
+    [format!("{}/.git", path), path.to_string()]
+        .iter()
+        .find(|x| std::path::Path::new(&format!("{}/HEAD", x)).is_file())
+        .cloned()
+}
+
+/// Opens the repository a server command is to serve, as given by its
+/// only argument after options it has no use for.
+fn served_repository(args: &[String]) -> io::Result<Git<MacOSFileSystem>> {
+    let mut dirs = args
+        .iter()
+        .filter(|x| !matches!(x.split('=').next(), Some("--strict") | Some("--timeout")));
+    let dir = match (dirs.next(), dirs.next()) {
+        (Some(dir), None) if !dir.starts_with('-') => dir,
+        (Some(x), None) => return Err(usage_error(format!("unknown option '{}'", x))),
+        _ => return Err(usage_error("a repository is required")),
+    };
+    let git_dir = git_dir_at(dir).ok_or_else(|| {
+        io::Error::other(format!("'{}' does not appear to be a git repository", dir))
+    })?;
+    Ok(Git::with_git_dir(MacOSFileSystem::init()?, git_dir))
+}
+
+fn upload_pack(args: &[String]) -> io::Result<()> {
+    let git = served_repository(args)?;
+    // Clients ask for version 2 in the environment, as `version=2` among
+    // other colon-separated parameters.
+    let version = match std::env::var("GIT_PROTOCOL") {
+        Ok(x) if x.split(':').any(|x| x == "version=2") => 2,
+        _ => 0,
+    };
+    let stdout = io::stdout();
+    let mut output = io::BufWriter::new(stdout.lock());
+    git.upload_pack(&mut io::stdin().lock(), &mut output, version)
+}
+
+fn receive_pack(args: &[String]) -> io::Result<()> {
+    let mut git = served_repository(args)?;
+    let stdout = io::stdout();
+    let mut output = io::BufWriter::new(stdout.lock());
+    git.receive_pack(&mut io::stdin().lock(), &mut output)
+}
+
+/// Starts `program` on the repository at `dir`, through the shell like
+/// git does, so the program may come with arguments of its own.
+fn spawn_service(program: &str, dir: &str, version: u32) -> io::Result<std::process::Child> {
+    let quoted = format!("'{}'", dir.replace('\'', "'\\''"));
+    let mut command = std::process::Command::new("sh");
+    command
+        .arg("-c")
+        .arg(format!("{} {}", program, quoted))
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped());
+    if version == 2 {
+        command.env("GIT_PROTOCOL", "version=2");
+    }
+    command.spawn()
+}
+
+/// The remote `name`, or the one the current branch uses (`key` being
+/// `remote` or `pushremote`), as long as it is a local repository or a
+/// bundle.
+fn local_remote<F: FileSystem>(
+    git: &Git<F>,
+    name: Option<&String>,
+    key: &str,
+) -> io::Result<Remote> {
+    let name = match name {
+        Some(name) => name.clone(),
+        None => {
+            let config = git.config()?;
+            let branch = git.head_ref().ok();
+            let branch = branch
+                .as_deref()
+                .and_then(|x| x.strip_prefix("refs/heads/"));
+            branch
+                .and_then(|x| config.get(&format!("branch.{}.{}", x, key)))
+                .or_else(|| branch.and_then(|x| config.get(&format!("branch.{}.remote", x))))
+                .unwrap_or_else(|| "origin".to_string())
+        }
+    };
+    let remote = git.remote(&name)?;
+    let path = remote.url.strip_prefix("file://").unwrap_or(&remote.url);
+    if git_dir_at(path).is_none() && !std::path::Path::new(path).is_file() {
+        return Err(io::Error::other(format!(
+            "'{}' does not appear to be a git repository",
+            name
+        )));
+    }
+    Ok(remote)
+}
+
+/// The program serving `service` for `remote`: the one configured as
+/// `remote.<name>.<key>`, or this one.
+fn service_program<F: FileSystem>(
+    git: &Git<F>,
+    remote: &Remote,
+    key: &str,
+    service: &str,
+) -> io::Result<String> {
+    if let Some(program) = git
+        .config()?
+        .get(&format!("remote.{}.{}", remote.name, key))
+    {
+        return Ok(program);
+    }
+    let exe = std::env::current_exe()?;
+    let exe = exe.to_string_lossy();
+    Ok(format!("'{}' {}", exe.replace('\'', "'\\''"), service))
+}
+
+/// A ref the way fetch and push show it, without its namespace.
+fn short_ref(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|x| name.strip_prefix(x))
+        .unwrap_or(name)
+}
+
+/// The flag and summary of an update in the tables of fetch and push, and
+/// the reason next to it.
+fn update_summary(update: &RefUpdate, new_name: &str) -> (char, String, Option<String>) {
+    let abbrev = |x: &Option<String>| -> String {
+        x.as_deref().unwrap_or_default().chars().take(7).collect()
+    };
+    let (old, new) = (abbrev(&update.old), abbrev(&update.new));
+    let tag = update
+        .dst
+        .as_deref()
+        .unwrap_or_default()
+        .starts_with("refs/tags/");
+    match &update.status {
+        UpdateStatus::Created => ('*', format!("[new {}]", new_name), None),
+        UpdateStatus::FastForward => (' ', format!("{}..{}", old, new), None),
+        UpdateStatus::Forced if tag => ('t', "[tag update]".to_string(), None),
+        UpdateStatus::Forced => (
+            '+',
+            format!("{}...{}", old, new),
+            Some("forced update".to_string()),
+        ),
+        UpdateStatus::Deleted => ('-', "[deleted]".to_string(), None),
+        UpdateStatus::UpToDate => ('=', "[up to date]".to_string(), None),
+        UpdateStatus::Fetched => ('*', new_name.to_string(), None),
+        UpdateStatus::Rejected(reason) => ('!', "[rejected]".to_string(), Some(reason.clone())),
+        UpdateStatus::RemoteRejected(reason) => {
+            ('!', "[remote rejected]".to_string(), Some(reason.clone()))
+        }
+    }
+}
+
+fn fetch<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<()> {
+    let mut options = FetchOptions {
+        reflog_action: ["fetch".to_string()]
+            .iter()
+            .chain(args)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" "),
+        ..FetchOptions::default()
+    };
+    let mut quiet = false;
+    let mut upload_pack = None;
+    let mut rest = Vec::new();
+
+    let mut args = Args::new(args);
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "-f" | "--force" => options.force = true,
+            "-u" | "--update-head-ok" => options.update_head_ok = true,
+            "-n" | "--no-tags" => options.no_tags = true,
+            "-q" | "--quiet" => quiet = true,
+            "--upload-pack" => upload_pack = Some(args.value()?),
+            x if x.starts_with('-') => return Err(usage_error(format!("unknown option '{}'", x))),
+            x => rest.push(x.to_string()),
+        }
+    }
+
+    let remote = local_remote(git, rest.first(), "remote")?;
+    if rest.len() > 1 {
+        let specs = rest[1..].iter().map(|x| Refspec::parse(x));
+        options.refspecs = Some(specs.collect::<io::Result<_>>()?);
+    }
+    let path = remote.url.strip_prefix("file://").unwrap_or(&remote.url);
+    let updates = if std::path::Path::new(path).is_file() {
+        let mut bundle = read_bundle(path)?;
+        // Refs the bundle doesn't have are reported ahead of commits the
+        // repository doesn't, as by git.
+        let updates = git.fetch(&mut bundle, &remote, &options, None);
+        if updates
+            .as_ref()
+            .is_err_and(|e| e.kind() != io::ErrorKind::NotFound)
+        {
+            check_prerequisites(git, &bundle)?;
+        }
+        updates?
+    } else {
+        let program = match upload_pack {
+            Some(program) => program,
+            None => service_program(git, &remote, "uploadpack", "upload-pack")?,
+        };
+        let mut child = spawn_service(&program, path, 2)?;
+        let (stdin, stdout) = (child.stdin.take().unwrap(), child.stdout.take().unwrap());
+        let mut connection = Connection::new(io::BufReader::new(stdout), stdin)?;
+
+        // Like git, only showing progress to someone watching.
+        let mut stderr = RemoteProgress(io::stderr(), Vec::new());
+        let progress = if quiet || !io::stderr().is_terminal() {
+            None
+        } else {
+            Some(&mut stderr as &mut dyn Write)
+        };
+        let updates = git.fetch(&mut connection, &remote, &options, progress);
+        drop(connection);
+        child.wait()?;
+        updates?
+    };
+
+    let shown: Vec<_> = updates
+        .iter()
+        .filter(|x| x.status != UpdateStatus::UpToDate)
+        .filter(|x| !quiet || x.status.is_rejected())
+        .collect();
+    // Refs only fetched into `FETCH_HEAD` don't widen the column.
+    let width = shown
+        .iter()
+        .filter(|x| x.dst.is_some())
+        .map(|x| short_ref(&x.src).len())
+        .fold(10, usize::max);
+    if !shown.is_empty() {
+        eprintln!("From {}", remote.url);
+    }
+    for update in shown {
+        let kind = match &update.src {
+            x if x.starts_with("refs/tags/") => "tag",
+            x if x.starts_with("refs/heads/") || x == "HEAD" => "branch",
+            _ => "ref",
+        };
+        let (flag, summary, reason) = update_summary(update, kind);
+        eprintln!(
+            " {} {:<17} {:<width$} -> {}{}",
+            flag,
+            summary,
+            short_ref(&update.src),
+            update.dst.as_deref().map_or("FETCH_HEAD", short_ref),
+            reason.map_or(String::new(), |x| format!("  ({})", x)),
+            width = width
+        );
+    }
+    match updates.iter().any(|x| x.status.is_rejected()) {
+        true => Err(exit(1)),
+        false => Ok(()),
+    }
+}
+
+fn push<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<()> {
+    let mut options = PushOptions::default();
+    let mut quiet = false;
+    let mut set_upstream = false;
+    let mut delete = false;
+    let mut receive_pack = None;
+    let mut rest = Vec::new();
+
+    let mut args = Args::new(args);
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "-f" | "--force" => options.force = true,
+            "--atomic" => options.atomic = true,
+            "-u" | "--set-upstream" => set_upstream = true,
+            "-d" | "--delete" => delete = true,
+            "-q" | "--quiet" => quiet = true,
+            "--receive-pack" | "--exec" => receive_pack = Some(args.value()?),
+            "--force-with-lease" => {
+                let inline = args.inline();
+                let lease = match inline.map(|x| x.split_once(':')) {
+                    None => Lease {
+                        refname: None,
+                        expect: None,
+                    },
+                    Some(None) => Lease {
+                        refname: inline.map(str::to_string),
+                        expect: None,
+                    },
+                    Some(Some((refname, expect))) => Lease {
+                        refname: Some(refname.to_string()),
+                        expect: Some(git.rev_parse(expect).map_err(|_| {
+                            eprintln!("error: cannot parse expected object name '{}'", expect);
+                            exit(129)
+                        })?),
+                    },
+                };
+                options.leases.push(lease);
+            }
+            x if x.starts_with('-') => return Err(usage_error(format!("unknown option '{}'", x))),
+            x => rest.push(x.to_string()),
+        }
+    }
+
+    let remote = local_remote(git, rest.first(), "pushremote")?;
+    let mut specs = rest.iter().skip(1).cloned().collect::<Vec<_>>();
+    if delete {
+        if specs.is_empty() {
+            return Err(usage_error("--delete doesn't make sense without any refs"));
+        }
+        specs = specs.iter().map(|x| format!(":{}", x)).collect();
+    }
+    let mut refspecs = specs
+        .iter()
+        .map(|x| Refspec::parse(x))
+        .collect::<io::Result<Vec<_>>>()?;
+    if refspecs.is_empty() {
+        refspecs = remote.push.clone();
+    }
+    if refspecs.is_empty() {
+        let branch = match git.head_ref() {
+            Ok(head) => head.trim_start_matches("refs/heads/").to_string(),
+            Err(_) => {
+                return Err(io::Error::other(format!(
+                    "You are not currently on a branch.\n\
+                     To push the history leading to the current (detached HEAD)\n\
+                     state now, use\n\n    clumsy push {} HEAD:<name-of-remote-branch>\n",
+                    remote.name
+                )))
+            }
+        };
+        let config = git.config()?;
+        let upstream = config.get(&format!("branch.{}.merge", branch)).filter(|_| {
+            config.get(&format!("branch.{}.remote", branch)).as_ref() == Some(&remote.name)
+        });
+        let spec = match upstream {
+            Some(merge) => format!("refs/heads/{}:{}", branch, merge),
+            None if set_upstream => format!("refs/heads/{}", branch),
+            None => {
+                return Err(io::Error::other(format!(
+                    "The current branch {0} has no upstream branch.\n\
+                     To push the current branch and set the remote as upstream, use\n\n    \
+                     clumsy push --set-upstream {1} {0}\n\n\
+                     To have this happen automatically for branches without a tracking\n\
+                     upstream, see 'push.autoSetupRemote' in 'git help config'.\n",
+                    branch, remote.name
+                )))
+            }
+        };
+        refspecs.push(Refspec::parse(&spec)?);
+    }
+
+    let program = match receive_pack {
+        Some(program) => program,
+        None => service_program(git, &remote, "receivepack", "receive-pack")?,
+    };
+    let path = remote.url.strip_prefix("file://").unwrap_or(&remote.url);
+    let mut child = spawn_service(&program, path, 0)?;
+    let (stdin, stdout) = (child.stdin.take().unwrap(), child.stdout.take().unwrap());
+    let mut connection = PushConnection::new(io::BufReader::new(stdout), stdin)?;
+    let updates = git.push(&mut connection, &remote, &refspecs, &options);
+    drop(connection);
+    child.wait()?;
+    let updates = match updates {
+        Ok(updates) => updates,
+        Err(e) if e.kind() == io::ErrorKind::InvalidInput => {
+            eprintln!("error: {}", e);
+            eprintln!("error: failed to push some refs to '{}'", remote.url);
+            return Err(exit(1));
+        }
+        Err(e) => return Err(e),
+    };
+
+    let rejected = updates.iter().any(|x| x.status.is_rejected());
+    let mut shown: Vec<_> = updates
+        .iter()
+        .filter(|x| x.status != UpdateStatus::UpToDate)
+        .filter(|x| !quiet || x.status.is_rejected())
+        .collect();
+    // Like git, what went through before what didn't.
+    shown.sort_by_key(|x| x.status.is_rejected());
+    if !shown.is_empty() {
+        eprintln!("To {}", remote.url);
+    }
+    for update in shown {
+        let dst = update.dst.as_deref().unwrap_or_default();
+        let kind = match dst {
+            x if x.starts_with("refs/heads/") => "branch",
+            x if x.starts_with("refs/tags/") => "tag",
+            _ => "reference",
+        };
+        let (flag, summary, reason) = update_summary(update, kind);
+        let refs = match update.status {
+            UpdateStatus::Deleted => short_ref(dst).to_string(),
+            _ => format!("{} -> {}", short_ref(&update.src), short_ref(dst)),
+        };
+        eprintln!(
+            " {} {:<17} {}{}",
+            flag,
+            summary,
+            refs,
+            reason.map_or(String::new(), |x| format!(" ({})", x))
+        );
+    }
+    if !rejected && !quiet && updates.iter().all(|x| x.status == UpdateStatus::UpToDate) {
+        eprintln!("Everything up-to-date");
+    }
+
+    if set_upstream {
+        let mut config = git.config_file(Level::Local)?;
+        for update in updates.iter().filter(|x| !x.status.is_rejected()) {
+            let dst = update.dst.as_deref().unwrap_or_default();
+            let src = git
+                .ref_candidates(&update.src)
+                .into_iter()
+                .find(|x| x.starts_with("refs/heads/") && git.read_ref(x.clone()).is_ok());
+            if let (Some(src), Some(merge)) = (src, dst.strip_prefix("refs/heads/")) {
+                let branch = &src["refs/heads/".len()..];
+                config.set(&format!("branch.{}.remote", branch), &remote.name)?;
+                config.set(&format!("branch.{}.merge", branch), dst)?;
+                if !quiet {
+                    println!(
+                        "branch '{}' set up to track '{}/{}'.",
+                        branch, remote.name, merge
+                    );
+                }
+            }
+        }
+        git.write_config_file(&config)?;
+    }
+
+    if !rejected {
+        return Ok(());
+    }
+    eprintln!("error: failed to push some refs to '{}'", remote.url);
+    let reasons: Vec<_> = updates
+        .iter()
+        .filter_map(|x| match &x.status {
+            UpdateStatus::Rejected(reason) => Some((x, reason.as_str())),
+            _ => None,
+        })
+        .collect();
+    // Like git, telling apart the remote ref of the same name as the
+    // branch we are on.
+    let head = git.head_ref().ok();
+    let non_ff: Vec<_> = reasons
+        .iter()
+        .filter(|x| x.1 == "non-fast-forward")
+        .collect();
+    let hint = if non_ff.iter().any(|(x, _)| x.dst == head) {
+        PUSH_BEHIND_HEAD
+    } else if !non_ff.is_empty() {
+        PUSH_BEHIND_OTHER
+    } else if reasons.iter().any(|x| x.1 == "already exists") {
+        "Updates were rejected because the tag already exists in the remote."
+    } else if reasons.iter().any(|x| x.1 == "fetch first") {
+        PUSH_FETCH_FIRST
+    } else {
+        ""
+    };
+    for line in hint.lines() {
+        eprintln!("hint: {}", line);
+    }
+    Err(exit(1))
+}
+
+const PUSH_BEHIND_HEAD: &str = "\
+Updates were rejected because the tip of your current branch is behind
+its remote counterpart. Integrate the remote changes (e.g.
+'git pull ...') before pushing again.
+See the 'Note about fast-forwards' in 'git push --help' for details.";
+
+const PUSH_BEHIND_OTHER: &str = "\
+Updates were rejected because a pushed branch tip is behind its remote
+counterpart. Check out this branch and integrate the remote changes
+(e.g. 'git pull ...') before pushing again.
+See the 'Note about fast-forwards' in 'git push --help' for details.";
+
+const PUSH_FETCH_FIRST: &str = "\
+Updates were rejected because the remote contains work that you do
+not have locally. This is usually caused by another repository pushing
+to the same ref. You may want to first integrate the remote changes
+(e.g., 'git pull ...') before pushing again.
+See the 'Note about fast-forwards' in 'git push --help' for details.";
+
+fn bundle<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<()> {
+    let (subcommand, args) = match args.split_first() {
+        Some((subcommand, args)) => (subcommand.as_str(), args),
+        None => return Err(usage_error("need a subcommand")),
+    };
+    let mut quiet = false;
+    let mut version = 2;
+    let mut rest = Vec::new();
+
+    let mut args = Args::new(args);
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "-q" | "--quiet" if subcommand != "list-heads" => quiet = true,
+            "--progress" if subcommand == "create" || subcommand == "unbundle" => {}
+            "--version" if subcommand == "create" => {
+                version = match args.value()?.as_str() {
+                    "2" => 2,
+                    "3" => 3,
+                    x => {
+                        return Err(io::Error::other(format!(
+                            "unsupported bundle version {}",
+                            x
+                        )))
+                    }
+                }
+            }
+            "--" => {
+                rest.extend(args.rest());
+                break;
+            }
+            x if x.starts_with('-') && x != "-" => {
+                return Err(usage_error(format!("unknown option '{}'", x)))
+            }
+            // The rest of a `create` are for walking history.
+            x => {
+                rest.push(x.to_string());
+                rest.extend(args.rest());
+                break;
+            }
+        }
+    }
+    let (file, rest) = match rest.split_first() {
+        Some((file, rest)) => (file, rest),
+        None => return Err(usage_error("need a <file> argument")),
+    };
+
+    match subcommand {
+        "create" => {
+            let bundle = git.create_bundle(rest, version)?;
+            match file.as_str() {
+                "-" => bundle.write_to(&mut io::stdout().lock()),
+                _ => bundle.write_to(&mut io::BufWriter::new(File::create(file)?)),
+            }
+        }
+        "verify" => {
+            let bundle = read_bundle(file)?;
+            check_prerequisites(git, &bundle)?;
+            if !quiet {
+                let count = |n: usize| match n {
+                    1 => "this ref".to_string(),
+                    n => format!("these {} refs", n),
+                };
+                println!("The bundle contains {}:", count(bundle.refs.len()));
+                for (hash, name) in &bundle.refs {
+                    println!("{} {}", hash, name);
+                }
+                match bundle.prerequisites.len() {
+                    0 => println!("The bundle records a complete history."),
+                    n => println!("The bundle requires {}:", count(n)),
+                }
+                for (hash, _) in &bundle.prerequisites {
+                    println!("{} ", hash);
+                }
+                println!("The bundle uses this hash algorithm: sha1");
+            }
+            eprintln!("{} is okay", file);
+            Ok(())
+        }
+        "list-heads" | "unbundle" => {
+            let bundle = read_bundle(file)?;
+            if subcommand == "unbundle" {
+                check_prerequisites(git, &bundle)?;
+                git.unbundle(&bundle)?;
+            }
+            let wanted = |name: &String| rest.is_empty() || rest.contains(name);
+            for (hash, name) in bundle.refs.iter().filter(|(_, name)| wanted(name)) {
+                println!("{} {}", hash, name);
+            }
             Ok(())
         }
-        "hash-object" => {
-            let blob = hash_object(args.get(2).unwrap().clone())?;
-            println!("{}", hex::encode(blob.calc_hash()));
-            Ok(())
+        x => Err(usage_error(format!("Unknown subcommand: {}", x))),
+    }
+}
+
+/// Reads the bundle at `path`, reporting anything amiss with it as an
+/// error of the command.
+fn read_bundle(path: &str) -> io::Result<Bundle> {
+    let bytes = match path {
+        "-" => {
+            let mut bytes = Vec::new();
+            io::stdin().read_to_end(&mut bytes)?;
+            bytes
+        }
+        _ => std::fs::read(path)?,
+    };
+    Bundle::parse(&bytes).map_err(|e| {
+        eprintln!("error: '{}' {}", path, e);
+        exit(1)
+    })
+}
+
+/// Fails, listing them, if the repository lacks any prerequisites of
+/// `bundle`.
+fn check_prerequisites<F: FileSystem>(git: &Git<F>, bundle: &Bundle) -> io::Result<()> {
+    let missing = git.missing_prerequisites(bundle);
+    if missing.is_empty() {
+        return Ok(());
+    }
+    eprintln!("error: Repository lacks these prerequisite commits:");
+    for hash in missing {
+        eprintln!("error: {} ", hash);
+    }
+    Err(exit(1))
+}
+
+fn cat_file<F: FileSystem>(git: &Git<F>, args: &[String]) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    let read = |name: &str| {
+        let hash = git.rev_parse(name)?;
+        match git.read_raw_object(hash.clone()) {
+            Ok(object) => Ok((hash, object)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Not a valid object name {}", name),
+            )),
+            Err(e) => Err(e),
+        }
+    };
+
+    let mut mode = None;
+    let mut rest = Vec::new();
+    let mut args = Args::new(args);
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "-t" | "-s" | "-e" | "-p" | "--batch" | "--batch-check" => {
+                if mode.as_ref().is_some_and(|x| *x != flag) {
+                    return Err(usage_error("options are incompatible"));
+                }
+                mode = Some(flag);
+            }
+            "--" => {
+                rest.extend(args.rest());
+                break;
+            }
+            x if x.starts_with('-') => return Err(usage_error(format!("unknown option '{}'", x))),
+            _ => rest.push(flag),
+        }
+    }
+
+    match (mode.as_deref(), rest.as_slice()) {
+        (Some(flag @ ("--batch" | "--batch-check")), []) => {
+            for line in io::stdin().lock().lines() {
+                let line = line?;
+                let name = line.trim();
+                match read(name) {
+                    Ok((hash, (object_type, content))) => {
+                        writeln!(out, "{} {} {}", hash, object_type, content.len())?;
+                        if flag == "--batch" {
+                            out.write_all(&content)?;
+                            writeln!(out)?;
+                        }
+                    }
+                    Err(_) => writeln!(out, "{} missing", name)?,
+                }
+                // Callers wait for each answer before asking the next one.
+                out.flush()?;
+            }
+        }
+        (Some("-e"), [name]) => {
+            if read(name).is_err() {
+                return Err(exit(1));
+            }
+        }
+        (Some(flag @ ("-t" | "-s" | "-p")), [name]) => {
+            let (_, (object_type, content)) = read(name)?;
+            match (flag, object_type) {
+                ("-t", _) => writeln!(out, "{}", object_type)?,
+                ("-s", _) => writeln!(out, "{}", content.len())?,
+                (_, ObjectType::Tree) => {
+                    for file in Tree::parse(&content)?.contents {
+                        write_tree_entry(&mut out, &file, &file.name, false)?;
+                    }
+                }
+                _ => out.write_all(&content)?,
+            }
+        }
+        (None, [object_type, name]) if ObjectType::from(object_type).is_some() => {
+            let hash = git.peel(read(name)?.0, object_type)?;
+            out.write_all(&git.read_raw_object(hash)?.1)?;
+        }
+        _ => return Err(usage_error("an object or a batch mode is expected")),
+    }
+
+    out.flush()
+}
+
+fn write_tree_entry(
+    out: &mut impl Write,
+    file: &tree::File,
+    path: &str,
+    name_only: bool,
+) -> io::Result<()> {
+    if name_only {
+        writeln!(out, "{}", quote_path(path))
+    } else {
+        writeln!(
+            out,
+            "{} {} {}\t{}",
+            file.mode,
+            file.mode.object_type(),
+            hex::encode(&file.hash),
+            quote_path(path)
+        )
+    }
+}
+
+fn ls_tree<F: FileSystem>(git: &Git<F>, args: &[String]) -> io::Result<()> {
+    let mut options = ListTree::default();
+    let mut rest = Vec::new();
+    for arg in Args::new(args) {
+        match arg.as_str() {
+            "-r" => options.recursive = true,
+            "-t" => options.show_trees = true,
+            "--name-only" | "--name-status" => options.name_only = true,
+            _ if arg.starts_with('-') => {
+                return Err(usage_error(format!("unknown option '{}'", arg)))
+            }
+            _ => rest.push(arg),
+        }
+    }
+
+    let (tree_ish, paths) = rest
+        .split_first()
+        .ok_or_else(|| usage_error("a tree-ish is expected"))?;
+    options.paths = paths.to_vec();
+    let tree = git.peel(git.rev_parse(tree_ish)?, "tree")?;
+
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    list_tree(git, &mut out, tree, "", &options)?;
+    out.flush()
+}
+
+#[derive(Default)]
+struct ListTree {
+    recursive: bool,
+    show_trees: bool,
+    name_only: bool,
+    paths: Vec<String>,
+}
+
+fn list_tree<F: FileSystem>(
+    git: &Git<F>,
+    out: &mut impl Write,
+    tree: String,
+    prefix: &str,
+    options: &ListTree,
+) -> io::Result<()> {
+    for file in git.read_tree(tree)?.contents {
+        let path = format!("{}{}", prefix, file.name);
+
+        // A path names itself, everything below it, or with a trailing
+        // slash only what's below it.
+        let matched = options.paths.is_empty()
+            || options.paths.iter().any(|p| {
+                path == *p
+                    || path.starts_with(&format!("{}/", p))
+                    || (p.ends_with('/') && path.starts_with(p.as_str()))
+            });
+        let leads_to_path = options
+            .paths
+            .iter()
+            .any(|p| p.starts_with(&format!("{}/", path)));
+
+        let recurse =
+            file.mode == FileMode::Tree && ((options.recursive && matched) || leads_to_path);
+        if recurse {
+            if options.show_trees {
+                write_tree_entry(out, &file, &path, options.name_only)?;
+            }
+            list_tree(
+                git,
+                out,
+                hex::encode(&file.hash),
+                &format!("{}/", path),
+                options,
+            )?;
+        } else if matched {
+            write_tree_entry(out, &file, &path, options.name_only)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn ls_files<F: FileSystem>(git: &Git<F>, args: &[String]) -> io::Result<()> {
+    let (mut cached, mut stage, mut modified, mut others, mut deleted) =
+        (false, false, false, false, false);
+    let mut exclude_standard = false;
+    for arg in Args::new(args) {
+        match arg.as_str() {
+            "-c" | "--cached" => cached = true,
+            "-s" | "--stage" => stage = true,
+            "-m" | "--modified" => modified = true,
+            "-o" | "--others" => others = true,
+            "-d" | "--deleted" => deleted = true,
+            "--exclude-standard" => exclude_standard = true,
+            _ => return Err(usage_error(format!("unknown option '{}'", arg))),
+        }
+    }
+    if !(cached || stage || modified || others || deleted) {
+        cached = true;
+    }
+
+    let index = match git.read_index() {
+        Ok(bytes) => git.ls_files_stage(&bytes)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Index::new(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    // Untracked files have no entry and are only ever listed by name.
+    let mut show = |name: &str, entry: Option<&index::Entry>| match entry {
+        Some(entry) if stage => writeln!(
+            out,
+            "{} {} 0\t{}",
+            entry.mode,
+            hex::encode(&entry.hash),
+            quote_path(name)
+        ),
+        _ => writeln!(out, "{}", quote_path(name)),
+    };
+
+    if others {
+        let tracked = index.entries.iter().map(|x| x.name.clone()).collect();
+        let mut ignore = if exclude_standard {
+            Some(git.ignore()?)
+        } else {
+            None
+        };
+        for path in git.untracked_files(&tracked, ignore.as_mut())? {
+            show(&path, None)?;
+        }
+    }
+
+    for entry in &index.entries {
+        if cached || stage {
+            show(&entry.name, Some(entry))?;
+        }
+        if deleted || modified {
+            let missing = match git.file_system.stat(entry.name.clone()) {
+                Ok(_) => false,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => true,
+                Err(e) => return Err(e),
+            };
+            if deleted && missing {
+                show(&entry.name, Some(entry))?;
+            }
+            if modified && (missing || git.worktree_changed(entry)?) {
+                show(&entry.name, Some(entry))?;
+            }
+        }
+    }
+
+    out.flush()
+}
+
+pub fn hash_object(path: String) -> io::Result<object::blob::Blob> {
+    let mut file = File::open(path)?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+
+    object::blob::Blob::from(&buf).ok_or(io::Error::from(io::ErrorKind::InvalidInput))
+}
+
+fn add<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<()> {
+    let mut options = AddOptions::default();
+    let (mut all, mut verbose) = (false, false);
+    let mut paths = Vec::new();
+
+    let mut args = Args::new(args);
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "-n" | "--dry-run" => options.dry_run = true,
+            "-v" | "--verbose" => verbose = true,
+            "-f" | "--force" => options.force = true,
+            "-N" | "--intent-to-add" => options.intent_to_add = true,
+            "-u" | "--update" => options.update = true,
+            "-A" | "--all" => all = true,
+            "--" => paths.extend(args.rest()),
+            x if x.starts_with('-') => return Err(usage_error(format!("unknown option '{}'", x))),
+            _ => paths.push(flag),
+        }
+    }
+    if all && options.update {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "options '-A' and '-u' cannot be used together",
+        ));
+    }
+    if paths.is_empty() && !all && !options.update {
+        eprintln!("Nothing specified, nothing added.");
+        eprintln!("hint: Maybe you wanted to say 'clumsy add .'?");
+        return Ok(());
+    }
+
+    let pathspec = Pathspec::parse(&paths)?;
+    let result = git.add(&pathspec, &options)?;
+
+    if verbose || options.dry_run {
+        for staged in &result.staged {
+            match staged {
+                Staged::Add(path) => println!("add '{}'", path),
+                Staged::Remove(path) => println!("remove '{}'", path),
+            }
         }
-        "add" => {
-            let bytes = git.file_system.read(args.get(2).unwrap().clone())?;
-            add(&mut git, args.get(2).unwrap().clone(), &bytes)
+    }
+    if !result.ignored.is_empty() {
+        eprintln!("The following paths are ignored by one of your .gitignore files:");
+        for path in &result.ignored {
+            eprintln!("{}", path);
         }
-        "commit" => commit(&mut git, args.get(2).unwrap().clone()),
-        "switch" => switch(&mut git, args.get(2).unwrap().clone()),
-        "log" => {
-            let obj = log(&mut git)?;
-            obj.iter().for_each(|x| println!("{}", x));
-            Ok(())
+        eprintln!("hint: Use -f if you really want to add them.");
+        return Err(exit(1));
+    }
+
+    Ok(())
+}
+
+fn reset<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<()> {
+    let mut mode = None;
+    let mut quiet = false;
+    let mut rest = Vec::new();
+    let mut paths = None;
+
+    let mut args = Args::new(args);
+    while let Some(flag) = args.next() {
+        let next = match flag.as_str() {
+            "--soft" => ResetMode::Soft,
+            "--mixed" => ResetMode::Mixed,
+            "--hard" => ResetMode::Hard,
+            "--keep" => ResetMode::Keep,
+            "--merge" => ResetMode::Merge,
+            "-q" | "--quiet" => {
+                quiet = true;
+                continue;
+            }
+            "--" => {
+                paths = Some(args.rest().collect::<Vec<_>>());
+                break;
+            }
+            x if x.starts_with('-') => return Err(usage_error(format!("unknown option '{}'", x))),
+            _ => {
+                rest.push(flag);
+                continue;
+            }
+        };
+        mode = Some(next);
+    }
+
+    // Without "--", the first argument is a revision only if it names one.
+    let (rev, paths) = match paths {
+        Some(_) if rest.len() > 1 => {
+            return Err(usage_error(format!("unexpected argument '{}'", rest[1])))
+        }
+        Some(paths) => (rest.pop(), paths),
+        None => match rest.first() {
+            Some(first) if git.rev_parse(first).is_ok() => {
+                let paths = rest.split_off(1);
+                (rest.pop(), paths)
+            }
+            Some(first) if git.file_system.stat(first.clone()).is_err() => {
+                return Err(ambiguous(first))
+            }
+            _ => (None, rest),
         },
+    };
+    let name = rev.unwrap_or_else(|| "HEAD".to_string());
+
+    if !paths.is_empty() {
+        if let Some(mode) = mode.filter(|x| *x != ResetMode::Mixed) {
+            let mode = format!("{:?}", mode).to_lowercase();
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot do {} reset with paths.", mode),
+            ));
+        }
+        // Resetting paths before the first commit unstages them.
+        let commit = match git.rev_parse(&name) {
+            Ok(hash) => Some(git.peel(hash, "commit")?),
+            Err(_) if name == "HEAD" => None,
+            Err(_) => return Err(ambiguous(&name)),
+        };
+        git.reset_paths(commit, &Pathspec::parse(&paths)?)?;
+        return if quiet { Ok(()) } else { unstaged_changes(git) };
+    }
+
+    let commit = git
+        .rev_parse(&name)
+        .and_then(|x| git.peel(x, "commit"))
+        .map_err(|_| ambiguous(&name))?;
+    let mode = mode.unwrap_or(ResetMode::Mixed);
+    git.reset(commit.clone(), mode, &format!("reset: moving to {}", name))
+        .map_err(|e| {
+            if matches!(mode, ResetMode::Keep | ResetMode::Merge) {
+                eprintln!("error: {}", e);
+                io::Error::other(format!(
+                    "Could not reset index file to revision '{}'.",
+                    name
+                ))
+            } else {
+                e
+            }
+        })?;
+
+    if quiet {
+        return Ok(());
+    }
+    match mode {
+        ResetMode::Hard => {
+            let subject = pretty::subject(&git.read_commit(commit.clone())?.message);
+            println!("HEAD is now at {} {}", pretty::abbrev(&commit), subject);
+            Ok(())
+        }
+        ResetMode::Mixed => unstaged_changes(git),
         _ => Ok(()),
     }
 }
 
-pub fn cat_file_p(hash: String) -> io::Result<GitObject> {
-    let (sub_dir, file) = hash.split_at(2);
-    let path = format!(".git/objects/{}/{}", sub_dir, file);
+fn ambiguous(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "ambiguous argument '{}': unknown revision or path not in the working tree.",
+            name
+        ),
+    )
+}
 
-    let mut file = File::open(path)?;
-    let mut buf = Vec::new();
-    file.read_to_end(&mut buf)?;
+/// Lists the tracked files whose worktree differs from the index, as
+/// `reset` does after changing the index.
+fn unstaged_changes<F: FileSystem>(git: &Git<F>) -> io::Result<()> {
+    let mut index = git.ls_files_stage(&git.read_index()?)?;
+    let changed = git.refresh_index(&mut index)?;
+    if changed.is_empty() {
+        return Ok(());
+    }
+    println!("Unstaged changes after reset:");
+    for name in changed {
+        let status = match git.file_system.stat(name.clone()) {
+            Ok(_) => 'M',
+            Err(_) => 'D',
+        };
+        println!("{}\t{}", status, name);
+    }
+    Ok(())
+}
 
-    let mut d = Decoder::new(&buf[..])?;
-    let mut buf = Vec::new();
-    d.read_to_end(&mut buf)?;
+/// `checkout [<tree-ish>] -- <paths>`: the worktree from the index, or
+/// both from the tree without removing anything it doesn't have. Like
+/// git, the files written are only counted when there's no "--".
+fn checkout_paths<F: FileSystem>(
+    git: &mut Git<F>,
+    tree_ish: Option<String>,
+    paths: &[String],
+    count: bool,
+) -> io::Result<()> {
+    let source = match &tree_ish {
+        Some(name) => Some(
+            git.rev_parse(name)
+                .and_then(|x| git.peel(x, "tree"))
+                .map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid reference: {}", name),
+                    )
+                })?,
+        ),
+        None => None,
+    };
+    let options = RestoreOptions {
+        staged: source.is_some(),
+        worktree: true,
+        source: source.clone(),
+        overlay: true,
+    };
 
-    GitObject::new(&buf).ok_or(io::Error::from(io::ErrorKind::InvalidData))
+    let written = restore_paths(git, paths, &options)?;
+    if !count {
+        return Ok(());
+    }
+    let from = match &source {
+        Some(tree) => pretty::abbrev(tree).to_string(),
+        None => "the index".to_string(),
+    };
+    match written.len() {
+        1 => eprintln!("Updated 1 path from {}", from),
+        n => eprintln!("Updated {} paths from {}", n, from),
+    }
+    Ok(())
 }
 
-pub fn hash_object(path: String) -> io::Result<object::blob::Blob> {
-    let mut file = File::open(path)?;
-    let mut buf = Vec::new();
-    file.read_to_end(&mut buf)?;
+fn restore<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<()> {
+    let mut options = RestoreOptions::default();
+    let mut source = None;
+    let mut paths = Vec::new();
 
-    object::blob::Blob::from(&buf).ok_or(io::Error::from(io::ErrorKind::InvalidInput))
+    let mut args = Args::new(args);
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "-S" | "--staged" => options.staged = true,
+            "-W" | "--worktree" => options.worktree = true,
+            "--overlay" => options.overlay = true,
+            "--no-overlay" => options.overlay = false,
+            "-q" | "--quiet" => {}
+            "-s" | "--source" => source = Some(args.value()?),
+            "--" => paths.extend(args.rest()),
+            x if x.starts_with('-') => return Err(usage_error(format!("unknown option '{}'", x))),
+            x => paths.push(x.to_string()),
+        }
+    }
+    if paths.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "you must specify path(s) to restore",
+        ));
+    }
+    if !options.staged {
+        options.worktree = true;
+    }
+    // The index is restored from HEAD unless told otherwise.
+    if options.staged && source.is_none() {
+        source = Some("HEAD".to_string());
+    }
+    if let Some(name) = source {
+        let tree = git
+            .rev_parse(&name)
+            .and_then(|x| git.peel(x, "tree"))
+            .map_err(|_| io::Error::other(format!("could not resolve {}", name)))?;
+        options.source = Some(tree);
+    }
+
+    restore_paths(git, paths.as_slice(), &options).map(|_| ())
+}
+
+/// Reports pathspec items that match nothing as errors rather than fatal.
+fn restore_paths<F: FileSystem>(
+    git: &mut Git<F>,
+    paths: &[String],
+    options: &RestoreOptions,
+) -> io::Result<Vec<String>> {
+    match git.restore(&Pathspec::parse(paths)?, options) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            eprintln!("error: {}", e);
+            Err(exit(1))
+        }
+        x => x,
+    }
 }
 
-pub fn add<F: FileSystem>(git: &mut Git<F>, file_name: String, bytes: &[u8]) -> io::Result<()> {
-    // git hash-object -w path
-    let blob = git.hash_object(&bytes).map(GitObject::Blob)?;
-    git.write_object(&blob)?;
+fn rm<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<()> {
+    let mut options = RmOptions::default();
+    let mut quiet = false;
+    let mut paths = Vec::new();
 
-    // git update-index --add --cacheinfo <mode> <hash> <name>
-    let index = git.read_index().and_then(|x| git.ls_files_stage(&x))?;
-    let index = git.update_index(index, &blob.calc_hash(), file_name)?;
-    git.write_index(&index)?;
-    println!("write_index");
+    let mut args = Args::new(args);
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "-f" | "--force" => options.force = true,
+            "-n" | "--dry-run" => options.dry_run = true,
+            "-q" | "--quiet" => quiet = true,
+            "-r" => options.recursive = true,
+            "--cached" => options.cached = true,
+            "--ignore-unmatch" => options.ignore_unmatch = true,
+            "--" => paths.extend(args.rest()),
+            x if x.starts_with('-') => return Err(usage_error(format!("unknown option '{}'", x))),
+            _ => paths.push(flag),
+        }
+    }
+    if paths.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "No pathspec was given. Which files should I remove?",
+        ));
+    }
+
+    let result = git.rm(&Pathspec::parse(&paths)?, &options)?;
+    if !result.refused.is_empty() {
+        let reasons = [
+            (
+                Unsafe::StagedAndLocal,
+                "staged content different from both the\nfile and the HEAD",
+                "(use -f to force removal)",
+            ),
+            (
+                Unsafe::Staged,
+                "changes staged in the index",
+                "(use --cached to keep the file, or -f to force removal)",
+            ),
+            (
+                Unsafe::Local,
+                "local modifications",
+                "(use --cached to keep the file, or -f to force removal)",
+            ),
+        ];
+        for (reason, what, hint) in reasons.iter() {
+            let files = result
+                .refused
+                .iter()
+                .filter(|(_, x)| x == reason)
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>();
+            match files.len() {
+                0 => continue,
+                1 => eprintln!("error: the following file has {}:", what),
+                _ => eprintln!("error: the following files have {}:", what),
+            }
+            for name in files {
+                eprintln!("    {}", name);
+            }
+            eprintln!("{}", hint);
+        }
+        return Err(exit(1));
+    }
+
+    if !quiet {
+        for name in &result.removed {
+            println!("rm '{}'", name);
+        }
+    }
+    Ok(())
+}
+
+fn mv<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<()> {
+    let mut options = MvOptions::default();
+    let mut verbose = false;
+    let mut paths = Vec::new();
+
+    let mut args = Args::new(args);
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "-f" | "--force" => options.force = true,
+            "-k" => options.skip_errors = true,
+            "-n" | "--dry-run" => options.dry_run = true,
+            "-v" | "--verbose" => verbose = true,
+            "--" => paths.extend(args.rest()),
+            x if x.starts_with('-') => return Err(usage_error(format!("unknown option '{}'", x))),
+            _ => paths.push(flag),
+        }
+    }
+    let (destination, sources) = match paths.split_last() {
+        Some((destination, sources)) if !sources.is_empty() => (destination, sources),
+        _ => return Err(usage_error("a source and a destination are needed")),
+    };
+
+    let renames = git.mv(sources, destination, &options)?;
+    if options.dry_run {
+        for (source, target) in &renames {
+            println!("Checking rename of '{}' to '{}'", source, target);
+        }
+    }
+    if verbose || options.dry_run {
+        for (source, target) in &renames {
+            println!("Renaming {} to {}", source, target);
+        }
+    }
+    Ok(())
+}
+
+/// Options apply to the paths after them, so `--add a --remove b` adds `a`
+/// and may remove `b`. The index is written once everything has been
+/// processed.
+fn update_index<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<()> {
+    let mut index = match git.read_index() {
+        Ok(bytes) => git.ls_files_stage(&bytes)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Index::new(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let (mut add, mut remove, mut force_remove, mut replace) = (false, false, false, false);
+    let mut needs_update = false;
+    let unable = |path: &str, msg: &str| {
+        eprintln!("error: {}: {}", path, msg);
+        io::Error::other(format!("Unable to process path {}", path))
+    };
+
+    // Options apply to the paths after them, up to a `--` after which
+    // everything is a path.
+    let mut paths_only = false;
+    let mut args = Args::new(args);
+    while let Some(flag) = match paths_only {
+        true => args.rest().next(),
+        false => args.next(),
+    } {
+        let path = match flag.as_str() {
+            _ if paths_only => flag.clone(),
+            "--add" => {
+                add = true;
+                continue;
+            }
+            "--remove" => {
+                remove = true;
+                continue;
+            }
+            "--force-remove" => {
+                force_remove = true;
+                continue;
+            }
+            "--replace" => {
+                replace = true;
+                continue;
+            }
+            "--refresh" => {
+                for path in git.refresh_index(&mut index)? {
+                    println!("{}: needs update", path);
+                    needs_update = true;
+                }
+                continue;
+            }
+            "--cacheinfo" => {
+                let info = args.value()?;
+                let (mode, hash, path) = match info.splitn(3, ',').collect::<Vec<_>>()[..] {
+                    [mode, hash, path] => (mode, hash.to_string(), path.to_string()),
+                    // The older form takes three arguments.
+                    _ => match (args.rest().next(), args.rest().next()) {
+                        (Some(hash), Some(path)) => (info.as_str(), hash, path),
+                        _ => {
+                            return Err(usage_error(
+                                "option 'cacheinfo' expects <mode>,<object>,<path>",
+                            ))
+                        }
+                    },
+                };
+                let mode = FileMode::from_octal(mode);
+                let hash = hex::decode(&hash).ok().filter(|x| x.len() == 20);
+                let (mode, hash) = mode.zip(hash).ok_or_else(|| {
+                    usage_error("option 'cacheinfo' expects <mode>,<object>,<path>")
+                })?;
+                if !replace && index.has_conflict(&path) {
+                    eprintln!(
+                        "error: '{}' appears as both a file and as a directory",
+                        path
+                    );
+                }
+                if !add && index.get(&path).is_none() || !replace && index.has_conflict(&path) {
+                    eprintln!(
+                        "error: {}: cannot add to the index - missing --add option?",
+                        path
+                    );
+                    return Err(io::Error::other(format!(
+                        "update-index: --cacheinfo cannot add {}",
+                        path
+                    )));
+                }
+                index.add(index::Entry::cacheinfo(mode, hash, path));
+                continue;
+            }
+            "--" => {
+                paths_only = true;
+                continue;
+            }
+            x if x.starts_with('-') => return Err(usage_error(format!("unknown option '{}'", x))),
+            _ => flag.clone(),
+        };
+        let path = &path;
+
+        if force_remove {
+            index.remove(path);
+            continue;
+        }
+        match git.file_system.stat(path.clone()) {
+            Ok(metadata) if metadata.is_dir() => {
+                return Err(unable(
+                    path,
+                    "is a directory - add individual files instead",
+                ))
+            }
+            Ok(_) if !replace && index.has_conflict(path) => {
+                return Err(unable(path, "appears as both a file and as a directory"))
+            }
+            Ok(_) if !add && index.get(path).is_none() => {
+                return Err(unable(
+                    path,
+                    "cannot add to the index - missing --add option?",
+                ))
+            }
+            Ok(_) => {
+                let bytes = git.read_worktree(path)?;
+                let blob = git.hash_object(&bytes).map(GitObject::Blob)?;
+                git.write_object(&blob)?;
+                index = git.update_index(index, &blob.calc_hash(), path.clone())?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound && remove => {
+                index.remove(path);
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(unable(path, "does not exist and --remove not passed"))
+            }
+            Err(e) => return Err(e),
+        }
+    }
 
+    git.write_index(&index)?;
+    if needs_update {
+        return Err(exit(1));
+    }
     Ok(())
 }
 
-fn commit<F: FileSystem>(git: &mut Git<F>, message: String) -> io::Result<()> {
+fn commit<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<()> {
+    let mut messages = Vec::new();
+    let mut file = None;
+    let mut author_ident = None;
+    let mut date = None;
+    let mut all = false;
+
+    let mut args = Args::new(args);
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "-a" | "--all" => all = true,
+            "-m" | "--message" => messages.push(args.value()?),
+            "-F" | "--file" => file = Some(args.value()?),
+            "--author" => author_ident = Some(args.value()?),
+            "--date" => date = Some(args.value()?),
+            "-q" | "--quiet" => {}
+            x if x.starts_with('-') => return Err(usage_error(format!("unknown option '{}'", x))),
+            x => return Err(usage_error(format!("unexpected argument '{}'", x))),
+        }
+    }
+    // Each `-m` is a paragraph of its own, as in git.
+    let message = match (file, messages.is_empty()) {
+        (Some(_), false) => {
+            return Err(io::Error::other(
+                "options '-m' and '-F' cannot be used together",
+            ))
+        }
+        (Some(file), true) => {
+            let mut message = String::new();
+            match file.as_str() {
+                "-" => io::stdin().read_to_string(&mut message)?,
+                _ => File::open(&file)
+                    .and_then(|mut x| x.read_to_string(&mut message))
+                    .map_err(|e| {
+                        io::Error::new(e.kind(), format!("could not read log file '{}'", file))
+                    })?,
+            };
+            message
+        }
+        (None, false) => messages.join("\n\n"),
+        (None, true) => return Err(usage_error("no commit message given")),
+    };
+
+    let committer = git.ident(ident::Role::Committer)?;
+    let mut author = match &author_ident {
+        Some(x) => {
+            let (name, email) = ident::parse_ident(x).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("--author '{}' is not 'Name <email>'", x),
+                )
+            })?;
+            let ts = ident::resolve_date(ident::Role::Author, |key| std::env::var(key).ok())?;
+            object::commit::User::new(name, email, ts)
+        }
+        None => git.ident(ident::Role::Author)?,
+    };
+    if let Some(date) = &date {
+        author.ts = ident::parse_date(date).ok_or_else(|| ident::invalid_date(date))?;
+    }
+
+    // git add -u
+    if all {
+        let options = AddOptions {
+            update: true,
+            ..AddOptions::default()
+        };
+        git.add(&Pathspec::parse::<String>(&[])?, &options)?;
+    }
+
     // git write-tree
     let tree = git.write_tree().map(GitObject::Tree)?;
     git.write_object(&tree)?;
@@ -86,71 +2643,804 @@ fn commit<F: FileSystem>(git: &mut Git<F>, message: String) -> io::Result<()> {
     let tree_hash = tree.calc_hash();
     // echo message | git commit-tree <hash>
     let commit = git
-        .commit_tree(
-            "uzimaru0000".to_string(),
-            "shuji365630@gmail.com".to_string(),
-            hex::encode(tree_hash),
-            message,
-        )
+        .commit_tree(author, committer, hex::encode(tree_hash), message)
         .map(GitObject::Commit)?;
     git.write_object(&commit)?;
 
     // git update-ref refs/heads/master <hash>
-    git.update_ref(git.head_ref()?, &commit.calc_hash())?;
+    let reflog = match &commit {
+        GitObject::Commit(x) if x.parents.is_empty() => {
+            format!("commit (initial): {}", pretty::subject(&x.message))
+        }
+        GitObject::Commit(x) => format!("commit: {}", pretty::subject(&x.message)),
+        _ => unreachable!(),
+    };
+    git.update_head(&hex::encode(commit.calc_hash()), &reflog)?;
 
     Ok(())
 }
 
-fn log<F: FileSystem>(git: &mut Git<F>) -> io::Result<Vec<GitObject>> {
-    let commit = git
-        .head_ref()
-        .and_then(|x| git.read_ref(x))
-        .and_then(|x| git.read_object(x))
-        .and_then(|x| git.cat_file_p(&x))?;
-
-    Ok((0..)
-        .scan(Some(commit), |st, _| {
-            let next = match st {
-                Some(GitObject::Commit(commit)) => {
-                    if let Some(parent) = &commit.parent {
-                        git
-                            .read_object(parent.clone())
-                            .and_then(|x| git.cat_file_p(&x))
-                            .ok()
-                    } else {
-                        None
+/// How `log` reads the patterns of `--author` and `--grep`.
+#[derive(Clone, Copy)]
+enum PatternType {
+    Basic,
+    Extended,
+    Fixed,
+}
+
+/// Turns a POSIX basic regular expression, as git reads patterns unless
+/// told otherwise, into the extended syntax `regex` reads: there `\(`,
+/// `\{`, `\|`, `\+` and `\?` are the operators and the characters alone
+/// are literal.
+fn basic_regex(pattern: &str) -> String {
+    let mut out = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c @ ('(' | ')' | '{' | '}' | '|' | '+' | '?')) => out.push(c),
+                Some(c) => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                None => out.push_str("\\\\"),
+            },
+            '(' | ')' | '{' | '}' | '|' | '+' | '?' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn log<F: FileSystem>(git: &Git<F>, args: &[String]) -> io::Result<()> {
+    let mut pretty = Pretty::new(Format::Medium);
+    let mut order = None;
+    let mut max_count = None;
+    let mut since = None;
+    let mut until = None;
+    let mut authors = Vec::new();
+    let mut greps = Vec::new();
+    let mut ignore_case = false;
+    let mut pattern_type = PatternType::Basic;
+    let mut graph = false;
+    let mut first_parent = false;
+    let mut reverse = false;
+    let mut decorate = false;
+    let mut revs = Vec::new();
+    let mut paths = Vec::new();
+
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    let date = |x: String| ident::parse_approxidate(&x).ok_or_else(|| ident::invalid_date(&x));
+    let count = |x: String| {
+        x.parse::<usize>()
+            .map_err(|_| invalid(format!("'{}': not an integer", x)))
+    };
+
+    let mut args = Args::new(args);
+    while let Some(flag) = args.next() {
+        // `-<n>`, short for `-n <n>`.
+        let number = flag
+            .strip_prefix('-')
+            .filter(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+        if let Some(n) = number {
+            max_count = Some(count(n.to_string())?);
+            continue;
+        }
+        match flag.as_str() {
+            "--oneline" => {
+                pretty.format = Format::Oneline;
+                pretty.abbrev = true;
+            }
+            "--pretty" | "--format" => {
+                let x = args.value()?;
+                pretty.format = Format::parse(&x)
+                    .ok_or_else(|| invalid(format!("invalid --pretty format: {}", x)))?;
+            }
+            "--abbrev-commit" => pretty.abbrev = true,
+            "--date" => {
+                let x = args.value()?;
+                pretty.date = DateFormat::parse(&x)
+                    .ok_or_else(|| invalid(format!("unknown date format {}", x)))?;
+            }
+            "-n" | "--max-count" => max_count = Some(count(args.value()?)?),
+            "--since" | "--after" => since = Some(date(args.value()?)?),
+            "--until" | "--before" => until = Some(date(args.value()?)?),
+            "--author" => authors.push(args.value()?),
+            "--grep" => greps.push(args.value()?),
+            "-i" | "--regexp-ignore-case" => ignore_case = true,
+            "--basic-regexp" => pattern_type = PatternType::Basic,
+            "-E" | "--extended-regexp" => pattern_type = PatternType::Extended,
+            "-F" | "--fixed-strings" => pattern_type = PatternType::Fixed,
+            "--graph" => graph = true,
+            "--first-parent" => first_parent = true,
+            "--reverse" => reverse = true,
+            "--decorate" => decorate = true,
+            "--no-decorate" => decorate = false,
+            "--topo-order" => order = Some(Sort::Topo),
+            "--date-order" => order = Some(Sort::Date),
+            "--author-date-order" => order = Some(Sort::AuthorDate),
+            "--" => {
+                paths.extend(args.rest());
+                break;
+            }
+            x if x.starts_with('-') => {
+                return Err(usage_error(format!("unrecognized argument: {}", x)))
+            }
+            x => match parse_range(git, x) {
+                Ok(range) => revs.extend(range),
+                Err(_) if git.file_system.stat(x.to_string()).is_ok() => paths.push(x.to_string()),
+                Err(e) => return Err(e),
+            },
+        }
+    }
+
+    if revs.iter().all(|(_, hidden)| *hidden) {
+        revs.push((git.rev_parse("HEAD")?, false));
+    }
+
+    let mut walk = git.rev_walk();
+    // --graph implies --topo-order, like git.
+    walk.sorting(order.unwrap_or(if graph { Sort::Topo } else { Sort::Default }));
+    walk.first_parent(first_parent);
+    walk.simplify_by_paths(paths);
+    for (hash, hidden) in &revs {
+        if *hidden {
+            walk.hide(hash)?;
+        } else {
+            walk.push(hash)?;
+        }
+    }
+
+    // Like git, a pattern may match any line of the text.
+    let compile = |patterns: Vec<String>| {
+        patterns
+            .into_iter()
+            .map(|x| {
+                let pattern = match pattern_type {
+                    PatternType::Basic => basic_regex(&x),
+                    PatternType::Extended => x.clone(),
+                    PatternType::Fixed => regex::escape(&x),
+                };
+                regex::RegexBuilder::new(&pattern)
+                    .case_insensitive(ignore_case)
+                    .multi_line(true)
+                    .build()
+                    .map_err(|e| invalid(format!("invalid regex '{}': {}", x, e)))
+            })
+            .collect::<io::Result<Vec<_>>>()
+    };
+    let (authors, greps) = (compile(authors)?, compile(greps)?);
+    let matches = |patterns: &[regex::Regex], text: &str| {
+        patterns.is_empty() || patterns.iter().any(|x| x.is_match(text))
+    };
+
+    let mut shown = Vec::new();
+    while let Some(hash) = walk.next() {
+        let hash = hash?;
+        let commit = &walk.commit(&hash).unwrap();
+        let ts = commit.committer.ts;
+        let author = format!("{} <{}>", commit.author.name, commit.author.email);
+
+        if since.is_none_or(|x| ts >= x)
+            && until.is_none_or(|x| ts <= x)
+            && matches(&authors, &author)
+            && matches(&greps, &commit.message)
+        {
+            shown.push(hash);
+            if Some(shown.len()) == max_count {
+                break;
+            }
+        }
+    }
+    if reverse {
+        shown.reverse();
+    }
+    let displayed = shown.iter().cloned().collect::<HashSet<_>>();
+
+    let decorations = if decorate
+        || matches!(&pretty.format, Format::Custom { format, .. } if format.contains("%d") || format.contains("%D"))
+    {
+        decorations(git)?
+    } else {
+        HashMap::new()
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut graph = if graph { Some(Graph::new()) } else { None };
+
+    for (i, hash) in shown.iter().enumerate() {
+        let mut text = pretty.format(
+            hash,
+            walk.commit(hash).unwrap(),
+            decorations
+                .get(hash)
+                .map(|x| x.as_slice())
+                .unwrap_or_default(),
+        );
+
+        let last = i + 1 == shown.len();
+        let lines = text.split('\n').collect::<Vec<_>>();
+        let separator = pretty.format.is_multiline() && !last;
+
+        match graph.as_mut() {
+            Some(graph) => {
+                let parents = rewrite_parents(&walk, &displayed, hash);
+                let rows = graph.next(hash, &parents);
+                let width = rows
+                    .connectors
+                    .iter()
+                    .chain([&rows.commit, &rows.padding])
+                    .map(|x| x.len())
+                    .max()
+                    .unwrap_or_default();
+
+                let mut prefixes = std::iter::once(&rows.commit).chain(rows.connectors.iter());
+                let rest = lines.len().max(rows.connectors.len() + 1);
+                for j in 0..rest {
+                    let prefix = prefixes.next().unwrap_or(&rows.padding);
+                    let line = lines.get(j).copied().unwrap_or_default();
+                    writeln!(out, "{:width$} {}", prefix, line, width = width)?;
+                }
+                if separator {
+                    writeln!(out, "{} ", rows.padding)?;
+                }
+            }
+            None => {
+                if separator {
+                    text.push('\n');
+                }
+                match &pretty.format {
+                    Format::Custom {
+                        terminator: false, ..
+                    } if last => write!(out, "{}", text)?,
+                    _ => writeln!(out, "{}", text)?,
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses `<rev>`, `^<rev>` and `<from>..<to>` into commits to walk from
+/// and commits to hide.
+fn parse_range<F: FileSystem>(git: &Git<F>, arg: &str) -> io::Result<Vec<(String, bool)>> {
+    let rev = |x: &str| {
+        let hash = git.rev_parse(if x.is_empty() { "HEAD" } else { x })?;
+        git.peel(hash, "commit")
+    };
+
+    if let Some((from, to)) = arg.split_once("..") {
+        Ok(vec![(rev(from)?, true), (rev(to)?, false)])
+    } else if let Some(x) = arg.strip_prefix('^') {
+        Ok(vec![(rev(x)?, true)])
+    } else {
+        Ok(vec![(rev(arg)?, false)])
+    }
+}
+
+/// The nearest displayed ancestors of `hash`, skipping commits hidden by
+/// filters so the graph stays connected.
+fn rewrite_parents<F: FileSystem>(
+    walk: &RevWalk<F>,
+    displayed: &HashSet<String>,
+    hash: &str,
+) -> Vec<String> {
+    let mut parents = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack = walk.parents(hash);
+    stack.reverse();
+
+    while let Some(parent) = stack.pop() {
+        if !visited.insert(parent.clone()) {
+            continue;
+        }
+        if displayed.contains(&parent) {
+            parents.push(parent);
+        } else {
+            stack.extend(walk.parents(&parent).into_iter().rev());
+        }
+    }
+
+    parents
+}
+
+/// Ref names to print next to each commit, `HEAD` first.
+fn decorations<F: FileSystem>(git: &Git<F>) -> io::Result<HashMap<String, Vec<String>>> {
+    let mut map: HashMap<String, Vec<String>> = HashMap::new();
+    let head = git.head_ref().ok();
+
+    if let Ok(hash) = git.resolve_ref("HEAD".to_string()) {
+        let label = match head.as_deref().and_then(|x| x.strip_prefix("refs/heads/")) {
+            Some(branch) => format!("HEAD -> {}", branch),
+            None => "HEAD".to_string(),
+        };
+        map.entry(hash).or_default().push(label);
+    }
+
+    for (name, hash) in git.all_refs()? {
+        if head.as_deref() == Some(name.as_str()) {
+            continue;
+        }
+        let label = if let Some(x) = name.strip_prefix("refs/heads/") {
+            x.to_string()
+        } else if let Some(x) = name.strip_prefix("refs/tags/") {
+            format!("tag: {}", x)
+        } else if let Some(x) = name.strip_prefix("refs/remotes/") {
+            x.to_string()
+        } else {
+            name.clone()
+        };
+        // Annotated tags decorate the commit they point at.
+        let hash = git.peel(hash.clone(), "").unwrap_or(hash);
+        map.entry(hash).or_default().push(label);
+    }
+
+    Ok(map)
+}
+
+/// `show`: commits with their patch, trees as a listing, blobs as they
+/// are and tags with their message followed by what they point at.
+fn show<F: FileSystem>(git: &Git<F>, args: &[String]) -> io::Result<()> {
+    let mut pretty = Pretty::new(Format::Medium);
+    let mut header = true;
+    let mut patch = false;
+    let mut stat = false;
+    let mut name_only = false;
+    let mut no_patch = false;
+    let mut dense = true;
+    let mut objects = Vec::new();
+
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    let mut args = Args::new(args);
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--oneline" => {
+                pretty.format = Format::Oneline;
+                pretty.abbrev = true;
+            }
+            "--pretty" | "--format" => {
+                let x = args.value()?;
+                // An empty format leaves out the header altogether.
+                header = !x.is_empty();
+                if header {
+                    pretty.format = Format::parse(&x)
+                        .ok_or_else(|| invalid(format!("invalid --pretty format: {}", x)))?;
+                }
+            }
+            "--abbrev-commit" => pretty.abbrev = true,
+            "--date" => {
+                let x = args.value()?;
+                pretty.date = DateFormat::parse(&x)
+                    .ok_or_else(|| invalid(format!("unknown date format {}", x)))?;
+            }
+            "-p" | "-u" | "--patch" => patch = true,
+            "-s" | "--no-patch" => no_patch = true,
+            "--stat" => stat = true,
+            "--name-only" => name_only = true,
+            "--cc" => dense = true,
+            "-c" => dense = false,
+            "--" => {
+                objects.extend(args.rest());
+                break;
+            }
+            x if x.starts_with('-') => {
+                return Err(usage_error(format!("unrecognized argument: {}", x)))
+            }
+            x => objects.push(x.to_string()),
+        }
+    }
+    if objects.is_empty() {
+        objects.push("HEAD".to_string());
+    }
+    // The patch is the default unless another kind of output is asked for.
+    let patch = !no_patch && (patch || (!stat && !name_only));
+    let (stat, name_only) = (stat && !no_patch, name_only && !no_patch);
+
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    let mut shown_commit = false;
+    for name in &objects {
+        let mut hash = git.rev_parse(name).map_err(|_| ambiguous(name))?;
+        loop {
+            let (object_type, data) = git.read_raw_object(hash.clone())?;
+            match object_type {
+                ObjectType::Blob => out.write_all(&data)?,
+                ObjectType::Tree => {
+                    writeln!(out, "tree {}\n", name)?;
+                    for file in git.read_tree(hash.clone())?.contents {
+                        let slash = if file.mode == FileMode::Tree { "/" } else { "" };
+                        writeln!(out, "{}{}", file.name, slash)?;
                     }
                 }
-                _ => None,
-            };
-            let curr = st.clone();
-            *st = next;
-            curr
-        })
-        .collect::<Vec<_>>())
+                ObjectType::Tag => {
+                    let tag = git.read_tag(hash.clone())?;
+                    writeln!(out, "tag {}", tag.tag)?;
+                    if let Some(tagger) = &tag.tagger {
+                        writeln!(out, "Tagger: {} <{}>", tagger.name, tagger.email)?;
+                        writeln!(out, "Date:   {}", pretty.date.format(&tagger.ts))?;
+                    }
+                    writeln!(out, "\n{}", tag.message)?;
+                    hash = tag.object;
+                    continue;
+                }
+                ObjectType::Commit => {
+                    let commit = git.read_commit(hash.clone())?;
+                    if shown_commit && header && pretty.format.is_multiline() {
+                        writeln!(out)?;
+                    }
+                    shown_commit = true;
+                    let diff = show_diff(git, &commit, patch, stat, name_only, dense)?;
+                    if header {
+                        writeln!(out, "{}", pretty.format(&hash, &commit, &[]))?;
+                        // Only a diff that follows is set off from the
+                        // message, so a clean merge ends with its message.
+                        let merge = commit.parents.len() > 1;
+                        let separate =
+                            !diff.is_empty() && !matches!(&pretty.format, Format::Oneline);
+                        if separate && patch && stat && !merge {
+                            writeln!(out, "---")?;
+                        } else if separate {
+                            writeln!(out)?;
+                        }
+                    }
+                    out.write_all(&diff)?;
+                }
+            }
+            break;
+        }
+    }
+    out.flush()
+}
+
+/// What `show` prints below a commit: its changes against the first
+/// parent, or against all of them at once for a patch of a merge.
+fn show_diff<F: FileSystem>(
+    git: &Git<F>,
+    commit: &object::commit::Commit,
+    patch: bool,
+    stat: bool,
+    name_only: bool,
+    dense: bool,
+) -> io::Result<Vec<u8>> {
+    let trees = commit
+        .parents
+        .iter()
+        .map(|x| Ok(git.read_commit(x.clone())?.tree))
+        .collect::<io::Result<Vec<_>>>()?;
+    let pairs = git.diff_trees(trees.first().cloned(), commit.tree.clone())?;
+    let merge = trees.len() > 1;
+
+    let mut out = Vec::new();
+    if stat && !pairs.is_empty() {
+        git.write_stat(&mut out, &pairs)?;
+    }
+    if name_only {
+        let paths = match merge {
+            true => git.combined_paths(&trees, &commit.tree)?,
+            false => pairs.iter().map(|x| x.path().to_string()).collect(),
+        };
+        for path in paths {
+            writeln!(out, "{}", quote_path(&path))?;
+        }
+    }
+    if patch {
+        let mut text = Vec::new();
+        match merge {
+            true => git.write_combined(&mut text, &trees, &commit.tree, dense)?,
+            false => git.write_patch(&mut text, &pairs)?,
+        }
+        if !out.is_empty() && !text.is_empty() {
+            out.push(b'\n');
+        }
+        out.extend(text);
+    }
+    Ok(out)
 }
 
-fn switch<F: FileSystem>(git: &mut Git<F>, branch: String) -> io::Result<()> {
-    let commit_hash = git.read_ref(format!("refs/heads/{}", branch))?;
-    let diff = git.reset_index(commit_hash.clone())?;
+/// `switch` and `checkout`. `checkout` also accepts any commit and then
+/// detaches `HEAD`; `switch` wants `--detach` for that.
+fn switch<F: FileSystem>(git: &mut Git<F>, args: &[String], checkout: bool) -> io::Result<()> {
+    let mut create = None;
+    let mut force_create = false;
+    let mut detach = false;
+    let mut force = false;
+    let mut rest = Vec::new();
 
-    git.diff_apply(diff)?;
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
 
-    let commit = git
-        .read_object(commit_hash)
-        .and_then(|x| git.cat_file_p(&x))
-        .and_then(|x| match x {
-            GitObject::Commit(commit) => Ok(commit),
-            _ => Err(io::Error::from(io::ErrorKind::InvalidData)),
-        })?;
-    let idx = git.tree2index(commit.tree)?;
+    let mut args = Args::new(args);
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "-c" | "--create" | "-b" | "-C" | "--force-create" | "-B" => {
+                force_create = matches!(flag.as_str(), "-C" | "--force-create" | "-B");
+                create = Some(args.value()?);
+            }
+            "-d" | "--detach" => detach = true,
+            "-f" | "--force" | "--discard-changes" => force = true,
+            "--" if checkout => {
+                let paths = args.rest().collect::<Vec<_>>();
+                if rest.len() > 1 {
+                    return Err(usage_error("only one reference expected"));
+                }
+                return checkout_paths(git, rest.pop(), &paths, false);
+            }
+            x if x.starts_with('-') => {
+                return Err(usage_error(format!("unrecognized argument: {}", x)))
+            }
+            _ => rest.push(flag),
+        }
+    }
+    // Without "--", anything but a single branch or commit names paths.
+    if checkout && create.is_none() && !rest.is_empty() {
+        let first = &rest[0];
+        if git.rev_parse(first).is_err() {
+            return checkout_paths(git, None, &rest, true);
+        }
+        if rest.len() > 1 {
+            let paths = rest.split_off(1);
+            return checkout_paths(git, rest.pop(), &paths, true);
+        }
+    }
+    if rest.len() > 1 {
+        return Err(usage_error("only one reference expected"));
+    }
+    if let Some(new) = create.as_deref().filter(|x| !is_valid_branch_name(x)) {
+        return Err(io::Error::other(format!(
+            "'{}' is not a valid branch name",
+            new
+        )));
+    }
+    let target = rest.first().cloned();
+
+    let branch_ref = |name: &str| format!("refs/heads/{}", name);
+    let is_branch = |name: &str| git.read_ref(branch_ref(name)).is_ok();
+
+    let (commit, branch) = match (&create, &target) {
+        (Some(new), start) => {
+            if is_branch(new) && !force_create {
+                return Err(invalid(format!("a branch named '{}' already exists", new)));
+            }
+            let start = git.rev_parse(start.as_deref().unwrap_or("HEAD"))?;
+            (git.peel(start, "commit")?, Some(new.clone()))
+        }
+        (None, Some(name)) if !detach && is_branch(name) => {
+            (git.resolve_ref(branch_ref(name))?, Some(name.clone()))
+        }
+        (None, Some(name)) => {
+            let hash = git
+                .rev_parse(name)
+                .map_err(|_| invalid(format!("invalid reference: {}", name)))?;
+            if !checkout && !detach {
+                return Err(invalid(format!(
+                    "a branch is expected, got '{}'\nhint: If you want to detach HEAD at the commit, try again with the --detach option.",
+                    name
+                )));
+            }
+            (git.peel(hash, "commit")?, None)
+        }
+        (None, None) if detach => (git.rev_parse("HEAD")?, None),
+        (None, None) => return Err(usage_error("missing branch or commit argument")),
+    };
+
+    let current = git.head_ref().ok();
+    if let (Some(branch), Some(current), None) = (&branch, &current, &create) {
+        if *current == branch_ref(branch) {
+            eprintln!("Already on '{}'", branch);
+            return Ok(());
+        }
+    }
+
+    let from = match &current {
+        Some(current) => current.trim_start_matches("refs/heads/").to_string(),
+        None => git.rev_parse("HEAD")?,
+    };
+    let old = git.resolve_ref("HEAD".to_string()).ok();
+    git.checkout(commit.clone(), force)?;
 
-    git.file_system.write(
-        ".git/HEAD".to_string(),
-        format!("ref: refs/heads/{}", branch).as_bytes(),
-    )?;
+    match &branch {
+        Some(branch) => {
+            if create.is_some() {
+                git.write_ref(branch_ref(branch), &hex::decode(&commit).unwrap())?;
+            }
+            git.write_symref("HEAD".to_string(), &branch_ref(branch))?;
+            if create.is_some() {
+                eprintln!("Switched to a new branch '{}'", branch);
+            } else {
+                eprintln!("Switched to branch '{}'", branch);
+            }
+        }
+        None => {
+            git.write_ref("HEAD".to_string(), &hex::decode(&commit).unwrap())?;
+            let subject = pretty::subject(&git.read_commit(commit.clone())?.message);
+            eprintln!("HEAD is now at {} {}", pretty::abbrev(&commit), subject);
+        }
+    }
 
-    git.write_index(&idx)?;
+    if let Some(branch) = branch.as_ref().filter(|_| create.is_some()) {
+        let message = format!(
+            "branch: Created from {}",
+            target.as_deref().unwrap_or("HEAD")
+        );
+        git.append_reflog(&branch_ref(branch), None, &commit, &message)?;
+    }
+    let to = branch.unwrap_or_else(|| commit.clone());
+    let message = format!("checkout: moving from {} to {}", from, to);
+    git.append_reflog("HEAD", old.as_deref(), &commit, &message)?;
 
     Ok(())
 }
+
+fn config<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<()> {
+    let mut level = None;
+    let mut file = None;
+    let mut action = None;
+    let mut value_type = None;
+    let mut rest = Vec::new();
+
+    // Options stop at the name, so that a value may start with `-`.
+    let mut args = Args::new(args);
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--system" => level = Some(Level::System),
+            "--global" => level = Some(Level::Global),
+            "--local" => level = Some(Level::Local),
+            "-f" | "--file" => file = Some(args.value()?),
+            "--type" => value_type = Some(args.value()?),
+            "--bool" | "--int" | "--path" => value_type = Some(flag[2..].to_string()),
+            "-l" => action = Some("--list".to_string()),
+            "--get" | "--get-all" | "--unset" | "--unset-all" | "--add" | "--list"
+            | "--replace-all" | "--remove-section" | "--rename-section" => action = Some(flag),
+            "--" => {
+                rest.extend(args.rest());
+                break;
+            }
+            x if x.starts_with('-') => return Err(usage_error(format!("unknown option '{}'", x))),
+            _ => {
+                rest.push(flag);
+                rest.extend(args.rest());
+                break;
+            }
+        }
+    }
+
+    let action = action.unwrap_or_else(|| match rest.len() {
+        1 => "--get".to_string(),
+        _ => "--set".to_string(),
+    });
+    let invalid = || usage_error("wrong number of arguments");
+
+    let read = |git: &Git<F>| -> io::Result<Config> {
+        match (&file, level) {
+            (Some(path), _) => {
+                let mut config = Config::new();
+                config.load(&git.file_system, path, Level::Local, &Default::default())?;
+                Ok(config)
+            }
+            (None, Some(level)) => git.config_level(level),
+            (None, None) => git.config(),
+        }
+    };
+    let open = |git: &Git<F>| -> io::Result<ConfigFile> {
+        match &file {
+            Some(path) => ConfigFile::open(&git.file_system, path.clone()),
+            None => git.config_file(level.unwrap_or(Level::Local)),
+        }
+    };
+
+    match action.as_str() {
+        "--list" => {
+            read(git)?.entries.iter().for_each(|x| println!("{}", x));
+            Ok(())
+        }
+        "--get" | "--get-all" => {
+            let key = rest.first().ok_or_else(invalid)?;
+            let config = read(git)?;
+            let values = if action == "--get" {
+                config.get(key).into_iter().collect::<Vec<_>>()
+            } else {
+                config.get_all(key)
+            };
+
+            if values.is_empty() {
+                return Err(exit(1));
+            }
+            values.iter().try_for_each(|x| {
+                println!("{}", format_config_value(x, value_type.as_deref())?);
+                Ok(())
+            })
+        }
+        "--set" | "--add" | "--replace-all" => {
+            let (key, value) = match rest.as_slice() {
+                [key, value] => (key, format_config_value(value, value_type.as_deref())?),
+                _ => return Err(invalid()),
+            };
+            let mut config_file = open(git)?;
+            match action.as_str() {
+                "--set" => config_file.set(key, &value)?,
+                "--add" => config_file.add(key, &value)?,
+                _ => {
+                    config_file.unset(key, true)?;
+                    config_file.add(key, &value)?;
+                }
+            }
+            git.write_config_file(&config_file)
+        }
+        "--unset" | "--unset-all" => {
+            let key = rest.first().ok_or_else(invalid)?;
+            let mut config_file = open(git)?;
+            let removed = match config_file.unset(key, action == "--unset-all") {
+                // More than one value, which `--unset` leaves alone.
+                Err(e)
+                    if config::Key::parse(key).is_some()
+                        && e.kind() == io::ErrorKind::InvalidInput =>
+                {
+                    eprintln!("warning: {}", e);
+                    return Err(exit(5));
+                }
+                x => x?,
+            };
+            if removed == 0 {
+                return Err(exit(5));
+            }
+            git.write_config_file(&config_file)
+        }
+        "--remove-section" | "--rename-section" => {
+            let (name, new_name) = match rest.as_slice() {
+                [name] if action == "--remove-section" => (name, None),
+                [name, new_name] if action == "--rename-section" => (name, Some(new_name)),
+                _ => return Err(invalid()),
+            };
+            let (section, subsection) = split_section(name);
+            let mut config_file = open(git)?;
+            let found = match new_name {
+                Some(new_name) => {
+                    let (new_section, new_subsection) = split_section(new_name);
+                    config_file.rename_section(
+                        &section,
+                        subsection,
+                        &new_section,
+                        new_subsection,
+                    )?
+                }
+                None => config_file.remove_section(&section, subsection)?,
+            };
+            if !found {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "no such section"));
+            }
+            git.write_config_file(&config_file)
+        }
+        _ => Err(invalid()),
+    }
+}
+
+fn split_section(name: &str) -> (String, Option<&str>) {
+    match name.find('.') {
+        Some(pos) => (name[..pos].to_string(), Some(&name[pos + 1..])),
+        None => (name.to_string(), None),
+    }
+}
+
+fn format_config_value(value: &str, value_type: Option<&str>) -> io::Result<String> {
+    let bad = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("bad config value '{}'", value),
+        )
+    };
+
+    match value_type {
+        Some("bool") => config::parse_bool(value)
+            .map(|x| x.to_string())
+            .ok_or_else(bad),
+        Some("int") => config::parse_int(value)
+            .map(|x| x.to_string())
+            .ok_or_else(bad),
+        Some("path") => Ok(config::expand_path(value)),
+        _ => Ok(value.to_string()),
+    }
+}
@@ -0,0 +1,234 @@
+use crate::fs::FileSystem;
+use crate::index::Index;
+use crate::pathspec::normalize;
+use crate::Git;
+use std::io;
+
+#[derive(Debug, Clone, Default)]
+pub struct MvOptions {
+    /// Overwrite an existing destination file (`-f`).
+    pub force: bool,
+    /// Leave out sources that can't be moved instead of failing (`-k`).
+    pub skip_errors: bool,
+    pub dry_run: bool,
+}
+
+impl<F: FileSystem> Git<F> {
+    /// Moves tracked files and directories, in the worktree and in the
+    /// index. With one source, `destination` is the new name unless it is
+    /// an existing directory; otherwise the sources are moved into it.
+    ///
+    /// Returns every rename as `(source, destination)`: a directory first,
+    /// then each of the files in it.
+    pub fn mv(
+        &mut self,
+        sources: &[String],
+        destination: &str,
+        options: &MvOptions,
+    ) -> io::Result<Vec<(String, String)>> {
+        let mut index = match self.read_index() {
+            Ok(bytes) => self.ls_files_stage(&bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Index::new(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let destination = normalize(destination);
+        let into_dir = match self.file_system.stat(destination.clone()) {
+            Ok(metadata) => metadata.is_dir(),
+            Err(_) => false,
+        };
+        if sources.len() > 1 && !into_dir {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("destination '{}' is not a directory", destination),
+            ));
+        }
+
+        let mut moves: Vec<(String, String)> = Vec::new();
+        let mut renames = Vec::new();
+        for source in sources {
+            let source = normalize(source);
+            let target = if into_dir {
+                let base = source.rsplit('/').next().unwrap_or(&source);
+                format!("{}/{}", destination, base)
+                    .trim_start_matches('/')
+                    .to_string()
+            } else {
+                destination.clone()
+            };
+
+            let files = match self.check_move(&index, &source, &target, options) {
+                Ok(_) if moves.iter().any(|(_, x)| *x == target) => {
+                    Err("multiple sources for the same target")
+                }
+                x => x,
+            };
+            let files = match files {
+                Ok(files) => files,
+                Err(_) if options.skip_errors => continue,
+                Err(msg) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{}, source={}, destination={}", msg, source, target),
+                    ))
+                }
+            };
+
+            if files != [source.clone()] {
+                renames.push((source.clone(), target.clone()));
+            }
+            renames.extend(files.into_iter().map(|x| {
+                let moved = format!("{}{}", target, &x[source.len()..]);
+                (x, moved)
+            }));
+            moves.push((source, target));
+        }
+        if options.dry_run {
+            return Ok(renames);
+        }
+
+        for (source, target) in &moves {
+            self.file_system.rename(source.clone(), target.clone())?;
+        }
+        for (source, target) in &renames {
+            if let Some(mut entry) = index.remove(source) {
+                entry.name = target.clone();
+                index.add(entry);
+            }
+        }
+        self.write_index(&index)?;
+
+        Ok(renames)
+    }
+
+    /// The tracked files moving `source` to `target` involves: the file
+    /// itself, or all the files in a directory.
+    fn check_move(
+        &self,
+        index: &Index,
+        source: &str,
+        target: &str,
+        options: &MvOptions,
+    ) -> Result<Vec<String>, &'static str> {
+        let metadata = match self.file_system.stat(source.to_string()) {
+            Ok(metadata) => metadata,
+            Err(_) => return Err("bad source"),
+        };
+        if target == source || target.starts_with(&format!("{}/", source)) {
+            return Err("can not move directory into itself");
+        }
+
+        let files = if metadata.is_dir() {
+            let dir = format!("{}/", source);
+            let files = index
+                .entries
+                .iter()
+                .filter(|x| x.name.starts_with(&dir))
+                .map(|x| x.name.clone())
+                .collect::<Vec<_>>();
+            if files.is_empty() {
+                return Err("source directory is empty");
+            }
+            files
+        } else if index.get(source).is_some() {
+            vec![source.to_string()]
+        } else {
+            return Err("not under version control");
+        };
+
+        match self.file_system.stat(target.to_string()) {
+            Ok(existing) if !options.force || existing.is_dir() || metadata.is_dir() => {
+                return Err("destination exists")
+            }
+            _ => {}
+        }
+        if let Some((parent, _)) = target.rsplit_once('/') {
+            match self.file_system.stat(parent.to_string()) {
+                Ok(metadata) if metadata.is_dir() => {}
+                _ => return Err("destination directory does not exist"),
+            }
+        }
+
+        Ok(files)
+    }
+}
+
+#[test]
+fn test_mv() {
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::pathspec::Pathspec;
+
+    let mut git = Git::new(InMemFileSystem::init());
+    git.file_system.create_dir("d/e".to_string()).unwrap();
+    git.file_system.create_dir("to".to_string()).unwrap();
+    for name in ["a", "b", "d/x", "d/e/y"] {
+        git.file_system
+            .write(name.to_string(), name.as_bytes())
+            .unwrap();
+    }
+    let spec = Pathspec::parse(&["."]).unwrap();
+    git.add(&spec, &Default::default()).unwrap();
+    git.file_system.write("untracked".to_string(), b"").unwrap();
+    let names = |git: &Git<InMemFileSystem>| {
+        let index = git.ls_files_stage(&git.read_index().unwrap()).unwrap();
+        index
+            .entries
+            .into_iter()
+            .map(|x| x.name)
+            .collect::<Vec<_>>()
+    };
+    let args = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+    let mv = |git: &mut Git<InMemFileSystem>, sources: &[&str], dst: &str, options| {
+        git.mv(&args(sources), dst, options)
+            .map_err(|e| e.to_string())
+    };
+    let default = MvOptions::default();
+
+    assert_eq!(
+        mv(&mut git, &["a"], "b", &default).unwrap_err(),
+        "destination exists, source=a, destination=b"
+    );
+    assert_eq!(
+        mv(&mut git, &["untracked"], "u", &default).unwrap_err(),
+        "not under version control, source=untracked, destination=u"
+    );
+    assert_eq!(
+        mv(&mut git, &["d"], "d/e", &default).unwrap_err(),
+        "can not move directory into itself, source=d, destination=d/e/d"
+    );
+    assert_eq!(
+        mv(&mut git, &["a", "b"], "c", &default).unwrap_err(),
+        "destination 'c' is not a directory"
+    );
+
+    let renames = mv(&mut git, &["d"], "moved", &default).unwrap();
+    assert_eq!(
+        renames,
+        vec![
+            ("d".to_string(), "moved".to_string()),
+            ("d/e/y".to_string(), "moved/e/y".to_string()),
+            ("d/x".to_string(), "moved/x".to_string()),
+        ]
+    );
+    assert_eq!(names(&git), vec!["a", "b", "moved/e/y", "moved/x"]);
+    assert_eq!(
+        git.file_system.read("moved/e/y".to_string()).unwrap(),
+        b"d/e/y"
+    );
+
+    // `-k` skips the sources that can't be moved.
+    let options = MvOptions {
+        skip_errors: true,
+        ..MvOptions::default()
+    };
+    mv(&mut git, &["a", "untracked"], "to", &options).unwrap();
+    assert_eq!(names(&git), vec!["b", "moved/e/y", "moved/x", "to/a"]);
+
+    let options = MvOptions {
+        force: true,
+        ..MvOptions::default()
+    };
+    mv(&mut git, &["b"], "to/a", &options).unwrap();
+    assert_eq!(names(&git), vec!["moved/e/y", "moved/x", "to/a"]);
+    assert_eq!(git.file_system.read("to/a".to_string()).unwrap(), b"b");
+}
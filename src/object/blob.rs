@@ -32,7 +32,7 @@ impl Blob {
     }
 
     pub fn calc_hash(&self) -> Vec<u8> {
-        Vec::from(Sha1::digest(&self.as_bytes()).as_slice())
+        Sha1::digest(&self.as_bytes()).to_vec()
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
@@ -43,6 +43,12 @@ impl Blob {
     }
 }
 
+/// Hashes raw file content as a blob, without requiring it to be UTF-8.
+pub fn hash_bytes(bytes: &[u8]) -> Vec<u8> {
+    let header = format!("{} {}\0", ObjectType::Blob, bytes.len());
+    Sha1::digest(&[header.as_bytes(), bytes].concat()).to_vec()
+}
+
 impl fmt::Display for Blob {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.content)
@@ -10,77 +10,106 @@ pub struct User {
     pub name: String,
     pub email: String,
     pub ts: DateTime<FixedOffset>,
+    /// The original ident text, kept only when it would not survive being
+    /// formatted again (e.g. a `-0000` zone or a missing date) so that the
+    /// object hash is preserved.
+    #[cfg_attr(feature = "json", serde(skip_serializing_if = "Option::is_none"))]
+    pub raw: Option<String>,
 }
 
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "json", derive(Serialize))]
 pub struct Commit {
     pub tree: String,
-    pub parent: Option<String>,
+    pub parents: Vec<String>,
     pub author: User,
     pub committer: User,
+    pub extra_headers: Vec<(String, String)>,
     pub message: String,
 }
 
 impl User {
     pub fn new(name: String, email: String, ts: DateTime<FixedOffset>) -> Self {
-        Self { name, email, ts }
+        Self {
+            name,
+            email,
+            ts,
+            raw: None,
+        }
     }
 
+    /// Parses `Name <email> <seconds> <±HHMM>` as leniently as git does:
+    /// a missing or garbled date falls back to the epoch and a missing or
+    /// garbled zone to `+0000`.
     pub fn from(bytes: &[u8]) -> Option<Self> {
-        let name = String::from_utf8(
-            bytes
-                .into_iter()
-                .take_while(|&&x| x != b'<')
-                .map(|&x| x)
-                .collect(),
-        )
-        .map(|x| String::from(x.trim()))
-        .ok()?;
-
-        let info = String::from_utf8(
-            bytes
-                .into_iter()
-                .skip_while(|&&x| x != b'<')
-                .map(|&x| x)
-                .collect(),
-        )
-        .ok()?;
+        let line = String::from_utf8(bytes.to_vec()).ok()?;
 
-        let mut info_iter = info.splitn(3, " ");
+        let email_begin = line.find('<')?;
+        let email_end = email_begin + line[email_begin..].find('>')?;
 
-        let email = info_iter
+        let name = line[..email_begin].trim().to_string();
+        let email = line[email_begin + 1..email_end].to_string();
+
+        let mut date = line[line.rfind('>')? + 1..].split_whitespace();
+        let ts = date
             .next()
-            .map(|x| String::from(x.trim_matches(|x| x == '<' || x == '>')))?;
-        let ts = Utc.timestamp(info_iter.next().and_then(|x| x.parse::<i64>().ok())?, 0);
-        let offset = info_iter
+            .and_then(|x| x.parse::<i64>().ok())
+            .and_then(|x| Utc.timestamp_opt(x, 0).single())
+            .unwrap_or_default();
+        let offset = date
             .next()
-            .and_then(|x| x.parse::<i32>().ok())
-            .map(|x| {
-                if x < 0 {
-                    FixedOffset::west(x / 100 * 60 * 60)
-                } else {
-                    FixedOffset::east(x / 100 * 60 * 60)
-                }
-            })?;
+            .and_then(parse_offset)
+            .unwrap_or_else(|| FixedOffset::east_opt(0).unwrap());
 
-        Some(Self::new(
-            name,
-            email,
-            offset.from_utc_datetime(&ts.naive_utc()),
-        ))
+        let mut user = Self::new(name, email, offset.from_utc_datetime(&ts.naive_utc()));
+        if user.to_string() != line {
+            user.raw = Some(line);
+        }
+
+        Some(user)
     }
 }
 
+/// Parses a `±HHMM` zone. Like git, the digits are read as a number so
+/// short forms such as `+530` are accepted too.
+pub fn parse_offset(tz: &str) -> Option<FixedOffset> {
+    let (sign, digits) = match tz.as_bytes().first()? {
+        b'+' => (1, &tz[1..]),
+        b'-' => (-1, &tz[1..]),
+        _ => return None,
+    };
+    if digits.is_empty() || digits.len() > 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let value = digits.parse::<i32>().ok()?;
+    let (hours, minutes) = (value / 100, value % 100);
+    if minutes >= 60 {
+        return None;
+    }
+
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
 impl fmt::Display for User {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(raw) = &self.raw {
+            return write!(f, "{}", raw);
+        }
+
+        let offset = self.ts.offset().local_minus_utc();
+        let sign = if offset < 0 { '-' } else { '+' };
+        let offset = offset.abs();
+
         write!(
             f,
-            "{} <{}> {} {:+05}",
+            "{} <{}> {} {}{:02}{:02}",
             self.name,
             self.email,
             self.ts.timestamp(),
-            self.ts.offset().local_minus_utc() / 36
+            sign,
+            offset / 3600,
+            offset % 3600 / 60
         )
     }
 }
@@ -88,88 +117,80 @@ impl fmt::Display for User {
 impl Commit {
     pub fn new(
         tree: String,
-        parent: Option<String>,
+        parents: Vec<String>,
         author: User,
         committer: User,
         message: String,
     ) -> Self {
         Self {
             tree,
-            parent,
+            parents,
             author,
             committer,
+            extra_headers: Vec::new(),
             message,
         }
     }
 
     pub fn from(bytes: &[u8]) -> Option<Self> {
-        let mut iter = bytes.split(|&x| x == b'\n').filter(|x| x != b"");
+        let (header, message) = match bytes.windows(2).position(|x| x == b"\n\n") {
+            Some(pos) => (&bytes[..pos], &bytes[pos + 2..]),
+            None => (bytes.strip_suffix(b"\n").unwrap_or(bytes), &b""[..]),
+        };
 
-        let tree = iter
-            .next()
-            .map(|x| {
-                x.splitn(2, |&x| x == b' ')
-                    .skip(1)
-                    .flatten()
-                    .map(|&x| x)
-                    .collect::<Vec<_>>()
-            })
-            .and_then(|x| String::from_utf8(x).ok())?;
-
-        let parent = &iter
-            .next()
-            .map(|x| {
-                x.splitn(2, |&x| x == b' ')
-                    .map(Vec::from)
-                    .map(|x| String::from_utf8(x).ok().unwrap_or_default())
-                    .collect::<Vec<_>>()
-            })
-            .ok_or(Vec::new())
-            .and_then(|x| match x[0].as_str() {
-                "parent" => Ok(x[1].clone()),
-                _ => Err([x[0].as_bytes(), b" ", x[1].as_bytes()].concat()),
+        // Continuation lines of multi-line headers (e.g. `gpgsig`) start
+        // with a space.
+        let headers = header
+            .split(|&x| x == b'\n')
+            .fold(Vec::<Vec<u8>>::new(), |mut acc, line| {
+                match (line.first(), acc.last_mut()) {
+                    (Some(b' '), Some(last)) => {
+                        last.push(b'\n');
+                        last.extend_from_slice(&line[1..]);
+                    }
+                    _ => acc.push(line.to_vec()),
+                }
+                acc
             });
 
-        let author = match parent {
-            Ok(_) => iter.next().map(|x| Vec::from(x)),
-            Err(v) => Some(v.clone()),
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        let mut extra_headers = Vec::new();
+
+        for line in headers {
+            let line = String::from_utf8(line).ok()?;
+            let (key, value) = match line.find(' ') {
+                Some(pos) => (&line[..pos], &line[pos + 1..]),
+                None => (&line[..], ""),
+            };
+
+            match key {
+                "tree" if tree.is_none() => tree = Some(value.to_string()),
+                "parent" if author.is_none() => parents.push(value.to_string()),
+                "author" if author.is_none() => author = User::from(value.as_bytes()),
+                "committer" if committer.is_none() => {
+                    committer = User::from(value.as_bytes())
+                }
+                _ => extra_headers.push((key.to_string(), value.to_string())),
+            }
         }
-        .map(|x| {
-            x.splitn(2, |&x| x == b' ')
-                .skip(1)
-                .flatten()
-                .map(|&x| x)
-                .collect::<Vec<_>>()
-        })
-        .and_then(|x| User::from(x.as_slice()))?;
 
-        let committer = iter
-            .next()
-            .map(|x| {
-                x.splitn(2, |&x| x == b' ')
-                    .skip(1)
-                    .flatten()
-                    .map(|&x| x)
-                    .collect::<Vec<_>>()
-            })
-            .and_then(|x| User::from(x.as_slice()))?;
-
-        let message = iter
-            .next()
-            .map(Vec::from)
-            .and_then(|x| String::from_utf8(x).ok())?;
+        let message = String::from_utf8(message.to_vec()).ok()?;
 
-        Some(Self::new(
-            tree,
-            parent.clone().ok(),
-            author,
-            committer,
+        Some(Self {
+            tree: tree?,
+            parents,
+            author: author?,
+            committer: committer?,
+            extra_headers,
             message,
-        ))
+        })
     }
 
     pub fn calc_hash(&self) -> Vec<u8> {
-        Vec::from(Sha1::digest(&self.as_bytes()).as_slice())
+        Sha1::digest(&self.as_bytes()).to_vec()
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
@@ -185,17 +206,123 @@ impl fmt::Display for Commit {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let tree = format!("tree {}", self.tree);
         let parent = self
-            .parent
-            .clone()
+            .parents
+            .iter()
             .map(|x| format!("parent {}\n", x))
-            .unwrap_or_default();
+            .collect::<String>();
         let author = format!("author {}", self.author);
         let committer = format!("committer {}", self.committer);
+        let extra_headers = self
+            .extra_headers
+            .iter()
+            .map(|(key, value)| format!("{} {}\n", key, value.replace('\n', "\n ")))
+            .collect::<String>();
 
         write!(
             f,
-            "{}\n{}{}\n{}\n\n{}\n",
-            tree, parent, author, committer, self.message
+            "{}\n{}{}\n{}\n{}\n{}",
+            tree, parent, author, committer, extra_headers, self.message
         )
     }
 }
+
+#[cfg(test)]
+fn assert_round_trip(content: &[u8], hash: &str) -> Commit {
+    let commit = Commit::from(content).expect("commit should parse");
+    assert_eq!(format!("{}", commit).as_bytes(), content);
+    assert_eq!(hex::encode(commit.calc_hash()), hash);
+    commit
+}
+
+#[test]
+fn test_user_offset() {
+    let user = User::from(b"Ravi <r@ex.in> 1600000000 +0530").unwrap();
+    assert_eq!(user.ts.offset().local_minus_utc(), 5 * 3600 + 30 * 60);
+    assert_eq!(user.to_string(), "Ravi <r@ex.in> 1600000000 +0530");
+    assert!(user.raw.is_none());
+
+    let user = User::from(b"Sita <s@ex.np> 1600000000 +0545").unwrap();
+    assert_eq!(user.ts.offset().local_minus_utc(), 5 * 3600 + 45 * 60);
+    assert_eq!(user.to_string(), "Sita <s@ex.np> 1600000000 +0545");
+
+    let user = User::from(b"Nfld <n@ex.ca> 1600000000 -0330").unwrap();
+    assert_eq!(user.ts.offset().local_minus_utc(), -(3 * 3600 + 30 * 60));
+    assert_eq!(user.to_string(), "Nfld <n@ex.ca> 1600000000 -0330");
+
+    let offset = FixedOffset::west_opt(9 * 3600 + 30 * 60).unwrap();
+    let user = User::new(
+        "Marquesas".to_string(),
+        "m@ex.pf".to_string(),
+        offset.timestamp_opt(1600000000, 0).unwrap(),
+    );
+    assert_eq!(user.to_string(), "Marquesas <m@ex.pf> 1600000000 -0930");
+}
+
+#[test]
+fn test_user_malformed() {
+    let user = User::from(b"No Date <nodate@ex.org>").unwrap();
+    assert_eq!(user.name, "No Date");
+    assert_eq!(user.email, "nodate@ex.org");
+    assert_eq!(user.ts.timestamp(), 0);
+    assert_eq!(user.to_string(), "No Date <nodate@ex.org>");
+
+    let user = User::from(b" <> 1600000000").unwrap();
+    assert_eq!(user.name, "");
+    assert_eq!(user.ts.timestamp(), 1600000000);
+    assert_eq!(user.to_string(), " <> 1600000000");
+
+    let user = User::from(b"Bad Zone <b@ex.org> 1600000000 +05x0").unwrap();
+    assert_eq!(user.ts.offset().local_minus_utc(), 0);
+
+    assert!(User::from(b"no email at all").is_none());
+}
+
+#[test]
+fn test_commit_round_trip_offsets() {
+    let commit = assert_round_trip(
+        b"tree 4d593e935186bcc35450336864a1aad148210a14\nauthor Ravi <r@ex.in> 1600000000 +0530\ncommitter Ravi <r@ex.in> 1600000000 +0530\n\ntz +0530\n\nmulti-line body\n  indented\n",
+        "f9b137fe0a8bcad3a3e3a2a69a60bc5ce3d7f210",
+    );
+    assert_eq!(commit.message, "tz +0530\n\nmulti-line body\n  indented\n");
+
+    let commit = assert_round_trip(
+        b"tree 4d593e935186bcc35450336864a1aad148210a14\nparent 8b3ed889259bbc85def499f8d73e5c138c85029d\nauthor Ravi <r@ex.in> 1600000000 -0330\ncommitter Ravi <r@ex.in> 1600000000 -0330\n\ntz -0330\n\nmulti-line body\n  indented\n",
+        "fe152caf9a62e68a38a726cd29270606faa4385f",
+    );
+    assert_eq!(commit.parents, vec!["8b3ed889259bbc85def499f8d73e5c138c85029d"]);
+
+    let commit = assert_round_trip(
+        b"tree 4d593e935186bcc35450336864a1aad148210a14\nauthor Old Importer <old@ex.org> 1000000000 -0000\ncommitter Old Importer <old@ex.org> 1000000000 -0000\n\nimported\n",
+        "a7a5184500a157307aab4d4f4e69d205c85964e2",
+    );
+    assert_eq!(commit.author.ts.offset().local_minus_utc(), 0);
+}
+
+#[test]
+fn test_commit_round_trip_headers() {
+    let commit = assert_round_trip(
+        b"tree 4d593e935186bcc35450336864a1aad148210a14\nauthor Signer <s@ex.org> 1600000000 +0100\ncommitter Signer <s@ex.org> 1600000000 +0100\ngpgsig -----BEGIN PGP SIGNATURE-----\n \n iQEzBAABCAAdFiEE\n -----END PGP SIGNATURE-----\n\nsigned\n",
+        "5bc9d444acf94ebed6f20eb4581abfaec8514f57",
+    );
+    assert_eq!(commit.extra_headers[0].0, "gpgsig");
+
+    assert_round_trip(
+        b"tree 4d593e935186bcc35450336864a1aad148210a14\nauthor No Date <nodate@ex.org>\ncommitter  <> 1600000000\n\nbroken idents\n",
+        "f0d001d52ff6d92db34d405da2f627a359d3899c",
+    );
+}
+
+#[test]
+fn test_commit_round_trip_merge() {
+    let commit = assert_round_trip(
+        b"tree 4d593e935186bcc35450336864a1aad148210a14\nparent fe152caf9a62e68a38a726cd29270606faa4385f\nparent 1e0d683b1a5ef3bd2056604d1f578d4a58a4e255\nauthor M <m@x> 1600000100 +0000\ncommitter M <m@x> 1600000100 +0000\n\nMerge branch 'side'\n",
+        "af4ef8a17ad65221116c1984a265af7babb9a8bb",
+    );
+    assert_eq!(
+        commit.parents,
+        vec![
+            "fe152caf9a62e68a38a726cd29270606faa4385f",
+            "1e0d683b1a5ef3bd2056604d1f578d4a58a4e255"
+        ]
+    );
+}
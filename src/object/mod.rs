@@ -1,5 +1,6 @@
 pub mod blob;
 pub mod commit;
+pub mod tag;
 pub mod tree;
 
 use blob::Blob;
@@ -7,13 +8,16 @@ use commit::Commit;
 #[cfg(feature = "json")]
 use serde::ser::{Serialize, SerializeStruct, Serializer};
 use std::fmt;
+use std::io;
+use tag::Tag;
 use tree::Tree;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ObjectType {
     Blob,
     Tree,
     Commit,
+    Tag,
 }
 
 impl ObjectType {
@@ -24,40 +28,68 @@ impl ObjectType {
             "blob" => Some(ObjectType::Blob),
             "tree" => Some(ObjectType::Tree),
             "commit" => Some(ObjectType::Commit),
+            "tag" => Some(ObjectType::Tag),
             _ => None,
         }
     }
 
-    pub fn to_string(self) -> String {
+}
+
+impl fmt::Display for ObjectType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            ObjectType::Blob => String::from("blob"),
-            ObjectType::Tree => String::from("tree"),
-            ObjectType::Commit => String::from("commit"),
+            ObjectType::Blob => write!(f, "blob"),
+            ObjectType::Tree => write!(f, "tree"),
+            ObjectType::Commit => write!(f, "commit"),
+            ObjectType::Tag => write!(f, "tag"),
         }
     }
 }
 
 #[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum GitObject {
     Blob(Blob),
     Tree(Tree),
     Commit(Commit),
+    Tag(Tag),
 }
 
 impl GitObject {
     pub fn new(bytes: &[u8]) -> Option<Self> {
+        Self::parse(bytes).ok()
+    }
+
+    /// Like `new`, but says what is wrong with a malformed object.
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
         let mut iter = bytes.splitn(2, |&byte| byte == b'\0');
 
         let obj_type = iter
             .next()
             .and_then(|x| String::from_utf8(x.to_vec()).ok())
-            .and_then(|x| ObjectType::from(&x))?;
+            .and_then(|x| ObjectType::from(&x))
+            .ok_or_else(|| invalid("bad object header"))?;
+        let body = iter.next().ok_or_else(|| invalid("bad object header"))?;
+        Self::from_raw(obj_type, body)
+    }
 
-        iter.next().and_then(|x| match obj_type {
-            ObjectType::Blob => Blob::from(x).map(GitObject::Blob),
-            ObjectType::Tree => Tree::from(x).map(GitObject::Tree),
-            ObjectType::Commit => Commit::from(x).map(GitObject::Commit),
-        })
+    /// Parses the content of an object of type `obj_type`, without the
+    /// header `parse` expects.
+    pub fn from_raw(obj_type: ObjectType, body: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        match obj_type {
+            ObjectType::Blob => Blob::from(body)
+                .map(GitObject::Blob)
+                .ok_or_else(|| invalid("malformed blob")),
+            ObjectType::Tree => Tree::parse(body).map(GitObject::Tree),
+            ObjectType::Commit => Commit::from(body)
+                .map(GitObject::Commit)
+                .ok_or_else(|| invalid("malformed commit")),
+            ObjectType::Tag => Tag::from(body)
+                .map(GitObject::Tag)
+                .ok_or_else(|| invalid("malformed tag")),
+        }
     }
 
     pub fn calc_hash(&self) -> Vec<u8> {
@@ -65,6 +97,7 @@ impl GitObject {
             Self::Blob(obj) => obj.calc_hash(),
             Self::Tree(obj) => obj.calc_hash(),
             Self::Commit(obj) => obj.calc_hash(),
+            Self::Tag(obj) => obj.calc_hash(),
         }
     }
 
@@ -73,6 +106,7 @@ impl GitObject {
             Self::Blob(obj) => obj.as_bytes(),
             Self::Tree(obj) => obj.as_bytes(),
             Self::Commit(obj) => obj.as_bytes(),
+            Self::Tag(obj) => obj.as_bytes(),
         }
     }
 }
@@ -94,6 +128,9 @@ impl Serialize for GitObject {
             GitObject::Commit(commit) => {
                 s.serialize_field("Commit", commit)?;
             }
+            GitObject::Tag(tag) => {
+                s.serialize_field("Tag", tag)?;
+            }
         }
         s.serialize_field("hash", &hex::encode(self.calc_hash()))?;
         s.end()
@@ -106,6 +143,7 @@ impl fmt::Display for GitObject {
             Self::Blob(obj) => obj.fmt(f),
             Self::Tree(obj) => obj.fmt(f),
             Self::Commit(obj) => obj.fmt(f),
+            Self::Tag(obj) => obj.fmt(f),
         }
     }
 }
@@ -0,0 +1,110 @@
+use super::commit::User;
+use super::ObjectType;
+#[cfg(feature = "json")]
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use std::fmt;
+
+/// An annotated tag: a named, signed-off pointer to another object.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct Tag {
+    pub object: String,
+    #[cfg_attr(feature = "json", serde(skip))]
+    pub object_type: ObjectType,
+    pub tag: String,
+    /// Very old tags were made without one.
+    pub tagger: Option<User>,
+    pub message: String,
+}
+
+impl Tag {
+    pub fn new(
+        object: String,
+        object_type: ObjectType,
+        tag: String,
+        tagger: User,
+        message: String,
+    ) -> Self {
+        Self {
+            object,
+            object_type,
+            tag,
+            tagger: Some(tagger),
+            message,
+        }
+    }
+
+    pub fn from(bytes: &[u8]) -> Option<Self> {
+        let text = String::from_utf8(bytes.to_vec()).ok()?;
+        let (header, message) = match text.find("\n\n") {
+            Some(pos) => (&text[..pos], &text[pos + 2..]),
+            None => (text.strip_suffix('\n').unwrap_or(&text), ""),
+        };
+
+        let mut object = None;
+        let mut object_type = None;
+        let mut tag = None;
+        let mut tagger = None;
+        for line in header.lines() {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "object" => object = Some(value.to_string()),
+                "type" => object_type = ObjectType::from(value),
+                "tag" => tag = Some(value.to_string()),
+                "tagger" => tagger = User::from(value.as_bytes()),
+                _ => {}
+            }
+        }
+
+        Some(Self {
+            object: object?,
+            object_type: object_type?,
+            tag: tag?,
+            tagger,
+            message: message.to_string(),
+        })
+    }
+
+    pub fn calc_hash(&self) -> Vec<u8> {
+        Sha1::digest(&self.as_bytes()).to_vec()
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let content = format!("{}", self);
+        let header = format!("{} {}\0", ObjectType::Tag, content.len());
+
+        [header.as_bytes(), content.as_bytes()].concat()
+    }
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "object {}\ntype {}\ntag {}\n",
+            self.object, self.object_type, self.tag
+        )?;
+        if let Some(tagger) = &self.tagger {
+            writeln!(f, "tagger {}", tagger)?;
+        }
+        write!(f, "\n{}", self.message)
+    }
+}
+
+#[test]
+fn test_tag_round_trip() {
+    let content = b"object 9f4ef0d8a4a27ed8d1ac8ba89d32e4a1a8e4f0ad\ntype commit\ntag v1.0\ntagger A U Thor <author@example.com> 1112911993 +0200\n\nFirst release\n";
+    let tag = Tag::from(content).unwrap();
+    assert_eq!(tag.tag, "v1.0");
+    assert_eq!(tag.tagger.as_ref().unwrap().name, "A U Thor");
+    assert_eq!(tag.message, "First release\n");
+    assert_eq!(tag.to_string().as_bytes(), content);
+
+    let tag = Tag::from(
+        b"object 9f4ef0d8a4a27ed8d1ac8ba89d32e4a1a8e4f0ad\ntype commit\ntag old\n\nno tagger\n",
+    )
+    .unwrap();
+    assert!(tag.tagger.is_none());
+    assert!(Tag::from(b"type commit\ntag v1\n\n").is_none());
+}
@@ -2,7 +2,10 @@ use super::ObjectType;
 #[cfg(feature = "json")]
 use serde::Serialize;
 use sha1::{Digest, Sha1};
+use std::cmp::Ordering;
 use std::fmt;
+use std::io;
+use std::str;
 
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "json", derive(Serialize))]
@@ -10,37 +13,167 @@ pub struct Tree {
     pub contents: Vec<File>,
 }
 
+/// The kind of a tree or index entry, spelled in octal in both.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub enum FileMode {
+    Tree,
+    Blob,
+    BlobExecutable,
+    Symlink,
+    /// A commit in another repository, i.e. a submodule.
+    Gitlink,
+}
+
+impl FileMode {
+    /// Parses an octal mode such as `100644` or `40000`. Regular files
+    /// with unusual permissions (`100664`) count as plain blobs.
+    pub fn from_octal(s: &str) -> Option<Self> {
+        if s.is_empty() || !s.bytes().all(|x| (b'0'..=b'7').contains(&x)) {
+            return None;
+        }
+        u32::from_str_radix(s, 8).ok().and_then(Self::from_bits)
+    }
+
+    /// Converts the raw mode bits of an index entry.
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits & 0o170000 {
+            0o040000 => Some(Self::Tree),
+            0o100000 if bits & 0o100 != 0 => Some(Self::BlobExecutable),
+            0o100000 => Some(Self::Blob),
+            0o120000 => Some(Self::Symlink),
+            0o160000 => Some(Self::Gitlink),
+            _ => None,
+        }
+    }
+
+    /// The mode git records for a file with the given `st_mode`: a symlink,
+    /// or a regular file that is executable or not.
+    pub fn from_stat(mode: u32) -> Self {
+        match mode & 0o170000 {
+            0o120000 => Self::Symlink,
+            _ if mode & 0o100 != 0 => Self::BlobExecutable,
+            _ => Self::Blob,
+        }
+    }
+
+    pub fn bits(self) -> u32 {
+        match self {
+            Self::Tree => 0o040000,
+            Self::Blob => 0o100644,
+            Self::BlobExecutable => 0o100755,
+            Self::Symlink => 0o120000,
+            Self::Gitlink => 0o160000,
+        }
+    }
+
+    /// The type of object an entry with this mode points at.
+    pub fn object_type(self) -> ObjectType {
+        match self {
+            Self::Tree => ObjectType::Tree,
+            Self::Gitlink => ObjectType::Commit,
+            _ => ObjectType::Blob,
+        }
+    }
+}
+
+/// Zero-padded to six digits, as `ls-tree` prints it. Trees themselves
+/// store the mode without padding.
+impl fmt::Display for FileMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:06o}", self.bits())
+    }
+}
+
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "json", derive(Serialize))]
 pub struct File {
-    pub mode: usize,
+    pub mode: FileMode,
     pub name: String,
     pub hash: Vec<u8>,
+    /// The name as stored when it isn't valid UTF-8; `name` then only
+    /// holds a lossy copy, and re-encoding must not change the tree's hash.
+    #[cfg_attr(feature = "json", serde(skip))]
+    raw_name: Option<Vec<u8>>,
+    /// The mode as stored when git would spell it otherwise (`100664`,
+    /// `040000`), kept for the same reason.
+    #[cfg_attr(feature = "json", serde(skip))]
+    raw_mode: Option<Vec<u8>>,
 }
 
 impl File {
-    pub fn new(mode: usize, name: String, hash: &[u8]) -> Self {
+    pub fn new(mode: FileMode, name: String, hash: &[u8]) -> Self {
         Self {
             mode,
             name,
             hash: hash.to_vec(),
+            raw_name: None,
+            raw_mode: None,
         }
     }
 
+    /// Parses an entry from its `<mode> <name>` header and hash. The name
+    /// runs to the end of the header and may contain spaces.
     pub fn from(header: &[u8], hash: &[u8]) -> Option<Self> {
-        let split_header = String::from_utf8(header.to_vec()).ok()?;
+        let space = header.iter().position(|&b| b == b' ')?;
+        let mode = str::from_utf8(&header[..space])
+            .ok()
+            .and_then(FileMode::from_octal)?;
+
+        Some(Self::with_raw(
+            mode,
+            &header[..space],
+            &header[space + 1..],
+            hash,
+        ))
+    }
 
-        let mut iter = split_header.split_whitespace();
+    /// An entry with the mode and name bytes it was stored with.
+    fn with_raw(mode: FileMode, raw_mode: &[u8], name: &[u8], hash: &[u8]) -> Self {
+        let file = match String::from_utf8(name.to_vec()) {
+            Ok(name) => Self::new(mode, name, hash),
+            Err(_) => Self {
+                raw_name: Some(name.to_vec()),
+                ..Self::new(mode, String::from_utf8_lossy(name).into_owned(), hash)
+            },
+        };
+        Self {
+            raw_mode: Some(raw_mode.to_vec()).filter(|x| *x != file.canonical_mode()),
+            ..file
+        }
+    }
 
-        let mode = iter.next().and_then(|x| x.parse::<usize>().ok())?;
-        let name = iter.next()?;
+    fn canonical_mode(&self) -> Vec<u8> {
+        format!("{:o}", self.mode.bits()).into_bytes()
+    }
 
-        Some(Self::new(mode, String::from(name), hash))
+    /// The name exactly as it is stored in the tree.
+    pub fn name_bytes(&self) -> &[u8] {
+        self.raw_name.as_deref().unwrap_or(self.name.as_bytes())
     }
 
     pub fn encode(&self) -> Vec<u8> {
-        let header = format!("{} {}\0", self.mode, self.name);
-        [header.as_bytes(), &self.hash].concat()
+        // A stored mode only stands while `mode` hasn't been changed since.
+        let mode = self
+            .raw_mode
+            .clone()
+            .filter(|x| str::from_utf8(x).ok().and_then(FileMode::from_octal) == Some(self.mode))
+            .unwrap_or_else(|| self.canonical_mode());
+        [&mode[..], b" ", self.name_bytes(), b"\0", &self.hash].concat()
+    }
+
+    /// Orders entries the way git sorts trees: by name, with directories
+    /// compared as if their name ended in `/`.
+    fn cmp_sort(&self, other: &Self) -> Ordering {
+        let key = |x: &Self| {
+            let slash = if x.mode == FileMode::Tree {
+                &b"/"[..]
+            } else {
+                b""
+            };
+            [x.name_bytes(), slash].concat()
+        };
+        key(self).cmp(&key(other))
     }
 }
 
@@ -48,8 +181,9 @@ impl fmt::Display for File {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "{:>06} ??? {}\t{}",
+            "{} {} {}\t{}",
             self.mode,
+            self.mode.object_type(),
             hex::encode(&self.hash),
             self.name
         )
@@ -62,24 +196,69 @@ impl Tree {
     }
 
     pub fn from(bytes: &[u8]) -> Option<Self> {
-        let contents: Vec<File> = Vec::new();
-        let mut iter = bytes.split(|&b| b == b'\0');
+        Self::parse(bytes).ok()
+    }
+
+    /// Reads the `<mode> <name>\0<20-byte hash>` entries of a tree one
+    /// after another, rejecting anything git wouldn't have written.
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let mut contents: Vec<File> = Vec::new();
+        let mut rest = bytes;
+
+        while !rest.is_empty() {
+            let offset = bytes.len() - rest.len();
+            let malformed = |what: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed tree entry at offset {}: {}", offset, what),
+                )
+            };
 
-        let mut header = iter.next()?;
-        let contents = iter.try_fold(contents, |mut acc, x| {
-            let (hash, next_header) = x.split_at(20);
-            let file = File::from(header, hash)?;
+            let space = rest
+                .iter()
+                .position(|&b| b == b' ' || b == b'\0')
+                .filter(|&i| rest[i] == b' ')
+                .ok_or_else(|| malformed("missing mode"))?;
+            let raw_mode = &rest[..space];
+            let mode = str::from_utf8(raw_mode)
+                .ok()
+                .and_then(FileMode::from_octal)
+                .ok_or_else(|| malformed("bad mode"))?;
+            rest = &rest[space + 1..];
 
-            acc.push(file);
-            header = next_header;
-            Some(acc)
-        })?;
+            let nul = rest
+                .iter()
+                .position(|&b| b == b'\0')
+                .ok_or_else(|| malformed("unterminated name"))?;
+            let name = &rest[..nul];
+            if name.is_empty() || name.contains(&b'/') {
+                return Err(malformed("bad name"));
+            }
+            rest = &rest[nul + 1..];
+
+            if rest.len() < 20 {
+                return Err(malformed("truncated hash"));
+            }
+            let (hash, tail) = rest.split_at(20);
+            rest = tail;
+
+            let file = File::with_raw(mode, raw_mode, name, hash);
+            if let Some(prev) = contents.last() {
+                if prev.name_bytes() == file.name_bytes() {
+                    return Err(malformed("duplicate entry"));
+                }
+                if prev.cmp_sort(&file) != Ordering::Less {
+                    return Err(malformed("entries out of order"));
+                }
+            }
+            contents.push(file);
+        }
 
-        Some(Self { contents })
+        Ok(Self { contents })
     }
     pub fn calc_hash(&self) -> Vec<u8> {
         let bytes = self.as_bytes();
-        Vec::from(Sha1::digest(&bytes).as_slice())
+        Sha1::digest(&bytes).to_vec()
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
@@ -103,3 +282,86 @@ impl fmt::Display for Tree {
         )
     }
 }
+
+#[test]
+fn test_file_mode() {
+    assert_eq!(FileMode::from_octal("40000"), Some(FileMode::Tree));
+    assert_eq!(FileMode::from_octal("100664"), Some(FileMode::Blob));
+    assert_eq!(FileMode::from_octal("160000"), Some(FileMode::Gitlink));
+    assert_eq!(FileMode::from_octal("100648"), None);
+    assert_eq!(FileMode::from_octal("+100644"), None);
+
+    let hash = [0xab; 20];
+    let file = File::new(FileMode::Tree, "src".to_string(), &hash);
+    assert_eq!(&file.encode()[..10], b"40000 src\0");
+    assert_eq!(
+        file.to_string(),
+        format!("040000 tree {}\tsrc", hex::encode(hash))
+    );
+    let file = File::new(FileMode::Gitlink, "sub".to_string(), &hash);
+    assert!(file.to_string().starts_with("160000 commit "));
+}
+
+#[test]
+fn test_tree_parse() {
+    // A hash with NUL bytes and names with spaces or invalid UTF-8.
+    let mut hash = [0u8; 20];
+    hash[3] = 0x20;
+    let bytes = [
+        &b"100644 a b\0"[..],
+        &hash,
+        b"100755 a.c\0",
+        &hash,
+        b"40000 a\0",
+        &hash,
+        b"120000 \xff\xfe\0",
+        &hash,
+    ]
+    .concat();
+
+    let tree = Tree::parse(&bytes).unwrap();
+    let names = tree
+        .contents
+        .iter()
+        .map(|x| x.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["a b", "a.c", "a", "\u{fffd}\u{fffd}"]);
+    assert_eq!(tree.contents[0].hash, hash);
+    assert_eq!(tree.contents[3].name_bytes(), b"\xff\xfe");
+    let content = tree
+        .contents
+        .iter()
+        .flat_map(|x| x.encode())
+        .collect::<Vec<_>>();
+    assert_eq!(content, bytes);
+
+    // "a" as a tree sorts after "a.c".
+    let unsorted = [&b"40000 a\0"[..], &hash, b"100644 a.c\0", &hash].concat();
+    let err = Tree::parse(&unsorted).unwrap_err();
+    assert!(err.to_string().contains("out of order"));
+
+    let duplicate = [&b"100644 a\0"[..], &hash, b"40000 a\0", &hash].concat();
+    assert!(Tree::parse(&duplicate).is_err());
+    assert!(Tree::parse(&bytes[..bytes.len() - 1]).is_err());
+    assert!(Tree::parse(b"100644 \0").is_err());
+    assert!(Tree::parse(b"abc x\0").is_err());
+    assert!(Tree::parse(b"100644x\0").is_err());
+}
+
+#[test]
+fn test_tree_raw_mode() {
+    // Modes git no longer writes still hash the same once re-encoded.
+    let hash = [0xab; 20];
+    let bytes = [&b"100664 a\0"[..], &hash, b"040000 b\0", &hash].concat();
+    let tree = Tree::parse(&bytes).unwrap();
+    assert_eq!(tree.contents[0].mode, FileMode::Blob);
+    assert_eq!(tree.contents[1].mode, FileMode::Tree);
+
+    let header = format!("tree {}\0", bytes.len());
+    let expected = Sha1::digest(&[header.as_bytes(), &bytes].concat()).to_vec();
+    assert_eq!(tree.calc_hash(), expected);
+
+    let mut file = tree.contents[0].clone();
+    file.mode = FileMode::BlobExecutable;
+    assert_eq!(&file.encode()[..9], b"100755 a\0");
+}
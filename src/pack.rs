@@ -0,0 +1,790 @@
+use crate::fs::FileSystem;
+use crate::object::tree::FileMode;
+use crate::object::ObjectType;
+use crate::Git;
+use libflate::zlib::{Decoder, Encoder};
+use sha1::{Digest, Sha1};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::sync::{Arc, Mutex, OnceLock};
+
+const OFS_DELTA: u8 = 6;
+const REF_DELTA: u8 = 7;
+/// How many deltas deep a packed object may be, as `pack-objects
+/// --depth` allows at most. A corrupt pack can make a chain loop.
+const MAX_DELTA_DEPTH: usize = 4095;
+
+/// The packs of a repository read so far, by name. Each index is read
+/// once, and each pack the first time an object is looked up in it.
+#[derive(Default)]
+pub struct PackCache(Mutex<HashMap<String, Arc<Pack>>>);
+
+impl fmt::Debug for PackCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let packs = self.0.lock().unwrap();
+        f.debug_set().entries(packs.keys()).finish()
+    }
+}
+
+/// A pack and its index.
+struct Pack {
+    name: String,
+    index: Vec<u8>,
+    count: usize,
+    data: OnceLock<Vec<u8>>,
+}
+
+impl<F: FileSystem> Git<F> {
+    /// The type and content of `hash` if one of the packs under
+    /// `objects/pack` has it, with deltas resolved.
+    pub fn read_packed_object(&self, hash: &str) -> io::Result<Option<(ObjectType, Vec<u8>)>> {
+        match self.find_packed(hash)? {
+            Some((pack, offset)) => self.unpack_entry(pack, offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether one of the packs has `hash`, without reading the object.
+    pub(crate) fn has_packed_object(&self, hash: &str) -> io::Result<bool> {
+        Ok(self.find_packed(hash)?.is_some())
+    }
+
+    /// The pack that has `hash`, and where in it the object starts.
+    fn find_packed(&self, hash: &str) -> io::Result<Option<(Arc<Pack>, usize)>> {
+        let id = hex::decode(hash).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        for pack in self.packs()? {
+            if let Some(offset) = find_offset(&pack.index, pack.count, &id)? {
+                return Ok(Some((pack, offset)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The packed objects whose names start with `prefix`.
+    pub(crate) fn packed_hashes(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let mut hashes = Vec::new();
+        for pack in self.packs()? {
+            hashes.extend(
+                (0..pack.count)
+                    .map(|i| hex::encode(&pack.index[1032 + i * 20..1052 + i * 20]))
+                    .filter(|x| x.starts_with(prefix)),
+            );
+        }
+        Ok(hashes)
+    }
+
+    /// The packs there are now, their indexes read and checked. Packs
+    /// added since the last call are read; those already read are not.
+    fn packs(&self) -> io::Result<Vec<Arc<Pack>>> {
+        let mut packs = Vec::new();
+        for name in self.pack_names()? {
+            let cached = self.packs.0.lock().unwrap().get(&name).cloned();
+            let pack = match cached {
+                Some(pack) => pack,
+                None => {
+                    let index = self
+                        .file_system
+                        .read(self.git_path(&format!("{}.idx", name)))?;
+                    let pack = Arc::new(Pack {
+                        name: name.clone(),
+                        count: check_index(&index)?,
+                        index,
+                        data: OnceLock::new(),
+                    });
+                    self.packs.0.lock().unwrap().insert(name, pack.clone());
+                    pack
+                }
+            };
+            packs.push(pack);
+        }
+        Ok(packs)
+    }
+
+    fn pack_data<'a>(&self, pack: &'a Pack) -> io::Result<&'a [u8]> {
+        if let Some(data) = pack.data.get() {
+            return Ok(data);
+        }
+        let data = self
+            .file_system
+            .read(self.git_path(&format!("{}.pack", pack.name)))?;
+        Ok(pack.data.get_or_init(|| data))
+    }
+
+    /// The packs of the repository as `objects/pack/pack-<hash>`, each
+    /// with a `.pack` and an `.idx` file.
+    pub fn pack_names(&self) -> io::Result<Vec<String>> {
+        let names = match self.file_system.read_dir(self.git_path("objects/pack")) {
+            Ok(names) => names,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(names
+            .iter()
+            .filter_map(|x| x.strip_suffix(".idx"))
+            .map(|x| format!("objects/pack/{}", x))
+            .collect())
+    }
+
+    /// Stores a pack as received from a remote under `objects/pack`, with
+    /// an index of its objects, and returns its name. Delta bases may also
+    /// be objects the repository already has.
+    pub fn index_pack(&mut self, pack: &[u8]) -> io::Result<String> {
+        let count = check_pack(pack)?;
+        let end = pack.len() - 20;
+
+        // Every entry with its offset and CRC, and its data until the
+        // deltas among them are resolved.
+        let mut entries = Vec::with_capacity(count);
+        let mut pos = 12;
+        for _ in 0..count {
+            let entry = read_entry(&pack[..end], pos)?;
+            pos = entry.end;
+            entries.push(entry);
+        }
+        if pos != end {
+            return Err(invalid("pack has junk at the end"));
+        }
+
+        let mut objects: Vec<Option<(ObjectType, Vec<u8>)>> = vec![None; count];
+        let mut ids = vec![Vec::new(); count];
+        let at_offset: HashMap<usize, usize> = entries
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (x.offset, i))
+            .collect();
+        let mut by_hash: HashMap<String, usize> = HashMap::new();
+        let mut pending = count;
+        while pending > 0 {
+            let mut progress = false;
+            for i in 0..count {
+                if objects[i].is_some() {
+                    continue;
+                }
+                let entry = &entries[i];
+                let (object_type, data) = match &entry.base {
+                    None => (entry.kind.unwrap(), entry.data.clone()),
+                    Some(Base::Offset(offset)) => {
+                        let base = at_offset
+                            .get(offset)
+                            .ok_or_else(|| invalid("bad delta base offset"))?;
+                        match &objects[*base] {
+                            Some((object_type, base)) => {
+                                (*object_type, apply_delta(base, &entry.data)?)
+                            }
+                            None => continue,
+                        }
+                    }
+                    Some(Base::Hash(hash)) => match by_hash.get(hash) {
+                        Some(&base) => {
+                            let (object_type, base) = objects[base].as_ref().unwrap();
+                            (*object_type, apply_delta(base, &entry.data)?)
+                        }
+                        None => match self.read_raw_object(hash.clone()) {
+                            Ok((object_type, base)) => {
+                                (object_type, apply_delta(&base, &entry.data)?)
+                            }
+                            // It may yet turn up in the pack.
+                            Err(_) => continue,
+                        },
+                    },
+                };
+                let mut hasher = Sha1::new();
+                hasher.update(format!("{} {}\0", object_type, data.len()));
+                hasher.update(&data);
+                let id = hasher.finalize().to_vec();
+                by_hash.insert(hex::encode(&id), i);
+                ids[i] = id;
+                objects[i] = Some((object_type, data));
+                pending -= 1;
+                progress = true;
+            }
+            if !progress {
+                return Err(invalid("pack has unresolved deltas"));
+            }
+        }
+
+        let mut index: Vec<(Vec<u8>, u32, usize)> = ids
+            .into_iter()
+            .zip(&entries)
+            .map(|(id, entry)| (id, entry.crc, entry.offset))
+            .collect();
+        index.sort();
+        let name = format!("objects/pack/pack-{}", hex::encode(&pack[end..]));
+        self.file_system.create_dir(self.git_path("objects/pack"))?;
+        self.file_system
+            .write(self.git_path(&format!("{}.pack", name)), pack)?;
+        self.file_system.write(
+            self.git_path(&format!("{}.idx", name)),
+            &write_index(&index, &pack[end..]),
+        )?;
+        Ok(name)
+    }
+
+    /// The objects reachable from `wants` but not from `haves`, commits
+    /// first, like `rev-list --objects`. What the trees of the commits
+    /// bordering on `haves` hold is taken to be there already.
+    pub fn objects_between(&self, wants: &[String], haves: &[String]) -> io::Result<Vec<String>> {
+        let mut objects = Vec::new();
+        let mut seen = HashSet::new();
+        let mut walk = self.rev_walk();
+        let mut roots = Vec::new();
+        for want in wants {
+            let mut hash = want.clone();
+            loop {
+                match self.read_raw_object(hash.clone())?.0 {
+                    ObjectType::Tag => {
+                        if seen.insert(hash.clone()) {
+                            objects.push(hash.clone());
+                        }
+                        hash = self.read_tag(hash)?.object;
+                    }
+                    ObjectType::Commit => break walk.push(&hash)?,
+                    _ => break roots.push(hash),
+                }
+            }
+        }
+        for have in haves {
+            // Only commits say anything about history.
+            if let Ok(hash) = self.peel(have.clone(), "commit") {
+                walk.hide(&hash)?;
+            }
+        }
+        let commits = walk.by_ref().collect::<io::Result<Vec<_>>>()?;
+
+        let mut excluded = HashSet::new();
+        let included: HashSet<&String> = commits.iter().collect();
+        for commit in &commits {
+            for parent in &walk.commit(commit).unwrap().parents {
+                if !included.contains(parent) {
+                    if let Ok(parent) = self.read_commit(parent.clone()) {
+                        self.tree_objects(parent.tree, &mut excluded, &mut Vec::new())?;
+                    }
+                }
+            }
+        }
+        seen.extend(excluded);
+        for commit in &commits {
+            objects.push(commit.clone());
+            roots.push(walk.commit(commit).unwrap().tree.clone());
+        }
+        for root in roots {
+            self.tree_objects(root, &mut seen, &mut objects)?;
+        }
+        Ok(objects)
+    }
+
+    /// Adds a tree or blob and everything in it to `objects`, skipping
+    /// what is in `seen` and adding it there.
+    fn tree_objects(
+        &self,
+        hash: String,
+        seen: &mut HashSet<String>,
+        objects: &mut Vec<String>,
+    ) -> io::Result<()> {
+        if !seen.insert(hash.clone()) {
+            return Ok(());
+        }
+        objects.push(hash.clone());
+        if self.read_raw_object(hash.clone())?.0 != ObjectType::Tree {
+            return Ok(());
+        }
+        for file in self.read_tree(hash)?.contents {
+            match file.mode {
+                FileMode::Tree => self.tree_objects(hex::encode(&file.hash), seen, objects)?,
+                // Submodule commits live in another repository.
+                FileMode::Gitlink => {}
+                _ => {
+                    let hash = hex::encode(&file.hash);
+                    if seen.insert(hash.clone()) {
+                        objects.push(hash);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// A pack of `hashes`, every object whole rather than as a delta.
+    pub fn write_pack(&self, hashes: &[String]) -> io::Result<Vec<u8>> {
+        let mut pack = b"PACK".to_vec();
+        pack.extend(2u32.to_be_bytes());
+        pack.extend((hashes.len() as u32).to_be_bytes());
+        for hash in hashes {
+            let (object_type, data) = self.read_raw_object(hash.clone())?;
+            let kind = match object_type {
+                ObjectType::Commit => 1,
+                ObjectType::Tree => 2,
+                ObjectType::Blob => 3,
+                ObjectType::Tag => 4,
+            };
+            // The type and size, four bits of the size and then seven
+            // more per byte.
+            let mut size = data.len() >> 4;
+            let mut byte = kind << 4 | (data.len() & 15) as u8;
+            while size > 0 {
+                pack.push(byte | 0x80);
+                byte = (size & 0x7f) as u8;
+                size >>= 7;
+            }
+            pack.push(byte);
+
+            let mut encoder = Encoder::new(Vec::new())?;
+            encoder.write_all(&data)?;
+            pack.extend(encoder.finish().into_result()?);
+        }
+        let checksum = Sha1::digest(&pack);
+        pack.extend(checksum);
+        Ok(pack)
+    }
+
+    /// Reads the object at `offset` of a pack, following its chain of
+    /// deltas down to a whole object and applying them on the way back.
+    fn unpack_entry(
+        &self,
+        mut pack: Arc<Pack>,
+        mut offset: usize,
+    ) -> io::Result<(ObjectType, Vec<u8>)> {
+        let mut deltas = Vec::new();
+        let (object_type, mut data) = loop {
+            if deltas.len() > MAX_DELTA_DEPTH {
+                return Err(invalid("delta chain too long"));
+            }
+            let entry = read_entry(self.pack_data(&pack)?, offset)?;
+            match entry.base {
+                None => break (entry.kind.unwrap(), entry.data),
+                Some(Base::Offset(start)) => offset = start,
+                Some(Base::Hash(hash)) => match self.find_packed(&hash)? {
+                    Some(base) => (pack, offset) = base,
+                    None => {
+                        deltas.push(entry.data);
+                        break self.read_raw_object(hash)?;
+                    }
+                },
+            }
+            deltas.push(entry.data);
+        };
+        for delta in deltas.iter().rev() {
+            data = apply_delta(&data, delta)?;
+        }
+        Ok((object_type, data))
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// What a delta applies to.
+enum Base {
+    Offset(usize),
+    Hash(String),
+}
+
+/// An entry of a pack, its data inflated but not yet resolved.
+struct Entry {
+    offset: usize,
+    /// Just past the entry.
+    end: usize,
+    crc: u32,
+    /// The type of an object that isn't a delta.
+    kind: Option<ObjectType>,
+    base: Option<Base>,
+    data: Vec<u8>,
+}
+
+/// Checks the header and trailing checksum of a pack and returns how many
+/// objects it holds, which it must have room for: every entry takes at
+/// least two bytes.
+fn check_pack(pack: &[u8]) -> io::Result<usize> {
+    if pack.len() < 32 || &pack[..4] != b"PACK" {
+        return Err(invalid("not a pack"));
+    }
+    let version = u32::from_be_bytes(pack[4..8].try_into().unwrap());
+    if version != 2 && version != 3 {
+        return Err(invalid("unsupported pack version"));
+    }
+    let end = pack.len() - 20;
+    if Sha1::digest(&pack[..end])[..] != pack[end..] {
+        return Err(invalid("pack checksum mismatch"));
+    }
+    let count = u32::from_be_bytes(pack[8..12].try_into().unwrap()) as usize;
+    if count > (end - 12) / 2 {
+        return Err(invalid("pack has fewer objects than its header says"));
+    }
+    Ok(count)
+}
+
+/// Parses the entry at `offset`, finding where it ends by inflating it.
+fn read_entry(pack: &[u8], offset: usize) -> io::Result<Entry> {
+    let truncated = || invalid("truncated pack entry");
+    let mut pos = offset;
+    let mut byte = *pack.get(pos).ok_or_else(truncated)?;
+    let kind = (byte >> 4) & 7;
+    // The size in the header is that of the delta or object before
+    // compression, which inflating finds anyway.
+    while byte & 0x80 != 0 {
+        pos += 1;
+        byte = *pack.get(pos).ok_or_else(truncated)?;
+    }
+    pos += 1;
+
+    let (kind, base) = match kind {
+        1 => (Some(ObjectType::Commit), None),
+        2 => (Some(ObjectType::Tree), None),
+        3 => (Some(ObjectType::Blob), None),
+        4 => (Some(ObjectType::Tag), None),
+        OFS_DELTA => {
+            let mut byte = *pack.get(pos).ok_or_else(truncated)?;
+            let mut distance = (byte & 0x7f) as usize;
+            while byte & 0x80 != 0 {
+                pos += 1;
+                byte = *pack.get(pos).ok_or_else(truncated)?;
+                distance = distance
+                    .checked_add(1)
+                    .and_then(|x| x.checked_mul(1 << 7))
+                    .map(|x| x + (byte & 0x7f) as usize)
+                    .ok_or_else(|| invalid("bad delta base offset"))?;
+            }
+            pos += 1;
+            // The base comes before the delta, never at it.
+            let start = offset
+                .checked_sub(distance)
+                .filter(|&x| x < offset)
+                .ok_or_else(|| invalid("bad delta base offset"))?;
+            (None, Some(Base::Offset(start)))
+        }
+        REF_DELTA => {
+            let id = pack.get(pos..pos + 20).ok_or_else(truncated)?;
+            pos += 20;
+            (None, Some(Base::Hash(hex::encode(id))))
+        }
+        _ => return Err(invalid("unknown object type in pack")),
+    };
+
+    // The decoder reads no further than the end of the zlib stream.
+    let mut rest = pack.get(pos..).ok_or_else(truncated)?;
+    let mut data = Vec::new();
+    Decoder::new(&mut rest)?.read_to_end(&mut data)?;
+    let end = pack.len() - rest.len();
+    Ok(Entry {
+        offset,
+        end,
+        crc: crc32(&pack[offset..end]),
+        kind,
+        base,
+        data,
+    })
+}
+
+/// A version 2 index of `objects`, given as name, CRC and offset sorted
+/// by name, for the pack with checksum `pack_hash`.
+fn write_index(objects: &[(Vec<u8>, u32, usize)], pack_hash: &[u8]) -> Vec<u8> {
+    let mut index = vec![0xff, b't', b'O', b'c', 0, 0, 0, 2];
+    let mut fanout = [0u32; 256];
+    for (id, _, _) in objects {
+        fanout[id[0] as usize] += 1;
+    }
+    let mut count = 0;
+    for x in fanout.iter() {
+        count += x;
+        index.extend(count.to_be_bytes());
+    }
+    for (id, _, _) in objects {
+        index.extend(id);
+    }
+    for (_, crc, _) in objects {
+        index.extend(crc.to_be_bytes());
+    }
+    let mut large = Vec::new();
+    for (_, _, offset) in objects {
+        if *offset < 0x8000_0000 {
+            index.extend((*offset as u32).to_be_bytes());
+        } else {
+            index.extend((0x8000_0000 | large.len() as u32).to_be_bytes());
+            large.push(*offset as u64);
+        }
+    }
+    for offset in large {
+        index.extend(offset.to_be_bytes());
+    }
+    index.extend(pack_hash);
+    let checksum = Sha1::digest(&index);
+    index.extend(checksum);
+    index
+}
+
+/// The CRC-32 of `bytes`, as zlib computes it.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// Checks that `index` is a version 2 pack index and returns how many
+/// objects it lists.
+fn check_index(index: &[u8]) -> io::Result<usize> {
+    if index.len() < 1032 || index[..8] != [0xff, b't', b'O', b'c', 0, 0, 0, 2] {
+        return Err(invalid("unsupported pack index version"));
+    }
+    let count = u32::from_be_bytes(index[1028..1032].try_into().unwrap()) as usize;
+    if index.len() < 1032 + count * 28 {
+        return Err(invalid("truncated pack index"));
+    }
+    Ok(count)
+}
+
+/// Where the object `id` starts in the pack, looking it up in the pack's
+/// index: a fan-out table by first byte, then the sorted names, their
+/// CRCs and their offsets, which refer to a table of 64-bit offsets when
+/// the top bit is set. `count` is what `check_index` found.
+fn find_offset(index: &[u8], count: usize, id: &[u8]) -> io::Result<Option<usize>> {
+    let fanout = |i: usize| {
+        let at = 8 + i * 4;
+        (u32::from_be_bytes(index[at..at + 4].try_into().unwrap()) as usize).min(count)
+    };
+    let (mut lo, mut hi) = match id[0] {
+        0 => (0, fanout(0)),
+        x => (fanout(x as usize - 1), fanout(x as usize)),
+    };
+    let name = |i: usize| &index[1032 + i * 20..1052 + i * 20];
+
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        match name(mid).cmp(id) {
+            std::cmp::Ordering::Less => lo = mid + 1,
+            std::cmp::Ordering::Greater => hi = mid,
+            std::cmp::Ordering::Equal => {
+                let at = 1032 + count * 24 + mid * 4;
+                let offset = u32::from_be_bytes(index[at..at + 4].try_into().unwrap());
+                if offset & 0x8000_0000 == 0 {
+                    return Ok(Some(offset as usize));
+                }
+                let at = 1032 + count * 28 + (offset & 0x7fff_ffff) as usize * 8;
+                let large = index
+                    .get(at..at + 8)
+                    .ok_or_else(|| invalid("truncated pack index"))?;
+                return Ok(Some(u64::from_be_bytes(large.try_into().unwrap()) as usize));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Reads a pack from a stream that may go on after it, finding its end
+/// by walking its entries, and returns it whole.
+pub fn read_pack(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut reader = Recorder {
+        reader,
+        bytes: Vec::new(),
+    };
+    let mut header = [0; 12];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != b"PACK" {
+        return Err(invalid("not a pack"));
+    }
+    let count = u32::from_be_bytes(header[8..12].try_into().unwrap());
+
+    let mut byte = [0; 1];
+    for _ in 0..count {
+        reader.read_exact(&mut byte)?;
+        let kind = (byte[0] >> 4) & 7;
+        while byte[0] & 0x80 != 0 {
+            reader.read_exact(&mut byte)?;
+        }
+        match kind {
+            OFS_DELTA => {
+                reader.read_exact(&mut byte)?;
+                while byte[0] & 0x80 != 0 {
+                    reader.read_exact(&mut byte)?;
+                }
+            }
+            REF_DELTA => reader.read_exact(&mut [0; 20])?,
+            _ => {}
+        }
+        io::copy(&mut Decoder::new(&mut reader)?, &mut io::sink())?;
+    }
+    reader.read_exact(&mut [0; 20])?;
+    Ok(reader.bytes)
+}
+
+/// Keeps a copy of everything read through it.
+struct Recorder<'a, R: Read> {
+    reader: &'a mut R,
+    bytes: Vec<u8>,
+}
+
+impl<R: Read> Read for Recorder<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.bytes.extend(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Rebuilds an object from its `base` and a delta: the sizes of both,
+/// then instructions to copy ranges of the base or insert new bytes.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    let bad = || invalid("bad delta");
+    let mut pos = 0;
+    let base_size = delta_size(delta, &mut pos)?;
+    let result_size = delta_size(delta, &mut pos)?;
+    if base_size != base.len() {
+        return Err(bad());
+    }
+
+    let mut result = Vec::with_capacity(result_size);
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        if op & 0x80 != 0 {
+            let mut read = |bits: std::ops::Range<u8>| {
+                let mut value = 0;
+                for (i, bit) in bits.enumerate() {
+                    if op & (1 << bit) != 0 {
+                        value |= (*delta.get(pos).ok_or_else(bad)? as usize) << (8 * i);
+                        pos += 1;
+                    }
+                }
+                Ok::<_, io::Error>(value)
+            };
+            let offset = read(0..4)?;
+            let len = match read(4..7)? {
+                0 => 0x10000,
+                x => x,
+            };
+            result.extend(base.get(offset..offset + len).ok_or_else(bad)?);
+        } else if op != 0 {
+            let len = op as usize;
+            result.extend(delta.get(pos..pos + len).ok_or_else(bad)?);
+            pos += len;
+        } else {
+            return Err(bad());
+        }
+    }
+    if result.len() != result_size {
+        return Err(bad());
+    }
+    Ok(result)
+}
+
+/// A size at the start of a delta: seven bits per byte, lowest first.
+fn delta_size(delta: &[u8], pos: &mut usize) -> io::Result<usize> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *delta.get(*pos).ok_or_else(|| invalid("bad delta"))?;
+        *pos += 1;
+        if shift >= usize::BITS {
+            return Err(invalid("bad delta"));
+        }
+        value |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+#[test]
+fn test_apply_delta() {
+    let base = b"hello, world\n";
+    // Sizes 13 and 18, copy 7 bytes from 0, insert "there", copy the
+    // last 6 bytes.
+    let mut delta = vec![13, 18, 0x90, 7, 5];
+    delta.extend(b"there");
+    delta.extend([0x91, 7, 6]);
+    assert_eq!(apply_delta(base, &delta).unwrap(), b"hello, thereworld\n");
+    assert!(apply_delta(b"short", &delta).is_err());
+
+    // A size longer than any usize is refused rather than overflowing.
+    let mut delta = vec![0x80; 11];
+    delta.extend([1, 0]);
+    assert!(apply_delta(base, &delta).is_err());
+}
+
+#[test]
+fn test_packed_objects() {
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::object::blob::Blob;
+    use crate::object::GitObject;
+
+    let mut source = Git::new(InMemFileSystem::init());
+    source.init(false, "main").unwrap();
+    let mut blob = |content: &[u8]| {
+        let blob = GitObject::Blob(Blob::from(content).unwrap());
+        source.write_object(&blob).unwrap();
+        hex::encode(blob.calc_hash())
+    };
+    let (first, second) = (blob(b"first\n"), blob(b"second\n"));
+
+    let mut git = Git::new(InMemFileSystem::init());
+    git.init(false, "main").unwrap();
+    git.index_pack(&source.write_pack(std::slice::from_ref(&first)).unwrap())
+        .unwrap();
+    assert!(git.has_object(&first) && !git.has_object(&second));
+    assert_eq!(git.read_blob(first.clone()).unwrap(), b"first\n");
+
+    // A pack added after the others were read is found all the same.
+    git.index_pack(&source.write_pack(std::slice::from_ref(&second)).unwrap())
+        .unwrap();
+    assert_eq!(git.read_blob(second.clone()).unwrap(), b"second\n");
+    assert_eq!(git.packed_hashes("").unwrap().len(), 2);
+}
+
+#[test]
+fn test_corrupt_deltas() {
+    use crate::fs::inmem::InMemFileSystem;
+
+    // An offset delta whose base would be itself.
+    let mut pack = b"PACK\0\0\0\x02\0\0\0\x01".to_vec();
+    pack.extend([OFS_DELTA << 4, 0]);
+    assert!(read_entry(&pack, 12).is_err());
+
+    // A ref delta whose base is the object it makes.
+    let id = [0xab; 20];
+    let mut pack = b"PACK\0\0\0\x02\0\0\0\x01".to_vec();
+    pack.push(REF_DELTA << 4);
+    pack.extend(id);
+    pack.extend(Encoder::new(Vec::new()).unwrap().finish().into_result().unwrap());
+    let checksum = Sha1::digest(&pack);
+    pack.extend(checksum);
+    let index = write_index(&[(id.to_vec(), crc32(&pack[12..]), 12)], &checksum);
+
+    let mut git = Git::new(InMemFileSystem::init());
+    git.init(false, "main").unwrap();
+    git.file_system
+        .write(git.git_path("objects/pack/pack-x.pack"), &pack)
+        .unwrap();
+    git.file_system
+        .write(git.git_path("objects/pack/pack-x.idx"), &index)
+        .unwrap();
+    let err = git.read_packed_object(&hex::encode(id)).unwrap_err();
+    assert_eq!(err.to_string(), "delta chain too long");
+}
+
+#[test]
+fn test_pack_count() {
+    use crate::fs::inmem::InMemFileSystem;
+
+    let mut git = Git::new(InMemFileSystem::init());
+    git.init(false, "main").unwrap();
+    let mut pack = git.write_pack(&[]).unwrap();
+    assert_eq!(check_pack(&pack).unwrap(), 0);
+
+    // A count the pack has no room for is refused before anything is
+    // allocated for it.
+    pack[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+    let end = pack.len() - 20;
+    let checksum = Sha1::digest(&pack[..end]);
+    pack[end..].copy_from_slice(&checksum);
+    let err = git.index_pack(&pack).unwrap_err();
+    assert_eq!(err.to_string(), "pack has fewer objects than its header says");
+}
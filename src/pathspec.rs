@@ -0,0 +1,181 @@
+use crate::wildmatch::{wildmatch, WM_CASEFOLD, WM_PATHNAME};
+use std::io;
+
+/// One path argument of a command, with its `:(magic)` parsed off.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    /// The argument as given, for messages.
+    pub original: String,
+    /// The path relative to the top of the worktree; empty for `.`.
+    pub pattern: String,
+    /// `*` and `?` stop at slashes (`:(glob)`).
+    pub glob: bool,
+    /// No wildcards at all (`:(literal)`).
+    pub literal: bool,
+    pub icase: bool,
+    /// Removes the paths it matches from the selection (`:(exclude)`, `:!`
+    /// or `:^`).
+    pub exclude: bool,
+}
+
+impl Item {
+    pub fn parse(arg: &str) -> io::Result<Self> {
+        let mut item = Self {
+            original: arg.to_string(),
+            pattern: String::new(),
+            glob: false,
+            literal: false,
+            icase: false,
+            exclude: false,
+        };
+
+        let rest = if let Some(rest) = arg.strip_prefix(":(") {
+            let end = rest.find(')').ok_or_else(|| {
+                invalid(format!(
+                    "Missing ')' at the end of pathspec magic in '{}'",
+                    arg
+                ))
+            })?;
+            for magic in rest[..end].split(',').filter(|x| !x.is_empty()) {
+                match magic {
+                    "glob" => item.glob = true,
+                    "literal" => item.literal = true,
+                    "icase" => item.icase = true,
+                    "exclude" => item.exclude = true,
+                    // Paths are always taken from the top of the worktree.
+                    "top" => {}
+                    _ => {
+                        return Err(invalid(format!(
+                            "Invalid pathspec magic '{}' in '{}'",
+                            magic, arg
+                        )))
+                    }
+                }
+            }
+            &rest[end + 1..]
+        } else if let Some(rest) = arg.strip_prefix(':') {
+            let magic = rest
+                .find(|c| !matches!(c, '!' | '^' | '/'))
+                .unwrap_or(rest.len());
+            item.exclude = rest[..magic].contains(['!', '^']);
+            rest[magic..].strip_prefix(':').unwrap_or(&rest[magic..])
+        } else {
+            arg
+        };
+        if item.glob && item.literal {
+            return Err(invalid(format!(
+                "'literal' and 'glob' are incompatible in '{}'",
+                arg
+            )));
+        }
+
+        item.pattern = normalize(rest);
+        Ok(item)
+    }
+
+    pub fn has_wildcards(&self) -> bool {
+        !self.literal && self.pattern.contains(['*', '?', '[', '\\'])
+    }
+
+    /// Whether `path` is the pattern itself, a file below the directory it
+    /// names, or matched by its wildcards. Without `:(glob)` a wildcard
+    /// also matches slashes, as in `*.c`.
+    pub fn matches(&self, path: &str) -> bool {
+        let (pattern, path) = if self.icase {
+            (self.pattern.to_lowercase(), path.to_lowercase())
+        } else {
+            (self.pattern.clone(), path.to_string())
+        };
+
+        if pattern.is_empty() || path == pattern {
+            return true;
+        }
+        if !self.has_wildcards() {
+            return path.starts_with(&pattern) && path.as_bytes()[pattern.len()] == b'/';
+        }
+
+        let flags = if self.glob { WM_PATHNAME } else { 0 };
+        wildmatch(
+            &pattern,
+            &path,
+            flags | if self.icase { WM_CASEFOLD } else { 0 },
+        )
+    }
+}
+
+/// The paths a command works on: everything matched by one of the
+/// positive items, or everything at all when there are only exclusions,
+/// minus whatever an exclusion matches.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Pathspec {
+    pub items: Vec<Item>,
+}
+
+impl Pathspec {
+    pub fn parse<S: AsRef<str>>(args: &[S]) -> io::Result<Self> {
+        let items = args
+            .iter()
+            .map(|x| Item::parse(x.as_ref()))
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self { items })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The positive items, which a command can check for matches.
+    pub fn includes(&self) -> impl Iterator<Item = &Item> {
+        self.items.iter().filter(|x| !x.exclude)
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        let mut includes = self.includes().peekable();
+        let included = includes.peek().is_none() || includes.any(|x| x.matches(path));
+        included && !self.items.iter().any(|x| x.exclude && x.matches(path))
+    }
+}
+
+/// Drops `./` components and trailing slashes.
+pub fn normalize(path: &str) -> String {
+    path.split('/')
+        .filter(|x| !x.is_empty() && *x != ".")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+#[test]
+fn test_pathspec() {
+    let spec = |args: &[&str]| Pathspec::parse(args).unwrap();
+
+    let all = spec(&["."]);
+    assert!(all.matches("a") && all.matches("d/e/f"));
+
+    let dir = spec(&["./src/"]);
+    assert!(dir.matches("src") && dir.matches("src/main.rs"));
+    assert!(!dir.matches("src.rs") && !dir.matches("srcx/a"));
+
+    // Wildcards cross directories unless `:(glob)` is given.
+    assert!(spec(&["*.c"]).matches("a/b.c"));
+    assert!(!spec(&[":(glob)*.c"]).matches("a/b.c"));
+    assert!(spec(&[":(glob)**/*.c"]).matches("a/b.c"));
+    assert!(!spec(&[":(glob)d*"]).matches("d1/x"));
+    assert!(spec(&[":(literal)*.c"]).matches("*.c"));
+    assert!(!spec(&[":(literal)*.c"]).matches("a.c"));
+    assert!(spec(&[":(icase)SRC"]).matches("src/a"));
+    assert!(spec(&[":(icase,glob)*.C"]).matches("a.c"));
+
+    let excluded = spec(&[":!src", ":(exclude)*.md"]);
+    assert!(excluded.matches("lib/a.rs"));
+    assert!(!excluded.matches("src/a.rs") && !excluded.matches("README.md"));
+    assert!(spec(&["src", ":^src/gen"]).matches("src/a"));
+    assert!(!spec(&["src", ":^src/gen"]).matches("src/gen/a"));
+
+    assert!(Pathspec::parse(&[":(bogus)x"]).is_err());
+    assert!(Pathspec::parse(&[":(glob,literal)x"]).is_err());
+    assert!(Pathspec::parse(&[":(globx"]).is_err());
+}
@@ -0,0 +1,438 @@
+use crate::object::commit::{Commit, User};
+use chrono::{DateTime, FixedOffset, Local};
+use std::fmt::Write;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Format {
+    Oneline,
+    Short,
+    Medium,
+    Full,
+    Fuller,
+    Raw,
+    /// `format:` separates commits, `tformat:` terminates each one.
+    Custom {
+        format: String,
+        terminator: bool,
+    },
+}
+
+impl Format {
+    /// Parses the argument of `--pretty`/`--format`. A bare string that
+    /// contains a `%` is treated as `tformat:`, like git does.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "oneline" => Some(Format::Oneline),
+            "short" => Some(Format::Short),
+            "medium" => Some(Format::Medium),
+            "full" => Some(Format::Full),
+            "fuller" => Some(Format::Fuller),
+            "raw" => Some(Format::Raw),
+            x => {
+                if let Some(format) = x.strip_prefix("format:") {
+                    Some(Format::Custom {
+                        format: format.to_string(),
+                        terminator: false,
+                    })
+                } else if let Some(format) = x.strip_prefix("tformat:") {
+                    Some(Format::Custom {
+                        format: format.to_string(),
+                        terminator: true,
+                    })
+                } else if x.contains('%') {
+                    Some(Format::Custom {
+                        format: x.to_string(),
+                        terminator: true,
+                    })
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Built-in multi-line formats are separated by a blank line.
+    pub fn is_multiline(&self) -> bool {
+        !matches!(self, Format::Oneline | Format::Custom { .. })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DateFormat {
+    Default,
+    Iso,
+    IsoStrict,
+    Rfc2822,
+    Short,
+    Raw,
+    Unix,
+    Relative,
+}
+
+impl DateFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "default" => Some(DateFormat::Default),
+            "iso" | "iso8601" => Some(DateFormat::Iso),
+            "iso-strict" | "iso8601-strict" => Some(DateFormat::IsoStrict),
+            "rfc" | "rfc2822" => Some(DateFormat::Rfc2822),
+            "short" => Some(DateFormat::Short),
+            "raw" => Some(DateFormat::Raw),
+            "unix" => Some(DateFormat::Unix),
+            "relative" => Some(DateFormat::Relative),
+            _ => None,
+        }
+    }
+
+    pub fn format(self, ts: &DateTime<FixedOffset>) -> String {
+        match self {
+            DateFormat::Default => ts.format("%a %b %-d %H:%M:%S %Y %z").to_string(),
+            DateFormat::Iso => ts.format("%Y-%m-%d %H:%M:%S %z").to_string(),
+            DateFormat::IsoStrict => ts.format("%Y-%m-%dT%H:%M:%S%:z").to_string(),
+            DateFormat::Rfc2822 => ts.format("%a, %-d %b %Y %H:%M:%S %z").to_string(),
+            DateFormat::Short => ts.format("%Y-%m-%d").to_string(),
+            DateFormat::Raw => ts.format("%s %z").to_string(),
+            DateFormat::Unix => ts.timestamp().to_string(),
+            DateFormat::Relative => relative(ts),
+        }
+    }
+}
+
+fn relative(ts: &DateTime<FixedOffset>) -> String {
+    let secs = Local::now().timestamp() - ts.timestamp();
+    if secs < 0 {
+        return "in the future".to_string();
+    }
+
+    let plural =
+        |n: i64, unit: &str| format!("{} {}{} ago", n, unit, if n == 1 { "" } else { "s" });
+
+    match secs {
+        s if s < 90 => plural(s, "second"),
+        s if s < 90 * 60 => plural((s + 30) / 60, "minute"),
+        s if s < 36 * 3600 => plural((s + 1800) / 3600, "hour"),
+        s if s < 14 * 86400 => plural((s + 43200) / 86400, "day"),
+        s if s < 10 * 7 * 86400 => plural((s + 3 * 86400) / (7 * 86400), "week"),
+        s if s < 365 * 86400 => plural((s + 15 * 86400) / (30 * 86400), "month"),
+        s => plural((s + 183 * 86400) / (365 * 86400), "year"),
+    }
+}
+
+/// Renders commits the way `git log --pretty` does.
+#[derive(Debug, Clone)]
+pub struct Pretty {
+    pub format: Format,
+    pub date: DateFormat,
+    pub abbrev: bool,
+}
+
+impl Pretty {
+    pub fn new(format: Format) -> Self {
+        Self {
+            format,
+            date: DateFormat::Default,
+            abbrev: false,
+        }
+    }
+
+    /// Formats a single commit without a trailing newline. `decorations`
+    /// are ref names pointing at the commit, e.g. `HEAD -> master`.
+    pub fn format(&self, hash: &str, commit: &Commit, decorations: &[String]) -> String {
+        let decoration = if decorations.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", decorations.join(", "))
+        };
+        let name = if self.abbrev { abbrev(hash) } else { hash };
+
+        let mut out = String::new();
+        match &self.format {
+            Format::Oneline => {
+                write!(out, "{}{} {}", name, decoration, subject(&commit.message)).unwrap();
+            }
+            Format::Custom { format, .. } => {
+                out = self.expand(format, hash, commit, decorations);
+            }
+            Format::Raw => {
+                writeln!(out, "commit {}{}", hash, decoration).unwrap();
+                let content = format!("{}", commit);
+                let header = content.split("\n\n").next().unwrap_or_default();
+                writeln!(out, "{}", header).unwrap();
+                out.push('\n');
+                out.push_str(&indent(&commit.message));
+            }
+            format => {
+                writeln!(out, "commit {}{}", name, decoration).unwrap();
+                if commit.parents.len() > 1 {
+                    let parents = commit
+                        .parents
+                        .iter()
+                        .map(|x| abbrev(x))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    writeln!(out, "Merge: {}", parents).unwrap();
+                }
+
+                let ident = |user: &User| format!("{} <{}>", user.name, user.email);
+                match format {
+                    Format::Short => {
+                        writeln!(out, "Author: {}", ident(&commit.author)).unwrap();
+                    }
+                    Format::Medium => {
+                        writeln!(out, "Author: {}", ident(&commit.author)).unwrap();
+                        writeln!(out, "Date:   {}", self.date.format(&commit.author.ts)).unwrap();
+                    }
+                    Format::Full => {
+                        writeln!(out, "Author: {}", ident(&commit.author)).unwrap();
+                        writeln!(out, "Commit: {}", ident(&commit.committer)).unwrap();
+                    }
+                    _ => {
+                        writeln!(out, "Author:     {}", ident(&commit.author)).unwrap();
+                        writeln!(out, "AuthorDate: {}", self.date.format(&commit.author.ts))
+                            .unwrap();
+                        writeln!(out, "Commit:     {}", ident(&commit.committer)).unwrap();
+                        writeln!(
+                            out,
+                            "CommitDate: {}",
+                            self.date.format(&commit.committer.ts)
+                        )
+                        .unwrap();
+                    }
+                }
+                out.push('\n');
+
+                if *format == Format::Short {
+                    write!(out, "    {}", subject(&commit.message)).unwrap();
+                } else {
+                    out.push_str(&indent(&commit.message));
+                }
+            }
+        }
+
+        out.trim_end_matches('\n').to_string()
+    }
+
+    fn expand(&self, format: &str, hash: &str, commit: &Commit, decorations: &[String]) -> String {
+        let mut out = String::new();
+        let mut rest = format;
+
+        while let Some(pos) = rest.find('%') {
+            out.push_str(&rest[..pos]);
+            rest = &rest[pos + 1..];
+
+            let (value, used) = self.placeholder(rest, hash, commit, decorations);
+            match value {
+                Some(value) => {
+                    out.push_str(&value);
+                    rest = &rest[used..];
+                }
+                None => out.push('%'),
+            }
+        }
+        out.push_str(rest);
+
+        out
+    }
+
+    /// Expands the placeholder at the start of `spec`, returning the text
+    /// and how many bytes of `spec` it consumed.
+    fn placeholder(
+        &self,
+        spec: &str,
+        hash: &str,
+        commit: &Commit,
+        decorations: &[String],
+    ) -> (Option<String>, usize) {
+        let two = spec.get(..2).unwrap_or_default();
+        let one = spec.get(..1).unwrap_or_default();
+
+        if let Some(user) = match one {
+            "a" => Some(&commit.author),
+            "c" => Some(&commit.committer),
+            _ => None,
+        } {
+            let value = match two.get(1..) {
+                Some("n") | Some("N") => Some(user.name.clone()),
+                Some("e") | Some("E") => Some(user.email.clone()),
+                Some("d") => Some(self.date.format(&user.ts)),
+                Some("D") => Some(DateFormat::Rfc2822.format(&user.ts)),
+                Some("r") => Some(DateFormat::Relative.format(&user.ts)),
+                Some("t") => Some(DateFormat::Unix.format(&user.ts)),
+                Some("i") => Some(DateFormat::Iso.format(&user.ts)),
+                Some("I") => Some(DateFormat::IsoStrict.format(&user.ts)),
+                Some("s") => Some(DateFormat::Short.format(&user.ts)),
+                _ => None,
+            };
+            if value.is_some() {
+                return (value, 2);
+            }
+        }
+
+        if let Some(color) = spec.strip_prefix("C(") {
+            return match color.find(')') {
+                Some(end) => (color_code(&color[..end]), end + 3),
+                None => (None, 0),
+            };
+        }
+        for (name, code) in [
+            ("Cred", "\x1b[31m"),
+            ("Cgreen", "\x1b[32m"),
+            ("Cblue", "\x1b[34m"),
+            ("Creset", "\x1b[m"),
+        ]
+        .iter()
+        {
+            if spec.starts_with(name) {
+                return (Some(code.to_string()), name.len());
+            }
+        }
+
+        if let Some(hex) = spec.strip_prefix('x') {
+            let digits = hex.get(..2).unwrap_or_default();
+            if let Ok(byte) = u8::from_str_radix(digits, 16) {
+                return (Some((byte as char).to_string()), 3);
+            }
+        }
+
+        let value = match one {
+            "H" => hash.to_string(),
+            "h" => abbrev(hash).to_string(),
+            "T" => commit.tree.clone(),
+            "t" => abbrev(&commit.tree).to_string(),
+            "P" => commit.parents.join(" "),
+            "p" => commit
+                .parents
+                .iter()
+                .map(|x| abbrev(x))
+                .collect::<Vec<_>>()
+                .join(" "),
+            "s" => subject(&commit.message),
+            "b" => body(&commit.message),
+            "B" => commit.message.clone(),
+            "n" => "\n".to_string(),
+            "%" => "%".to_string(),
+            "d" if !decorations.is_empty() => format!(" ({})", decorations.join(", ")),
+            "d" => String::new(),
+            "D" => decorations.join(", "),
+            _ => return (None, 0),
+        };
+
+        (Some(value), 1)
+    }
+}
+
+fn color_code(name: &str) -> Option<String> {
+    let code = match name {
+        "reset" => "\x1b[m",
+        "black" => "\x1b[30m",
+        "red" => "\x1b[31m",
+        "green" => "\x1b[32m",
+        "yellow" => "\x1b[33m",
+        "blue" => "\x1b[34m",
+        "magenta" => "\x1b[35m",
+        "cyan" => "\x1b[36m",
+        "white" => "\x1b[37m",
+        "bold" => "\x1b[1m",
+        "dim" => "\x1b[2m",
+        "ul" => "\x1b[4m",
+        "auto" => "",
+        _ => return None,
+    };
+    Some(code.to_string())
+}
+
+pub fn abbrev(hash: &str) -> &str {
+    hash.get(..7).unwrap_or(hash)
+}
+
+/// The first paragraph of a message, joined onto one line.
+pub fn subject(message: &str) -> String {
+    message
+        .trim_start_matches('\n')
+        .lines()
+        .take_while(|x| !x.trim().is_empty())
+        .map(|x| x.trim())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Everything after the subject paragraph.
+pub fn body(message: &str) -> String {
+    let mut lines = message.trim_start_matches('\n').lines();
+    lines
+        .by_ref()
+        .take_while(|x| !x.trim().is_empty())
+        .for_each(drop);
+
+    let body = lines
+        .skip_while(|x| x.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    if body.is_empty() {
+        body
+    } else {
+        format!("{}\n", body.trim_end())
+    }
+}
+
+fn indent(message: &str) -> String {
+    message
+        .trim_end()
+        .lines()
+        .skip_while(|x| x.trim().is_empty())
+        .map(|x| format!("    {}\n", x))
+        .collect()
+}
+
+#[cfg(test)]
+fn test_commit() -> Commit {
+    Commit::from(
+        b"tree 4d593e935186bcc35450336864a1aad148210a14\nparent fe152caf9a62e68a38a726cd29270606faa4385f\nparent 1e0d683b1a5ef3bd2056604d1f578d4a58a4e255\nauthor A U Thor <author@example.com> 1112911993 -0700\ncommitter C O Mitter <committer@example.com> 1112912053 +0530\n\nSubject line\ncontinued\n\nBody paragraph.\n",
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_pretty_medium() {
+    let pretty = Pretty::new(Format::Medium);
+    let out = pretty.format(
+        "af4ef8a17ad65221116c1984a265af7babb9a8bb",
+        &test_commit(),
+        &["HEAD -> master".to_string()],
+    );
+
+    assert_eq!(
+        out,
+        "commit af4ef8a17ad65221116c1984a265af7babb9a8bb (HEAD -> master)\nMerge: fe152ca 1e0d683\nAuthor: A U Thor <author@example.com>\nDate:   Thu Apr 7 15:13:13 2005 -0700\n\n    Subject line\n    continued\n    \n    Body paragraph."
+    );
+}
+
+#[test]
+fn test_pretty_placeholders() {
+    let pretty = Pretty::new(Format::parse("%h %p %an <%ae> %at %ci%n%s|%b%%%x41%Cred").unwrap());
+    let out = pretty.format(
+        "af4ef8a17ad65221116c1984a265af7babb9a8bb",
+        &test_commit(),
+        &[],
+    );
+
+    assert_eq!(
+        out,
+        "af4ef8a fe152ca 1e0d683 A U Thor <author@example.com> 1112911993 2005-04-08 03:44:13 +0530\nSubject line continued|Body paragraph.\n%A\x1b[31m"
+    );
+}
+
+#[test]
+fn test_pretty_oneline() {
+    let mut pretty = Pretty::new(Format::Oneline);
+    pretty.abbrev = true;
+
+    assert_eq!(
+        pretty.format(
+            "af4ef8a17ad65221116c1984a265af7babb9a8bb",
+            &test_commit(),
+            &[]
+        ),
+        "af4ef8a Subject line continued"
+    );
+}
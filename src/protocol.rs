@@ -0,0 +1,540 @@
+use crate::fs::FileSystem;
+use crate::Git;
+use std::collections::BTreeMap;
+use std::io;
+use std::io::prelude::*;
+
+/// The most a pkt-line may carry after its four-byte length.
+const MAX_DATA: usize = 65516;
+
+/// How many haves the first round of negotiation sends. Each round after
+/// sends twice as many as the one before.
+const INITIAL_HAVES: usize = 16;
+
+/// A pkt-line, the framing of everything in git's wire protocol: data
+/// after its length in four hex digits, or one of the special lengths
+/// that separate messages.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Packet {
+    Data(Vec<u8>),
+    /// `0000`, the end of a message.
+    Flush,
+    /// `0001`, between the sections of a message.
+    Delim,
+    /// `0002`, the end of a response in stateless transports.
+    ResponseEnd,
+}
+
+impl Packet {
+    /// A data packet with `line` and the newline that ends it.
+    pub fn line(line: &str) -> Self {
+        Packet::Data(format!("{}\n", line).into_bytes())
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Self> {
+        let mut len = [0; 4];
+        reader.read_exact(&mut len)?;
+        let len = std::str::from_utf8(&len)
+            .ok()
+            .and_then(|x| usize::from_str_radix(x, 16).ok())
+            .ok_or_else(|| invalid("bad packet length"))?;
+        match len {
+            0 => Ok(Packet::Flush),
+            1 => Ok(Packet::Delim),
+            2 => Ok(Packet::ResponseEnd),
+            3 => Err(invalid("bad packet length")),
+            len => {
+                let mut data = vec![0; len - 4];
+                reader.read_exact(&mut data)?;
+                Ok(Packet::Data(data))
+            }
+        }
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        match self {
+            Packet::Data(data) if data.len() > MAX_DATA => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "packet too long",
+            )),
+            Packet::Data(data) => {
+                write!(writer, "{:04x}", data.len() + 4)?;
+                writer.write_all(data)
+            }
+            Packet::Flush => writer.write_all(b"0000"),
+            Packet::Delim => writer.write_all(b"0001"),
+            Packet::ResponseEnd => writer.write_all(b"0002"),
+        }
+    }
+
+    /// The text of a data packet without its newline, if it has one.
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            Packet::Data(data) => std::str::from_utf8(data)
+                .ok()
+                .map(|x| x.strip_suffix('\n').unwrap_or(x)),
+            _ => None,
+        }
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// A ref of the remote, as `ls-refs` lists it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RemoteRef {
+    pub name: String,
+    pub hash: String,
+    /// The ref a symbolic ref like `HEAD` points to.
+    pub symref_target: Option<String>,
+    /// The object an annotated tag points to.
+    pub peeled: Option<String>,
+}
+
+/// The client side of a protocol version 2 conversation with
+/// `upload-pack`, over any pair of byte streams: the pipes of a child
+/// process, a socket, or a server in the same process.
+pub struct Connection<R: Read, W: Write> {
+    reader: R,
+    writer: W,
+    /// The capabilities the server advertised, like `fetch=shallow`.
+    pub capabilities: Vec<String>,
+}
+
+impl<R: Read, W: Write> Connection<R, W> {
+    /// Reads the server's capability advertisement, which opens the
+    /// conversation.
+    pub fn new(mut reader: R, writer: W) -> io::Result<Self> {
+        match Packet::read_from(&mut reader)?.text() {
+            Some("version 2") => {}
+            _ => return Err(invalid("server does not support protocol version 2")),
+        }
+        let mut capabilities = Vec::new();
+        loop {
+            match Packet::read_from(&mut reader)? {
+                Packet::Flush => break,
+                packet => capabilities.push(
+                    packet
+                        .text()
+                        .ok_or_else(|| invalid("bad capability advertisement"))?
+                        .to_string(),
+                ),
+            }
+        }
+        Ok(Connection {
+            reader,
+            writer,
+            capabilities,
+        })
+    }
+
+    /// The value of a capability the server advertised, empty when it
+    /// has none.
+    pub fn capability(&self, name: &str) -> Option<&str> {
+        self.capabilities
+            .iter()
+            .find_map(|x| match x.split_once('=') {
+                Some((key, value)) if key == name => Some(value),
+                None if x == name => Some(""),
+                _ => None,
+            })
+    }
+
+    /// The refs of the remote, or those starting with one of `prefixes`
+    /// if there are any.
+    pub fn ls_refs(&mut self, prefixes: &[String]) -> io::Result<Vec<RemoteRef>> {
+        let mut args = vec!["symrefs".to_string(), "peel".to_string()];
+        args.extend(prefixes.iter().map(|x| format!("ref-prefix {}", x)));
+        self.request("ls-refs", &args)?;
+
+        let mut refs = Vec::new();
+        loop {
+            let packet = self.read()?;
+            if packet == Packet::Flush {
+                return Ok(refs);
+            }
+            let bad = || invalid("bad ls-refs response");
+            let line = packet.text().ok_or_else(bad)?;
+            let mut fields = line.split(' ');
+            let (hash, name) = match (fields.next(), fields.next()) {
+                (Some(hash), Some(name)) => (hash, name),
+                _ => return Err(bad()),
+            };
+            let mut remote = RemoteRef {
+                name: name.to_string(),
+                hash: hash.to_string(),
+                symref_target: None,
+                peeled: None,
+            };
+            for attribute in fields {
+                if let Some(target) = attribute.strip_prefix("symref-target:") {
+                    remote.symref_target = Some(target.to_string());
+                } else if let Some(peeled) = attribute.strip_prefix("peeled:") {
+                    remote.peeled = Some(peeled.to_string());
+                }
+            }
+            refs.push(remote);
+        }
+    }
+
+    /// Asks for a pack with `wants` and whatever they need, and returns
+    /// it. Commits the client has are offered from `haves`, newest first,
+    /// in rounds until the server is ready to send a pack leaving out
+    /// what they share or the haves run out. The server's progress
+    /// messages go to `progress`; without it the server sends none.
+    pub fn fetch(
+        &mut self,
+        wants: &[String],
+        mut haves: impl Iterator<Item = io::Result<String>>,
+        progress: Option<&mut dyn Write>,
+    ) -> io::Result<Vec<u8>> {
+        // The server forgets between requests, so every round repeats the
+        // wants and the haves it acknowledged.
+        let mut common = Vec::new();
+        let mut batch = INITIAL_HAVES;
+        loop {
+            let round = haves.by_ref().take(batch).collect::<io::Result<Vec<_>>>()?;
+            let done = round.len() < batch;
+
+            let mut args = vec!["ofs-delta".to_string(), "include-tag".to_string()];
+            if progress.is_none() {
+                args.push("no-progress".to_string());
+            }
+            args.extend(wants.iter().map(|x| format!("want {}", x)));
+            args.extend(common.iter().chain(&round).map(|x| format!("have {}", x)));
+            if done {
+                args.push("done".to_string());
+            }
+            self.request("fetch", &args)?;
+
+            if !done {
+                if self.read()?.text() != Some("acknowledgments") {
+                    return Err(invalid("expected acknowledgments"));
+                }
+                let mut ready = false;
+                loop {
+                    let packet = self.read()?;
+                    match (&packet, packet.text()) {
+                        (Packet::Flush, _) => break,
+                        // The pack follows.
+                        (Packet::Delim, _) if ready => break,
+                        (_, Some("NAK")) => {}
+                        (_, Some("ready")) => ready = true,
+                        (_, Some(line)) if line.starts_with("ACK ") => {
+                            common.push(line[4..].to_string())
+                        }
+                        _ => return Err(invalid("bad acknowledgment")),
+                    }
+                }
+                if !ready {
+                    batch *= 2;
+                    continue;
+                }
+            }
+            return self.read_pack(progress);
+        }
+    }
+
+    /// Reads the sections of a fetch response up to and including the
+    /// pack, which comes in band 1 of its packets with progress in band 2
+    /// and errors in band 3.
+    fn read_pack(&mut self, mut progress: Option<&mut dyn Write>) -> io::Result<Vec<u8>> {
+        // Skips sections we didn't ask for, like `shallow-info`.
+        loop {
+            match self.read()?.text() {
+                Some("packfile") => break,
+                Some(_) => while !matches!(self.read()?, Packet::Delim) {},
+                None => return Err(invalid("expected packfile")),
+            }
+        }
+
+        let mut pack = Vec::new();
+        loop {
+            match self.read()? {
+                Packet::Flush => return Ok(pack),
+                Packet::Data(data) => match data.split_first() {
+                    Some((1, data)) => pack.extend(data),
+                    Some((2, data)) => {
+                        if let Some(progress) = progress.as_deref_mut() {
+                            progress.write_all(data)?;
+                        }
+                    }
+                    Some((3, data)) => {
+                        return Err(io::Error::other(format!(
+                            "remote error: {}",
+                            String::from_utf8_lossy(data).trim_end()
+                        )))
+                    }
+                    _ => return Err(invalid("bad sideband")),
+                },
+                _ => return Err(invalid("bad packfile section")),
+            }
+        }
+    }
+
+    /// Sends a command with our capabilities and its arguments.
+    fn request(&mut self, command: &str, args: &[String]) -> io::Result<()> {
+        Packet::line(&format!("command={}", command)).write_to(&mut self.writer)?;
+        Packet::line(&format!("agent=clumsy/{}", env!("CARGO_PKG_VERSION")))
+            .write_to(&mut self.writer)?;
+        if self.capability("object-format").is_some() {
+            Packet::line("object-format=sha1").write_to(&mut self.writer)?;
+        }
+        Packet::Delim.write_to(&mut self.writer)?;
+        for arg in args {
+            Packet::line(arg).write_to(&mut self.writer)?;
+        }
+        Packet::Flush.write_to(&mut self.writer)?;
+        self.writer.flush()
+    }
+
+    /// The next packet of a response, failing on an error from the
+    /// server.
+    fn read(&mut self) -> io::Result<Packet> {
+        let packet = Packet::read_from(&mut self.reader)?;
+        match packet.text().and_then(|x| x.strip_prefix("ERR ")) {
+            Some(msg) => Err(io::Error::other(format!("remote error: {}", msg))),
+            None => Ok(packet),
+        }
+    }
+}
+
+/// The client side of a push to `receive-pack`, which speaks protocol
+/// version 0: the server advertises its refs, and the client answers with
+/// the updates it wants and the pack they need.
+pub struct PushConnection<R: Read, W: Write> {
+    reader: R,
+    writer: W,
+    /// The refs of the remote and what they point to.
+    pub refs: BTreeMap<String, String>,
+    /// The capabilities the server advertised, like `atomic`.
+    pub capabilities: Vec<String>,
+}
+
+impl<R: Read, W: Write> PushConnection<R, W> {
+    /// Reads the server's ref advertisement, which opens the
+    /// conversation.
+    pub fn new(mut reader: R, writer: W) -> io::Result<Self> {
+        let mut refs = BTreeMap::new();
+        let mut capabilities = Vec::new();
+        loop {
+            let packet = Packet::read_from(&mut reader)?;
+            if packet == Packet::Flush {
+                break;
+            }
+            let bad = || invalid("bad ref advertisement");
+            let line = packet.text().ok_or_else(bad)?;
+            // The first ref carries the capabilities after a NUL.
+            let line = match line.split_once('\0') {
+                Some((line, rest)) => {
+                    capabilities.extend(rest.split(' ').map(str::to_string));
+                    line
+                }
+                None => line,
+            };
+            let (hash, name) = line.split_once(' ').ok_or_else(bad)?;
+            // What a repository without refs advertises instead.
+            if name != "capabilities^{}" {
+                refs.insert(name.to_string(), hash.to_string());
+            }
+        }
+        Ok(PushConnection {
+            reader,
+            writer,
+            refs,
+            capabilities,
+        })
+    }
+
+    /// Asks the server to move each of `commands`' refs from their old
+    /// value to their new one, `NULL_HASH` for none, and sends it `pack`
+    /// for the objects it needs. Returns the ones it refused, and why.
+    /// Without commands, only ends the conversation.
+    pub fn send(
+        &mut self,
+        commands: &[(String, String, String)],
+        pack: Option<&[u8]>,
+        atomic: bool,
+    ) -> io::Result<BTreeMap<String, String>> {
+        for (i, (old, new, refname)) in commands.iter().enumerate() {
+            let line = format!("{} {} {}", old, new, refname);
+            match i {
+                0 => Packet::line(&format!(
+                    "{}\0report-status{} agent=clumsy/{}",
+                    line,
+                    if atomic { " atomic" } else { "" },
+                    env!("CARGO_PKG_VERSION")
+                )),
+                _ => Packet::line(&line),
+            }
+            .write_to(&mut self.writer)?;
+        }
+        Packet::Flush.write_to(&mut self.writer)?;
+        if let (Some(pack), false) = (pack, commands.is_empty()) {
+            self.writer.write_all(pack)?;
+        }
+        self.writer.flush()?;
+        if commands.is_empty() {
+            return Ok(BTreeMap::new());
+        }
+
+        let mut refused = BTreeMap::new();
+        let bad = || invalid("bad report-status");
+        match Packet::read_from(&mut self.reader)?.text() {
+            Some("unpack ok") => {}
+            Some(line) => {
+                let reason = line.strip_prefix("unpack ").ok_or_else(bad)?;
+                return Err(io::Error::other(format!("remote unpack failed: {}", reason)));
+            }
+            None => return Err(bad()),
+        }
+        loop {
+            let packet = Packet::read_from(&mut self.reader)?;
+            if packet == Packet::Flush {
+                return Ok(refused);
+            }
+            let line = packet.text().ok_or_else(bad)?;
+            if let Some(rest) = line.strip_prefix("ng ") {
+                let (refname, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+                refused.insert(refname.to_string(), reason.to_string());
+            } else if !line.starts_with("ok ") {
+                return Err(bad());
+            }
+        }
+    }
+}
+
+impl<F: FileSystem> Git<F> {
+    /// Fetches over `connection` the objects of `wants` the repository
+    /// lacks and everything they need, offering the history of its own
+    /// refs to keep the pack small. Returns the name of the stored pack,
+    /// if there was anything to fetch.
+    pub fn fetch_pack<R: Read, W: Write>(
+        &mut self,
+        connection: &mut Connection<R, W>,
+        wants: &[String],
+        progress: Option<&mut dyn Write>,
+    ) -> io::Result<Option<String>> {
+        let mut missing = Vec::new();
+        for want in wants {
+            if !missing.contains(want) && !self.has_object(want) {
+                missing.push(want.clone());
+            }
+        }
+        if missing.is_empty() {
+            return Ok(None);
+        }
+
+        let mut walk = self.rev_walk();
+        let head = self.resolve_ref("HEAD".to_string()).ok();
+        for (_, hash) in self
+            .all_refs()?
+            .into_iter()
+            .chain(head.map(|x| (String::new(), x)))
+        {
+            // Refs to trees or blobs have no history to offer.
+            let _ = walk.push(&hash);
+        }
+        let pack = connection.fetch(&missing, walk, progress)?;
+        self.index_pack(&pack).map(Some)
+    }
+}
+
+#[test]
+fn test_packet() {
+    let mut bytes = Vec::new();
+    Packet::line("version 2").write_to(&mut bytes).unwrap();
+    Packet::Delim.write_to(&mut bytes).unwrap();
+    Packet::Flush.write_to(&mut bytes).unwrap();
+    assert_eq!(bytes, b"000eversion 2\n00010000");
+
+    let mut reader = &bytes[..];
+    let packet = Packet::read_from(&mut reader).unwrap();
+    assert_eq!(packet.text(), Some("version 2"));
+    assert_eq!(Packet::read_from(&mut reader).unwrap(), Packet::Delim);
+    assert_eq!(Packet::read_from(&mut reader).unwrap(), Packet::Flush);
+    assert!(Packet::read_from(&mut reader).is_err());
+    assert!(Packet::read_from(&mut &b"0003"[..]).is_err());
+}
+
+#[test]
+fn test_fetch() {
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::object::tree::FileMode;
+    use crate::test_util::{blob, tree};
+
+    // A pack of a blob and a tree holding it.
+    let mut source = Git::new(InMemFileSystem::init());
+    source.init(true, "main").unwrap();
+    let blob_hash = blob(&mut source, b"hello\n");
+    let tree_hash = tree(&mut source, &[(FileMode::Blob, "file", &blob_hash)]);
+    let pack = source
+        .write_pack(&[blob_hash.clone(), tree_hash.clone()])
+        .unwrap();
+
+    // What the server says, all of it up front.
+    let mut server = Vec::new();
+    let mut say = |packets: &[Packet]| {
+        for packet in packets {
+            packet.write_to(&mut server).unwrap();
+        }
+    };
+    say(&[
+        Packet::line("version 2"),
+        Packet::line("ls-refs=unborn"),
+        Packet::line("fetch=shallow"),
+        Packet::Flush,
+        Packet::line(&format!("{} HEAD symref-target:refs/heads/main", tree_hash)),
+        Packet::line(&format!("{} refs/heads/main", tree_hash)),
+        Packet::Flush,
+        Packet::line("acknowledgments"),
+        Packet::line(&format!("ACK {}", blob_hash)),
+        Packet::line("ready"),
+        Packet::Delim,
+        Packet::line("packfile"),
+        Packet::Data(b"\x02Counting objects: 2, done.\n".to_vec()),
+    ]);
+    for chunk in pack.chunks(20) {
+        say(&[Packet::Data([&[1], chunk].concat())]);
+    }
+    say(&[Packet::Flush]);
+
+    let mut client = Vec::new();
+    let mut connection = Connection::new(&server[..], &mut client).unwrap();
+    assert_eq!(connection.capability("fetch"), Some("shallow"));
+    assert_eq!(connection.capability("object-format"), None);
+    let refs = connection.ls_refs(&["refs/heads/".to_string()]).unwrap();
+    assert_eq!(refs.len(), 2);
+    assert_eq!(refs[0].symref_target.as_deref(), Some("refs/heads/main"));
+
+    // Enough haves to hold the last of them back for another round, which
+    // the server being ready makes unnecessary.
+    let haves = (0..=INITIAL_HAVES).map(|_| Ok(blob_hash.clone()));
+    let mut progress = Vec::new();
+    let received = connection
+        .fetch(std::slice::from_ref(&tree_hash), haves, Some(&mut progress))
+        .unwrap();
+    assert_eq!(received, pack);
+    assert_eq!(progress, b"Counting objects: 2, done.\n");
+    drop(connection);
+
+    let client = String::from_utf8_lossy(&client);
+    assert!(client.contains("command=ls-refs\n"));
+    assert!(client.contains("ref-prefix refs/heads/\n"));
+    assert!(client.contains(&format!("want {}\n", tree_hash)));
+    assert!(!client.contains("done"));
+
+    let mut git = Git::new(InMemFileSystem::init());
+    git.init(false, "main").unwrap();
+    git.index_pack(&received).unwrap();
+    let (object_type, _) = git.read_raw_object(tree_hash.clone()).unwrap();
+    assert_eq!(object_type, crate::object::ObjectType::Tree);
+    assert_eq!(git.read_blob(blob_hash.clone()).unwrap(), b"hello\n");
+
+    // Everything it wants being there, the repository asks for nothing.
+    let mut connection = Connection::new(&server[..], Vec::new()).unwrap();
+    let name = git.fetch_pack(&mut connection, &[tree_hash], None).unwrap();
+    assert_eq!(name, None);
+}
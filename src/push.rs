@@ -0,0 +1,355 @@
+use crate::fs::FileSystem;
+use crate::object::ObjectType;
+use crate::protocol::PushConnection;
+use crate::reflog::NULL_HASH;
+use crate::remote::{RefUpdate, Refspec, Remote, UpdateStatus};
+use crate::Git;
+use std::collections::BTreeMap;
+use std::io;
+use std::io::prelude::*;
+
+#[derive(Debug, Default)]
+pub struct PushOptions {
+    /// Allows updates other than fast-forwards, as if every refspec had
+    /// a `+`.
+    pub force: bool,
+    /// Updates every ref or, if one can't be, none of them.
+    pub atomic: bool,
+    /// The refs `--force-with-lease` lets overwrite.
+    pub leases: Vec<Lease>,
+}
+
+/// A remote ref, or every one if none is named, that may be overwritten
+/// so long as it still is at `expect`, or without it where our
+/// remote-tracking ref for it is. An `expect` of `None` alongside a
+/// missing tracking ref means the remote ref mustn't exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub refname: Option<String>,
+    pub expect: Option<String>,
+}
+
+/// A remote ref to update, and what with.
+struct Command {
+    src: String,
+    dst: String,
+    new: Option<String>,
+    force: bool,
+}
+
+impl<F: FileSystem> Git<F> {
+    /// Pushes to `remote` over `connection` what `refspecs` map to its
+    /// refs: new refs are created, existing ones only fast-forwarded
+    /// unless forced or leased, tags never moved unless forced, and
+    /// refspecs without a source delete their destination. Sends the
+    /// objects the remote lacks, then updates our remote-tracking refs
+    /// for whatever the remote took. Returns what happened to each ref.
+    pub fn push<R: Read, W: Write>(
+        &mut self,
+        connection: &mut PushConnection<R, W>,
+        remote: &Remote,
+        refspecs: &[Refspec],
+        options: &PushOptions,
+    ) -> io::Result<Vec<RefUpdate>> {
+        let remote_refs = connection.refs.clone();
+        let mut commands = Vec::new();
+        for spec in refspecs {
+            commands.extend(self.push_commands(spec, &remote_refs)?);
+        }
+
+        let mut updates = Vec::new();
+        for command in commands {
+            let old = remote_refs.get(&command.dst).cloned();
+            // Forcing outright overrides a lease.
+            let force = command.force || options.force;
+            let lease = options
+                .leases
+                .iter()
+                .filter(|_| !force)
+                .find(|x| match &x.refname {
+                    Some(name) => self.ref_candidates(name).contains(&command.dst),
+                    None => true,
+                });
+            let expected = lease.map(|x| {
+                x.expect.clone().or_else(|| {
+                    let tracking = remote.tracking_ref(&command.dst)?;
+                    self.resolve_ref(tracking).ok()
+                })
+            });
+            let force = force || lease.is_some();
+            let rejected = |reason: &str| UpdateStatus::Rejected(reason.to_string());
+
+            let status = match (&old, &command.new) {
+                (old, new) if old == new => UpdateStatus::UpToDate,
+                _ if expected.as_ref().is_some_and(|x| *x != old) => rejected("stale info"),
+                (_, None) => UpdateStatus::Deleted,
+                (None, Some(_)) => UpdateStatus::Created,
+                (Some(_), Some(_)) if command.dst.starts_with("refs/tags/") && !force => {
+                    rejected("already exists")
+                }
+                (Some(old), Some(_)) if !self.has_object(old) => match force {
+                    true => UpdateStatus::Forced,
+                    false => rejected("fetch first"),
+                },
+                (Some(old), Some(new)) if self.is_fast_forward(old, new) => {
+                    UpdateStatus::FastForward
+                }
+                _ if force => UpdateStatus::Forced,
+                _ => rejected("non-fast-forward"),
+            };
+            updates.push(RefUpdate {
+                src: command.src,
+                dst: Some(command.dst),
+                old,
+                new: command.new,
+                status,
+            });
+        }
+
+        let sent = |x: &UpdateStatus| !x.is_rejected() && *x != UpdateStatus::UpToDate;
+        if options.atomic && updates.iter().any(|x| x.status.is_rejected()) {
+            for update in updates.iter_mut().filter(|x| sent(&x.status)) {
+                update.status = UpdateStatus::Rejected("atomic push failed".to_string());
+            }
+        }
+
+        let mut lines = Vec::new();
+        let mut news = Vec::new();
+        for update in updates.iter().filter(|x| sent(&x.status)) {
+            let hash = |x: &Option<String>| x.clone().unwrap_or_else(|| NULL_HASH.to_string());
+            let dst = update.dst.clone().unwrap_or_default();
+            lines.push((hash(&update.old), hash(&update.new), dst));
+            news.extend(update.new.clone());
+        }
+        let pack = match news.is_empty() {
+            true => None,
+            false => {
+                let haves: Vec<_> = remote_refs
+                    .values()
+                    .filter(|x| self.has_object(x))
+                    .cloned()
+                    .collect();
+                Some(self.write_pack(&self.objects_between(&news, &haves)?)?)
+            }
+        };
+        let refused = connection.send(&lines, pack.as_deref(), options.atomic)?;
+
+        for update in updates.iter_mut().filter(|x| sent(&x.status)) {
+            let dst = update.dst.as_deref().unwrap_or_default();
+            if let Some(reason) = refused.get(dst) {
+                update.status = UpdateStatus::RemoteRejected(reason.clone());
+                continue;
+            }
+            let tracking = match remote.tracking_ref(dst) {
+                Some(tracking) => tracking,
+                None => continue,
+            };
+            match &update.new {
+                Some(new) => {
+                    let old = self.resolve_ref(tracking.clone()).ok();
+                    self.update_ref_logged(&tracking, old.as_deref(), new, "update by push")?
+                }
+                None => self.delete_ref(&tracking)?,
+            }
+        }
+        Ok(updates)
+    }
+
+    /// The remote refs `spec` updates: every local ref a glob matches,
+    /// or the revision it names, which goes to the ref of the same name
+    /// when it has no destination.
+    fn push_commands(
+        &self,
+        spec: &Refspec,
+        remote_refs: &BTreeMap<String, String>,
+    ) -> io::Result<Vec<Command>> {
+        if spec.is_glob() {
+            return Ok(self
+                .all_refs()?
+                .into_iter()
+                .filter_map(|(name, hash)| {
+                    Some(Command {
+                        dst: spec.map(&name)?,
+                        src: name,
+                        new: Some(hash),
+                        force: spec.force,
+                    })
+                })
+                .collect());
+        }
+
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        let dst = spec.dst.clone().unwrap_or_default();
+        if spec.src.is_empty() {
+            let dst = self
+                .ref_candidates(&dst)
+                .into_iter()
+                .find(|x| remote_refs.contains_key(x))
+                .ok_or_else(|| {
+                    invalid(format!(
+                        "unable to delete '{}': remote ref does not exist",
+                        dst
+                    ))
+                })?;
+            return Ok(vec![Command {
+                src: String::new(),
+                dst,
+                new: None,
+                force: spec.force,
+            }]);
+        }
+
+        let no_match = || invalid(format!("src refspec {} does not match any", spec.src));
+        let new = self.rev_parse(&spec.src).map_err(|_| no_match())?;
+        let src_ref = match spec.src.as_str() {
+            "HEAD" | "@" => self.head_ref().ok(),
+            src => self
+                .ref_candidates(src)
+                .into_iter()
+                .find(|x| self.resolve_ref(x.clone()).is_ok()),
+        };
+        let dst = if dst.starts_with("refs/") {
+            dst
+        } else if dst.is_empty() {
+            src_ref.ok_or_else(|| {
+                invalid(format!(
+                    "the destination of {} is not a full refname (i.e., starting with \"refs/\")",
+                    spec.src
+                ))
+            })?
+        } else if let Some(existing) = self
+            .ref_candidates(&dst)
+            .into_iter()
+            .find(|x| remote_refs.contains_key(x))
+        {
+            existing
+        } else if src_ref
+            .as_ref()
+            .is_some_and(|x| x.starts_with("refs/tags/"))
+            || matches!(self.read_raw_object(new.clone()), Ok((ObjectType::Tag, _)))
+        {
+            format!("refs/tags/{}", dst)
+        } else {
+            format!("refs/heads/{}", dst)
+        };
+        Ok(vec![Command {
+            src: spec.src.clone(),
+            dst,
+            new: Some(new),
+            force: spec.force,
+        }])
+    }
+}
+
+#[test]
+fn test_push() {
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::test_util::commit_file;
+
+    let mut server = Git::with_git_dir(InMemFileSystem::init(), "repo.git".to_string());
+    server.init(true, "main").unwrap();
+
+    let mut git = Git::new(InMemFileSystem::init());
+    git.init(false, "main").unwrap();
+    let first = commit_file(&mut git, b"one\n", Vec::new());
+    let mut config = git.config_file(crate::config::Level::Local).unwrap();
+    config.set("remote.origin.url", "/repo.git").unwrap();
+    config
+        .set("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*")
+        .unwrap();
+    git.write_config_file(&config).unwrap();
+    let remote = git.remote("origin").unwrap();
+
+    let push = |git: &mut Git<InMemFileSystem>,
+                server: &mut Git<InMemFileSystem>,
+                specs: &[&str],
+                options: &PushOptions| {
+        let specs: Vec<_> = specs.iter().map(|x| Refspec::parse(x).unwrap()).collect();
+        std::thread::scope(|scope| {
+            let (mut requests, client_output) = io::pipe().unwrap();
+            let (client_input, mut responses) = io::pipe().unwrap();
+            let served = scope.spawn(move || server.receive_pack(&mut requests, &mut responses));
+            let mut connection = PushConnection::new(client_input, client_output).unwrap();
+            let updates = git.push(&mut connection, &remote, &specs, options);
+            drop(connection);
+            served.join().unwrap().unwrap();
+            updates
+                .unwrap()
+                .into_iter()
+                .map(|x| x.status)
+                .collect::<Vec<_>>()
+        })
+    };
+    let rejected = |reason: &str| UpdateStatus::Rejected(reason.to_string());
+    let options = PushOptions::default();
+
+    let statuses = push(
+        &mut git,
+        &mut server,
+        &["main", "main:refs/tags/v1"],
+        &options,
+    );
+    assert_eq!(statuses, [UpdateStatus::Created, UpdateStatus::Created]);
+    assert_eq!(
+        server.read_ref("refs/heads/main".to_string()).unwrap(),
+        first
+    );
+    assert!(server.read_commit(first.clone()).is_ok());
+    let tracking =
+        |git: &Git<InMemFileSystem>| git.read_ref("refs/remotes/origin/main".to_string());
+    assert_eq!(tracking(&git).unwrap(), first);
+
+    // Rewritten history, and a tag that is already there.
+    let second = commit_file(&mut git, b"two\n", Vec::new());
+    let statuses = push(&mut git, &mut server, &["main", "main:v1"], &options);
+    assert_eq!(
+        statuses,
+        [rejected("non-fast-forward"), rejected("already exists")]
+    );
+    let atomic = PushOptions {
+        atomic: true,
+        ..PushOptions::default()
+    };
+    let statuses = push(&mut git, &mut server, &["+main", "main:v1"], &atomic);
+    assert_eq!(
+        statuses,
+        [rejected("atomic push failed"), rejected("already exists")]
+    );
+    assert_eq!(
+        server.read_ref("refs/heads/main".to_string()).unwrap(),
+        first
+    );
+
+    // A lease holds while the remote ref is where we last saw it.
+    let stale = PushOptions {
+        leases: vec![Lease {
+            refname: Some("main".to_string()),
+            expect: Some(second.clone()),
+        }],
+        ..PushOptions::default()
+    };
+    let statuses = push(&mut git, &mut server, &["main"], &stale);
+    assert_eq!(statuses, [rejected("stale info")]);
+    let lease = PushOptions {
+        leases: vec![Lease {
+            refname: None,
+            expect: None,
+        }],
+        ..PushOptions::default()
+    };
+    let statuses = push(&mut git, &mut server, &["main"], &lease);
+    assert_eq!(statuses, [UpdateStatus::Forced]);
+    assert_eq!(
+        server.read_ref("refs/heads/main".to_string()).unwrap(),
+        second
+    );
+    assert_eq!(tracking(&git).unwrap(), second);
+
+    let statuses = push(&mut git, &mut server, &["main", ":v1"], &options);
+    assert_eq!(statuses, [UpdateStatus::UpToDate, UpdateStatus::Deleted]);
+    assert!(server.read_ref("refs/tags/v1".to_string()).is_err());
+    let statuses = push(&mut git, &mut server, &[":main"], &options);
+    assert_eq!(statuses, [UpdateStatus::Deleted]);
+    assert!(server.read_ref("refs/heads/main".to_string()).is_err());
+    assert!(tracking(&git).is_err());
+}
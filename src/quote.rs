@@ -0,0 +1,36 @@
+/// Quotes a path the way git prints it with `core.quotePath` on: paths with
+/// control characters, `"`, `\` or non-ASCII bytes are wrapped in double
+/// quotes with C-style escapes, everything else is printed as is.
+pub fn quote_path(path: &str) -> String {
+    let needs_quoting = |b: u8| b < 0x20 || b == b'"' || b == b'\\' || b >= 0x7f;
+    if !path.bytes().any(needs_quoting) {
+        return path.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for b in path.bytes() {
+        match b {
+            b'\x07' => quoted.push_str("\\a"),
+            b'\x08' => quoted.push_str("\\b"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\x0b' => quoted.push_str("\\v"),
+            b'\x0c' => quoted.push_str("\\f"),
+            b'\r' => quoted.push_str("\\r"),
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            _ if needs_quoting(b) => quoted.push_str(&format!("\\{:03o}", b)),
+            _ => quoted.push(b as char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[test]
+fn test_quote_path() {
+    assert_eq!(quote_path("a b/c.txt"), "a b/c.txt");
+    assert_eq!(quote_path("tab\there"), "\"tab\\there\"");
+    assert_eq!(quote_path("say \"hi\""), "\"say \\\"hi\\\"\"");
+    assert_eq!(quote_path("caf\u{e9}"), "\"caf\\303\\251\"");
+}
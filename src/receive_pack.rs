@@ -0,0 +1,261 @@
+use crate::fs::FileSystem;
+use crate::pack::read_pack;
+use crate::protocol::Packet;
+use crate::reflog::NULL_HASH;
+use crate::revision::is_valid_ref_name;
+use crate::upload_pack::{advertise, read_packet};
+use crate::Git;
+use std::collections::BTreeMap;
+use std::io;
+use std::io::prelude::*;
+
+/// An update of a ref the client asks for, and why it was refused.
+struct Command {
+    old: String,
+    new: String,
+    refname: String,
+    error: Option<String>,
+}
+
+impl<F: FileSystem> Git<F> {
+    /// Takes a push from a client over `input` and `output`, as `git
+    /// receive-pack` does: advertises the refs, reads the updates asked
+    /// for and the pack they need, stores the pack and updates the refs,
+    /// all of them or none if the client asks for an atomic push, and
+    /// reports how each went.
+    pub fn receive_pack(
+        &mut self,
+        input: &mut impl Read,
+        output: &mut impl Write,
+    ) -> io::Result<()> {
+        let capabilities = format!(
+            "report-status delete-refs atomic no-thin ofs-delta agent=clumsy/{} object-format=sha1",
+            env!("CARGO_PKG_VERSION")
+        );
+        let refs = self.all_refs()?;
+        let advertised: Vec<_> = refs.clone().into_iter().collect();
+        advertise(&advertised, &capabilities, output, |_| None)?;
+        output.flush()?;
+
+        let mut commands = Vec::new();
+        let mut features = Vec::new();
+        while let Some(packet) = read_packet(input)? {
+            if packet == Packet::Flush {
+                break;
+            }
+            let bad = || io::Error::new(io::ErrorKind::InvalidData, "bad command");
+            // The first command carries the client's capabilities.
+            let line = packet.text().ok_or_else(bad)?;
+            let (line, rest) = line.split_once('\0').unwrap_or((line, ""));
+            features.extend(rest.split(' ').map(str::to_string));
+            let mut words = line.split(' ');
+            match (words.next(), words.next(), words.next()) {
+                (Some(old), Some(new), Some(refname)) => commands.push(Command {
+                    old: old.to_string(),
+                    new: new.to_string(),
+                    refname: refname.to_string(),
+                    error: None,
+                }),
+                _ => return Err(bad()),
+            }
+        }
+        if commands.is_empty() {
+            return Ok(());
+        }
+
+        // Deletions alone come without a pack. An empty one is left out.
+        let unpacked = match commands.iter().all(|x| x.new == NULL_HASH) {
+            true => Ok(()),
+            false => read_pack(input).and_then(|pack| match pack[8..12] {
+                [0, 0, 0, 0] => Ok(()),
+                _ => self.index_pack(&pack).map(|_| ()),
+            }),
+        };
+        for command in &mut commands {
+            command.error = match &unpacked {
+                Ok(()) => self.check_update(command, &refs)?,
+                Err(_) => Some("unpacker error".to_string()),
+            };
+        }
+        let refused = commands.iter().any(|x| x.error.is_some());
+        if refused && features.iter().any(|x| x == "atomic") {
+            for command in commands.iter_mut().filter(|x| x.error.is_none()) {
+                command.error = Some("atomic push failure".to_string());
+            }
+        }
+        for command in commands.iter_mut().filter(|x| x.error.is_none()) {
+            if self.apply_update(command).is_err() {
+                command.error = Some("failed to update ref".to_string());
+            }
+        }
+
+        if features.iter().any(|x| x == "report-status") {
+            match &unpacked {
+                Ok(()) => Packet::line("unpack ok"),
+                Err(e) => Packet::line(&format!("unpack {}", e)),
+            }
+            .write_to(output)?;
+            for command in &commands {
+                match &command.error {
+                    None => Packet::line(&format!("ok {}", command.refname)),
+                    Some(e) => Packet::line(&format!("ng {} {}", command.refname, e)),
+                }
+                .write_to(output)?;
+            }
+            Packet::Flush.write_to(output)?;
+        }
+        output.flush()
+    }
+
+    /// Why an update may not go ahead, if it may not: the ref having
+    /// moved since the client looked, objects missing, or the config
+    /// forbidding it.
+    fn check_update(
+        &self,
+        command: &Command,
+        refs: &BTreeMap<String, String>,
+    ) -> io::Result<Option<String>> {
+        let refname = &command.refname;
+        if !refname.starts_with("refs/") || !is_valid_ref_name(refname) {
+            return Ok(Some("funny refname".to_string()));
+        }
+        let config = self.config()?;
+        let bare = config.get_bool("core.bare")?.unwrap_or(false);
+        let current = !bare && self.head_ref().ok().as_ref() == Some(refname);
+        let old = refs.get(refname);
+
+        let error = if command.new == NULL_HASH {
+            if config.get_bool("receive.denydeletes")?.unwrap_or(false) {
+                Some("deletion prohibited")
+            } else if current {
+                Some("deletion of the current branch prohibited")
+            } else if old.is_some_and(|x| *x != command.old) {
+                Some("failed to update ref")
+            } else {
+                None
+            }
+        } else if self
+            .objects_between(
+                std::slice::from_ref(&command.new),
+                &refs.values().cloned().collect::<Vec<_>>(),
+            )
+            .is_err()
+        {
+            Some("missing necessary objects")
+        } else if current
+            && matches!(
+                config.get("receive.denycurrentbranch").as_deref(),
+                None | Some("refuse") | Some("true")
+            )
+        {
+            Some("branch is currently checked out")
+        } else if old.map_or(NULL_HASH, String::as_str) != command.old {
+            Some("failed to update ref")
+        } else if old.is_some()
+            && refname.starts_with("refs/heads/")
+            && config
+                .get_bool("receive.denynonfastforwards")?
+                .unwrap_or(false)
+            && self.ahead_behind(&command.old, &command.new)?.0 > 0
+        {
+            Some("non-fast-forward")
+        } else {
+            None
+        };
+        Ok(error.map(str::to_string))
+    }
+
+    fn apply_update(&mut self, command: &Command) -> io::Result<()> {
+        let refname = &command.refname;
+        if command.new == NULL_HASH {
+            return self.delete_ref(refname);
+        }
+        self.write_branch(refname, &command.new)?;
+        if self
+            .config()?
+            .get_bool("core.logallrefupdates")?
+            .unwrap_or(false)
+        {
+            let old = Some(command.old.as_str()).filter(|x| *x != NULL_HASH);
+            self.append_reflog(refname, old, &command.new, "push")?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_receive_pack() {
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::test_util::commit_file;
+
+    let mut client = Git::new(InMemFileSystem::init());
+    client.init(false, "main").unwrap();
+    let commit = commit_file(&mut client, b"hello\n", Vec::new());
+    let objects = client
+        .objects_between(std::slice::from_ref(&commit), &[])
+        .unwrap();
+    let pack = client.write_pack(&objects).unwrap();
+
+    let mut server = Git::with_git_dir(InMemFileSystem::init(), "repo.git".to_string());
+    server.init(true, "main").unwrap();
+    let push = |server: &mut Git<InMemFileSystem>, commands: &[String]| {
+        let mut input = Vec::new();
+        for (i, command) in commands.iter().enumerate() {
+            match i {
+                0 => Packet::line(&format!("{}\0report-status atomic", command)),
+                _ => Packet::line(command),
+            }
+            .write_to(&mut input)
+            .unwrap();
+        }
+        Packet::Flush.write_to(&mut input).unwrap();
+        input.extend(&pack);
+
+        let mut output = Vec::new();
+        server.receive_pack(&mut &input[..], &mut output).unwrap();
+        // The report, after the advertisement.
+        let mut output = &output[..];
+        while Packet::read_from(&mut output).unwrap() != Packet::Flush {}
+        let mut report = Vec::new();
+        loop {
+            match Packet::read_from(&mut output).unwrap() {
+                Packet::Flush => return report,
+                packet => report.push(packet.text().unwrap().to_string()),
+            }
+        }
+    };
+
+    let report = push(
+        &mut server,
+        &[
+            format!("{} {} refs/heads/main", NULL_HASH, commit),
+            format!("{} {} refs/heads/a..b", NULL_HASH, commit),
+        ],
+    );
+    assert_eq!(
+        report,
+        [
+            "unpack ok",
+            "ng refs/heads/main atomic push failure",
+            "ng refs/heads/a..b funny refname",
+        ]
+    );
+
+    let report = push(
+        &mut server,
+        &[format!("{} {} refs/heads/main", NULL_HASH, commit)],
+    );
+    assert_eq!(report, ["unpack ok", "ok refs/heads/main"]);
+    assert_eq!(server.resolve_ref("HEAD".to_string()).unwrap(), commit);
+    assert_eq!(server.read_blob(objects[2].clone()).unwrap(), b"hello\n");
+
+    // The ref has moved since.
+    let report = push(
+        &mut server,
+        &[format!("{} {} refs/heads/main", NULL_HASH, commit)],
+    );
+    assert_eq!(
+        report,
+        ["unpack ok", "ng refs/heads/main failed to update ref"]
+    );
+}
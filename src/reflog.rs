@@ -0,0 +1,141 @@
+use crate::fs::FileSystem;
+use crate::ident::{self, Role};
+use crate::object::commit::User;
+use crate::Git;
+use std::fmt;
+use std::io;
+
+pub(crate) const NULL_HASH: &str = "0000000000000000000000000000000000000000";
+
+/// One line of `.git/logs/<ref>`: a ref moving from `old` to `new`.
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    pub old: String,
+    pub new: String,
+    pub committer: User,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    pub fn from(line: &str) -> Option<Self> {
+        let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+        let mut fields = head.splitn(3, ' ');
+        let old = fields.next()?.to_string();
+        let new = fields.next()?.to_string();
+        let committer = User::from(fields.next()?.as_bytes())?;
+
+        Some(Self {
+            old,
+            new,
+            committer,
+            message: message.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for ReflogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}\t{}",
+            self.old, self.new, self.committer, self.message
+        )
+    }
+}
+
+impl<F: FileSystem> Git<F> {
+    /// The entries of the reflog of `refname`, oldest first. A ref without
+    /// a reflog has none.
+    pub fn read_reflog(&self, refname: &str) -> io::Result<Vec<ReflogEntry>> {
+        let bytes = match self
+            .file_system
+            .read(self.git_path(&format!("logs/{}", refname)))
+        {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        Ok(String::from_utf8_lossy(&bytes)
+            .lines()
+            .filter_map(ReflogEntry::from)
+            .collect())
+    }
+
+    /// Records that `refname` moved from `old` (`None` when it was just
+    /// created) to `new`.
+    pub fn append_reflog(
+        &mut self,
+        refname: &str,
+        old: Option<&str>,
+        new: &str,
+        message: &str,
+    ) -> io::Result<()> {
+        // Unlike commits, reflogs are written even without an identity.
+        let committer = self
+            .ident(Role::Committer)
+            .unwrap_or_else(|_| User::new(String::new(), String::new(), ident::now()));
+        let entry = ReflogEntry {
+            old: old.unwrap_or(NULL_HASH).to_string(),
+            new: new.to_string(),
+            committer,
+            // A message is a single line.
+            message: message.lines().next().unwrap_or("").to_string(),
+        };
+
+        let path = self.git_path(&format!("logs/{}", refname));
+        let mut bytes = match self.file_system.read(path.clone()) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                if let Some((dir, _)) = path.rsplit_once('/') {
+                    self.file_system.create_dir(dir.to_string())?;
+                }
+                Vec::new()
+            }
+            Err(e) => return Err(e),
+        };
+        bytes.extend(format!("{}\n", entry).as_bytes());
+        self.file_system.write(path, &bytes)
+    }
+
+    /// Points whatever `HEAD` is on (a branch, or `HEAD` itself when
+    /// detached) at `hash`, logging the move for both.
+    pub fn update_head(&mut self, hash: &str, message: &str) -> io::Result<()> {
+        let old = self.resolve_ref("HEAD".to_string()).ok();
+        let target = self.head_ref().unwrap_or_else(|_| "HEAD".to_string());
+
+        let bytes = hex::decode(hash).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        self.write_ref(target.clone(), &bytes)?;
+        if target != "HEAD" {
+            self.append_reflog(&target, old.as_deref(), hash, message)?;
+        }
+        self.append_reflog("HEAD", old.as_deref(), hash, message)
+    }
+}
+
+#[test]
+fn test_reflog() {
+    use crate::fs::inmem::InMemFileSystem;
+
+    let mut git = Git::new(InMemFileSystem::init());
+    git.file_system
+        .create_dir(".git/refs/heads".to_string())
+        .unwrap();
+    git.write_symref("HEAD".to_string(), "refs/heads/master")
+        .unwrap();
+
+    let (a, b) = ("a".repeat(40), "b".repeat(40));
+    git.update_head(&a, "commit (initial): first").unwrap();
+    git.update_head(&b, "reset: moving to HEAD~\nignored")
+        .unwrap();
+    assert_eq!(git.resolve_ref("HEAD".to_string()).unwrap(), b);
+
+    for refname in ["HEAD", "refs/heads/master"] {
+        let log = git.read_reflog(refname).unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].old, NULL_HASH);
+        assert_eq!((&log[1].old, &log[1].new), (&a, &b));
+        assert_eq!(log[1].message, "reset: moving to HEAD~");
+    }
+    assert!(git.read_reflog("refs/heads/other").unwrap().is_empty());
+}
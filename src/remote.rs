@@ -0,0 +1,196 @@
+use crate::branch::map_glob;
+use crate::fs::FileSystem;
+use crate::Git;
+use std::io;
+
+/// A mapping between refs of two repositories, `[+]<src>[:<dst>]`, as
+/// `remote.<name>.fetch` and `remote.<name>.push` hold and `fetch` and
+/// `push` take. A `*` in `src` and `dst` stands for the same text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refspec {
+    /// Whether the update may be other than a fast-forward.
+    pub force: bool,
+    /// Empty in a push that deletes `dst`.
+    pub src: String,
+    pub dst: Option<String>,
+}
+
+impl Refspec {
+    /// Parses a refspec, failing on one with a `*` on only one side or
+    /// more than one on either.
+    pub fn parse(spec: &str) -> io::Result<Self> {
+        let (force, rest) = match spec.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, spec),
+        };
+        let (src, dst) = match rest.split_once(':') {
+            Some((src, dst)) => (src, Some(dst).filter(|x| !x.is_empty())),
+            None => (rest, None),
+        };
+        let globs = |x: &str| x.matches('*').count();
+        let valid = match dst {
+            Some(dst) => globs(src) == globs(dst) && globs(src) <= 1,
+            None => globs(src) == 0,
+        };
+        if !valid || (src.is_empty() && dst.is_none()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid refspec '{}'", spec),
+            ));
+        }
+        Ok(Refspec {
+            force,
+            src: src.to_string(),
+            dst: dst.map(str::to_string),
+        })
+    }
+
+    pub fn is_glob(&self) -> bool {
+        self.src.contains('*')
+    }
+
+    /// Where `name` on the source side goes, if the refspec covers it.
+    pub fn map(&self, name: &str) -> Option<String> {
+        map_glob(&self.src, self.dst.as_deref()?, name)
+    }
+
+    /// Where `name` on the destination side comes from, if the refspec
+    /// covers it.
+    pub fn reverse_map(&self, name: &str) -> Option<String> {
+        map_glob(self.dst.as_deref()?, &self.src, name)
+    }
+}
+
+/// A repository to fetch from and push to, as `remote.<name>.*`
+/// describes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remote {
+    /// The remote's name, or its URL when it has none.
+    pub name: String,
+    pub url: String,
+    pub fetch: Vec<Refspec>,
+    pub push: Vec<Refspec>,
+}
+
+impl Remote {
+    /// The remote-tracking ref `refname` of the remote is fetched into.
+    pub fn tracking_ref(&self, refname: &str) -> Option<String> {
+        self.fetch
+            .iter()
+            .filter(|x| !x.src.is_empty())
+            .find_map(|x| x.map(refname))
+    }
+}
+
+/// What happened, or would have, to one ref in a fetch or a push.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefUpdate {
+    /// The ref, or in a push any revision, the new value comes from,
+    /// named as on its side.
+    pub src: String,
+    /// The ref updated. `None` for a ref only fetched into `FETCH_HEAD`.
+    pub dst: Option<String>,
+    pub old: Option<String>,
+    /// `None` for a deletion.
+    pub new: Option<String>,
+    pub status: UpdateStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpdateStatus {
+    UpToDate,
+    Created,
+    FastForward,
+    Forced,
+    Deleted,
+    /// Fetched into `FETCH_HEAD` alone.
+    Fetched,
+    /// Refused here, for the reason given.
+    Rejected(String),
+    /// Refused by the other side, for the reason given.
+    RemoteRejected(String),
+}
+
+impl UpdateStatus {
+    pub fn is_rejected(&self) -> bool {
+        matches!(
+            self,
+            UpdateStatus::Rejected(_) | UpdateStatus::RemoteRejected(_)
+        )
+    }
+}
+
+impl<F: FileSystem> Git<F> {
+    /// The remote `name` from the config or, if there is none by that
+    /// name, one without refspecs at `name` taken as a URL.
+    pub fn remote(&self, name: &str) -> io::Result<Remote> {
+        let config = self.config()?;
+        let specs = |key: &str| {
+            config
+                .get_all(&format!("remote.{}.{}", name, key))
+                .iter()
+                .map(|x| Refspec::parse(x))
+                .collect::<io::Result<Vec<_>>>()
+        };
+        Ok(Remote {
+            name: name.to_string(),
+            url: config
+                .get(&format!("remote.{}.url", name))
+                .unwrap_or_else(|| name.to_string()),
+            fetch: specs("fetch")?,
+            push: specs("push")?,
+        })
+    }
+
+    /// Whether `new` has `old` in its history, so moving a ref from one
+    /// to the other loses nothing. False where either isn't a commit.
+    pub(crate) fn is_fast_forward(&self, old: &str, new: &str) -> bool {
+        self.ahead_behind(old, new)
+            .is_ok_and(|(ahead, _)| ahead == 0)
+    }
+
+    /// Points `refname` at `new`, logging the move if
+    /// `core.logAllRefUpdates` asks for it.
+    pub(crate) fn update_ref_logged(
+        &mut self,
+        refname: &str,
+        old: Option<&str>,
+        new: &str,
+        message: &str,
+    ) -> io::Result<()> {
+        self.write_branch(refname, new)?;
+        if self
+            .config()?
+            .get_bool("core.logallrefupdates")?
+            .unwrap_or(false)
+        {
+            self.append_reflog(refname, old, new, message)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_refspec() {
+    let spec = Refspec::parse("+refs/heads/*:refs/remotes/origin/*").unwrap();
+    assert!(spec.force && spec.is_glob());
+    assert_eq!(
+        spec.map("refs/heads/a/b").as_deref(),
+        Some("refs/remotes/origin/a/b")
+    );
+    assert_eq!(spec.map("refs/tags/v1"), None);
+    assert_eq!(
+        spec.reverse_map("refs/remotes/origin/main").as_deref(),
+        Some("refs/heads/main")
+    );
+
+    let spec = Refspec::parse("main").unwrap();
+    assert_eq!((spec.force, spec.dst), (false, None));
+    let spec = Refspec::parse(":refs/heads/gone").unwrap();
+    assert_eq!(spec.src, "");
+    assert_eq!(spec.dst.as_deref(), Some("refs/heads/gone"));
+
+    for bad in &["refs/heads/*:refs/x", "a*b*:c*d*", "refs/*", ":", "+"] {
+        assert!(Refspec::parse(bad).is_err(), "{}", bad);
+    }
+}
@@ -0,0 +1,280 @@
+use crate::fs::FileSystem;
+use crate::index::diff::Diff;
+use crate::index::{Entry, Index};
+use crate::object::tree::FileMode;
+use crate::pathspec::Pathspec;
+use crate::Git;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+/// What `reset` does besides moving `HEAD`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResetMode {
+    /// Nothing: the index and worktree stay as they are.
+    Soft,
+    /// The index is reset, the worktree is left alone.
+    Mixed,
+    /// Both are reset, throwing away every change to tracked files.
+    Hard,
+    /// Both are reset, but files that are the same in `HEAD` and the
+    /// commit keep their local changes. Other files with changes, staged
+    /// or not, make the reset fail.
+    Keep,
+    /// Like `Keep`, but only unstaged changes to files that differ make
+    /// the reset fail; staged ones are thrown away.
+    Merge,
+}
+
+impl<F: FileSystem> Git<F> {
+    /// Resets to `commit`: the index and worktree as `mode` says, then
+    /// `HEAD`'s branch (or `HEAD` when detached), which is logged with
+    /// `message`. The old `HEAD` is kept in `ORIG_HEAD` to undo it with.
+    /// Nothing is changed when the reset is refused.
+    pub fn reset(&mut self, commit: String, mode: ResetMode, message: &str) -> io::Result<()> {
+        if mode != ResetMode::Soft {
+            self.reset_tree(&commit, mode)?;
+        }
+        if let Ok(old) = self.resolve_ref("HEAD".to_string()) {
+            self.write_branch("ORIG_HEAD", &old)?;
+        }
+        self.update_head(&commit, message)
+    }
+
+    /// Resets the index entries `pathspec` matches to `commit` (nothing,
+    /// i.e. removal, without one), leaving `HEAD` and the worktree alone.
+    pub fn reset_paths(&mut self, commit: Option<String>, pathspec: &Pathspec) -> io::Result<()> {
+        let target = match commit {
+            Some(commit) => self.tree_map(self.read_commit(commit)?.tree)?,
+            None => BTreeMap::new(),
+        };
+        let mut index = match self.read_index() {
+            Ok(bytes) => self.ls_files_stage(&bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Index::new(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let names = index
+            .entries
+            .iter()
+            .map(|x| x.name.clone())
+            .chain(target.keys().cloned())
+            .filter(|x| pathspec.matches(x))
+            .collect::<BTreeSet<_>>();
+        for name in names {
+            match target.get(&name) {
+                Some(t)
+                    if index.get(&name).map(|x| (x.mode, x.hash.clone())) == Some(t.clone()) => {}
+                Some((mode, hash)) => index.add(Entry::cacheinfo(*mode, hash.clone(), name)),
+                None => {
+                    index.remove(&name);
+                }
+            }
+        }
+
+        self.refresh_index(&mut index)?;
+        self.write_index(&index)
+    }
+
+    fn reset_tree(&mut self, commit: &str, mode: ResetMode) -> io::Result<()> {
+        let head = match self.resolve_ref("HEAD".to_string()) {
+            Ok(hash) => self.tree_map(self.read_commit(hash)?.tree)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        let target = self.tree_map(self.read_commit(commit.to_string())?.tree)?;
+        let index = match self.read_index() {
+            Ok(bytes) => self.ls_files_stage(&bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Index::new(Vec::new()),
+            Err(e) => return Err(e),
+        }
+        .entries
+        .into_iter()
+        .map(|x| (x.name.clone(), x))
+        .collect::<BTreeMap<_, _>>();
+
+        let names = head
+            .keys()
+            .chain(target.keys())
+            .chain(index.keys())
+            .cloned()
+            .collect::<BTreeSet<_>>();
+
+        let mut next = Index::new(Vec::new());
+        let mut diff = Vec::new();
+        let mut refused = Vec::new();
+
+        for name in names {
+            let h = head.get(&name);
+            let t = target.get(&name);
+            let entry = index.get(&name);
+            let i = entry.map(|x| (x.mode, x.hash.clone()));
+            let i = i.as_ref();
+
+            // Keeps the entry's stat data when it already has the content.
+            let staged = |t: &(FileMode, Vec<u8>)| match entry {
+                Some(entry) if i == Some(t) => entry.clone(),
+                _ => Entry::cacheinfo(t.0, t.1.clone(), name.clone()),
+            };
+            let leave_worktree = match mode {
+                ResetMode::Soft | ResetMode::Mixed => true,
+                ResetMode::Hard => false,
+                ResetMode::Keep | ResetMode::Merge => h == t,
+            };
+            if leave_worktree {
+                if let Some(t) = t {
+                    next.add(staged(t));
+                }
+                continue;
+            }
+
+            let changed = match entry {
+                Some(entry) => self.worktree_changed(entry)? || self.missing(&entry.name)?,
+                None => false,
+            };
+            if mode == ResetMode::Hard && i == t && !changed {
+                if let Some(entry) = entry {
+                    next.add(entry.clone());
+                }
+                continue;
+            }
+            if mode == ResetMode::Keep && i != h {
+                refused.push(format!(
+                    "Entry '{}' would be overwritten by merge. Cannot merge.",
+                    name
+                ));
+                continue;
+            }
+            if changed && mode != ResetMode::Hard {
+                refused.push(format!("Entry '{}' not uptodate. Cannot merge.", name));
+                continue;
+            }
+            if let (ResetMode::Keep | ResetMode::Merge, None, Some(t)) = (mode, entry, t) {
+                let lost = self.untracked_in_the_way(&name, t, &index)?;
+                refused.extend(lost.into_iter().map(|x| {
+                    format!(
+                        "Untracked working tree file '{}' would be overwritten by merge.",
+                        x
+                    )
+                }));
+            }
+
+            match (t, entry) {
+                (Some(t), Some(entry)) => diff.push(Diff::Modify(staged(t), entry.clone())),
+                (Some(t), None) => diff.push(Diff::Add(staged(t))),
+                (None, Some(entry)) => diff.push(Diff::Remove(entry.clone())),
+                (None, None) => {}
+            }
+            if let Some(t) = t {
+                next.add(staged(t));
+            }
+        }
+
+        if !refused.is_empty() {
+            return Err(io::Error::other(refused.join("\n")));
+        }
+
+        let updated = diff
+            .iter()
+            .filter_map(|x| match x {
+                Diff::Add(e) | Diff::Modify(e, _) => Some(e.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        self.diff_apply(diff)?;
+        for entry in updated {
+            let mode = entry.mode;
+            let mut entry = self.index_entry(entry.name.clone(), &entry.hash)?;
+            entry.mode = mode;
+            next.add(entry);
+        }
+
+        if mode == ResetMode::Mixed {
+            self.refresh_index(&mut next)?;
+        }
+        self.write_index(&next)
+    }
+
+    fn missing(&self, path: &str) -> io::Result<bool> {
+        match self.file_system.stat(path.to_string()) {
+            Ok(_) => Ok(false),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[test]
+fn test_reset() {
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::test_util::{blob, commit, tree};
+
+    let mut git = Git::new(InMemFileSystem::init());
+    git.file_system
+        .create_dir(".git/refs/heads".to_string())
+        .unwrap();
+    git.write_symref("HEAD".to_string(), "refs/heads/master")
+        .unwrap();
+
+    let (a1, a2, shared) = (
+        blob(&mut git, b"a1"),
+        blob(&mut git, b"a2"),
+        blob(&mut git, b"s"),
+    );
+    let mut commit = |a: &str| {
+        let tree = tree(
+            &mut git,
+            &[
+                (FileMode::Blob, "a", a),
+                (FileMode::Blob, "shared", &shared),
+            ],
+        );
+        commit(&mut git, &tree, &[], "message")
+    };
+    let (c1, c2) = (commit(&a1), commit(&a2));
+
+    let read = |git: &Git<InMemFileSystem>, x: &str| git.file_system.read(x.to_string()).unwrap();
+    let staged = |git: &Git<InMemFileSystem>, x: &str| {
+        let index = git.ls_files_stage(&git.read_index().unwrap()).unwrap();
+        hex::encode(&index.get(x).unwrap().hash)
+    };
+
+    git.reset(c1.clone(), ResetMode::Hard, "reset: moving to c1")
+        .unwrap();
+    assert_eq!(read(&git, "a"), b"a1");
+    assert_eq!(git.read_reflog("refs/heads/master").unwrap().len(), 1);
+
+    // Only the index and HEAD move; the worktree keeps the old content.
+    git.reset(c2.clone(), ResetMode::Mixed, "").unwrap();
+    assert_eq!(git.resolve_ref("HEAD".to_string()).unwrap(), c2);
+    assert_eq!(staged(&git, "a"), a2);
+    assert_eq!(read(&git, "a"), b"a1");
+
+    git.reset(c1.clone(), ResetMode::Soft, "").unwrap();
+    assert_eq!(staged(&git, "a"), a2);
+    assert_eq!(git.rev_parse("ORIG_HEAD").unwrap(), c2);
+
+    // Local changes block `--keep` and `--merge` only on files that differ.
+    git.reset(c2.clone(), ResetMode::Hard, "").unwrap();
+    git.file_system
+        .write("shared".to_string(), b"local")
+        .unwrap();
+    git.reset(c1.clone(), ResetMode::Keep, "").unwrap();
+    assert_eq!(read(&git, "a"), b"a1");
+    assert_eq!(read(&git, "shared"), b"local");
+
+    git.file_system.write("a".to_string(), b"dirty").unwrap();
+    let err = git.reset(c2.clone(), ResetMode::Merge, "").unwrap_err();
+    assert_eq!(err.to_string(), "Entry 'a' not uptodate. Cannot merge.");
+    assert_eq!(git.resolve_ref("HEAD".to_string()).unwrap(), c1);
+
+    git.reset(c2.clone(), ResetMode::Hard, "").unwrap();
+    assert_eq!(read(&git, "a"), b"a2");
+    assert_eq!(read(&git, "shared"), b"s");
+
+    // Resetting paths leaves HEAD and the worktree alone.
+    git.reset_paths(Some(c1), &Pathspec::parse(&["a"]).unwrap())
+        .unwrap();
+    assert_eq!(staged(&git, "a"), a1);
+    assert_eq!(git.resolve_ref("HEAD".to_string()).unwrap(), c2);
+    assert_eq!(read(&git, "a"), b"a2");
+}
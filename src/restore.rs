@@ -0,0 +1,203 @@
+use crate::fs::FileSystem;
+use crate::index::diff::Diff;
+use crate::index::{Entry, Index};
+use crate::pathspec::Pathspec;
+use crate::Git;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+/// Where `restore` takes the files from and what it writes them to.
+#[derive(Debug, Clone, Default)]
+pub struct RestoreOptions {
+    /// Restore the index (`--staged`).
+    pub staged: bool,
+    /// Restore the worktree (`--worktree`).
+    pub worktree: bool,
+    /// The tree to restore from. Without one the worktree is restored
+    /// from the index.
+    pub source: Option<String>,
+    /// Leave files the source doesn't have alone instead of removing them
+    /// (`--overlay`), as `checkout <tree-ish> -- <paths>` does.
+    pub overlay: bool,
+}
+
+impl<F: FileSystem> Git<F> {
+    /// Restores the paths `pathspec` matches from the source, leaving
+    /// every other file as it is. Returns the worktree files that were
+    /// written or removed; nothing is changed when one of the items matches
+    /// nothing.
+    pub fn restore(
+        &mut self,
+        pathspec: &Pathspec,
+        options: &RestoreOptions,
+    ) -> io::Result<Vec<String>> {
+        let mut index = match self.read_index() {
+            Ok(bytes) => self.ls_files_stage(&bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Index::new(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let source = match &options.source {
+            Some(tree) => self.tree2index(tree.clone())?.entries,
+            // Files only intended to be added have nothing to restore.
+            None => index
+                .entries
+                .iter()
+                .filter(|x| !x.intent_to_add)
+                .cloned()
+                .collect(),
+        }
+        .into_iter()
+        .map(|x| (x.name.clone(), x))
+        .collect::<BTreeMap<_, _>>();
+
+        let mut names = source.keys().cloned().collect::<BTreeSet<_>>();
+        if !options.overlay {
+            names.extend(index.entries.iter().map(|x| x.name.clone()));
+        }
+        names.retain(|x| pathspec.matches(x));
+
+        for item in pathspec.includes() {
+            if !names.iter().any(|x| item.matches(x)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "pathspec '{}' did not match any file(s) known to git",
+                        item.original
+                    ),
+                ));
+            }
+        }
+
+        let mut diff = Vec::new();
+        for name in &names {
+            let current = index.get(name).cloned();
+            match source.get(name) {
+                Some(entry) => {
+                    let same = current
+                        .as_ref()
+                        .is_some_and(|x| x.mode == entry.mode && x.hash == entry.hash);
+                    if options.staged && !same {
+                        index.add(entry.clone());
+                    }
+                    if options.worktree {
+                        let clean = match &current {
+                            Some(current) if same => !self.worktree_changed(current)?,
+                            _ => false,
+                        };
+                        if !clean || self.file_system.stat(name.clone()).is_err() {
+                            diff.push(Diff::Add(entry.clone()));
+                        }
+                    }
+                }
+                None if options.source.is_none() => {}
+                None => {
+                    if options.staged {
+                        index.remove(name);
+                    }
+                    if let (true, Some(current)) = (options.worktree, current) {
+                        diff.push(Diff::Remove(current));
+                    }
+                }
+            }
+        }
+
+        let changed = diff
+            .iter()
+            .filter_map(|x| match x {
+                Diff::Add(e) | Diff::Remove(e) => Some(e.name.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let written = diff
+            .iter()
+            .filter_map(|x| match x {
+                Diff::Add(e) => Some(e.clone()),
+                _ => None,
+            })
+            .collect::<Vec<Entry>>();
+        self.diff_apply(diff)?;
+        // The index now matches the files written from it.
+        for entry in written {
+            if index.get(&entry.name).map(|x| &x.hash) == Some(&entry.hash) {
+                let mut updated = self.index_entry(entry.name.clone(), &entry.hash)?;
+                updated.mode = entry.mode;
+                index.add(updated);
+            }
+        }
+        self.write_index(&index)?;
+
+        Ok(changed)
+    }
+}
+
+#[test]
+fn test_restore() {
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::object::GitObject;
+
+    let mut git = Git::new(InMemFileSystem::init());
+    git.file_system.create_dir("d".to_string()).unwrap();
+    for name in ["a", "d/x"] {
+        git.file_system
+            .write(name.to_string(), name.as_bytes())
+            .unwrap();
+    }
+    let spec = |args: &[&str]| Pathspec::parse(args).unwrap();
+    git.add(&spec(&["."]), &Default::default()).unwrap();
+    let tree = git.write_tree().map(GitObject::Tree).unwrap();
+    git.write_object(&tree).unwrap();
+    let tree = hex::encode(tree.calc_hash());
+
+    let read = |git: &Git<InMemFileSystem>, x: &str| git.file_system.read(x.to_string());
+    let staged = |git: &Git<InMemFileSystem>| {
+        let index = git.ls_files_stage(&git.read_index().unwrap()).unwrap();
+        index
+            .entries
+            .into_iter()
+            .map(|x| x.name)
+            .collect::<Vec<_>>()
+    };
+
+    // Only the named file is restored from the index.
+    git.file_system.write("a".to_string(), b"edit").unwrap();
+    git.file_system.write("d/x".to_string(), b"edit").unwrap();
+    let worktree = RestoreOptions {
+        worktree: true,
+        ..RestoreOptions::default()
+    };
+    let written = git.restore(&spec(&["a"]), &worktree).unwrap();
+    assert_eq!(written, vec!["a"]);
+    assert_eq!(read(&git, "a").unwrap(), b"a");
+    assert_eq!(read(&git, "d/x").unwrap(), b"edit");
+
+    let err = git.restore(&spec(&["nope"]), &worktree).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "pathspec 'nope' did not match any file(s) known to git"
+    );
+
+    // Unstaging a new file removes it from the index only.
+    git.file_system.write("new".to_string(), b"new").unwrap();
+    git.add(&spec(&["new"]), &Default::default()).unwrap();
+    let unstage = RestoreOptions {
+        staged: true,
+        source: Some(tree.clone()),
+        ..RestoreOptions::default()
+    };
+    git.restore(&spec(&["new"]), &unstage).unwrap();
+    assert_eq!(staged(&git), vec!["a", "d/x"]);
+    assert_eq!(read(&git, "new").unwrap(), b"new");
+
+    // Both from the tree; without overlay, files it doesn't have go.
+    git.add(&spec(&["new"]), &Default::default()).unwrap();
+    let both = RestoreOptions {
+        staged: true,
+        worktree: true,
+        source: Some(tree),
+        overlay: false,
+    };
+    git.restore(&spec(&["."]), &both).unwrap();
+    assert_eq!(staged(&git), vec!["a", "d/x"]);
+    assert_eq!(read(&git, "d/x").unwrap(), b"d/x");
+    assert!(read(&git, "new").is_err());
+}
@@ -0,0 +1,242 @@
+use crate::fs::FileSystem;
+use crate::object::GitObject;
+use crate::Git;
+use std::io;
+
+impl<F: FileSystem> Git<F> {
+    /// Resolves a revision such as `HEAD~2`, `master^2`, `main^{tree}`,
+    /// `HEAD:src/main.rs` or an abbreviated object name to a full hash.
+    pub fn rev_parse(&self, rev: &str) -> io::Result<String> {
+        if let Some((rev, path)) = rev.split_once(':') {
+            return self.resolve_path(rev, path);
+        }
+
+        let split = rev.find(['~', '^']).unwrap_or(rev.len());
+        let (name, mut suffix) = rev.split_at(split);
+        let mut hash = self.resolve_name(name)?;
+
+        while !suffix.is_empty() {
+            let op = suffix.as_bytes()[0];
+            suffix = &suffix[1..];
+
+            if op == b'^' && suffix.starts_with('{') {
+                let end = suffix.find('}').ok_or_else(|| unknown_revision(rev))?;
+                hash = self.peel(hash, &suffix[1..end])?;
+                suffix = &suffix[end + 1..];
+                continue;
+            }
+
+            let digits = suffix
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(suffix.len());
+            let n = if digits == 0 {
+                1
+            } else {
+                suffix[..digits]
+                    .parse::<usize>()
+                    .map_err(|_| unknown_revision(rev))?
+            };
+            suffix = &suffix[digits..];
+
+            hash = match op {
+                b'~' => (0..n).try_fold(hash, |acc, _| {
+                    self.read_commit(self.peel(acc, "commit")?)?
+                        .parents
+                        .first()
+                        .cloned()
+                        .ok_or_else(|| unknown_revision(rev))
+                })?,
+                _ if n == 0 => self.peel(hash, "commit")?,
+                _ => self
+                    .read_commit(self.peel(hash, "commit")?)?
+                    .parents
+                    .get(n - 1)
+                    .cloned()
+                    .ok_or_else(|| unknown_revision(rev))?,
+            };
+        }
+
+        Ok(hash)
+    }
+
+    /// Looks `path` up in the tree of `rev`, or in the index when `rev` is
+    /// empty (`:path`).
+    fn resolve_path(&self, rev: &str, path: &str) -> io::Result<String> {
+        let not_found = |place: &str| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("path '{}' does not exist in {}", path, place),
+            )
+        };
+
+        if rev.is_empty() {
+            let index = self.ls_files_stage(&self.read_index()?)?;
+            return index
+                .entries
+                .iter()
+                .find(|x| x.name == path)
+                .map(|x| hex::encode(&x.hash))
+                .ok_or_else(|| not_found("the index"));
+        }
+
+        let place = format!("'{}'", rev);
+        path.split('/')
+            .filter(|x| !x.is_empty())
+            .try_fold(self.peel(self.rev_parse(rev)?, "tree")?, |hash, name| {
+                self.read_tree(hash)
+                    .ok()
+                    .and_then(|tree| tree.contents.into_iter().find(|x| x.name == name))
+                    .map(|x| hex::encode(&x.hash))
+                    .ok_or_else(|| not_found(&place))
+            })
+    }
+
+    /// Resolves a ref name or (abbreviated) object name using git's lookup
+    /// order: `<name>`, `refs/<name>`, `refs/tags/<name>`, `refs/heads/<name>`,
+    /// `refs/remotes/<name>` and `refs/remotes/<name>/HEAD`.
+    pub fn resolve_name(&self, name: &str) -> io::Result<String> {
+        let name = if name == "@" || name.is_empty() {
+            "HEAD"
+        } else {
+            name
+        };
+
+        if name.len() == 40 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(name.to_lowercase());
+        }
+
+        if let Some(hash) = self
+            .ref_candidates(name)
+            .into_iter()
+            .find_map(|x| self.resolve_ref(x).ok())
+        {
+            return Ok(hash);
+        }
+
+        if name.len() >= 4 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+            return self.expand_abbrev(&name.to_lowercase());
+        }
+
+        Err(unknown_revision(name))
+    }
+
+    /// The full ref names `name` could refer to, in lookup order.
+    pub fn ref_candidates(&self, name: &str) -> Vec<String> {
+        let mut candidates = Vec::new();
+        let pseudo = name.chars().all(|c| c.is_ascii_uppercase() || c == '_');
+        if pseudo || name.starts_with("refs/") {
+            candidates.push(name.to_string());
+        }
+
+        candidates.extend(
+            [
+                "refs/{}",
+                "refs/tags/{}",
+                "refs/heads/{}",
+                "refs/remotes/{}",
+                "refs/remotes/{}/HEAD",
+            ]
+            .iter()
+            .map(|x| x.replace("{}", name)),
+        );
+
+        candidates
+    }
+
+    fn expand_abbrev(&self, prefix: &str) -> io::Result<String> {
+        let (sub_dir, rest) = prefix.split_at(2);
+        let mut matches = self
+            .file_system
+            .read_dir(self.git_path(&format!("objects/{}", sub_dir)))
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|x| x.starts_with(rest))
+            .map(|x| format!("{}{}", sub_dir, x))
+            .collect::<Vec<_>>();
+        matches.extend(self.packed_hashes(prefix).unwrap_or_default());
+        matches.sort();
+        matches.dedup();
+
+        match matches.as_slice() {
+            [hash] => Ok(hash.clone()),
+            [] => Err(unknown_revision(prefix)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("short object ID {} is ambiguous", prefix),
+            )),
+        }
+    }
+
+    /// Peels `hash` to an object of type `kind` (`commit`, `tree`, or empty
+    /// for "whatever it ultimately points at").
+    pub fn peel(&self, hash: String, kind: &str) -> io::Result<String> {
+        let object = self.read_git_object(hash.clone())?;
+
+        match (object, kind) {
+            (GitObject::Tag(_), "tag") => Ok(hash),
+            (GitObject::Tag(tag), _) => self.peel(tag.object, kind),
+            (GitObject::Commit(_), "commit") | (_, "") => Ok(hash),
+            (GitObject::Commit(commit), "tree") => Ok(commit.tree),
+            (GitObject::Tree(_), "tree") | (GitObject::Blob(_), "blob") => Ok(hash),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} cannot be peeled to a {}", hash, kind),
+            )),
+        }
+    }
+}
+
+/// Whether `name` is a ref name `check-ref-format` accepts: no empty or
+/// dot-led components, no `..`, `@{` or special characters, and no
+/// `.lock` or `.` at the end.
+pub fn is_valid_ref_name(name: &str) -> bool {
+    let bad_char = |c: char| c.is_ascii_control() || " ~^:?*[\\".contains(c);
+    !name.ends_with('.')
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.chars().any(bad_char)
+        && name
+            .split('/')
+            .all(|x| !x.is_empty() && !x.starts_with('.') && !x.ends_with(".lock"))
+}
+
+fn unknown_revision(rev: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!(
+            "ambiguous argument '{}': unknown revision or path not in the working tree",
+            rev
+        ),
+    )
+}
+
+#[test]
+fn test_rev_parse() {
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::test_util::{commit, tree};
+
+    let mut git = Git::new(InMemFileSystem::init());
+    let tree = tree(&mut git, &[]);
+    let mut commit = |parents: &[&str], message: &str| commit(&mut git, &tree, parents, message);
+
+    let root = commit(&[], "root");
+    let side = commit(&[&root], "side");
+    let main = commit(&[&root], "main");
+    let merge = commit(&[&main, &side], "merge");
+    git.write_ref(
+        "refs/heads/master".to_string(),
+        &hex::decode(&merge).unwrap(),
+    )
+    .unwrap();
+
+    assert_eq!(git.rev_parse("HEAD").unwrap(), merge);
+    assert_eq!(git.rev_parse("master~1").unwrap(), main);
+    assert_eq!(git.rev_parse("@^2").unwrap(), side);
+    assert_eq!(git.rev_parse("HEAD^2~").unwrap(), root);
+    assert_eq!(git.rev_parse(&merge[..8]).unwrap(), merge);
+    assert_eq!(git.rev_parse("HEAD^{tree}").unwrap(), tree);
+    assert_eq!(git.rev_parse("HEAD:").unwrap(), tree);
+    assert!(git.rev_parse("HEAD:missing").is_err());
+    assert!(git.rev_parse("HEAD^3").is_err());
+    assert!(git.rev_parse("nope").is_err());
+}
@@ -0,0 +1,453 @@
+use crate::fs::FileSystem;
+use crate::object::commit::Commit;
+use crate::object::tree::FileMode;
+use crate::Git;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::io;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Sort {
+    /// Newest committer date first, as commits are reached.
+    Default,
+    /// Like `Default`, but never shows a parent before all of its children.
+    Date,
+    /// As `Date`, using the author date.
+    AuthorDate,
+    /// Children before parents, keeping lines of history together.
+    Topo,
+}
+
+/// Path, mode and hash of a blob in a tree.
+type TreeEntry = (String, FileMode, Vec<u8>);
+
+/// Walks the commit graph from a set of starting points, like
+/// `git rev-list`. Commits reachable from a hidden commit are excluded.
+///
+/// Without hidden commits, reordering or reversal the walk is lazy and
+/// only reads as much history as is consumed.
+pub struct RevWalk<'a, F: FileSystem> {
+    git: &'a Git<F>,
+    sort: Sort,
+    reverse: bool,
+    first_parent: bool,
+    paths: Vec<String>,
+
+    queue: BinaryHeap<(i64, Reverse<usize>, String)>,
+    seq: usize,
+    seen: HashSet<String>,
+    hidden: HashSet<String>,
+    commits: HashMap<String, Commit>,
+    /// Parents the walk continued into after simplification.
+    parents: HashMap<String, Vec<String>>,
+    /// Walked commits that simplification left out.
+    treesame: HashSet<String>,
+    trees: HashMap<String, Vec<TreeEntry>>,
+    prepared: Option<VecDeque<String>>,
+}
+
+impl<F: FileSystem> Git<F> {
+    pub fn rev_walk(&self) -> RevWalk<'_, F> {
+        RevWalk {
+            git: self,
+            sort: Sort::Default,
+            reverse: false,
+            first_parent: false,
+            paths: Vec::new(),
+            queue: BinaryHeap::new(),
+            seq: 0,
+            seen: HashSet::new(),
+            hidden: HashSet::new(),
+            commits: HashMap::new(),
+            parents: HashMap::new(),
+            treesame: HashSet::new(),
+            trees: HashMap::new(),
+            prepared: None,
+        }
+    }
+}
+
+impl<'a, F: FileSystem> RevWalk<'a, F> {
+    /// Adds a starting point. Tags and other objects are peeled to commits.
+    pub fn push(&mut self, hash: &str) -> io::Result<()> {
+        let hash = self.git.peel(hash.to_string(), "commit")?;
+        self.enqueue(hash)
+    }
+
+    /// Excludes `hash` and everything reachable from it.
+    pub fn hide(&mut self, hash: &str) -> io::Result<()> {
+        let hash = self.git.peel(hash.to_string(), "commit")?;
+        self.mark_hidden(hash.clone());
+        self.enqueue(hash)
+    }
+
+    pub fn sorting(&mut self, sort: Sort) {
+        self.sort = sort;
+    }
+
+    pub fn reverse(&mut self, reverse: bool) {
+        self.reverse = reverse;
+    }
+
+    /// Only follows the first parent of merges.
+    pub fn first_parent(&mut self, first_parent: bool) {
+        self.first_parent = first_parent;
+    }
+
+    /// Limits the walk to commits that change one of `paths`. A merge that
+    /// matches one of its parents at those paths is skipped and only that
+    /// parent is followed, like git's default history simplification.
+    pub fn simplify_by_paths(&mut self, paths: Vec<String>) {
+        self.paths = paths
+            .into_iter()
+            .map(|x| x.trim_end_matches('/').to_string())
+            .filter(|x| !x.is_empty() && x != ".")
+            .collect();
+    }
+
+    /// A commit the walk has read.
+    pub fn commit(&self, hash: &str) -> Option<&Commit> {
+        self.commits.get(hash)
+    }
+
+    /// The parents of a walked commit, rewritten to the nearest ancestors
+    /// the walk yields. Hidden commits are dropped.
+    pub fn parents(&self, hash: &str) -> Vec<String> {
+        let mut parents = Vec::new();
+        let mut visited = HashSet::new();
+        let mut stack = self
+            .parents
+            .get(hash)
+            .map(|x| x.iter().rev().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        while let Some(parent) = stack.pop() {
+            if self.hidden.contains(&parent) || !visited.insert(parent.clone()) {
+                continue;
+            }
+            if self.treesame.contains(&parent) {
+                if let Some(x) = self.parents.get(&parent) {
+                    stack.extend(x.iter().rev().cloned());
+                }
+            } else {
+                parents.push(parent);
+            }
+        }
+
+        parents
+    }
+
+    fn enqueue(&mut self, hash: String) -> io::Result<()> {
+        if !self.seen.insert(hash.clone()) {
+            return Ok(());
+        }
+
+        let ts = self.load(&hash)?.committer.ts.timestamp();
+        self.queue.push((ts, Reverse(self.seq), hash));
+        self.seq += 1;
+
+        Ok(())
+    }
+
+    fn load(&mut self, hash: &str) -> io::Result<&Commit> {
+        if !self.commits.contains_key(hash) {
+            let commit = self.git.read_commit(hash.to_string())?;
+            self.commits.insert(hash.to_string(), commit);
+        }
+        Ok(&self.commits[hash])
+    }
+
+    fn mark_hidden(&mut self, hash: String) {
+        let mut stack = vec![hash];
+        while let Some(hash) = stack.pop() {
+            if !self.hidden.insert(hash.clone()) {
+                continue;
+            }
+            // Commits already walked won't propagate the mark themselves.
+            if let Some(parents) = self.parents.get(&hash) {
+                stack.extend(parents.iter().cloned());
+            }
+        }
+    }
+
+    /// Walks one commit, returning it and whether it should be yielded.
+    fn step(&mut self) -> io::Result<Option<(String, bool)>> {
+        let hash = match self.queue.pop() {
+            Some((_, _, hash)) => hash,
+            None => return Ok(None),
+        };
+        let commit = &self.commits[&hash];
+        let mut parents = commit.parents.clone();
+
+        if self.hidden.contains(&hash) {
+            for parent in &parents {
+                self.mark_hidden(parent.clone());
+                self.enqueue(parent.clone())?;
+            }
+            self.parents.insert(hash.clone(), parents);
+            return Ok(Some((hash, false)));
+        }
+
+        if self.first_parent {
+            parents.truncate(1);
+        }
+
+        let mut treesame = false;
+        if !self.paths.is_empty() {
+            let tree = commit.tree.clone();
+            let own = self.limited_entries(&tree)?;
+
+            let mut same = None;
+            for parent in &parents {
+                let tree = self.load(parent)?.tree.clone();
+                if self.limited_entries(&tree)? == own {
+                    same = Some(parent.clone());
+                    break;
+                }
+            }
+
+            match same {
+                Some(parent) => {
+                    parents = vec![parent];
+                    treesame = true;
+                }
+                None => treesame = parents.is_empty() && own.is_empty(),
+            }
+        }
+
+        for parent in &parents {
+            self.enqueue(parent.clone())?;
+        }
+        if treesame {
+            self.treesame.insert(hash.clone());
+        }
+        self.parents.insert(hash.clone(), parents);
+
+        Ok(Some((hash, !treesame)))
+    }
+
+    /// The entries of `tree` that fall under one of the limiting paths.
+    fn limited_entries(&mut self, tree: &str) -> io::Result<Vec<TreeEntry>> {
+        if let Some(entries) = self.trees.get(tree) {
+            return Ok(entries.clone());
+        }
+
+        let paths = &self.paths;
+        let entries = self
+            .git
+            .read_tree_recursive(tree.to_string())?
+            .into_iter()
+            .filter(|(name, _)| {
+                paths
+                    .iter()
+                    .any(|x| name == x || name.starts_with(&format!("{}/", x)))
+            })
+            .map(|(name, file)| (name, file.mode, file.hash))
+            .collect::<Vec<_>>();
+        self.trees.insert(tree.to_string(), entries.clone());
+
+        Ok(entries)
+    }
+
+    /// Walks everything up front; needed to exclude hidden history and to
+    /// reorder the result.
+    fn prepare(&mut self) -> io::Result<VecDeque<String>> {
+        let mut walked = Vec::new();
+        let mut yielded = Vec::new();
+
+        // Stop once only hidden commits are left to walk, and they are too
+        // old to reach anything walked (which they would then hide).
+        let mut oldest = None;
+        loop {
+            let interesting = self.queue.iter().any(|(_, _, x)| !self.hidden.contains(x));
+            let reaching = match (self.queue.peek(), oldest) {
+                (Some((ts, _, _)), Some(oldest)) => *ts >= oldest,
+                _ => false,
+            };
+            if !interesting && !reaching {
+                break;
+            }
+            match self.step()? {
+                Some((hash, true)) => {
+                    let ts = self.commits[&hash].committer.ts.timestamp();
+                    oldest = Some(oldest.map_or(ts, |x: i64| x.min(ts)));
+                    walked.push(hash.clone());
+                    yielded.push(hash);
+                }
+                Some((hash, false)) => walked.push(hash),
+                None => break,
+            }
+        }
+
+        let order = match self.sort {
+            Sort::Default => yielded,
+            _ => {
+                let yielded = yielded.into_iter().collect::<HashSet<_>>();
+                self.sort_topologically(&walked)
+                    .into_iter()
+                    .filter(|x| yielded.contains(x))
+                    .collect()
+            }
+        };
+
+        let mut order = order
+            .into_iter()
+            .filter(|x| !self.hidden.contains(x))
+            .collect::<VecDeque<_>>();
+        if self.reverse {
+            order = order.into_iter().rev().collect();
+        }
+
+        Ok(order)
+    }
+
+    fn sort_topologically(&self, walked: &[String]) -> Vec<String> {
+        let walked = walked
+            .iter()
+            .filter(|x| !self.hidden.contains(*x))
+            .collect::<Vec<_>>();
+
+        let mut children = HashMap::new();
+        for hash in &walked {
+            for parent in &self.parents[*hash] {
+                *children.entry(parent.as_str()).or_insert(0) += 1;
+            }
+        }
+
+        let key = |hash: &str| {
+            let commit = &self.commits[hash];
+            match self.sort {
+                Sort::AuthorDate => commit.author.ts.timestamp(),
+                _ => commit.committer.ts.timestamp(),
+            }
+        };
+
+        let mut stack = Vec::new();
+        let mut heap = BinaryHeap::new();
+        for (seq, hash) in walked.iter().enumerate() {
+            if !children.contains_key(hash.as_str()) {
+                stack.insert(0, hash.as_str());
+                heap.push((key(hash), Reverse(seq), hash.as_str()));
+            }
+        }
+
+        let mut ordered = Vec::new();
+        let mut seq = walked.len();
+        loop {
+            let hash = if self.sort == Sort::Topo {
+                stack.pop()
+            } else {
+                heap.pop().map(|x| x.2)
+            };
+            let hash = match hash {
+                Some(hash) => hash,
+                None => break,
+            };
+
+            for parent in &self.parents[hash] {
+                if let Some(n) = children.get_mut(parent.as_str()) {
+                    *n -= 1;
+                    if *n == 0 && self.parents.contains_key(parent) {
+                        stack.push(parent);
+                        heap.push((key(parent), Reverse(seq), parent));
+                        seq += 1;
+                    }
+                }
+            }
+            ordered.push(hash.to_string());
+        }
+
+        ordered
+    }
+}
+
+impl<'a, F: FileSystem> Iterator for RevWalk<'a, F> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let limited = !self.hidden.is_empty() || self.sort != Sort::Default || self.reverse;
+
+        if limited {
+            if self.prepared.is_none() {
+                match self.prepare() {
+                    Ok(order) => self.prepared = Some(order),
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            return self.prepared.as_mut()?.pop_front().map(Ok);
+        }
+
+        loop {
+            match self.step() {
+                Ok(Some((hash, true))) => return Some(Ok(hash)),
+                Ok(Some(_)) => continue,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_rev_walk() {
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::test_util::{blob, commit_at, tree};
+
+    let mut git = Git::new(InMemFileSystem::init());
+    let (a1, a2) = (blob(&mut git, b"a1"), blob(&mut git, b"a2"));
+    let t1 = tree(&mut git, &[(FileMode::Blob, "a", &a1)]);
+    let t2 = tree(&mut git, &[(FileMode::Blob, "a", &a2)]);
+
+    let mut n = 0;
+    let mut commit = |tree: &str, parents: &[&str]| {
+        n += 1;
+        commit_at(&mut git, tree, parents, &n.to_string(), n)
+    };
+
+    // root - main - merge - release
+    //      \ fix  /
+    let root = commit(&t1, &[]);
+    let main = commit(&t1, &[&root]);
+    let fix = commit(&t2, &[&root]);
+    let merge = commit(&t2, &[&main, &fix]);
+    let release = commit(&t2, &[&merge]);
+
+    let collect = |walk: RevWalk<_>| walk.collect::<io::Result<Vec<_>>>().unwrap();
+
+    let mut walk = git.rev_walk();
+    walk.push(&release).unwrap();
+    assert_eq!(
+        collect(walk),
+        [&release, &merge, &fix, &main, &root].map(String::clone)
+    );
+
+    // Commits in release but not in main.
+    let mut walk = git.rev_walk();
+    walk.push(&release).unwrap();
+    walk.hide(&main).unwrap();
+    assert_eq!(collect(walk), [&release, &merge, &fix].map(String::clone));
+
+    let mut walk = git.rev_walk();
+    walk.push(&release).unwrap();
+    walk.sorting(Sort::Topo);
+    walk.reverse(true);
+    assert_eq!(
+        collect(walk),
+        [&root, &main, &fix, &merge, &release].map(String::clone)
+    );
+
+    let mut walk = git.rev_walk();
+    walk.push(&release).unwrap();
+    walk.first_parent(true);
+    assert_eq!(
+        collect(walk),
+        [&release, &merge, &main, &root].map(String::clone)
+    );
+
+    let mut walk = git.rev_walk();
+    walk.push(&release).unwrap();
+    walk.simplify_by_paths(vec!["a".to_string()]);
+    let walked = walk.by_ref().collect::<io::Result<Vec<_>>>().unwrap();
+    assert_eq!(walked, [&fix, &root].map(String::clone));
+    assert_eq!(walk.parents(&fix), vec![root.clone()]);
+}
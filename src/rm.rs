@@ -0,0 +1,210 @@
+use crate::fs::FileSystem;
+use crate::index::Index;
+use crate::object::tree::FileMode;
+use crate::pathspec::{Item, Pathspec};
+use crate::Git;
+use std::collections::BTreeMap;
+use std::io;
+
+#[derive(Debug, Clone, Default)]
+pub struct RmOptions {
+    /// Only untrack the files and leave them in the worktree.
+    pub cached: bool,
+    /// Allow an item to name a directory (`-r`).
+    pub recursive: bool,
+    /// Remove files even if that loses changes (`-f`).
+    pub force: bool,
+    pub dry_run: bool,
+    pub ignore_unmatch: bool,
+}
+
+/// Why a file isn't removed without `-f`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Unsafe {
+    /// The index matches neither `HEAD` nor the worktree, so its content
+    /// would be lost even with `--cached`.
+    StagedAndLocal,
+    /// The index differs from `HEAD`.
+    Staged,
+    /// The worktree differs from the index.
+    Local,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RmResult {
+    pub removed: Vec<String>,
+    /// When there is any, nothing is removed and `removed` is empty.
+    pub refused: Vec<(String, Unsafe)>,
+}
+
+impl<F: FileSystem> Git<F> {
+    /// Removes the tracked files `pathspec` matches from the index and,
+    /// without `cached`, from the worktree. Directories left empty are
+    /// removed too.
+    pub fn rm(&mut self, pathspec: &Pathspec, options: &RmOptions) -> io::Result<RmResult> {
+        let mut index = match self.read_index() {
+            Ok(bytes) => self.ls_files_stage(&bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Index::new(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let items = pathspec.includes().collect::<Vec<_>>();
+        let mut seen = vec![false; items.len()];
+        let mut result = RmResult::default();
+
+        for entry in &index.entries {
+            if !pathspec.matches(&entry.name) {
+                continue;
+            }
+            for (item, seen) in items.iter().zip(seen.iter_mut()) {
+                if !item.matches(&entry.name) {
+                    continue;
+                }
+                *seen = true;
+                if !options.recursive && names_directory(item, &entry.name) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("not removing '{}' recursively without -r", item.original),
+                    ));
+                }
+            }
+            result.removed.push(entry.name.clone());
+        }
+
+        if !options.ignore_unmatch {
+            if let Some((item, _)) = items.iter().zip(&seen).find(|(_, &seen)| !seen) {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("pathspec '{}' did not match any files", item.original),
+                ));
+            }
+        }
+
+        if !options.force {
+            let head = match self.resolve_ref("HEAD".to_string()) {
+                Ok(hash) => self.tree_map(self.read_commit(hash)?.tree)?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+                Err(e) => return Err(e),
+            };
+
+            for name in &result.removed {
+                let entry = index.get(name).unwrap();
+                // Nothing is lost when the file is gone already.
+                match self.file_system.stat(name.clone()) {
+                    Ok(metadata) if metadata.is_dir() => continue,
+                    Ok(_) => {}
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) if e.kind() == io::ErrorKind::NotADirectory => continue,
+                    Err(e) => return Err(e),
+                }
+                if entry.mode == FileMode::Gitlink {
+                    continue;
+                }
+
+                let local = self.worktree_changed(entry)?;
+                let staged = head.get(name) != Some(&(entry.mode, entry.hash.clone()));
+                let reason = if local && staged {
+                    if options.cached && entry.intent_to_add {
+                        continue;
+                    }
+                    Unsafe::StagedAndLocal
+                } else if options.cached {
+                    continue;
+                } else if staged {
+                    Unsafe::Staged
+                } else if local {
+                    Unsafe::Local
+                } else {
+                    continue;
+                };
+                result.refused.push((name.clone(), reason));
+            }
+            if !result.refused.is_empty() {
+                result.removed.clear();
+                return Ok(result);
+            }
+        }
+        if options.dry_run {
+            return Ok(result);
+        }
+
+        for name in &result.removed {
+            index.remove(name);
+            if options.cached {
+                continue;
+            }
+            match self.file_system.stat(name.clone()) {
+                Ok(metadata) if !metadata.is_dir() => {
+                    self.file_system.remove(name.clone())?;
+                    self.prune_empty_dirs(name)?;
+                }
+                _ => {}
+            }
+        }
+        self.write_index(&index)?;
+
+        Ok(result)
+    }
+}
+
+/// Whether `item` matches `path` only because it names a directory above
+/// it.
+fn names_directory(item: &Item, path: &str) -> bool {
+    !item.has_wildcards() && path.len() > item.pattern.len()
+}
+
+#[test]
+fn test_rm() {
+    use crate::fs::inmem::InMemFileSystem;
+
+    let mut git = Git::new(InMemFileSystem::init());
+    git.file_system.create_dir("d/e".to_string()).unwrap();
+    for name in ["a", "b", "c", "d/x", "d/e/y"] {
+        git.file_system
+            .write(name.to_string(), name.as_bytes())
+            .unwrap();
+    }
+    let spec = |args: &[&str]| Pathspec::parse(args).unwrap();
+    git.add(&spec(&["."]), &Default::default()).unwrap();
+
+    // Nothing is committed yet, so every file has staged changes.
+    let result = git.rm(&spec(&["a"]), &RmOptions::default()).unwrap();
+    assert_eq!(result.refused, vec![("a".to_string(), Unsafe::Staged)]);
+    assert!(git.file_system.stat("a".to_string()).is_ok());
+
+    let err = git.rm(&spec(&["d"]), &RmOptions::default()).unwrap_err();
+    assert_eq!(err.to_string(), "not removing 'd' recursively without -r");
+    let err = git.rm(&spec(&["zz"]), &RmOptions::default()).unwrap_err();
+    assert_eq!(err.to_string(), "pathspec 'zz' did not match any files");
+
+    let options = RmOptions {
+        recursive: true,
+        force: true,
+        ..RmOptions::default()
+    };
+    let result = git.rm(&spec(&["d"]), &options).unwrap();
+    assert_eq!(result.removed, vec!["d/e/y", "d/x"]);
+    assert!(git.file_system.stat("d".to_string()).is_err());
+
+    git.file_system.write("b".to_string(), b"changed").unwrap();
+    let options = RmOptions {
+        cached: true,
+        ..RmOptions::default()
+    };
+    let result = git.rm(&spec(&["b", "c"]), &options).unwrap();
+    assert_eq!(
+        result.refused,
+        vec![("b".to_string(), Unsafe::StagedAndLocal)]
+    );
+    let result = git.rm(&spec(&["c"]), &options).unwrap();
+    assert_eq!(result.removed, vec!["c"]);
+    assert!(git.file_system.stat("c".to_string()).is_ok());
+
+    let index = git.ls_files_stage(&git.read_index().unwrap()).unwrap();
+    let names = index
+        .entries
+        .iter()
+        .map(|x| x.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["a", "b"]);
+}
@@ -0,0 +1,199 @@
+use crate::fs::FileSystem;
+use crate::ident::Role;
+use crate::object::tag::Tag;
+use crate::object::GitObject;
+use crate::revision::is_valid_ref_name;
+use crate::wildmatch::wildmatch;
+use crate::Git;
+use std::cmp::Ordering;
+use std::io;
+
+impl<F: FileSystem> Git<F> {
+    /// The tags matching one of the glob `patterns` (all of them without
+    /// any) with the objects their refs point at, sorted by name.
+    pub fn list_tags(&self, patterns: &[String]) -> io::Result<Vec<(String, String)>> {
+        Ok(self
+            .all_refs()?
+            .into_iter()
+            .filter_map(|(refname, hash)| {
+                Some((refname.strip_prefix("refs/tags/")?.to_string(), hash))
+            })
+            .filter(|(name, _)| {
+                patterns.is_empty() || patterns.iter().any(|x| wildmatch(x, name, 0))
+            })
+            .collect())
+    }
+
+    /// Points `refs/tags/<name>` at `object`, through a new tag object
+    /// holding `message` when there is one. Returns what the tag pointed
+    /// at before, which only `force` allows.
+    pub fn create_tag(
+        &mut self,
+        name: &str,
+        object: &str,
+        message: Option<&str>,
+        force: bool,
+    ) -> io::Result<Option<String>> {
+        if name.starts_with('-') || !is_valid_ref_name(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' is not a valid tag name.", name),
+            ));
+        }
+        let refname = format!("refs/tags/{}", name);
+        let old = self.read_ref(refname.clone()).ok();
+        if old.is_some() && !force {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("tag '{}' already exists", name),
+            ));
+        }
+
+        let hash = match message {
+            Some(message) => {
+                let (object_type, _) = self.read_raw_object(object.to_string())?;
+                let message = if message.is_empty() || message.ends_with('\n') {
+                    message.to_string()
+                } else {
+                    format!("{}\n", message)
+                };
+                let tagger = self.ident(Role::Committer)?;
+                let tag = GitObject::Tag(Tag::new(
+                    object.to_string(),
+                    object_type,
+                    name.to_string(),
+                    tagger,
+                    message,
+                ));
+                self.write_object(&tag)?;
+                tag.calc_hash()
+            }
+            None => {
+                hex::decode(object).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?
+            }
+        };
+
+        let path = self.git_path(&refname);
+        if let Some((dir, _)) = path.rsplit_once('/') {
+            self.file_system.create_dir(dir.to_string())?;
+        }
+        self.write_ref(refname, &hash)?;
+        Ok(old)
+    }
+
+    /// Deletes a tag, returning the object it pointed at.
+    pub fn delete_tag(&mut self, name: &str) -> io::Result<String> {
+        let refname = format!("refs/tags/{}", name);
+        let hash = self.read_ref(refname.clone()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("tag '{}' not found.", name),
+            )
+        })?;
+        self.delete_ref(&refname)?;
+        Ok(hash)
+    }
+
+    pub fn read_tag(&self, hash: String) -> io::Result<Tag> {
+        self.read_git_object(hash.clone())
+            .and_then(|x| match x {
+                GitObject::Tag(tag) => Ok(tag),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is not a tag", hash),
+                )),
+            })
+    }
+}
+
+/// Orders names as versions (`--sort=version:refname`): runs of digits
+/// compare as numbers, so `v1.9` comes before `v1.10`.
+pub fn version_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (x, y) = match (a.chars().next(), b.chars().next()) {
+            (Some(x), Some(y)) => (x, y),
+            _ => return a.len().cmp(&b.len()),
+        };
+        if !x.is_ascii_digit() || !y.is_ascii_digit() {
+            if x != y {
+                return x.cmp(&y);
+            }
+            a = &a[x.len_utf8()..];
+            b = &b[y.len_utf8()..];
+            continue;
+        }
+
+        let (x, rest_a) = split_digits(a);
+        let (y, rest_b) = split_digits(b);
+        let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+        match x.len().cmp(&y.len()).then_with(|| x.cmp(y)) {
+            Ordering::Equal => {
+                a = rest_a;
+                b = rest_b;
+            }
+            other => return other,
+        }
+    }
+}
+
+fn split_digits(s: &str) -> (&str, &str) {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    s.split_at(end)
+}
+
+#[test]
+fn test_version_cmp() {
+    let mut names = vec!["v2.0", "v1.10", "v1.2", "v1.9", "v1.2.1", "v10"];
+    names.sort_by(|a, b| version_cmp(a, b));
+    assert_eq!(
+        names,
+        vec!["v1.2", "v1.2.1", "v1.9", "v1.10", "v2.0", "v10"]
+    );
+    assert_eq!(version_cmp("a", "b"), Ordering::Less);
+    assert_eq!(version_cmp("v1", "v1"), Ordering::Equal);
+}
+
+#[test]
+fn test_tag() {
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::object::blob::Blob;
+
+    let mut git = Git::new(InMemFileSystem::init());
+    git.file_system.create_dir(".git/refs".to_string()).unwrap();
+    std::env::set_var("GIT_COMMITTER_NAME", "C O Mitter");
+    std::env::set_var("GIT_COMMITTER_EMAIL", "committer@example.com");
+    let blob = GitObject::Blob(Blob::from(b"content").unwrap());
+    git.write_object(&blob).unwrap();
+    let blob = hex::encode(blob.calc_hash());
+
+    git.create_tag("light", &blob, None, false).unwrap();
+    git.create_tag("v1.0", &blob, Some("First"), false).unwrap();
+    let err = git.create_tag("v1.0", &blob, None, false).unwrap_err();
+    assert_eq!(err.to_string(), "tag 'v1.0' already exists");
+    assert!(git.create_tag("a..b", &blob, None, false).is_err());
+
+    let tags = git.list_tags(&[]).unwrap();
+    assert_eq!(tags[0], ("light".to_string(), blob.clone()));
+    let tag = git.read_tag(tags[1].1.clone()).unwrap();
+    assert_eq!(
+        (tag.tag.as_str(), tag.message.as_str()),
+        ("v1.0", "First\n")
+    );
+    assert_eq!(git.peel(tags[1].1.clone(), "blob").unwrap(), blob);
+
+    assert_eq!(git.list_tags(&["v*".to_string()]).unwrap().len(), 1);
+    // Tags `pack-refs` moved into `packed-refs` are listed too.
+    let packed = format!("{} refs/tags/packed\n", blob);
+    git.file_system
+        .write(".git/packed-refs".to_string(), packed.as_bytes())
+        .unwrap();
+    assert_eq!(
+        git.list_tags(&["p*".to_string()]).unwrap(),
+        vec![("packed".to_string(), blob.clone())]
+    );
+    assert_eq!(git.delete_tag("light").unwrap(), blob);
+    assert!(git.delete_tag("light").is_err());
+    assert_eq!(git.delete_tag("packed").unwrap(), blob);
+    assert!(git.delete_tag("packed").is_err());
+}
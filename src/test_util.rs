@@ -0,0 +1,85 @@
+//! Objects and commits for the unit tests to build repositories from.
+
+use crate::fs::FileSystem;
+use crate::object::blob::Blob;
+use crate::object::commit::{Commit, User};
+use crate::object::tree::{File, FileMode, Tree};
+use crate::object::GitObject;
+use crate::Git;
+use chrono::DateTime;
+
+/// Writes `object` and returns its hash.
+pub(crate) fn write<F: FileSystem>(git: &mut Git<F>, object: GitObject) -> String {
+    git.write_object(&object).unwrap();
+    hex::encode(object.calc_hash())
+}
+
+pub(crate) fn blob<F: FileSystem>(git: &mut Git<F>, content: &[u8]) -> String {
+    write(git, GitObject::Blob(Blob::from(content).unwrap()))
+}
+
+/// Writes a tree of `(mode, name, hash)` entries, given in tree order.
+pub(crate) fn tree<F: FileSystem>(git: &mut Git<F>, files: &[(FileMode, &str, &str)]) -> String {
+    let files = files
+        .iter()
+        .map(|(mode, name, hash)| File::new(*mode, name.to_string(), &hex::decode(hash).unwrap()))
+        .collect();
+    write(git, GitObject::Tree(Tree::new(files)))
+}
+
+/// A U Thor, `seconds` past 22:13 on 2005-04-07 +0200.
+pub(crate) fn user_at(seconds: u32) -> User {
+    let date = format!("2005-04-07T22:13:{:02}+02:00", seconds);
+    User::new(
+        "A U Thor".to_string(),
+        "author@example.com".to_string(),
+        DateTime::parse_from_rfc3339(&date).unwrap(),
+    )
+}
+
+/// Writes a commit of `tree` on top of `parents`, authored and committed
+/// by `user_at(seconds)`, with `message` as its only line.
+pub(crate) fn commit_at<F: FileSystem>(
+    git: &mut Git<F>,
+    tree: &str,
+    parents: &[&str],
+    message: &str,
+    seconds: u32,
+) -> String {
+    let user = user_at(seconds);
+    write(
+        git,
+        GitObject::Commit(Commit::new(
+            tree.to_string(),
+            parents.iter().map(|x| x.to_string()).collect(),
+            user.clone(),
+            user,
+            format!("{}\n", message),
+        )),
+    )
+}
+
+/// `commit_at` with every commit at the same time.
+pub(crate) fn commit<F: FileSystem>(
+    git: &mut Git<F>,
+    tree: &str,
+    parents: &[&str],
+    message: &str,
+) -> String {
+    commit_at(git, tree, parents, message, 13)
+}
+
+/// Commits a file with `content` on top of `parents` and points `main`
+/// at the commit.
+pub(crate) fn commit_file<F: FileSystem>(
+    git: &mut Git<F>,
+    content: &[u8],
+    parents: Vec<String>,
+) -> String {
+    let blob = blob(git, content);
+    let tree = tree(git, &[(FileMode::Blob, "file", &blob)]);
+    let parents = parents.iter().map(String::as_str).collect::<Vec<_>>();
+    let commit = commit(git, &tree, &parents, "message");
+    git.write_branch("refs/heads/main", &commit).unwrap();
+    commit
+}
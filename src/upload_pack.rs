@@ -0,0 +1,482 @@
+use crate::fs::FileSystem;
+use crate::protocol::Packet;
+use crate::Git;
+use std::collections::HashSet;
+use std::io;
+use std::io::prelude::*;
+
+/// The most of a pack one sideband packet carries, after its band byte.
+const MAX_BAND_DATA: usize = 65515;
+
+/// What a client asks of `fetch`, or of the negotiation after the ref
+/// advertisement in version 0.
+#[derive(Default)]
+struct FetchRequest {
+    wants: Vec<String>,
+    haves: Vec<String>,
+    done: bool,
+    no_progress: bool,
+    include_tag: bool,
+    sideband: bool,
+}
+
+impl<F: FileSystem> Git<F> {
+    /// Serves the repository to a client fetching over `input` and
+    /// `output`, as `git upload-pack` does: in version 2 of the protocol,
+    /// answering `ls-refs` and `fetch` commands until the client leaves,
+    /// otherwise advertising the refs and sending one pack. Packs hold no
+    /// deltas.
+    pub fn upload_pack(
+        &self,
+        input: &mut impl Read,
+        output: &mut impl Write,
+        version: u32,
+    ) -> io::Result<()> {
+        if version != 2 {
+            return self.upload_pack_v0(input, output);
+        }
+
+        for line in &[
+            "version 2".to_string(),
+            format!("agent=clumsy/{}", env!("CARGO_PKG_VERSION")),
+            "ls-refs=unborn".to_string(),
+            "fetch".to_string(),
+            "server-option".to_string(),
+            "object-format=sha1".to_string(),
+        ] {
+            Packet::line(line).write_to(output)?;
+        }
+        Packet::Flush.write_to(output)?;
+        output.flush()?;
+
+        loop {
+            let command = match read_packet(input)? {
+                Some(Packet::Flush) | None => return Ok(()),
+                Some(packet) => packet
+                    .text()
+                    .and_then(|x| x.strip_prefix("command="))
+                    .ok_or_else(|| invalid("expected a command"))?
+                    .to_string(),
+            };
+            // The client's capabilities, which we needn't know, then its
+            // arguments.
+            let mut args = Vec::new();
+            let mut in_args = false;
+            loop {
+                match read_packet(input)?.ok_or_else(|| invalid("unexpected end of request"))? {
+                    Packet::Flush => break,
+                    Packet::Delim => in_args = true,
+                    packet if in_args => args.push(
+                        packet
+                            .text()
+                            .ok_or_else(|| invalid("bad argument"))?
+                            .to_string(),
+                    ),
+                    _ => {}
+                }
+            }
+
+            match command.as_str() {
+                "ls-refs" => self.ls_refs(&args, output)?,
+                "fetch" => {
+                    let mut request = FetchRequest {
+                        sideband: true,
+                        ..FetchRequest::default()
+                    };
+                    for arg in &args {
+                        request.parse(arg);
+                    }
+                    self.serve_fetch(&request, output)?;
+                }
+                x => {
+                    Packet::line(&format!("ERR unknown command '{}'", x)).write_to(output)?;
+                    return output.flush();
+                }
+            }
+            output.flush()?;
+        }
+    }
+
+    /// `HEAD`, if it points to a commit, and every ref after it, with the
+    /// objects they point to.
+    pub(crate) fn advertised_refs(&self) -> io::Result<Vec<(String, String)>> {
+        let mut refs = Vec::new();
+        if let Ok(hash) = self.resolve_ref("HEAD".to_string()) {
+            refs.push(("HEAD".to_string(), hash));
+        }
+        refs.extend(self.all_refs()?);
+        Ok(refs)
+    }
+
+    /// The object behind an annotated tag, and behind any tags it points
+    /// to in turn.
+    fn peeled(&self, hash: &str) -> Option<String> {
+        let mut hash = hash.to_string();
+        let mut peeled = None;
+        while let Ok(tag) = self.read_tag(hash.clone()) {
+            hash = tag.object;
+            peeled = Some(hash.clone());
+        }
+        peeled
+    }
+
+    fn ls_refs(&self, args: &[String], output: &mut impl Write) -> io::Result<()> {
+        let has = |arg: &str| args.iter().any(|x| x == arg);
+        let prefixes: Vec<_> = args
+            .iter()
+            .filter_map(|x| x.strip_prefix("ref-prefix "))
+            .collect();
+        let wanted =
+            |name: &str| prefixes.is_empty() || prefixes.iter().any(|x| name.starts_with(x));
+
+        let head = self.head_ref().ok();
+        let refs = self.advertised_refs()?;
+        if let (Some(head), true) = (&head, has("unborn") && wanted("HEAD")) {
+            if refs.first().is_none_or(|x| x.0 != "HEAD") {
+                Packet::line(&format!("unborn HEAD symref-target:{}", head)).write_to(output)?;
+            }
+        }
+        for (name, hash) in refs {
+            if !wanted(&name) {
+                continue;
+            }
+            let mut line = format!("{} {}", hash, name);
+            if let (Some(head), true) = (&head, name == "HEAD" && has("symrefs")) {
+                line.push_str(&format!(" symref-target:{}", head));
+            }
+            if let (Some(peeled), true) = (self.peeled(&hash), has("peel")) {
+                line.push_str(&format!(" peeled:{}", peeled));
+            }
+            Packet::line(&line).write_to(output)?;
+        }
+        Packet::Flush.write_to(output)
+    }
+
+    /// Answers one round of `fetch`: the haves we share and, once the
+    /// client is done or we are ready, the pack.
+    fn serve_fetch(&self, request: &FetchRequest, output: &mut impl Write) -> io::Result<()> {
+        self.check_wants(&request.wants, output)?;
+        let common: Vec<_> = request
+            .haves
+            .iter()
+            .filter(|x| self.has_object(x))
+            .cloned()
+            .collect();
+
+        if !request.done {
+            Packet::line("acknowledgments").write_to(output)?;
+            if common.is_empty() {
+                Packet::line("NAK").write_to(output)?;
+            }
+            for have in &common {
+                Packet::line(&format!("ACK {}", have)).write_to(output)?;
+            }
+            if !self.ready(&request.wants, &common)? {
+                return Packet::Flush.write_to(output);
+            }
+            Packet::line("ready").write_to(output)?;
+            Packet::Delim.write_to(output)?;
+        }
+        Packet::line("packfile").write_to(output)?;
+        self.send_pack(request, &common, output)
+    }
+
+    /// Refuses wants other than the refs we advertise and what their tags
+    /// peel to, as git does unless told to serve more.
+    fn check_wants(&self, wants: &[String], output: &mut impl Write) -> io::Result<()> {
+        let mut tips = HashSet::new();
+        for (_, hash) in self.advertised_refs()? {
+            tips.extend(self.peeled(&hash));
+            tips.insert(hash);
+        }
+        match wants.iter().find(|x| !tips.contains(*x)) {
+            Some(want) => {
+                Packet::line(&format!("ERR upload-pack: not our ref {}", want)).write_to(output)?;
+                output.flush()?;
+                Err(invalid(&format!("not our ref {}", want)))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Whether every commit the client wants has one the client has in
+    /// its history, so that going on would make the pack little smaller.
+    fn ready(&self, wants: &[String], common: &[String]) -> io::Result<bool> {
+        if common.is_empty() {
+            return Ok(false);
+        }
+        let common: HashSet<_> = common.iter().collect();
+        for want in wants {
+            let want = match self.peel(want.clone(), "commit") {
+                Ok(want) => want,
+                Err(_) => continue,
+            };
+            let mut walk = self.rev_walk();
+            walk.push(&want)?;
+            if !walk.any(|x| x.is_ok_and(|x| common.contains(&x))) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Sends what `request` wants and `common` lacks, through band 1 of
+    /// the sideband if the client asked for one.
+    fn send_pack(
+        &self,
+        request: &FetchRequest,
+        common: &[String],
+        output: &mut impl Write,
+    ) -> io::Result<()> {
+        let mut objects = self.objects_between(&request.wants, common)?;
+        if request.include_tag {
+            // Annotated tags of what is being sent, which the client would
+            // otherwise have to fetch again.
+            let sent: HashSet<_> = objects.iter().cloned().collect();
+            for (name, hash) in self.all_refs()? {
+                if !name.starts_with("refs/tags/") || sent.contains(&hash) {
+                    continue;
+                }
+                if let Ok(tag) = self.read_tag(hash.clone()) {
+                    if sent.contains(&tag.object) {
+                        objects.push(hash);
+                    }
+                }
+            }
+        }
+        let pack = self.write_pack(&objects)?;
+
+        if !request.sideband {
+            output.write_all(&pack)?;
+            return output.flush();
+        }
+        if !request.no_progress {
+            let total = format!(
+                "Total {} (delta 0), reused 0 (delta 0), pack-reused 0\n",
+                objects.len()
+            );
+            Packet::Data([b"\x02", total.as_bytes()].concat()).write_to(output)?;
+        }
+        for chunk in pack.chunks(MAX_BAND_DATA) {
+            Packet::Data([&[1], chunk].concat()).write_to(output)?;
+        }
+        Packet::Flush.write_to(output)
+    }
+
+    /// Version 0: the refs with our capabilities on the first, then the
+    /// client's wants, rounds of haves, each ending with a flush answered
+    /// by `NAK` until one is shared and acknowledged, and `done`.
+    fn upload_pack_v0(&self, input: &mut impl Read, output: &mut impl Write) -> io::Result<()> {
+        let mut capabilities = String::from("side-band-64k ofs-delta no-progress include-tag");
+        if let Ok(head) = self.head_ref() {
+            capabilities.push_str(&format!(" symref=HEAD:{}", head));
+        }
+        capabilities.push_str(&format!(
+            " agent=clumsy/{} object-format=sha1",
+            env!("CARGO_PKG_VERSION")
+        ));
+        let refs = self.advertised_refs()?;
+        advertise(&refs, &capabilities, output, |hash| self.peeled(hash))?;
+        output.flush()?;
+
+        let mut request = FetchRequest::default();
+        loop {
+            match read_packet(input)? {
+                Some(Packet::Flush) => break,
+                // Nothing wanted.
+                None => return Ok(()),
+                Some(packet) => {
+                    // The first want carries the client's capabilities.
+                    let line = packet.text().ok_or_else(|| invalid("bad want"))?;
+                    let mut words = line.split(' ');
+                    if let (Some("want"), Some(hash)) = (words.next(), words.next()) {
+                        request.wants.push(hash.to_string());
+                        for feature in words {
+                            request.parse(feature);
+                            request.sideband |= feature == "side-band-64k";
+                        }
+                    }
+                }
+            }
+        }
+        if request.wants.is_empty() {
+            return Ok(());
+        }
+        self.check_wants(&request.wants, output)?;
+
+        let mut common = Vec::new();
+        loop {
+            match read_packet(input)?.ok_or_else(|| invalid("unexpected end of negotiation"))? {
+                Packet::Flush if common.is_empty() => Packet::line("NAK").write_to(output)?,
+                Packet::Flush => {}
+                packet => match packet.text() {
+                    Some("done") => break,
+                    Some(line) => {
+                        if let Some(have) = line.strip_prefix("have ") {
+                            if self.has_object(have) {
+                                // Without multi_ack, only the first is
+                                // acknowledged.
+                                if common.is_empty() {
+                                    Packet::line(&format!("ACK {}", have)).write_to(output)?;
+                                }
+                                common.push(have.to_string());
+                            }
+                        }
+                    }
+                    None => return Err(invalid("bad have")),
+                },
+            }
+            output.flush()?;
+        }
+        if common.is_empty() {
+            Packet::line("NAK").write_to(output)?;
+        }
+        self.send_pack(&request, &common, output)?;
+        output.flush()
+    }
+}
+
+impl FetchRequest {
+    /// Takes in one argument of a request; unknown ones are ignored.
+    fn parse(&mut self, arg: &str) {
+        if let Some(want) = arg.strip_prefix("want ") {
+            self.wants.push(want.to_string());
+        } else if let Some(have) = arg.strip_prefix("have ") {
+            self.haves.push(have.to_string());
+        } else {
+            match arg {
+                "done" => self.done = true,
+                "no-progress" => self.no_progress = true,
+                "include-tag" => self.include_tag = true,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Sends the refs in the form of a version 0 advertisement: each with its
+/// peeled value after it, if it has one, and `capabilities` after the
+/// first. Without refs, the capabilities still need a line.
+pub(crate) fn advertise(
+    refs: &[(String, String)],
+    capabilities: &str,
+    output: &mut impl Write,
+    peeled: impl Fn(&str) -> Option<String>,
+) -> io::Result<()> {
+    if refs.is_empty() {
+        Packet::line(&format!(
+            "{} capabilities^{{}}\0{}",
+            "0".repeat(40),
+            capabilities
+        ))
+        .write_to(output)?;
+    }
+    for (i, (name, hash)) in refs.iter().enumerate() {
+        match i {
+            0 => Packet::line(&format!("{} {}\0{}", hash, name, capabilities)),
+            _ => Packet::line(&format!("{} {}", hash, name)),
+        }
+        .write_to(output)?;
+        if let Some(peeled) = peeled(hash) {
+            Packet::line(&format!("{} {}^{{}}", peeled, name)).write_to(output)?;
+        }
+    }
+    Packet::Flush.write_to(output)
+}
+
+/// The next packet from the client, or `None` if it has hung up.
+pub(crate) fn read_packet(input: &mut impl Read) -> io::Result<Option<Packet>> {
+    match Packet::read_from(input) {
+        Ok(packet) => Ok(Some(packet)),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+#[test]
+fn test_upload_pack() {
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::protocol::Connection;
+    use crate::test_util::commit_file;
+
+    let mut server = Git::new(InMemFileSystem::init());
+    server.init(true, "main").unwrap();
+    let first = commit_file(&mut server, b"one\n", Vec::new());
+    let second = commit_file(&mut server, b"two\n", vec![first.clone()]);
+
+    let mut client = Git::new(InMemFileSystem::init());
+    client.init(false, "main").unwrap();
+    commit_file(&mut client, b"one\n", Vec::new());
+
+    let (mut requests, client_output) = io::pipe().unwrap();
+    let (client_input, mut responses) = io::pipe().unwrap();
+    let served = std::thread::spawn(move || server.upload_pack(&mut requests, &mut responses, 2));
+
+    let mut connection = Connection::new(client_input, client_output).unwrap();
+    let refs = connection.ls_refs(&[]).unwrap();
+    assert_eq!(refs[0].name, "HEAD");
+    assert_eq!(refs[0].symref_target.as_deref(), Some("refs/heads/main"));
+    assert_eq!(refs[1].name, "refs/heads/main");
+    assert_eq!(refs[1].hash, second);
+
+    let name = client
+        .fetch_pack(&mut connection, std::slice::from_ref(&second), None)
+        .unwrap()
+        .unwrap();
+    drop(connection);
+    served.join().unwrap().unwrap();
+
+    // The client having the first commit, only the second, its tree and
+    // its blob come.
+    let pack = client
+        .file_system
+        .read(client.git_path(&format!("{}.pack", name)))
+        .unwrap();
+    assert_eq!(pack[8..12], [0, 0, 0, 3]);
+    let commit = client.read_commit(second).unwrap();
+    assert_eq!(commit.parents, vec![first]);
+    let files = client.read_tree_recursive(commit.tree).unwrap();
+    assert_eq!(
+        client.read_blob(hex::encode(&files[0].1.hash)).unwrap(),
+        b"two\n"
+    );
+}
+
+#[test]
+fn test_upload_pack_unadvertised_want() {
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::protocol::Connection;
+    use crate::test_util::commit_file;
+
+    let mut server = Git::new(InMemFileSystem::init());
+    server.init(true, "main").unwrap();
+    let first = commit_file(&mut server, b"one\n", Vec::new());
+    commit_file(&mut server, b"two\n", vec![first.clone()]);
+
+    let mut client = Git::new(InMemFileSystem::init());
+    client.init(false, "main").unwrap();
+
+    let (mut requests, client_output) = io::pipe().unwrap();
+    let (client_input, mut responses) = io::pipe().unwrap();
+    let served = std::thread::spawn(move || server.upload_pack(&mut requests, &mut responses, 2));
+
+    // The first commit is there, but no ref points at it.
+    let mut connection = Connection::new(client_input, client_output).unwrap();
+    let err = client
+        .fetch_pack(&mut connection, std::slice::from_ref(&first), None)
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains(&format!("upload-pack: not our ref {}", first)),
+        "{}",
+        err
+    );
+    drop(connection);
+    let err = served.join().unwrap().unwrap_err();
+    assert_eq!(err.to_string(), format!("not our ref {}", first));
+    assert!(!client.has_object(&first));
+}
@@ -0,0 +1,259 @@
+pub const WM_CASEFOLD: u32 = 1;
+pub const WM_PATHNAME: u32 = 2;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Matched {
+    Match,
+    NoMatch,
+    AbortAll,
+    AbortToStarstar,
+}
+
+/// Matches `text` against a shell glob the same way git's `wildmatch` does.
+///
+/// With `WM_PATHNAME`, `*` and `?` never match `/` and `**` only spans
+/// directories when it makes up a whole path component.
+pub fn wildmatch(pattern: &str, text: &str, flags: u32) -> bool {
+    dowild(pattern.as_bytes(), 0, text.as_bytes(), 0, flags) == Matched::Match
+}
+
+fn at(bytes: &[u8], i: usize) -> u8 {
+    bytes.get(i).copied().unwrap_or(0)
+}
+
+fn dowild(pattern: &[u8], mut p: usize, text: &[u8], mut t: usize, flags: u32) -> Matched {
+    let casefold = flags & WM_CASEFOLD != 0;
+    let pathname = flags & WM_PATHNAME != 0;
+
+    while p < pattern.len() {
+        let mut p_ch = pattern[p];
+        let mut t_ch = at(text, t);
+
+        if t_ch == 0 && p_ch != b'*' {
+            return Matched::AbortAll;
+        }
+        if casefold {
+            t_ch = t_ch.to_ascii_lowercase();
+            p_ch = p_ch.to_ascii_lowercase();
+        }
+
+        match p_ch {
+            b'?' => {
+                if pathname && t_ch == b'/' {
+                    return Matched::NoMatch;
+                }
+            }
+            b'*' => {
+                p += 1;
+                let match_slash = if at(pattern, p) == b'*' {
+                    let prev_is_boundary = p < 2 || pattern[p - 2] == b'/';
+                    while at(pattern, p) == b'*' {
+                        p += 1;
+                    }
+                    if !pathname {
+                        true
+                    } else if prev_is_boundary
+                        && (at(pattern, p) == 0
+                            || at(pattern, p) == b'/'
+                            || (at(pattern, p) == b'\\' && at(pattern, p + 1) == b'/'))
+                    {
+                        if at(pattern, p) == b'/'
+                            && dowild(pattern, p + 1, text, t, flags) == Matched::Match
+                        {
+                            return Matched::Match;
+                        }
+                        true
+                    } else {
+                        false
+                    }
+                } else {
+                    !pathname
+                };
+
+                if p >= pattern.len() {
+                    if !match_slash && text[t..].contains(&b'/') {
+                        return Matched::NoMatch;
+                    }
+                    return Matched::Match;
+                } else if !match_slash && pattern[p] == b'/' {
+                    match text[t..].iter().position(|&x| x == b'/') {
+                        Some(pos) => {
+                            t += pos + 1;
+                            p += 1;
+                            continue;
+                        }
+                        None => return Matched::NoMatch,
+                    }
+                }
+
+                while t < text.len() {
+                    let t_ch = text[t];
+                    match dowild(pattern, p, text, t, flags) {
+                        Matched::NoMatch => {
+                            if !match_slash && t_ch == b'/' {
+                                return Matched::AbortToStarstar;
+                            }
+                        }
+                        Matched::AbortToStarstar if match_slash => {}
+                        matched => return matched,
+                    }
+                    t += 1;
+                }
+                return Matched::AbortAll;
+            }
+            b'[' => {
+                p += 1;
+                let mut p_ch = at(pattern, p);
+                if p_ch == b'^' {
+                    p_ch = b'!';
+                }
+                let negated = p_ch == b'!';
+                if negated {
+                    p += 1;
+                    p_ch = at(pattern, p);
+                }
+                let mut prev_ch = 0u8;
+                let mut matched = false;
+                loop {
+                    if p_ch == 0 {
+                        return Matched::AbortAll;
+                    }
+                    if p_ch == b'\\' {
+                        p += 1;
+                        p_ch = at(pattern, p);
+                        if p_ch == 0 {
+                            return Matched::AbortAll;
+                        }
+                        if t_ch == p_ch {
+                            matched = true;
+                        }
+                    } else if p_ch == b'-'
+                        && prev_ch != 0
+                        && at(pattern, p + 1) != 0
+                        && at(pattern, p + 1) != b']'
+                    {
+                        p += 1;
+                        p_ch = pattern[p];
+                        if p_ch == b'\\' {
+                            p += 1;
+                            p_ch = at(pattern, p);
+                            if p_ch == 0 {
+                                return Matched::AbortAll;
+                            }
+                        }
+                        if t_ch <= p_ch && t_ch >= prev_ch {
+                            matched = true;
+                        } else if casefold && t_ch.is_ascii_lowercase() {
+                            let upper = t_ch.to_ascii_uppercase();
+                            if upper <= p_ch && upper >= prev_ch {
+                                matched = true;
+                            }
+                        }
+                        p_ch = 0;
+                    } else if p_ch == b'[' && at(pattern, p + 1) == b':' {
+                        let start = p + 2;
+                        let end = match pattern[start..].iter().position(|&x| x == b']') {
+                            Some(pos) => start + pos,
+                            None => return Matched::AbortAll,
+                        };
+                        if end == start || pattern[end - 1] != b':' {
+                            // `[:` without a closing `:]` is an ordinary `[`.
+                            p_ch = b'[';
+                            if t_ch == p_ch {
+                                matched = true;
+                            }
+                        } else {
+                            p = end;
+                            let class = &pattern[start..end - 1];
+                            match match_class(class, t_ch, casefold) {
+                                Some(true) => matched = true,
+                                Some(false) => {}
+                                None => return Matched::AbortAll,
+                            }
+                            p_ch = 0;
+                        }
+                    } else if t_ch == p_ch {
+                        matched = true;
+                    }
+
+                    prev_ch = p_ch;
+                    p += 1;
+                    p_ch = at(pattern, p);
+                    if p_ch == b']' {
+                        break;
+                    }
+                }
+                if matched == negated || (pathname && t_ch == b'/') {
+                    return Matched::NoMatch;
+                }
+            }
+            _ => {
+                if p_ch == b'\\' {
+                    p += 1;
+                    p_ch = at(pattern, p);
+                    if casefold {
+                        p_ch = p_ch.to_ascii_lowercase();
+                    }
+                }
+                if t_ch != p_ch {
+                    return Matched::NoMatch;
+                }
+            }
+        }
+
+        p += 1;
+        t += 1;
+    }
+
+    if t < text.len() {
+        Matched::NoMatch
+    } else {
+        Matched::Match
+    }
+}
+
+fn match_class(class: &[u8], ch: u8, casefold: bool) -> Option<bool> {
+    let result = match class {
+        b"alnum" => ch.is_ascii_alphanumeric(),
+        b"alpha" => ch.is_ascii_alphabetic(),
+        b"blank" => ch == b' ' || ch == b'\t',
+        b"cntrl" => ch.is_ascii_control(),
+        b"digit" => ch.is_ascii_digit(),
+        b"graph" => ch.is_ascii_graphic(),
+        b"lower" => ch.is_ascii_lowercase(),
+        b"print" => ch.is_ascii_graphic() || ch == b' ',
+        b"punct" => ch.is_ascii_punctuation(),
+        b"space" => ch.is_ascii_whitespace() || ch == 0x0b,
+        b"upper" => ch.is_ascii_uppercase() || (casefold && ch.is_ascii_lowercase()),
+        b"xdigit" => ch.is_ascii_hexdigit(),
+        _ => return None,
+    };
+    Some(result)
+}
+
+#[test]
+fn test_wildmatch_basic() {
+    assert!(wildmatch("foo", "foo", 0));
+    assert!(!wildmatch("foo", "bar", 0));
+    assert!(wildmatch("f?o", "foo", 0));
+    assert!(wildmatch("*.rs", "main.rs", 0));
+    assert!(wildmatch("*.rs", "src/main.rs", 0));
+    assert!(!wildmatch("*.rs", "src/main.rs", WM_PATHNAME));
+    assert!(wildmatch("[a-c]at", "bat", 0));
+    assert!(!wildmatch("[!a-c]at", "bat", 0));
+    assert!(wildmatch("[[:digit:]]x", "1x", 0));
+    assert!(wildmatch("\\*", "*", 0));
+    assert!(wildmatch("FOO", "foo", WM_CASEFOLD));
+}
+
+#[test]
+fn test_wildmatch_double_star() {
+    assert!(wildmatch("**/foo", "foo", WM_PATHNAME));
+    assert!(wildmatch("**/foo", "a/b/foo", WM_PATHNAME));
+    assert!(wildmatch("a/**/b", "a/b", WM_PATHNAME));
+    assert!(wildmatch("a/**/b", "a/x/y/b", WM_PATHNAME));
+    assert!(wildmatch("a/**", "a/x/y", WM_PATHNAME));
+    assert!(!wildmatch("a/**", "b/x", WM_PATHNAME));
+    assert!(!wildmatch("a*/b", "ax/y/b", WM_PATHNAME));
+    assert!(wildmatch("/home/**", "/home/user/repo/.git", WM_PATHNAME));
+}
@@ -0,0 +1,224 @@
+//! Runs the `clumsy` binary against repositories in temporary directories.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// A repository with a `main` branch yet to be born, removed when dropped.
+struct Repo {
+    dir: PathBuf,
+}
+
+impl Repo {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("clumsy-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for sub in &[".git/objects", ".git/refs/heads", ".git/refs/tags"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        fs::write(dir.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        Repo { dir }
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_clumsy"))
+            .args(args)
+            .current_dir(&self.dir)
+            .env("HOME", &self.dir)
+            .env("XDG_CONFIG_HOME", self.dir.join(".config"))
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_AUTHOR_NAME", "A U Thor")
+            .env("GIT_AUTHOR_EMAIL", "author@example.com")
+            .env("GIT_AUTHOR_DATE", "1112911993 +0200")
+            .env("GIT_COMMITTER_NAME", "C O Mitter")
+            .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+            .env("GIT_COMMITTER_DATE", "1112911993 +0200")
+            .output()
+            .unwrap()
+    }
+
+    /// Runs a command that is expected to succeed and returns its output.
+    fn ok(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "clumsy {:?}: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    /// Runs a command that is expected to fail with `code` and returns
+    /// what it printed to stderr.
+    fn fails(&self, args: &[&str], code: i32) -> String {
+        let output = self.run(args);
+        assert_eq!(output.status.code(), Some(code), "clumsy {:?}", args);
+        String::from_utf8(output.stderr).unwrap()
+    }
+
+    /// Commits `content` as `file` with `message` as the subject.
+    fn commit(&self, file: &str, content: &str, message: &str) {
+        fs::write(self.dir.join(file), content).unwrap();
+        self.ok(&["add", file]);
+        self.ok(&["commit", "-qm", message]);
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.dir.join(path).exists()
+    }
+}
+
+impl Drop for Repo {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn test_switch_create_rejects_bad_names() {
+    let repo = Repo::new("switch-create");
+    repo.commit("file", "one\n", "one");
+
+    for name in &["a..b", "-b", "topic.lock", "../../evil"] {
+        for flag in &["-c", "-C"] {
+            let err = repo.fails(&["switch", flag, name], 128);
+            assert_eq!(
+                err,
+                format!("fatal: '{}' is not a valid branch name\n", name)
+            );
+        }
+        let err = repo.fails(&["checkout", "-b", name], 128);
+        assert!(err.contains("is not a valid branch name"));
+    }
+    assert!(!repo.exists(".git/evil"));
+    assert_eq!(
+        fs::read_to_string(repo.dir.join(".git/HEAD")).unwrap(),
+        "ref: refs/heads/main\n"
+    );
+
+    repo.ok(&["switch", "-c", "topic"]);
+    assert!(repo.exists(".git/refs/heads/topic"));
+}
+
+#[test]
+fn test_log_revisions_ending_in_digits() {
+    let repo = Repo::new("log-revisions");
+    repo.commit("file", "one\n", "one");
+    repo.ok(&["tag", "v1"]);
+    repo.commit("file", "two\n", "two");
+
+    let subjects = |args: &[&str]| {
+        let mut all = vec!["log", "--format=%s"];
+        all.extend(args);
+        repo.ok(&all)
+    };
+    assert_eq!(subjects(&["v1"]), "one\n");
+    assert_eq!(subjects(&["-1"]), "two\n");
+    assert_eq!(subjects(&["-n1", "v1"]), "one\n");
+
+    let err = repo.fails(&["log", "é"], 128);
+    assert!(err.contains("'é'"), "{}", err);
+}
+
+#[test]
+fn test_config_options() {
+    let repo = Repo::new("config-options");
+    repo.ok(&["config", "core.flag", "yes"]);
+
+    assert_eq!(
+        repo.ok(&["config", "--type", "bool", "core.flag"]),
+        "true\n"
+    );
+    assert_eq!(repo.ok(&["config", "--type=bool", "core.flag"]), "true\n");
+
+    repo.ok(&["config", "--file", "other", "a.b", "c"]);
+    assert_eq!(repo.ok(&["config", "--file=other", "a.b"]), "c\n");
+    assert_eq!(repo.ok(&["config", "-f", "other", "a.b"]), "c\n");
+
+    let err = repo.fails(&["config", "--type"], 129);
+    assert!(err.contains("option '--type' requires a value"), "{}", err);
+    let err = repo.fails(&["config", "-f"], 129);
+    assert!(err.contains("option '-f' requires a value"), "{}", err);
+    let err = repo.fails(&["config", "--bogus", "core.flag"], 129);
+    assert!(err.contains("unknown option '--bogus'"), "{}", err);
+
+    // Options end at the name, so values may look like them.
+    repo.ok(&["config", "core.dash", "-x"]);
+    assert_eq!(repo.ok(&["config", "core.dash"]), "-x\n");
+}
+
+#[test]
+fn test_config_unset_multiple_values() {
+    let repo = Repo::new("config-unset");
+    repo.ok(&["config", "--add", "a.b", "one"]);
+    repo.ok(&["config", "--add", "a.b", "two"]);
+
+    let err = repo.fails(&["config", "--unset", "a.b"], 5);
+    assert_eq!(err, "warning: a.b has multiple values\n");
+    assert_eq!(repo.ok(&["config", "--get-all", "a.b"]), "one\ntwo\n");
+
+    repo.ok(&["config", "--unset-all", "a.b"]);
+    repo.fails(&["config", "a.b"], 1);
+}
+
+#[test]
+fn test_plumbing_options() {
+    let repo = Repo::new("plumbing-options");
+    repo.commit("file", "one\n", "one");
+    let blob = repo.ok(&["hash-object", "file"]);
+    let blob = blob.trim();
+
+    assert_eq!(repo.ok(&["cat-file", "-t", blob]), "blob\n");
+    assert_eq!(repo.ok(&["cat-file", "blob", blob]), "one\n");
+    assert_eq!(repo.ok(&["cat-file", "-p", "--", blob]), "one\n");
+    repo.fails(&["cat-file", "-t", "-s", blob], 129);
+    repo.fails(&["cat-file", "--bogus", blob], 129);
+
+    let cacheinfo = format!("--cacheinfo=100644,{},copy", blob);
+    repo.ok(&["update-index", "--add", &cacheinfo]);
+    repo.ok(&["update-index", "--cacheinfo", "100644", blob, "copy"]);
+    repo.fails(&["update-index", "--cacheinfo"], 129);
+
+    // After `--`, what looks like an option is a path.
+    fs::write(repo.dir.join("-x"), "dash\n").unwrap();
+    repo.ok(&["update-index", "--add", "--", "-x"]);
+    assert_eq!(repo.ok(&["ls-files"]), "-x\ncopy\nfile\n");
+}
+
+#[test]
+fn test_log_patterns() {
+    let repo = Repo::new("log-patterns");
+    repo.commit("file", "one\n", "Fix the parser");
+    repo.commit("file", "two\n", "a+b in the body");
+    repo.commit("file", "three\n", "unrelated");
+
+    let subjects = |args: &[&str]| {
+        let mut all = vec!["log", "--format=%s"];
+        all.extend(args);
+        repo.ok(&all)
+    };
+    assert_eq!(subjects(&["--grep", "^Fix.*parser$"]), "Fix the parser\n");
+    assert_eq!(subjects(&["--grep", "^fix", "-i"]), "Fix the parser\n");
+    assert_eq!(subjects(&["--grep", "^fix"]), "");
+
+    // Basic expressions take `+` literally, extended ones don't.
+    assert_eq!(subjects(&["--grep", "a+b"]), "a+b in the body\n");
+    assert_eq!(
+        subjects(&["--grep", "b \\(in\\|on\\)"]),
+        "a+b in the body\n"
+    );
+    assert_eq!(
+        subjects(&["-E", "--grep", "b (in|on)"]),
+        "a+b in the body\n"
+    );
+    assert_eq!(subjects(&["-E", "--grep", "^a+b"]), "");
+    assert_eq!(subjects(&["-F", "--grep", "a+b"]), "a+b in the body\n");
+    assert_eq!(subjects(&["-F", "--grep", "."]), "");
+
+    assert_eq!(subjects(&["--author", "^A U Thor <"]).lines().count(), 3);
+    assert_eq!(subjects(&["--author", "^Thor"]), "");
+
+    let err = repo.fails(&["log", "-E", "--grep", "(unclosed"], 128);
+    assert!(err.contains("invalid regex '(unclosed'"), "{}", err);
+}